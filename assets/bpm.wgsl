@@ -0,0 +1,105 @@
+// Split-step Fourier beam propagation. One workgroup marches the transverse field through the
+// medium map column by column: refraction as a phase screen in real space, diffraction as the
+// paraxial propagator in k-space. |E|^2 of each column goes to the output texture.
+
+struct Params {
+    // vacuum wavenumber (rad/mm)
+    k0: f32,
+    // reference index of the propagator
+    n0: f32,
+    // step along the axis and transverse sample spacing (mm)
+    dz: f32,
+    dy: f32,
+    columns: u32,
+    // linear RGB the intensity is drawn in
+    color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+// (index, fraction absorbed) per cell, column-major
+@group(0) @binding(1)
+var<storage, read> medium: array<vec2<f32>>;
+
+// complex field at the first column
+@group(0) @binding(2)
+var<storage, read> input: array<vec2<f32>>;
+
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+// Transverse samples; must match BPM_N in src/gpu/bpm.rs
+var<workgroup> field: array<vec2<f32>, 512>;
+
+fn cmul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+fn phasor(phase: f32) -> vec2<f32> {
+    return vec2<f32>(cos(phase), sin(phase));
+}
+
+// Damps the outer tenth on each side so energy leaving the window doesn't wrap around.
+fn edge_window(i: u32) -> f32 {
+    let x = abs(2.0 * (f32(i) + 0.5) / 512.0 - 1.0);
+    return 1.0 - 0.2 * pow(x, 16.0);
+}
+
+// In-place radix-2 FFT of `field`, two elements per invocation; `sign` is -1 forward, +1 inverse.
+fn fft(t: u32, sign: f32) {
+    for (var half = 0u; half < 2u; half = half + 1u) {
+        let i = t + half * 256u;
+        let j = reverseBits(i) >> 23u;
+        if (j > i) {
+            let swap = field[i];
+            field[i] = field[j];
+            field[j] = swap;
+        }
+    }
+    workgroupBarrier();
+    for (var span = 1u; span < 512u; span = span * 2u) {
+        let pos = t % span;
+        let i = (t / span) * 2u * span + pos;
+        let j = i + span;
+        let w = phasor(sign * 3.14159265 * f32(pos) / f32(span));
+        let a = field[i];
+        let b = cmul(field[j], w);
+        field[i] = a + b;
+        field[j] = a - b;
+        workgroupBarrier();
+    }
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn propagate(@builtin(local_invocation_id) local: vec3<u32>) {
+    let t = local.x;
+    field[t] = input[t];
+    field[t + 256u] = input[t + 256u];
+    workgroupBarrier();
+    for (var z = 0u; z < params.columns; z = z + 1u) {
+        for (var half = 0u; half < 2u; half = half + 1u) {
+            let i = t + half * 256u;
+            let cell = medium[z * 512u + i];
+            let screen = phasor(params.k0 * (cell.x - params.n0) * params.dz);
+            field[i] = cmul(field[i], screen) * sqrt(1.0 - cell.y) * edge_window(i);
+        }
+        workgroupBarrier();
+        fft(t, -1.0);
+        for (var half = 0u; half < 2u; half = half + 1u) {
+            let i = t + half * 256u;
+            let k = select(f32(i), f32(i) - 512.0, i >= 256u);
+            let kx = 6.28318531 * k / (512.0 * params.dy);
+            field[i] = cmul(field[i], phasor(-kx * kx * params.dz / (2.0 * params.k0 * params.n0))) / 512.0;
+        }
+        workgroupBarrier();
+        fft(t, 1.0);
+        for (var half = 0u; half < 2u; half = half + 1u) {
+            let i = t + half * 256u;
+            let intensity = min(dot(field[i], field[i]), 1.0);
+            // row 0 of the texture is the +y edge of the window
+            textureStore(output, vec2<i32>(i32(z), i32(511u - i)), vec4<f32>(params.color.rgb * intensity, intensity));
+        }
+        workgroupBarrier();
+    }
+}
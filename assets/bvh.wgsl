@@ -0,0 +1,155 @@
+#define_import_path beams::bvh
+
+// Nearest-hit queries against the scene's surfaces. Bounded surfaces sit in a flattened LBVH
+// laid out depth-first: an internal node is followed by its left subtree, and `skip` points
+// past the node's whole subtree. Rays and lines can't be boxed and are tested one by one.
+
+struct BvhNode {
+    min: vec2<f32>,
+    max: vec2<f32>,
+    // index into `bvh_surfaces` for leaves, -1 for internal nodes
+    surface: i32,
+    skip: u32,
+};
+
+struct BvhSurface {
+    p1: vec2<f32>,
+    p2: vec2<f32>,
+    // 0 segment, 1 ray, 2 line
+    extent: u32,
+    // of an arc through p1 and p2, bulging to the left of p1 -> p2 when positive; 0 if straight
+    curvature: f32,
+};
+
+struct BvhInfo {
+    nodes: u32,
+    // surfaces in the tree; the rest up to `surfaces` are unbounded
+    bounded: u32,
+    surfaces: u32,
+};
+
+struct BvhHit {
+    distance: f32,
+    // -1 when nothing was hit
+    surface: i32,
+};
+
+@group(1) @binding(0)
+var<storage, read> bvh_nodes: array<BvhNode>;
+
+@group(1) @binding(1)
+var<storage, read> bvh_surfaces: array<BvhSurface>;
+
+@group(1) @binding(2)
+var<uniform> bvh_info: BvhInfo;
+
+let BVH_MISS: f32 = 1e30;
+// hits on an arc closer than this (mm) to where a ray left it are its start found again
+let REHIT_DISTANCE: f32 = 0.001;
+
+fn cross2(a: vec2<f32>, b: vec2<f32>) -> f32 {
+    return a.x * b.y - a.y * b.x;
+}
+
+// Distance along the ray to the arc of a curved segment, at least `near`, as `intersect_arc`
+// on the CPU
+fn intersect_arc(p: vec2<f32>, l: vec2<f32>, s: BvhSurface, near: f32) -> f32 {
+    let dp = s.p2 - s.p1;
+    let half = length(dp) / 2.0;
+    let radius = max(1.0 / abs(s.curvature), half);
+    let rise = sqrt(radius * radius - half * half);
+    let normal = normalize(vec2<f32>(-dp.y, dp.x));
+    let center = (s.p1 + s.p2) / 2.0 - normal * sign(s.curvature) * rise;
+    let oc = p - center;
+    let a = dot(l, l);
+    let b = dot(oc, l);
+    let discriminant = b * b - a * (dot(oc, oc) - radius * radius);
+    if (discriminant < 0.0) {
+        return BVH_MISS;
+    }
+    let root = sqrt(discriminant);
+    for (var k = 0; k < 2; k = k + 1) {
+        let t = (-b + f32(2 * k - 1) * root) / a;
+        // the arc is the part of the circle on the side of the chord it bulges to
+        if (t >= near && cross2(dp, p + l * t - s.p1) * s.curvature >= 0.0) {
+            return t;
+        }
+    }
+    return BVH_MISS;
+}
+
+// Distance along the ray to `s`, as `intersect` computes it on the CPU
+fn intersect_surface(p: vec2<f32>, l: vec2<f32>, s: BvhSurface) -> f32 {
+    if (s.extent == 0u && s.curvature != 0.0) {
+        return intersect_arc(p, l, s, 0.0);
+    }
+    let v1 = p - s.p1;
+    let v2 = s.p2 - s.p1;
+    let v3 = vec2<f32>(-l.y, l.x);
+    let d = dot(v2, v3);
+    if (abs(d) < 0.000001) {
+        return BVH_MISS;
+    }
+    let t1 = cross2(v2, v1) / d;
+    let t2 = dot(v1, v3) / d;
+    var on_surface = true;
+    if (s.extent == 0u) {
+        on_surface = t2 >= 0.0 && t2 <= 1.0;
+    } else if (s.extent == 1u) {
+        on_surface = t2 >= 0.0;
+    }
+    if (t1 > 0.0 && on_surface) {
+        return t1;
+    }
+    return BVH_MISS;
+}
+
+// Distance along the ray to surface `k`, as `hit_distance` on the CPU: a straight surface the
+// ray just left is skipped, while an arc can be hit again farther along, as inside a mirror
+fn hit_distance(p: vec2<f32>, l: vec2<f32>, k: i32, exclude: i32) -> f32 {
+    let s = bvh_surfaces[k];
+    if (k != exclude) {
+        return intersect_surface(p, l, s);
+    }
+    if (s.extent == 0u && s.curvature != 0.0) {
+        return intersect_arc(p, l, s, REHIT_DISTANCE);
+    }
+    return BVH_MISS;
+}
+
+// Slab test against a node's box, out to `far`
+fn hits_box(p: vec2<f32>, inv_l: vec2<f32>, node: BvhNode, far: f32) -> bool {
+    let a = (node.min - p) * inv_l;
+    let b = (node.max - p) * inv_l;
+    let near = max(max(min(a.x, b.x), min(a.y, b.y)), 0.0);
+    let exit = min(min(max(a.x, b.x), max(a.y, b.y)), far);
+    return near <= exit;
+}
+
+// Closest surface hit by the ray from `p` along `l`, which just left `exclude`
+fn nearest_hit(p: vec2<f32>, l: vec2<f32>, exclude: i32) -> BvhHit {
+    var hit = BvhHit(BVH_MISS, -1);
+    let inv_l = 1.0 / l;
+    var i = 0u;
+    while (i < bvh_info.nodes) {
+        let node = bvh_nodes[i];
+        if (!hits_box(p, inv_l, node, hit.distance)) {
+            i = node.skip;
+            continue;
+        }
+        if (node.surface >= 0) {
+            let d = hit_distance(p, l, node.surface, exclude);
+            if (d < hit.distance) {
+                hit = BvhHit(d, node.surface);
+            }
+        }
+        i = i + 1u;
+    }
+    for (var k = bvh_info.bounded; k < bvh_info.surfaces; k = k + 1u) {
+        let d = hit_distance(p, l, i32(k), exclude);
+        if (d < hit.distance) {
+            hit = BvhHit(d, i32(k));
+        }
+    }
+    return hit;
+}
@@ -0,0 +1,26 @@
+#define_import_path beams::colormap
+
+// Tonemapping shared by the fluence views. Fluence is RGB in the rays' colors plus plain power
+// in alpha; either maps to 1 - e^(-f / exposure), and power can go through a colormap instead.
+
+// Inferno, as a degree 6 polynomial fit to matplotlib's table, in linear RGB
+fn inferno(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(0.0002189403691192265, 0.001651004631001012, -0.01948089843709184);
+    let c1 = vec3<f32>(0.1065134194856116, 0.5639564367884091, 3.932712388889277);
+    let c2 = vec3<f32>(11.60249308247187, -3.972853965665698, -15.9423941062914);
+    let c3 = vec3<f32>(-41.70399613139459, 17.43639888205313, 44.35414519872813);
+    let c4 = vec3<f32>(77.162935699427, -33.40235894210092, -81.80730925738993);
+    let c5 = vec3<f32>(-71.31942824499214, 32.62606426397723, 73.20951985803202);
+    let c6 = vec3<f32>(25.13112622477341, -12.24266895238567, -23.07032500287172);
+    let srgb = c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+    return pow(clamp(srgb, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(2.2));
+}
+
+// Texel color for `fluence`, through the inferno colormap when `colormap` is 1
+fn tonemap(fluence: vec4<f32>, exposure: f32, colormap: u32) -> vec4<f32> {
+    let mapped = vec4<f32>(1.0) - exp(-fluence / exposure);
+    if (colormap == 1u) {
+        return vec4<f32>(inferno(mapped.a), mapped.a);
+    }
+    return vec4<f32>(mapped.rgb, max(mapped.r, max(mapped.g, mapped.b)));
+}
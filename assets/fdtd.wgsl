@@ -0,0 +1,136 @@
+// 2D FDTD (TMz) on a Yee grid: Ez split into x and y parts for the PML, Hx and Hy. Each step
+// runs update_h then update_e over the whole grid; draw writes Ez to the output texture.
+
+struct Params {
+    // c dt / dx, at most 1/sqrt(2) for stability
+    courant: f32,
+    // source angular frequency (rad per step)
+    omega: f32,
+    step: u32,
+    size: u32,
+    // PML thickness (cells) on each edge
+    pml: u32,
+    // column the plane-wave source is injected along
+    source_column: u32,
+    // linear RGB positive Ez is drawn in
+    color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+// (Ezx, Ezy, Hx, Hy) per cell, column-major
+@group(0) @binding(1)
+var<storage, read_write> cells: array<vec4<f32>>;
+
+// (index, fraction absorbed) per cell, column-major
+@group(0) @binding(2)
+var<storage, read> medium: array<vec2<f32>>;
+
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+// PML conductivity (per step) at the outer edge, graded cubically to zero at its inner edge
+let SIGMA_MAX: f32 = 0.5;
+
+fn index(z: u32, i: u32) -> u32 {
+    return z * params.size + i;
+}
+
+fn ez(z: u32, i: u32) -> f32 {
+    let cell = cells[index(z, i)];
+    return cell.x + cell.y;
+}
+
+fn sigma(c: u32) -> f32 {
+    let d = min(c, params.size - 1u - c);
+    if (d >= params.pml) {
+        return 0.0;
+    }
+    let depth = f32(params.pml - d) / f32(params.pml);
+    return SIGMA_MAX * depth * depth * depth;
+}
+
+// (decay, gain) of a field component in a cell with PML conductivity `s`
+fn coefficients(s: f32) -> vec2<f32> {
+    return vec2<f32>((1.0 - s) / (1.0 + s), params.courant / (1.0 + s));
+}
+
+fn inside(id: vec3<u32>) -> bool {
+    return id.x < params.size && id.y < params.size;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn update_h(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (!inside(id)) {
+        return;
+    }
+    let z = id.x;
+    let i = id.y;
+    let here = index(z, i);
+    let e = ez(z, i);
+    if (i + 1u < params.size) {
+        let y = coefficients(sigma(i));
+        cells[here].z = y.x * cells[here].z - y.y * (ez(z, i + 1u) - e);
+    }
+    if (z + 1u < params.size) {
+        let x = coefficients(sigma(z));
+        cells[here].w = x.x * cells[here].w + x.y * (ez(z + 1u, i) - e);
+    }
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn update_e(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (!inside(id)) {
+        return;
+    }
+    let z = id.x;
+    let i = id.y;
+    let here = index(z, i);
+    let cell = cells[here];
+    let material = medium[here];
+    var hy_behind = 0.0;
+    if (z > 0u) {
+        hy_behind = cells[index(z - 1u, i)].w;
+    }
+    var hx_below = 0.0;
+    if (i > 0u) {
+        hx_below = cells[index(z, i - 1u)].z;
+    }
+    let eps = material.x * material.x;
+    let x = coefficients(sigma(z));
+    let y = coefficients(sigma(i));
+    var ezx = x.x * cell.x + x.y / eps * (cell.w - hy_behind);
+    var ezy = y.x * cell.y - y.y / eps * (cell.z - hx_below);
+    if (z == params.source_column) {
+        // ramped in over three periods to keep the start-up transient small
+        let period = 6.28318531 / params.omega;
+        let ramp = min(f32(params.step) / (3.0 * period), 1.0);
+        let drive = 0.25 * sin(params.omega * f32(params.step)) * ramp;
+        ezx = ezx + drive;
+        ezy = ezy + drive;
+    }
+    // absorbing cells damp the field; fully absorbing ones act as a perfect conductor
+    let keep = 1.0 - min(material.y, 1.0);
+    cells[here].x = ezx * keep;
+    cells[here].y = ezy * keep;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn draw(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (!inside(id)) {
+        return;
+    }
+    let z = id.x;
+    let i = id.y;
+    let e = 2.0 * ez(z, i);
+    let material = medium[index(z, i)];
+    // glass shows as a faint grey, conductors as solid grey
+    var color = vec3<f32>(0.1 * (material.x - 1.0));
+    if (material.y >= 1.0) {
+        color = vec3<f32>(0.5);
+    }
+    color = color + params.color.rgb * max(e, 0.0) + (vec3<f32>(1.0) - params.color.rgb) * max(-e, 0.0);
+    // row 0 of the texture is the +y edge of the grid
+    textureStore(output, vec2<i32>(i32(z), i32(params.size - 1u - i)), vec4<f32>(min(color, vec3<f32>(1.0)), 1.0));
+}
@@ -0,0 +1,92 @@
+// Coherent sum over traced rays, one invocation per screen texel. Each ray segment stands for a
+// Gaussian beamlet whose phase follows the optical path; amplitudes of one group (wavelength)
+// add as complex numbers, groups add in intensity, and the result is tonemapped. Segments are
+// binned into square tiles of the texture beforehand, so a texel only sums those near it.
+
+struct Segment {
+    p0: vec2<f32>,
+    p1: vec2<f32>,
+    // phase (rad) at p0
+    phase: f32,
+    // wavenumber in the medium (rad/mm)
+    k: f32,
+    amplitude: f32,
+    // segments are sorted by group
+    group: u32,
+    // linear RGB of the group
+    color: vec4<f32>,
+};
+
+struct Params {
+    // scene position (mm) of the top-left texel corner
+    origin: vec2<f32>,
+    mm_per_texel: f32,
+    // 1/e half width (mm) of a beamlet
+    beamlet: f32,
+    exposure: f32,
+    // columns and rows of tiles
+    tiles: vec2<u32>,
+};
+
+@group(0) @binding(0)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+@group(0) @binding(1)
+var<storage, read> segments: array<Segment>;
+
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+// start and length in `indices` of each tile's segments, row by row
+@group(0) @binding(3)
+var<storage, read> tiles: array<vec2<u32>>;
+
+// segments reaching into each tile, in order
+@group(0) @binding(4)
+var<storage, read> indices: array<u32>;
+
+// side (texels) of a tile
+let TILE: u32 = 16u;
+
+@compute @workgroup_size(8, 8, 1)
+fn accumulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (i32(id.x) >= size.x || i32(id.y) >= size.y) {
+        return;
+    }
+    // texel rows run down, scene y runs up
+    let p = params.origin + vec2<f32>(f32(id.x) + 0.5, -(f32(id.y) + 0.5)) * params.mm_per_texel;
+    var total = vec3<f32>(0.0);
+    var sum = vec2<f32>(0.0);
+    var color = vec3<f32>(0.0);
+    let tile = tiles[(id.y / TILE) * params.tiles.x + id.x / TILE];
+    var group = 0u;
+    if (tile.y > 0u) {
+        group = segments[indices[tile.x]].group;
+    }
+    for (var n = tile.x; n < tile.x + tile.y; n = n + 1u) {
+        let s = segments[indices[n]];
+        if (s.group != group) {
+            total = total + color * dot(sum, sum);
+            sum = vec2<f32>(0.0);
+            group = s.group;
+        }
+        color = s.color.rgb;
+        let d = s.p1 - s.p0;
+        let len = length(d);
+        if (len <= 0.0) {
+            continue;
+        }
+        let along = dot(p - s.p0, d) / len;
+        let across = abs(dot(p - s.p0, vec2<f32>(-d.y, d.x)) / len);
+        if (along < 0.0 || along > len || across > 3.0 * params.beamlet) {
+            continue;
+        }
+        let a = s.amplitude * exp(-(across * across) / (params.beamlet * params.beamlet));
+        let phase = s.phase + s.k * along;
+        sum = sum + a * vec2<f32>(cos(phase), sin(phase));
+    }
+    total = total + color * dot(sum, sum);
+    let resolved = vec3<f32>(1.0) - exp(-params.exposure * total);
+    textureStore(output, vec2<i32>(id.xy), vec4<f32>(resolved, max(resolved.r, max(resolved.g, resolved.b))));
+}
@@ -0,0 +1,119 @@
+// Photon-packet Monte Carlo in a turbid medium. Each invocation of `simulate` follows one
+// packet from a ray entry point until it leaves the medium or is lost to roulette, adding the
+// weight it loses to absorption to the fluence grid. `resolve` turns the grid into an image.
+
+struct Launch {
+    p: vec2<f32>,
+    l: vec2<f32>,
+    weight: f32,
+};
+
+struct Params {
+    min: vec2<f32>,
+    max: vec2<f32>,
+    // absorption and scattering coefficients (1/mm) and anisotropy
+    mu_a: f32,
+    mu_s: f32,
+    g: f32,
+    launches: u32,
+    // packets launched so far, counting this frame's
+    photons: u32,
+    seed: u32,
+    size: u32,
+    // linear RGB the fluence is drawn in
+    color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+@group(0) @binding(1)
+var<storage, read> launches: array<Launch>;
+
+// absorbed weight per cell, in 1/SCALE units
+@group(0) @binding(2)
+var<storage, read_write> fluence: array<atomic<u32>>;
+
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+let SCALE: f32 = 1000.0;
+// packets below this weight play roulette
+let ROULETTE_WEIGHT: f32 = 0.0001;
+let ROULETTE_SURVIVAL: f32 = 0.1;
+let MAX_STEPS: u32 = 100000u;
+let PI: f32 = 3.14159265;
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// uniform in (0, 1]
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = pcg(*state);
+    return (f32(*state >> 8u) + 1.0) / 16777216.0;
+}
+
+fn inside(p: vec2<f32>) -> bool {
+    return all(p >= params.min) && all(p <= params.max);
+}
+
+fn cell(p: vec2<f32>) -> u32 {
+    let uv = (p - params.min) / (params.max - params.min);
+    let xy = min(vec2<u32>(uv * f32(params.size)), vec2<u32>(params.size - 1u));
+    return xy.y * params.size + xy.x;
+}
+
+// Henyey-Greenstein phase function in 2D (wrapped Cauchy): deflection angle for anisotropy g
+fn deflection(g: f32, state: ptr<function, u32>) -> f32 {
+    return 2.0 * atan((1.0 - g) / (1.0 + g) * tan(PI * (random(state) - 0.5)));
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn simulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (params.launches == 0u) {
+        return;
+    }
+    var state = pcg(id.x ^ pcg(params.seed));
+    let launch = launches[id.x % params.launches];
+    var p = launch.p;
+    var l = launch.l;
+    var w = launch.weight;
+    let mu_t = params.mu_a + params.mu_s;
+    for (var step = 0u; step < MAX_STEPS; step = step + 1u) {
+        p = p - log(random(&state)) / mu_t * l;
+        if (!inside(p)) {
+            return;
+        }
+        let absorbed = w * params.mu_a / mu_t;
+        // stochastic rounding keeps small deposits unbiased
+        atomicAdd(&fluence[cell(p)], u32(absorbed * SCALE + random(&state)));
+        w = w - absorbed;
+        let theta = deflection(params.g, &state);
+        l = vec2<f32>(l.x * cos(theta) - l.y * sin(theta), l.x * sin(theta) + l.y * cos(theta));
+        if (w < ROULETTE_WEIGHT) {
+            if (random(&state) > ROULETTE_SURVIVAL) {
+                return;
+            }
+            w = w / ROULETTE_SURVIVAL;
+        }
+    }
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn resolve(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.size || id.y >= params.size) {
+        return;
+    }
+    // texture row 0 is the top edge of the medium
+    let index = (params.size - 1u - id.y) * params.size + id.x;
+    let area = (params.max.x - params.min.x) * (params.max.y - params.min.y) / f32(params.size * params.size);
+    // fluence per packet: absorbed weight over mu_a and cell area
+    let phi = f32(atomicLoad(&fluence[index])) / SCALE / max(f32(params.photons), 1.0) / (params.mu_a * area);
+    // four decades below 10 / mm
+    let v = clamp((log(max(phi, 1e-12)) / log(10.0) + 3.0) / 4.0, 0.0, 1.0);
+    let color = mix(params.color.rgb * 2.0 * v, vec3<f32>(1.0), max(2.0 * v - 1.0, 0.0));
+    textureStore(output, vec2<i32>(id.xy), vec4<f32>(min(color, vec3<f32>(1.0)), v));
+}
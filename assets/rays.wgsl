@@ -0,0 +1,87 @@
+#import beams::colormap
+
+// Histograms traced ray segments into a fluence buffer, then tonemaps it into the ray texture.
+// `accumulate` runs one invocation per segment and only when the segments change; `resolve`
+// runs one invocation per texel every frame.
+
+struct Segment {
+    p0: vec2<f32>,
+    p1: vec2<f32>,
+    // linear RGB and alpha, scaled by intensity
+    color: vec4<f32>,
+};
+
+struct Params {
+    // scene position (mm) of the top-left texel corner
+    origin: vec2<f32>,
+    mm_per_texel: f32,
+    // fluence that maps to 1 - 1/e of full brightness
+    exposure: f32,
+    count: u32,
+    size: vec2<u32>,
+    // 1 to draw the power through the inferno colormap, 0 in the rays' colors
+    colormap: u32,
+};
+
+@group(0) @binding(0)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+@group(0) @binding(1)
+var<storage, read> segments: array<Segment>;
+
+// RGB and power fluence per texel, in 1/SCALE units
+@group(0) @binding(2)
+var<storage, read_write> fluence: array<atomic<u32>>;
+
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+let SCALE: f32 = 4096.0;
+// longest segment walked, in texels; longer ones are sampled more coarsely
+let MAX_STEPS: u32 = 4096u;
+
+fn deposit(texel: vec2<i32>, rgba: vec4<f32>) {
+    if (any(texel < vec2<i32>(0)) || any(texel >= vec2<i32>(params.size))) {
+        return;
+    }
+    let index = 4u * (u32(texel.y) * params.size.x + u32(texel.x));
+    let units = vec4<u32>(rgba * SCALE + 0.5);
+    atomicAdd(&fluence[index], units.r);
+    atomicAdd(&fluence[index + 1u], units.g);
+    atomicAdd(&fluence[index + 2u], units.b);
+    atomicAdd(&fluence[index + 3u], units.a);
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn accumulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.count) {
+        return;
+    }
+    let segment = segments[id.x];
+    // texel space, rows running down
+    let a = (segment.p0 - params.origin) * vec2<f32>(1.0, -1.0) / params.mm_per_texel;
+    let b = (segment.p1 - params.origin) * vec2<f32>(1.0, -1.0) / params.mm_per_texel;
+    let span = distance(a, b);
+    let steps = clamp(u32(ceil(span)), 1u, MAX_STEPS);
+    // each step deposits the power times the path length it covers, in texels
+    let rgba = segment.color * span / f32(steps);
+    for (var i = 0u; i < steps; i = i + 1u) {
+        let p = mix(a, b, (f32(i) + 0.5) / f32(steps));
+        deposit(vec2<i32>(floor(p)), rgba);
+    }
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn resolve(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.size.x || id.y >= params.size.y) {
+        return;
+    }
+    let index = 4u * (id.y * params.size.x + id.x);
+    let rgba = vec4<f32>(
+        f32(atomicLoad(&fluence[index])),
+        f32(atomicLoad(&fluence[index + 1u])),
+        f32(atomicLoad(&fluence[index + 2u])),
+        f32(atomicLoad(&fluence[index + 3u]))
+    ) / SCALE;
+    textureStore(output, vec2<i32>(id.xy), tonemap(rgba, params.exposure, params.colormap));
+}
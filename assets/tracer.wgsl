@@ -0,0 +1,265 @@
+#import beams::bvh
+#import beams::colormap
+
+// Traces dense ray launches through the scene's surfaces. Each invocation of `trace` follows
+// one ray from a jittered launch, choosing at random between the branches a surface splits it
+// into in proportion to their power, and adds the power along each leg to a fluence buffer.
+// `resolve` averages the fluence over the launches traced so far and tonemaps it.
+
+struct Launch {
+    p: vec2<f32>,
+    l: vec2<f32>,
+    // span across which the start point is spread, centered on p
+    jitter: vec2<f32>,
+    // full angle (rad) over which the direction is spread
+    turn: f32,
+    index: f32,
+    // wavelength (nm)
+    w: f32,
+    // linear RGB and alpha, scaled by power (mW)
+    color: vec4<f32>,
+};
+
+struct Material {
+    index: f32,
+    // index inside the body the surface bounds, 0 when it bounds none
+    interior: f32,
+    // dispersion model of the body's glass: 0 for none, 1 for Sellmeier, 2 for Cauchy
+    dispersion: u32,
+    // Sellmeier B coefficients, or Cauchy's A, B and C
+    b: vec3<f32>,
+    // Sellmeier C coefficients (µm²)
+    c: vec3<f32>,
+    interior_absorption: f32,
+    reflection: f32,
+    absorption: f32,
+    transmittance: f32,
+    // of an ideal thin lens, 0 for none
+    focal_length: f32,
+    // transmitted band (nm), both 0 for none
+    passband: vec2<f32>,
+};
+
+struct Params {
+    // scene position (mm) of the top-left texel corner
+    origin: vec2<f32>,
+    mm_per_texel: f32,
+    // fluence (mW/mm) that maps to 1 - 1/e of full brightness
+    exposure: f32,
+    launches: u32,
+    // rays traced so far, counting this frame's
+    rays: u32,
+    size: vec2<u32>,
+    // 1 to draw the power through the inferno colormap, 0 in the rays' colors
+    colormap: u32,
+};
+
+@group(0) @binding(0)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+@group(0) @binding(1)
+var<storage, read> launches: array<Launch>;
+
+// one per surface, in the BVH's order
+@group(0) @binding(2)
+var<storage, read> materials: array<Material>;
+
+// RGB and power fluence per texel, summed over every ray traced, in 1/SCALE units
+@group(0) @binding(3)
+var<storage, read_write> fluence: array<atomic<u32>>;
+
+@group(0) @binding(4)
+var<uniform> params: Params;
+
+let SCALE: f32 = 256.0;
+// rays traced per frame; a multiple of the trace kernel's workgroup size
+let RAYS_PER_FRAME: u32 = 65536u;
+let MAX_BOUNCES: u32 = 64u;
+// rays weaker than this are dropped, as the CPU tracer does below its minimum intensity
+let MIN_WEIGHT: f32 = 0.0001;
+// longest leg walked, in texels; longer ones are sampled more coarsely
+let MAX_STEPS: u32 = 4096u;
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// uniform in [0, 1)
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = pcg(*state);
+    return f32(*state >> 8u) / 16777216.0;
+}
+
+fn rotate(l: vec2<f32>, angle: f32) -> vec2<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    return vec2<f32>(c * l.x - s * l.y, s * l.x + c * l.y);
+}
+
+fn reflect2(l: vec2<f32>, normal: vec2<f32>) -> vec2<f32> {
+    return l - 2.0 * dot(l, normal) * normal;
+}
+
+// Unit normal of `s` at `at`, on the side of `Surface::normal_at`
+fn normal_at(s: BvhSurface, at: vec2<f32>) -> vec2<f32> {
+    let dp = s.p2 - s.p1;
+    let normal = normalize(vec2<f32>(-dp.y, dp.x));
+    if (s.extent != 0u || s.curvature == 0.0) {
+        return normal;
+    }
+    let half = length(dp) / 2.0;
+    let radius = max(1.0 / abs(s.curvature), half);
+    let rise = sqrt(radius * radius - half * half);
+    let center = (s.p1 + s.p2) / 2.0 - normal * sign(s.curvature) * rise;
+    return normalize(at - center) * sign(s.curvature);
+}
+
+// Distance along the ray to the edge of the texture
+fn exit_distance(p: vec2<f32>, l: vec2<f32>) -> f32 {
+    let lo = params.origin - vec2<f32>(0.0, f32(params.size.y) * params.mm_per_texel);
+    let hi = params.origin + vec2<f32>(f32(params.size.x) * params.mm_per_texel, 0.0);
+    let a = (lo - p) / l;
+    let b = (hi - p) / l;
+    return max(min(max(a.x, b.x), max(a.y, b.y)), 0.0);
+}
+
+// Adds the power along a leg, decaying by e^(-alpha s), as power per unit length across it
+fn deposit_leg(p: vec2<f32>, l: vec2<f32>, d: f32, rgba: vec4<f32>, alpha: f32, seed: ptr<function, u32>) {
+    // texel space, rows running down
+    let a = (p - params.origin) * vec2<f32>(1.0, -1.0) / params.mm_per_texel;
+    let b = (p + l * d - params.origin) * vec2<f32>(1.0, -1.0) / params.mm_per_texel;
+    let steps = clamp(u32(ceil(distance(a, b))), 1u, MAX_STEPS);
+    // each step covers d / steps of path in a texel of mm_per_texel² area
+    let per_step = d / f32(steps) / (params.mm_per_texel * params.mm_per_texel);
+    for (var i = 0u; i < steps; i = i + 1u) {
+        let t = (f32(i) + 0.5) / f32(steps);
+        let texel = vec2<i32>(floor(mix(a, b, t)));
+        if (any(texel < vec2<i32>(0)) || any(texel >= vec2<i32>(params.size))) {
+            continue;
+        }
+        let index = 4u * (u32(texel.y) * params.size.x + u32(texel.x));
+        // rounded at random, so that faint rays add up without bias
+        let units = rgba * exp(-alpha * d * t) * per_step * SCALE;
+        atomicAdd(&fluence[index], u32(units.r + random(seed)));
+        atomicAdd(&fluence[index + 1u], u32(units.g + random(seed)));
+        atomicAdd(&fluence[index + 2u], u32(units.b + random(seed)));
+        atomicAdd(&fluence[index + 3u], u32(units.a + random(seed)));
+    }
+}
+
+// Index inside a surface's body at wavelength `w` (nm), as `Material::index`.
+fn interior_index(m: Material, w: f32) -> f32 {
+    let l2 = (w / 1000.0) * (w / 1000.0);
+    if (m.dispersion == 1u) {
+        let terms = m.b * l2 / (vec3<f32>(l2) - m.c);
+        return sqrt(max(1.0 + terms.x + terms.y + terms.z, 1.0));
+    }
+    if (m.dispersion == 2u) {
+        return m.b.x + m.b.y / l2 + m.b.z / (l2 * l2);
+    }
+    return m.interior;
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn trace(@builtin(global_invocation_id) id: vec3<u32>) {
+    // numbered across frames, so each launch is sampled at fresh points
+    let n = params.rays - RAYS_PER_FRAME + id.x;
+    let launch = launches[n % params.launches];
+    var seed = pcg(n);
+    var p = launch.p + launch.jitter * (random(&seed) - 0.5);
+    var l = rotate(launch.l, launch.turn * (random(&seed) - 0.5));
+    var index = launch.index;
+    var alpha = 0.0;
+    var weight = 1.0;
+    var exclude = -1;
+    for (var bounce = 0u; bounce < MAX_BOUNCES && weight >= MIN_WEIGHT; bounce = bounce + 1u) {
+        let hit = nearest_hit(p, l, exclude);
+        let d = min(hit.distance, exit_distance(p, l));
+        deposit_leg(p, l, d, launch.color * weight, alpha, &seed);
+        if (hit.surface < 0 || hit.distance > d) {
+            return;
+        }
+        weight = weight * exp(-alpha * d);
+        p = p + l * d;
+        exclude = hit.surface;
+        let m = materials[hit.surface];
+        let normal = normal_at(bvh_surfaces[hit.surface], p);
+        if (m.absorption >= 1.0) {
+            return;
+        }
+        let banded = m.passband.y > 0.0;
+        if (banded && (launch.w < m.passband.x || launch.w > m.passband.y)) {
+            if (m.reflection <= 0.0) {
+                return;
+            }
+            l = reflect2(l, normal);
+            weight = weight * m.reflection;
+            continue;
+        }
+        if (m.reflection > 0.0 && !banded) {
+            // reflected, straight through or absorbed, in proportion to each share
+            let u = random(&seed);
+            if (u < m.reflection) {
+                l = reflect2(l, normal);
+            } else if (u >= 1.0 - m.absorption) {
+                return;
+            }
+            continue;
+        }
+        weight = weight * (1.0 - m.absorption) * m.transmittance;
+        let inward = dot(l, normal) < 0.0;
+        var beyond = m.index;
+        var alpha_beyond = alpha;
+        if (m.interior > 0.0) {
+            beyond = select(m.index, interior_index(m, launch.w), inward);
+            alpha_beyond = select(0.0, m.interior_absorption, inward);
+        }
+        // refraction as `refract`, with the Fresnel reflection taken at random
+        let facing = select(-normal, normal, inward);
+        let eta = index / beyond;
+        let cos_i = -dot(facing, l);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        var reflected = sin2_t >= 1.0;
+        if (!reflected && index != beyond) {
+            let cos_t = sqrt(1.0 - sin2_t);
+            let rs = (index * cos_i - beyond * cos_t) / (index * cos_i + beyond * cos_t);
+            let rp = (beyond * cos_i - index * cos_t) / (beyond * cos_i + index * cos_t);
+            reflected = random(&seed) < (rs * rs + rp * rp) / 2.0;
+        }
+        if (reflected) {
+            l = reflect2(l, normal);
+        } else {
+            l = normalize(eta * l + (eta * cos_i - sqrt(1.0 - sin2_t)) * facing);
+            index = beyond;
+            alpha = alpha_beyond;
+        }
+        if (m.focal_length != 0.0) {
+            // tan θ' = tan θ - h / f, with θ measured from the lens axis
+            let s = bvh_surfaces[hit.surface];
+            let along = normalize(s.p2 - s.p1);
+            let h = dot(p - (s.p1 + s.p2) / 2.0, along);
+            let axis = normal * sign(dot(l, normal));
+            let tangent = dot(l, along) / dot(l, axis) - h / m.focal_length;
+            l = normalize(axis + along * tangent);
+        }
+    }
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn resolve(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.size.x || id.y >= params.size.y) {
+        return;
+    }
+    let index = 4u * (id.y * params.size.x + id.x);
+    // every launch has been sampled this many times
+    let passes = f32(params.rays) / f32(params.launches);
+    let rgba = vec4<f32>(
+        f32(atomicLoad(&fluence[index])),
+        f32(atomicLoad(&fluence[index + 1u])),
+        f32(atomicLoad(&fluence[index + 2u])),
+        f32(atomicLoad(&fluence[index + 3u]))
+    ) / (SCALE * passes);
+    textureStore(output, vec2<i32>(id.xy), tonemap(rgba, params.exposure, params.colormap));
+}
@@ -0,0 +1,280 @@
+use bevy::{prelude::*, window::RequestRedraw};
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    config::Config, cross2, fans::RayFan, links::Param, locale::Locale, reflect, scene_tree::Selected, trace_limited,
+    ui::{self, UiFont}, units::Quantity, viewports::MainCamera, BeamSource, Ray, Scale, Surface, TraceLimits
+};
+
+// Newton iterations of the walk, and the residual (mm) at which it stops
+const WALK_ITERATIONS: usize = 50;
+const WALK_TOLERANCE: f32 = 1e-4;
+// Below this, the beam runs along a mirror
+const PARALLEL_TOLERANCE: f32 = 1e-6;
+// Mirror turn (degrees) for the numerical Jacobian, and the largest turn per iteration
+const ANGLE_STEP: f32 = 1e-3;
+const MAX_TURN: f32 = 5.;
+// Time (s) the mirrors take to turn to the solution
+const ANIMATION_SECONDS: f32 = 2.;
+// Half size (mm) of the target markers
+const MARKER_SIZE: f32 = 0.4;
+const TARGET_COLOR: Color = Color::rgb(1.0, 0.4, 0.8);
+
+/// Why the walk has no solution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalkError {
+    /// The beam misses the mirrors' lines, or the targets end up behind the beam
+    Unreachable,
+    /// The solution hits a mirror outside its length
+    OffMirror(usize),
+    NoConvergence
+}
+
+/// Angles (degrees) of both mirrors before and after the walk.
+#[derive(Clone, Copy, Debug)]
+pub struct WalkSolution {
+    pub mirrors: [Entity; 2],
+    pub from: [f32; 2],
+    pub to: [f32; 2]
+}
+
+/// Two-mirror beam walk. W toggles the assistant; right clicks place two targets, and the two
+/// selected surfaces, taken as mirrors, turn to steer the fan's source through both.
+#[derive(Resource, Default)]
+pub struct BeamWalk {
+    pub enabled: bool,
+    pub targets: Vec<Vec2>,
+    pub solution: Option<Result<WalkSolution, WalkError>>,
+    /// Progress of the animation from 0 to 1, while it runs
+    pub progress: Option<f32>
+}
+
+#[derive(Component)]
+pub struct WalkAnnotation;
+
+/// Reflects the beam at `p` along `l` off the infinite mirror through `center` at `angle`.
+fn bounce(p: Vec2, l: Vec2, center: Vec2, angle: f32) -> Option<(Vec2, Vec2)> {
+    let normal = Vec2::from_angle(angle.to_radians()).perp();
+    let along = l.dot(normal);
+    if along.abs() < PARALLEL_TOLERANCE {
+        return None
+    }
+    let t = (center - p).dot(normal) / along;
+    (t > 0.).then(|| (p + l * t, reflect(l, normal)))
+}
+
+/// Where the beam hits both mirrors, and where it goes after the second.
+fn walk(ray: &Ray, mirrors: &[(Vec2, f32); 2]) -> Option<([Vec2; 2], Vec2)> {
+    let (hit1, l1) = bounce(ray.p, ray.l, mirrors[0].0, mirrors[0].1)?;
+    let (hit2, l2) = bounce(hit1, l1, mirrors[1].0, mirrors[1].1)?;
+    Some(([hit1, hit2], l2))
+}
+
+/// Distances of the targets from the beam leaving the second mirror.
+fn residuals(ray: &Ray, mirrors: &[(Vec2, f32); 2], targets: [Vec2; 2]) -> Option<Vec2> {
+    let ([_, hit], l) = walk(ray, mirrors)?;
+    Some(Vec2::new(cross2(l, targets[0] - hit), cross2(l, targets[1] - hit)))
+}
+
+/// Turns both mirrors about their centers until the beam passes through both targets, by
+/// Newton's method on the two angles.
+pub fn solve(ray: &Ray, mirrors: [(Entity, &Surface); 2], targets: [Vec2; 2]) -> Result<WalkSolution, WalkError> {
+    let center = |surface: &Surface| (surface.p1 + surface.p2) / 2.;
+    let from = mirrors.map(|(_, surface)| Param::Angle.get(surface).unwrap_or(0.));
+    let mut angles = [(center(mirrors[0].1), from[0]), (center(mirrors[1].1), from[1])];
+    let mut converged = false;
+    for _ in 0..WALK_ITERATIONS {
+        let r = residuals(ray, &angles, targets).ok_or(WalkError::Unreachable)?;
+        if r.abs().max_element() < WALK_TOLERANCE {
+            converged = true;
+            break
+        }
+        let column = |k: usize| -> Result<Vec2, WalkError> {
+            let mut turned = angles;
+            turned[k].1 += ANGLE_STEP;
+            Ok((residuals(ray, &turned, targets).ok_or(WalkError::Unreachable)? - r) / ANGLE_STEP)
+        };
+        let jacobian = Mat2::from_cols(column(0)?, column(1)?);
+        if jacobian.determinant().abs() < f32::EPSILON {
+            return Err(WalkError::NoConvergence)
+        }
+        let step = (jacobian.inverse() * -r).clamp(Vec2::splat(-MAX_TURN), Vec2::splat(MAX_TURN));
+        angles[0].1 += step.x;
+        angles[1].1 += step.y;
+    }
+    if !converged {
+        return Err(WalkError::NoConvergence)
+    }
+    let (hits, l) = walk(ray, &angles).ok_or(WalkError::Unreachable)?;
+    if targets.iter().any(|target| (*target - hits[1]).dot(l) < 0.) {
+        return Err(WalkError::Unreachable)
+    }
+    for (k, (hit, (_, surface))) in hits.iter().zip(mirrors).enumerate() {
+        if hit.distance(center(surface)) > surface.length().0 / 2. {
+            return Err(WalkError::OffMirror(k + 1))
+        }
+    }
+    Ok(WalkSolution {
+        mirrors: mirrors.map(|(entity, _)| entity),
+        from,
+        to: [angles[0].1, angles[1].1]
+    })
+}
+
+/// W toggles the assistant, and a right click places a target, replacing the older of two.
+pub fn walk_input_system(
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Transform, &OrthographicProjection), MainCamera>,
+    mut beam_walk: ResMut<BeamWalk>
+) {
+    // Ctrl+W closes a tab
+    if keys.just_pressed(KeyCode::W) && !keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        beam_walk.enabled = !beam_walk.enabled;
+        beam_walk.targets.clear();
+        beam_walk.solution = None;
+    }
+    if !beam_walk.enabled || !buttons.just_pressed(MouseButton::Right) {
+        return
+    }
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    let Some(cursor) = window.cursor_position() else {
+        return
+    };
+    // cursor positions start at the bottom left
+    let centered = cursor - Vec2::new(window.width(), window.height()) / 2.;
+    let target = camera.translation.truncate() + centered * projection.scale;
+    if beam_walk.targets.len() == 2 {
+        beam_walk.targets.remove(0);
+    }
+    beam_walk.targets.push(target);
+    beam_walk.solution = None;
+    beam_walk.progress = None;
+}
+
+/// Solves the walk once two targets are placed and two mirrors selected, with the mirrors in
+/// the order the fan's source reaches them.
+pub fn walk_solve_system(
+    fan: Res<RayFan>,
+    mut beam_walk: ResMut<BeamWalk>,
+    source_query: Query<&BeamSource>,
+    mirror_query: Query<(Entity, &Surface), With<Selected>>,
+    surface_query: Query<(Entity, &Surface)>
+) {
+    if beam_walk.solution.is_some() {
+        return
+    }
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    let mut mirrors: Vec<(Entity, &Surface)> = mirror_query.iter().collect();
+    let (&[first, second], false, 2) = (&beam_walk.targets[..], sources.is_empty(), mirrors.len()) else {
+        return
+    };
+    let source = sources[fan.source % sources.len()];
+    let ray = source.chief_ray();
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    let order: Vec<Entity> = trace_limited(&ray, &surfaces, |_| None, TraceLimits::sequential()).segments.iter().map(|segment| segment.surface).collect();
+    mirrors.sort_by_key(|(entity, _)| order.iter().position(|hit| hit == entity).unwrap_or(usize::MAX));
+    let solution = solve(&ray, [mirrors[0], mirrors[1]], [first, second]);
+    beam_walk.progress = solution.is_ok().then_some(0.);
+    beam_walk.solution = Some(solution);
+}
+
+/// Turns the mirrors towards the solution over [`ANIMATION_SECONDS`], requesting frames so
+/// it also plays in power-saving mode.
+pub fn walk_animation_system(
+    time: Res<Time>,
+    mut beam_walk: ResMut<BeamWalk>,
+    mut surface_query: Query<&mut Surface>,
+    mut redraw: EventWriter<RequestRedraw>
+) {
+    let (Some(progress), Some(Ok(solution))) = (beam_walk.progress, beam_walk.solution) else {
+        return
+    };
+    let progress = (progress + time.delta_seconds() / ANIMATION_SECONDS).min(1.);
+    // ease in and out
+    let eased = progress * progress * (3. - 2. * progress);
+    for (k, entity) in solution.mirrors.iter().enumerate() {
+        if let Ok(mut surface) = surface_query.get_mut(*entity) {
+            let angle = solution.from[k] + (solution.to[k] - solution.from[k]) * eased;
+            // the angle setter only fails for focal lengths
+            let _ = Param::Angle.set(&mut surface, angle);
+        }
+    }
+    beam_walk.progress = (progress < 1.).then_some(progress);
+    redraw.send(RequestRedraw);
+}
+
+pub fn walk_panel_system(
+    mut commands: Commands,
+    beam_walk: Res<BeamWalk>,
+    (config, scale): (Res<Config>, Res<Scale>),
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    annotation_query: Query<Entity, With<WalkAnnotation>>
+) {
+    if !beam_walk.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for entity in annotation_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !beam_walk.enabled {
+        return
+    }
+    for target in beam_walk.targets.iter() {
+        let mut path_builder = PathBuilder::new();
+        for (from, to) in [(Vec2::new(-1., 0.), Vec2::new(1., 0.)), (Vec2::new(0., -1.), Vec2::new(0., 1.))] {
+            path_builder.move_to(*target + from * MARKER_SIZE);
+            path_builder.line_to(*target + to * MARKER_SIZE);
+        }
+        commands.spawn((GeometryBuilder::build_as(
+            &path_builder.build(),
+            DrawMode::Stroke(StrokeMode::new(TARGET_COLOR, scale.px(2.))),
+            Transform::from_xyz(0., 0., 2.)
+        ), WalkAnnotation));
+    }
+    let mut lines = vec![
+        locale.text("walk.title").to_string(),
+        locale.format("walk.targets", &[("count", &beam_walk.targets.len())])
+    ];
+    match beam_walk.solution {
+        None if beam_walk.targets.len() == 2 => lines.push(locale.text("walk.mirrors").to_string()),
+        None => {}
+        Some(Ok(solution)) => {
+            for k in 0..2 {
+                lines.push(locale.format("walk.turn", &[
+                    ("k", &(k + 1)),
+                    ("from", &Param::Angle.show(config.units, solution.from[k])),
+                    ("to", &Param::Angle.show(config.units, solution.to[k])),
+                    ("by", &format!("{:+}", config.units.show(Quantity::Angle, (solution.to[k] - solution.from[k]).to_radians())))
+                ]));
+            }
+            lines.push(locale.text("walk.lesson").to_string());
+        }
+        Some(Err(WalkError::OffMirror(k))) => lines.push(locale.format("walk.off-mirror", &[("k", &k)])),
+        Some(Err(WalkError::Unreachable)) => lines.push(locale.text("walk.unreachable").to_string()),
+        Some(Err(WalkError::NoConvergence)) => lines.push(locale.text("walk.no-convergence").to_string())
+    }
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(30.),
+                top: Val::Percent(40.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, WalkAnnotation)).with_children(|panel| {
+        for line in lines {
+            panel.spawn(TextBundle::from_section(line, ui::text_style(&font, ui::TEXT_COLOR)));
+        }
+    });
+}
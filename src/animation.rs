@@ -0,0 +1,91 @@
+use bevy::{prelude::*, window::RequestRedraw};
+use serde::{Deserialize, Serialize};
+
+use crate::{links::Param, Surface};
+
+/// Scene clock driving animation tracks. Space pauses and resumes it.
+#[derive(Resource, Default)]
+pub struct Clock {
+    /// Seconds played so far
+    pub time: f32,
+    pub playing: bool
+}
+
+/// Sweeps a parameter of its surface from `from` to `to` and back, once per `period` seconds.
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Track {
+    pub param: Param,
+    pub from: f32,
+    pub to: f32,
+    pub period: f32
+}
+
+impl Track {
+    /// Value at `time`, a triangle wave starting at `from`.
+    pub fn at(&self, time: f32) -> f32 {
+        let phase = (time / self.period.max(f32::EPSILON)).fract();
+        let t = 1. - (2. * phase - 1.).abs();
+        self.from + (self.to - self.from) * t
+    }
+}
+
+pub fn clock_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut clock: ResMut<Clock>
+) {
+    if keys.just_pressed(KeyCode::Space) {
+        clock.playing = !clock.playing;
+    }
+}
+
+/// Advances the clock while it plays.
+pub fn clock_system(
+    time: Res<Time>,
+    mut clock: ResMut<Clock>
+) {
+    if clock.playing {
+        clock.time += time.delta_seconds();
+    }
+}
+
+/// Sets every tracked parameter, requesting frames so tracks also play in power-saving mode.
+pub fn track_system(
+    clock: Res<Clock>,
+    mut track_query: Query<(&Track, &mut Surface)>,
+    mut redraw: EventWriter<RequestRedraw>
+) {
+    if !clock.playing || track_query.is_empty() {
+        return
+    }
+    for (track, mut surface) in track_query.iter_mut() {
+        if let Err(err) = track.param.set(&mut surface, track.at(clock.time)) {
+            warn!("animation track on {:?}: {}", track.param, err);
+        }
+    }
+    redraw.send(RequestRedraw);
+}
+
+/// Turns chopper wheels with the clock. Each step of a wheel retraces, so choppers are best watched
+/// at frequencies well below the frame rate.
+pub fn chopper_system(
+    clock: Res<Clock>,
+    mut surface_query: Query<&mut Surface>,
+    mut redraw: EventWriter<RequestRedraw>
+) {
+    if !clock.playing {
+        return
+    }
+    let mut turning = false;
+    for mut surface in surface_query.iter_mut() {
+        let Some(frequency) = surface.chopper.as_ref().map(|chopper| chopper.frequency) else {
+            continue
+        };
+        if let Some(chopper) = surface.chopper.as_mut() {
+            chopper.angle = (frequency * clock.time).fract();
+        }
+        turning = true;
+    }
+    if turning {
+        redraw.send(RequestRedraw);
+    }
+}
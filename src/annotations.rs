@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+
+use crate::{config::Config, locale::Locale, presentation::Presentation, ui::{self, UiFont}, units::Quantity, RaySegment, Scale};
+
+// Segments weaker than this aren't annotated, so ghosts don't bury the main beam in labels
+const ANNOTATED_INTENSITY: f32 = 0.1;
+// Hits closer than this (mm) to one already annotated are left without a label
+const LABEL_SPACING: f32 = 3.;
+// Hits within this angle (degrees) of Brewster's or the critical angle are flagged
+const NEAR_ANGLE: f32 = 2.;
+
+const FLAG_COLOR: Color = Color::rgb(1.0, 0.6, 0.2);
+
+/// Whether refraction angles are written at the hits. I switches them on and off.
+#[derive(Resource, Default)]
+pub struct AngleAnnotations(pub bool);
+
+#[derive(Component)]
+pub struct AngleLabel;
+
+pub fn annotation_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut annotations: ResMut<AngleAnnotations>
+) {
+    if keys.just_pressed(KeyCode::I) {
+        annotations.0 = !annotations.0;
+    }
+}
+
+/// Writes the angles of incidence and refraction where rays refract, flagging hits near
+/// Brewster's angle or the critical angle. Labels are children of their segment, so they go
+/// away with it and are redrawn when it is retraced.
+pub fn annotation_system(
+    mut commands: Commands,
+    (config, locale, font): (Res<Config>, Res<Locale>, Res<UiFont>),
+    (scale, presentation): (Res<Scale>, Res<Presentation>),
+    annotations: Res<AngleAnnotations>,
+    label_query: Query<Entity, With<AngleLabel>>,
+    all_segments: Query<(Entity, &RaySegment)>,
+    changed_segments: Query<(Entity, &RaySegment), Changed<RaySegment>>
+) {
+    let redraw = annotations.is_changed() || config.is_changed() || locale.is_changed() || presentation.is_changed();
+    if redraw {
+        for label in label_query.iter() {
+            commands.entity(label).despawn_recursive();
+        }
+    }
+    if !annotations.0 {
+        return
+    }
+    let mut segments: Vec<(Entity, &RaySegment)> = if redraw {
+        all_segments.iter().collect()
+    } else {
+        changed_segments.iter().collect()
+    };
+    // the strongest rays get their labels first
+    segments.sort_by(|a, b| b.1.intensity.total_cmp(&a.1.intensity));
+    let show = |angle: f32| config.units.show(Quantity::Angle, angle).to_string();
+    let near = |angle: f32, to: f32| (angle - to).abs() < NEAR_ANGLE.to_radians();
+    let size = presentation.size();
+    let mut labelled: Vec<Vec2> = Vec::new();
+    for (entity, segment) in segments {
+        let Some(incidence) = segment.incidence.filter(|_| segment.intensity >= ANNOTATED_INTENSITY) else {
+            continue
+        };
+        if labelled.iter().any(|p| p.distance(segment.to) < LABEL_SPACING * size) {
+            continue
+        }
+        labelled.push(segment.to);
+        let mut lines = vec![match incidence.refracted {
+            Some(refracted) => locale.format("annotations.refracted", &[("incident", &show(incidence.incident)), ("refracted", &show(refracted))]),
+            None => locale.format("annotations.tir", &[("incident", &show(incidence.incident))])
+        }];
+        if near(incidence.incident, incidence.brewster()) {
+            lines.push(locale.format("annotations.brewster", &[("angle", &show(incidence.brewster()))]));
+        }
+        if let Some(critical) = incidence.critical().filter(|critical| near(incidence.incident, *critical)) {
+            lines.push(locale.format("annotations.critical", &[("angle", &show(critical))]));
+        }
+        let color = if lines.len() > 1 { FLAG_COLOR } else { ui::TEXT_COLOR };
+        let label = commands.spawn((Text2dBundle {
+            text: Text::from_section(lines.join("\n"), ui::text_style(&font, color)).with_alignment(TextAlignment::BOTTOM_LEFT),
+            transform: Transform::from_xyz(segment.to.x + scale.px(4. * size), segment.to.y + scale.px(4. * size), 0.5).with_scale(Vec3::splat(scale.px(size))),
+            ..default()
+        }, AngleLabel)).id();
+        commands.entity(entity).add_child(label);
+    }
+}
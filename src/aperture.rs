@@ -0,0 +1,141 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{scene_tree::Selected, units::Millimeters, Surface};
+
+// Blade edits smaller than this (mm) are the aperture's own updates, not a blade being moved
+const BLADE_TOLERANCE: f32 = 1e-4;
+// Change (mm) of the opening per press of [ or ]; Shift makes it ten times larger
+const OPENING_STEP: f32 = 0.1;
+const STEP_FACTOR: f32 = 10.;
+
+/// Iris or slit: two blocking blades across `width` about `center`, leaving an opening of
+/// `diameter` between them. Rays that hit a blade stop there and rays through the opening go
+/// on, so closing it down shows vignetting and, in a focus, spatial filtering.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Aperture {
+    pub center: Vec2,
+    /// Direction across the opening, along the blades
+    pub across: Vec2,
+    /// Outer width of the blades together with the opening
+    pub width: Millimeters,
+    pub diameter: Millimeters
+}
+
+/// One of the two blades of an aperture: 0 on the `-across` side, 1 on the other.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct ApertureBlade {
+    pub aperture: Entity,
+    pub side: usize
+}
+
+impl Aperture {
+    /// Aperture spanning `p1` to `p2`, open over `diameter` in the middle.
+    pub fn new(p1: Vec2, p2: Vec2, diameter: Millimeters) -> Self {
+        let width = p1.distance(p2);
+        Self {
+            center: (p1 + p2) / 2.,
+            across: (p2 - p1).normalize_or_zero(),
+            width: Millimeters(width),
+            diameter: Millimeters(diameter.0.clamp(0., width))
+        }
+    }
+
+    /// The two blades, each from an outer end to the edge of the opening.
+    pub fn blades(&self) -> [Surface; 2] {
+        let (outer, inner) = (self.width.0 / 2., self.diameter.0.clamp(0., self.width.0) / 2.);
+        let at = |x: f32| self.center + self.across * x;
+        [Surface::blocker(at(-outer), at(-inner)), Surface::blocker(at(inner), at(outer))]
+    }
+}
+
+/// Spawns the blades of new apertures as their children.
+pub fn aperture_spawn_system(
+    mut commands: Commands,
+    aperture_query: Query<(Entity, &Aperture), Added<Aperture>>
+) {
+    for (entity, aperture) in aperture_query.iter() {
+        commands.entity(entity).with_children(|parent| {
+            for (side, surface) in aperture.blades().into_iter().enumerate() {
+                parent.spawn((surface, ApertureBlade {
+                    aperture: entity,
+                    side
+                }));
+            }
+        });
+    }
+}
+
+/// Keeps the blades of an aperture together. Moving or turning a blade moves the whole
+/// aperture with it; stretching a blade is undone, as the opening is set on the aperture.
+/// Blades follow edits of the aperture itself.
+pub fn aperture_coupling_system(
+    mut aperture_query: Query<(Entity, &mut Aperture, ChangeTrackers<Aperture>)>,
+    mut blade_query: Query<(&ApertureBlade, &mut Surface, ChangeTrackers<Surface>)>
+) {
+    let edits: Vec<(ApertureBlade, Surface)> = blade_query.iter()
+        .filter(|(_, _, changes)| changes.is_changed() && !changes.is_added())
+        .map(|(blade, surface, _)| (*blade, surface.clone()))
+        .collect();
+    let mut touched: Vec<Entity> = Vec::new();
+    for (blade, surface) in edits {
+        let Ok((_, mut aperture, _)) = aperture_query.get_mut(blade.aperture) else {
+            continue
+        };
+        let expected = &aperture.blades()[blade.side];
+        if expected.p1.distance(surface.p1) < BLADE_TOLERANCE && expected.p2.distance(surface.p2) < BLADE_TOLERANCE {
+            continue
+        }
+        // rigid motion carrying the expected blade onto the edited one
+        let rotation = Vec2::from_angle(expected.dp().angle_between(surface.dp()));
+        let (from, to) = ((expected.p1 + expected.p2) / 2., (surface.p1 + surface.p2) / 2.);
+        aperture.center = to + rotation.rotate(aperture.center - from);
+        aperture.across = rotation.rotate(aperture.across);
+        touched.push(blade.aperture);
+    }
+    touched.extend(aperture_query.iter()
+        .filter(|(_, _, changes)| changes.is_changed() && !changes.is_added())
+        .map(|(entity, ..)| entity));
+    for (blade, mut surface, changes) in blade_query.iter_mut() {
+        let edited = changes.is_changed() && !changes.is_added();
+        if !edited && !touched.contains(&blade.aperture) {
+            continue
+        }
+        let Ok((_, aperture, _)) = aperture_query.get(blade.aperture) else {
+            continue
+        };
+        let expected = &aperture.blades()[blade.side];
+        if expected.p1.distance(surface.p1) >= BLADE_TOLERANCE || expected.p2.distance(surface.p2) >= BLADE_TOLERANCE {
+            surface.p1 = expected.p1;
+            surface.p2 = expected.p2;
+        }
+    }
+}
+
+/// [ and ] close and open the apertures whose blades are selected, by 0.1 mm or, with Shift,
+/// by 1 mm.
+pub fn aperture_keys_system(
+    keys: Res<Input<KeyCode>>,
+    blade_query: Query<&ApertureBlade, With<Selected>>,
+    mut aperture_query: Query<&mut Aperture>
+) {
+    let direction = [(KeyCode::LBracket, -1.), (KeyCode::RBracket, 1.)].into_iter()
+        .filter(|(key, _)| keys.just_pressed(*key))
+        .map(|(_, sign)| sign)
+        .sum::<f32>();
+    if direction == 0. {
+        return
+    }
+    let factor = if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) { STEP_FACTOR } else { 1. };
+    let mut opened: Vec<Entity> = Vec::new();
+    for blade in blade_query.iter() {
+        if opened.contains(&blade.aperture) {
+            continue
+        }
+        opened.push(blade.aperture);
+        if let Ok(mut aperture) = aperture_query.get_mut(blade.aperture) {
+            aperture.diameter = Millimeters((aperture.diameter.0 + direction * OPENING_STEP * factor).clamp(0., aperture.width.0));
+            info!("aperture open over {}", aperture.diameter);
+        }
+    }
+}
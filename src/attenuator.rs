@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+
+use crate::{
+    animation::{Clock, Track}, config::Config, links::Param, locale::Locale, scene_tree::Selected, ui::{self, UiFont},
+    units::Quantity, RaySegment, Surface
+};
+
+// Optical density at the right end of the slider
+const MAX_OD: f32 = 6.;
+// Seconds for an animated sweep from 0 to MAX_OD and back
+const SWEEP_PERIOD: f32 = 8.;
+const SLIDER_WIDTH: f32 = 200.;
+const SLIDER_HEIGHT: f32 = 12.;
+const SLIDER_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+const FILL_COLOR: Color = Color::rgb(0.4, 0.6, 0.9);
+
+type ReadoutChanged = Or<(Changed<RaySegment>, Added<OdReadout>, Added<Track>)>;
+type TrackedAttenuator<'a> = (Entity, &'a Surface, ChangeTrackers<Surface>, Option<&'a Track>);
+
+#[derive(Component)]
+pub struct AttenuatorPanel;
+
+/// Slider bar setting the selected attenuator's OD from where it is dragged.
+#[derive(Component)]
+pub struct OdSlider;
+
+#[derive(Component)]
+pub struct OdFill;
+
+#[derive(Component)]
+pub struct OdReadout;
+
+/// Starts or stops an animation track sweeping the OD.
+#[derive(Component)]
+pub struct SweepButton;
+
+/// Shows the attenuator panel while an attenuator is selected. It is only rebuilt when another
+/// one is selected, so dragging the slider isn't interrupted.
+pub fn attenuator_panel_system(
+    mut commands: Commands,
+    mut shown: Local<Option<Entity>>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    selected_query: Query<(Entity, &Surface), With<Selected>>,
+    panel_query: Query<Entity, With<AttenuatorPanel>>
+) {
+    let selected = selected_query.iter()
+        .find(|(_, surface)| surface.optical_density.is_some())
+        .map(|(entity, _)| entity);
+    if selected == *shown && !locale.is_changed() {
+        return
+    }
+    *shown = selected;
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if selected.is_none() {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Percent(30.),
+                bottom: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, AttenuatorPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("attenuator.title"), style.clone()));
+        panel.spawn((ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(SLIDER_WIDTH), Val::Px(SLIDER_HEIGHT)),
+                margin: UiRect::vertical(Val::Px(4.)),
+                ..default()
+            },
+            background_color: SLIDER_COLOR.into(),
+            ..default()
+        }, OdSlider)).with_children(|slider| {
+            slider.spawn((NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(0.), Val::Percent(100.)),
+                    ..default()
+                },
+                background_color: FILL_COLOR.into(),
+                ..default()
+            }, OdFill));
+        });
+        panel.spawn((TextBundle::from_section("", style.clone()), OdReadout));
+        panel.spawn((ButtonBundle {
+            style: Style {
+                margin: UiRect::top(Val::Px(4.)),
+                padding: UiRect::horizontal(Val::Px(4.)),
+                align_self: AlignSelf::FlexStart,
+                ..default()
+            },
+            background_color: SLIDER_COLOR.into(),
+            ..default()
+        }, SweepButton)).with_children(|button| {
+            button.spawn(TextBundle::from_section(locale.text("attenuator.sweep"), style.clone()));
+        });
+    });
+}
+
+/// Sets the OD from the cursor while the slider is held, stopping any sweep of it.
+pub fn od_slider_system(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    slider_query: Query<(&Interaction, &Node, &GlobalTransform), With<OdSlider>>,
+    mut selected_query: Query<(Entity, &mut Surface), With<Selected>>
+) {
+    let Some((Interaction::Clicked, node, transform)) = slider_query.iter().next() else {
+        return
+    };
+    let Some(cursor) = windows.get_primary().and_then(|window| window.cursor_position()) else {
+        return
+    };
+    let left = transform.translation().x - node.size().x / 2.;
+    let od = ((cursor.x - left) / node.size().x.max(1.)).clamp(0., 1.) * MAX_OD;
+    let Some((entity, mut surface)) = selected_query.iter_mut().find(|(_, surface)| surface.optical_density.is_some()) else {
+        return
+    };
+    if surface.optical_density != Some(od) {
+        // the selected surface is an attenuator, so setting its OD can't fail
+        let _ = Param::Od.set(&mut surface, od);
+    }
+    commands.entity(entity).remove::<Track>();
+}
+
+/// Starts a sweep of the selected attenuator's OD, playing the clock, or stops it.
+pub fn sweep_button_system(
+    mut commands: Commands,
+    mut clock: ResMut<Clock>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<SweepButton>)>,
+    selected_query: Query<(Entity, &Surface, Option<&Track>), With<Selected>>
+) {
+    if !button_query.iter().any(|interaction| *interaction == Interaction::Clicked) {
+        return
+    }
+    let Some((entity, _, track)) = selected_query.iter().find(|(_, surface, _)| surface.optical_density.is_some()) else {
+        return
+    };
+    if track.is_some() {
+        commands.entity(entity).remove::<Track>();
+        return
+    }
+    commands.entity(entity).insert(Track {
+        param: Param::Od,
+        from: 0.,
+        to: MAX_OD,
+        period: SWEEP_PERIOD
+    });
+    clock.playing = true;
+}
+
+/// Updates the slider and the power readout in place whenever the OD changes, rays are
+/// retraced or a sweep starts or stops. The incident power is what the traced rays carry onto
+/// the attenuator.
+pub fn attenuator_readout_system(
+    (locale, config): (Res<Locale>, Res<Config>),
+    changed: Query<(), ReadoutChanged>,
+    stopped: RemovedComponents<Track>,
+    selected_query: Query<TrackedAttenuator, With<Selected>>,
+    segment_query: Query<&RaySegment>,
+    mut fill_query: Query<&mut Style, With<OdFill>>,
+    mut readout_query: Query<&mut Text, With<OdReadout>>
+) {
+    let Some((entity, surface, changes, track)) = selected_query.iter().find(|(_, surface, _, _)| surface.optical_density.is_some()) else {
+        return
+    };
+    if !changes.is_changed() && !config.is_changed() && changed.is_empty() && stopped.iter().next().is_none() {
+        return
+    }
+    let od = surface.optical_density.unwrap_or_default();
+    let incident: f32 = segment_query.iter()
+        .filter(|segment| segment.surface == entity)
+        .map(|segment| segment.power)
+        .sum();
+    for mut style in fill_query.iter_mut() {
+        style.size.width = Val::Percent((od / MAX_OD).clamp(0., 1.) * 100.);
+    }
+    let lines = [
+        locale.format("attenuator.od", &[
+            ("od", &format!("{:.2}", od)),
+            ("t", &format!("{:.4}", surface.transmittance() * 100.))
+        ]),
+        locale.format("attenuator.power", &[
+            ("incident", &config.units.show(Quantity::Power, incident)),
+            ("transmitted", &config.units.show(Quantity::Power, incident * surface.transmittance()))
+        ]),
+        locale.text(if track.is_some() { "attenuator.sweeping" } else { "attenuator.still" }).to_string()
+    ];
+    for mut text in readout_query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -0,0 +1,167 @@
+use bevy::{ecs::entity::Entity, math::Vec2};
+
+use super::{Extent, Ray, Surface};
+
+// Child rays start this far (mm) off the surface they leave, on the side they travel into.
+// Well above f32 rounding at scene scale, well below any real element thickness.
+pub const SURFACE_OFFSET: f32 = 1e-4;
+// Hits on an arc closer than this (mm) to where a ray left it are the ray's own start found
+// again through rounding, as the ray starts SURFACE_OFFSET off the arc.
+const REHIT_DISTANCE: f32 = 10. * SURFACE_OFFSET;
+
+#[inline]
+pub fn cross2(a: Vec2, b: Vec2) -> f32 {
+    a[0]*b[1] - b[0]*a[1]
+}
+
+pub fn intersect(ray: &Ray, surface: &Surface) -> f32 {
+    intersect_beyond(ray, surface, 0.)
+}
+
+/// Distance along `ray` to its first crossing with `surface` at least `near` along it.
+fn intersect_beyond(ray: &Ray, surface: &Surface, near: f32) -> f32 {
+    if let Some((center, radius)) = surface.circle() {
+        return intersect_arc(ray, surface, center, radius, near)
+    }
+    let v1 = ray.p - surface.p1;
+    let v2 = surface.p2 - surface.p1;
+    let v3 = Vec2::new(-ray.l[1], ray.l[0]);
+    let dot = v2.dot(v3);
+    if dot.abs() < 0.000001 {
+        f32::INFINITY
+    } else {
+        let cross = v2.perp_dot(v1);
+        let t1 = cross / dot;
+        let t2 = v1.dot(v3) / dot;
+        let on_surface = match surface.extent {
+            Extent::Segment => (0.0..=1.0).contains(&t2),
+            Extent::Ray => t2 >= 0.0,
+            Extent::Line => true
+        };
+        if t1 >= near && on_surface {
+            t1
+        } else {
+            f32::INFINITY
+        }
+    }
+}
+
+/// Nearest crossing of `ray` with the arc of `surface`, part of the circle about `center`,
+/// at least `near` along the ray.
+fn intersect_arc(ray: &Ray, surface: &Surface, center: Vec2, radius: f32, near: f32) -> f32 {
+    let oc = ray.p - center;
+    let (a, b, c) = (ray.l.length_squared(), oc.dot(ray.l), oc.length_squared() - radius * radius);
+    let discriminant = b * b - a * c;
+    if discriminant < 0. || a < f32::EPSILON {
+        return f32::INFINITY
+    }
+    let root = discriminant.sqrt();
+    [(-b - root) / a, (-b + root) / a].into_iter()
+        // the arc is the part of the circle on the side of the chord it bulges to
+        .find(|t| *t >= near && surface.dp().perp_dot(ray.p + ray.l * *t - surface.p1) * surface.curvature >= 0.)
+        .unwrap_or(f32::INFINITY)
+}
+
+/// Distance along `ray` to where it hits `surface`, if it does. A straight surface the ray
+/// just left, `exclude`, can only be hit again through rounding error and is skipped. An arc
+/// can be hit again, as inside a concave mirror, so on it only hits right by the ray's origin
+/// are skipped.
+pub fn hit_distance(ray: &Ray, entity: Entity, surface: &Surface, exclude: Option<Entity>) -> Option<f32> {
+    let near = match (Some(entity) == exclude, surface.circle()) {
+        (false, _) => 0.,
+        (true, Some(_)) => REHIT_DISTANCE,
+        (true, None) => return None
+    };
+    let d = intersect_beyond(ray, surface, near);
+    (d.is_finite() && d > 0.).then_some(d)
+}
+
+/// Closest surface hit by `ray`, as (distance, entity, surface). `exclude` is the surface the
+/// ray just left, see [`hit_distance`].
+pub fn nearest_hit<'a>(
+    ray: &Ray,
+    surfaces: impl Iterator<Item = (Entity, &'a Surface)>,
+    exclude: Option<Entity>
+) -> Option<(f32, Entity, &'a Surface)> {
+    let mut nearest: Option<(f32, Entity, &Surface)> = None;
+    for (entity, surface) in surfaces {
+        let Some(d) = hit_distance(ray, entity, surface, exclude) else {
+            continue
+        };
+        if nearest.is_none_or(|(best, _, _)| d < best) {
+            nearest = Some((d, entity, surface));
+        }
+    }
+    nearest
+}
+
+/// Moves the origin of a ray leaving a surface slightly off it, towards where the ray is going.
+pub fn offset_from_surface(ray: &mut Ray, normal: Vec2) {
+    let side = if ray.l.dot(normal) >= 0. { 1. } else { -1. };
+    ray.p += side * SURFACE_OFFSET * normal;
+}
+
+pub fn reflect(l: Vec2, normal: Vec2) -> Vec2 {
+    l - 2. * l.dot(normal) * normal
+}
+
+/// Refracts `l` through an interface with the given normal, going from index `n1` to `n2`.
+/// Returns `None` on total internal reflection.
+pub fn refract(l: Vec2, normal: Vec2, n1: f32, n2: f32) -> Option<Vec2> {
+    let normal = if normal.dot(l) > 0. { -normal } else { normal };
+    let eta = n1 / n2;
+    let cos_i = -normal.dot(l);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i);
+    if sin2_t > 1. {
+        None
+    } else {
+        Some((eta * l + (eta * cos_i - (1. - sin2_t).sqrt()) * normal).normalize())
+    }
+}
+
+/// Fresnel intensity reflectances (Rs, Rp) of an interface from index `n1` to `n2` at incidence
+/// cosine `cos_i`; both are 1 past the critical angle.
+pub fn fresnel(cos_i: f32, n1: f32, n2: f32) -> (f32, f32) {
+    let cos_i = cos_i.abs().min(1.);
+    let sin2_t = (n1 / n2).powi(2) * (1. - cos_i * cos_i);
+    if sin2_t >= 1. {
+        return (1., 1.)
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let rs = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+    let rp = (n2 * cos_i - n1 * cos_t) / (n2 * cos_i + n1 * cos_t);
+    (rs * rs, rp * rp)
+}
+
+/// Angles (radians from the normal) at which a ray meets an index step from `n1` to `n2`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Incidence {
+    pub n1: f32,
+    pub n2: f32,
+    pub incident: f32,
+    /// Angle of the refracted ray, None past the critical angle
+    pub refracted: Option<f32>
+}
+
+impl Incidence {
+    pub fn new(l: Vec2, normal: Vec2, n1: f32, n2: f32) -> Self {
+        let incident = l.dot(normal).abs().min(1.).acos();
+        let sin_t = n1 / n2 * incident.sin();
+        Self {
+            n1,
+            n2,
+            incident,
+            refracted: (sin_t <= 1.).then(|| sin_t.asin())
+        }
+    }
+
+    /// Angle at which p light isn't reflected at all.
+    pub fn brewster(&self) -> f32 {
+        (self.n2 / self.n1).atan()
+    }
+
+    /// Angle beyond which all the light is reflected, going into a lower index.
+    pub fn critical(&self) -> Option<f32> {
+        (self.n2 < self.n1).then(|| (self.n2 / self.n1).asin())
+    }
+}
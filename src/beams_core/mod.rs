@@ -0,0 +1,24 @@
+// Headless simulation core: rays, surfaces, sources and the tracer that builds ray trees from
+// them. Bevy is only used for its math types and to make sources and surfaces components, and
+// besides the plain `units`, `material` and `coating` modules nothing of the app is needed, so
+// the physics runs, and can be tested and benchmarked, without a window or renderer. It is
+// part of the `beams` library, and the app re-exports all of it at its crate root.
+
+mod geometry;
+mod polarization;
+mod profile;
+mod ray;
+mod source;
+mod surface;
+mod trace;
+
+pub use geometry::*;
+pub use polarization::*;
+pub use profile::*;
+pub use ray::*;
+pub use source::*;
+pub use surface::*;
+pub use trace::*;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,131 @@
+use bevy::math::{Mat4, Vec4};
+use serde::{Deserialize, Serialize};
+
+use super::Ray;
+use crate::units::Degrees;
+
+/// Normalized Stokes vector (S0 = 1; the ray's `i` carries the intensity). Since every plane
+/// of incidence is the table, S1 = 1 is s-polarized (out of the table) and S1 = -1 is
+/// p-polarized (in the table, across the ray).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stokes(pub Vec4);
+
+impl Stokes {
+    pub const UNPOLARIZED: Self = Self(Vec4::new(1., 0., 0., 0.));
+    pub const S: Self = Self(Vec4::new(1., 1., 0., 0.));
+    pub const P: Self = Self(Vec4::new(1., -1., 0., 0.));
+
+    /// Linearly polarized at `angle` from s towards p.
+    pub fn linear(angle: Degrees) -> Self {
+        let theta = 2. * angle.to_radians();
+        Self(Vec4::new(1., theta.cos(), theta.sin(), 0.))
+    }
+
+    /// Right- (or left-) handed circular polarization.
+    pub fn circular(right: bool) -> Self {
+        Self(Vec4::new(1., 0., 0., if right { 1. } else { -1. }))
+    }
+
+    /// Fraction of the light that is polarized.
+    pub fn degree(&self) -> f32 {
+        self.0.truncate().length().min(1.)
+    }
+
+    /// State after `mueller`, and the fraction of intensity it lets through.
+    pub fn through(&self, mueller: &Mueller) -> (Self, f32) {
+        let out = mueller.0 * self.0;
+        if out.x <= 0. {
+            return (Self::UNPOLARIZED, 0.)
+        }
+        (Self(out / out.x), out.x)
+    }
+}
+
+/// Mueller matrix of an element, in the same s/p frame as [`Stokes`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mueller(pub Mat4);
+
+impl Mueller {
+    pub const IDENTITY: Self = Self(Mat4::IDENTITY);
+
+    /// Partial polarizer with intensity transmittances `ts` and `tp`, e.g. a Fresnel
+    /// interface or a metal mirror.
+    pub fn diattenuator(ts: f32, tp: f32) -> Self {
+        let (sum, difference, cross) = ((ts + tp) / 2., (ts - tp) / 2., (ts * tp).max(0.).sqrt());
+        Self(Mat4::from_cols(
+            Vec4::new(sum, difference, 0., 0.),
+            Vec4::new(difference, sum, 0., 0.),
+            Vec4::new(0., 0., cross, 0.),
+            Vec4::new(0., 0., 0., cross)
+        ))
+    }
+
+    /// Ideal linear polarizer passing light polarized at `angle` from s.
+    pub fn polarizer(angle: Degrees) -> Self {
+        Self::diattenuator(1., 0.).rotated(angle)
+    }
+
+    /// Transmission axis (from s, 0 to 180°) if this is an ideal linear polarizer.
+    pub fn polarizer_axis(&self) -> Option<Degrees> {
+        let first = self.0.x_axis;
+        let ideal = (first.x - 0.5).abs() < 1e-4
+            && (first.y.hypot(first.z) - 0.5).abs() < 1e-4
+            && self.0.w_axis.w.abs() < 1e-4;
+        ideal.then(|| Degrees((first.z.atan2(first.y).to_degrees() / 2.).rem_euclid(180.)))
+    }
+
+    /// Waveplate delaying the slow axis by `retardance` (360° is one wave), with its fast
+    /// axis at `angle` from s.
+    pub fn retarder(retardance: Degrees, angle: Degrees) -> Self {
+        let (sin, cos) = retardance.to_radians().sin_cos();
+        Self(Mat4::from_cols(
+            Vec4::X,
+            Vec4::Y,
+            Vec4::new(0., 0., cos, -sin),
+            Vec4::new(0., 0., sin, cos)
+        )).rotated(angle)
+    }
+
+    /// Keeps `1 - fraction` of the polarized part, e.g. for scattering or diffusers.
+    pub fn depolarizer(fraction: f32) -> Self {
+        let kept = 1. - fraction.clamp(0., 1.);
+        Self(Mat4::from_diagonal(Vec4::new(1., kept, kept, kept)))
+    }
+
+    /// The same element turned by `angle` about the ray.
+    pub fn rotated(&self, angle: Degrees) -> Self {
+        let rotation = |theta: f32| {
+            let (sin, cos) = (2. * theta).sin_cos();
+            Mat4::from_cols(
+                Vec4::X,
+                Vec4::new(0., cos, -sin, 0.),
+                Vec4::new(0., sin, cos, 0.),
+                Vec4::W
+            )
+        };
+        let theta = angle.to_radians();
+        Self(rotation(-theta) * self.0 * rotation(theta))
+    }
+}
+
+impl Ray {
+    /// Passes the ray through an element: Stokes rays go through `mueller`, scalar rays are
+    /// scaled by `transmittance`, what the element passes of unpolarized light.
+    pub fn through(&mut self, mueller: &Mueller, transmittance: f32) {
+        match self.stokes {
+            Some(stokes) => {
+                let (out, fraction) = stokes.through(mueller);
+                self.stokes = Some(out);
+                self.i *= fraction;
+            }
+            None => self.i *= transmittance
+        }
+    }
+
+    /// Scrambles the polarization of a tracked ray, e.g. after diffuse scattering.
+    pub fn depolarize(&mut self) {
+        if self.stokes.is_some() {
+            self.stokes = Some(Stokes::UNPOLARIZED);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// What a measured profile is measured against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProfileAxis {
+    /// Transverse position (mm) from the beam center
+    Position,
+    /// Angle (degrees) from the beam direction
+    Angle
+}
+
+/// Measured beam profile, relative intensity against position or angle, that weights the
+/// rays a source emits.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub axis: ProfileAxis,
+    /// (position or angle, intensity), sorted
+    pub samples: Vec<(f32, f32)>
+}
+
+impl Profile {
+    /// Reads two columns, position or angle and intensity, separated by commas, semicolons or
+    /// whitespace. Lines starting with `#` are skipped. A header naming the first column
+    /// `angle` makes it an angular profile; otherwise it's against position.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut axis = ProfileAxis::Position;
+        let mut samples = Vec::new();
+        for (k, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let columns: Vec<&str> = line.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+                .filter(|column| !column.is_empty())
+                .collect();
+            let [x, intensity, ..] = columns[..] else {
+                return Err(format!("line {}: expected two columns", k + 1))
+            };
+            match (x.parse::<f32>(), intensity.parse::<f32>()) {
+                (Ok(x), Ok(intensity)) => samples.push((x, intensity.max(0.))),
+                _ if samples.is_empty() => {
+                    if x.to_lowercase().contains("angle") {
+                        axis = ProfileAxis::Angle;
+                    }
+                }
+                _ => return Err(format!("line {}: not a number", k + 1))
+            }
+        }
+        if samples.len() < 2 {
+            return Err("a profile needs at least two samples".to_string())
+        }
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self {
+            axis,
+            samples
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Self::parse(&text)
+    }
+
+    /// Intensity at `x`, interpolated linearly and zero outside the measured range.
+    pub fn at(&self, x: f32) -> f32 {
+        let after = self.samples.partition_point(|(sample, _)| *sample < x);
+        match (after.checked_sub(1).map(|k| self.samples[k]), self.samples.get(after)) {
+            (Some((x0, i0)), Some(&(x1, i1))) if x1 > x0 => i0 + (i1 - i0) * (x - x0) / (x1 - x0),
+            (_, Some(&(x1, i1))) if x1 == x => i1,
+            _ => 0.
+        }
+    }
+
+    /// Largest distance of a sample from the center.
+    pub fn extent(&self) -> f32 {
+        self.samples.iter().fold(0., |extent, (x, _)| extent.max(x.abs()))
+    }
+}
@@ -0,0 +1,72 @@
+use bevy::math::Vec2;
+
+use super::Stokes;
+use crate::units::Nanometers;
+
+/// Where a ray met a surface.
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub point: Vec2,
+    pub distance: f32,
+    pub normal: Vec2
+}
+
+#[derive(Clone)]
+pub struct Ray {
+    pub p: Vec2,
+    pub l: Vec2,
+    pub i: f32,
+    /// Power this ray carried when emitted; `i` is the fraction of it still left
+    pub power: f32,
+    /// Optical path length (mm) from the source to `p`
+    pub opl: f32,
+    /// Polarization, when the scene tracks it
+    pub stokes: Option<Stokes>,
+    /// Split off the beam by a Fresnel reflection at an index step, or descended from such a ray
+    pub ghost: bool,
+    pub(crate) index: f32,
+    /// Absorption coefficient (1/mm) of the medium the ray is travelling in
+    pub(crate) absorption: f32,
+    pub(crate) w: Nanometers
+}
+
+impl Ray {
+    pub fn new(p: Vec2, l: Vec2, index: f32) -> Self {
+        Self {
+            p, 
+            l,
+            i: 1.0, 
+            power: 1.0,
+            opl: 0.,
+            stokes: None,
+            ghost: false,
+            index,
+            absorption: 0.,
+            w: Nanometers(532.)
+        }
+    }
+
+    pub fn wavelength(&self) -> Nanometers {
+        self.w
+    }
+
+    /// Refractive index of the medium the ray is travelling in.
+    pub fn medium_index(&self) -> f32 {
+        self.index
+    }
+
+    /// Fraction of the intensity left after `d` mm through the ray's medium, by the
+    /// Beer–Lambert law.
+    pub fn transmission(&self, d: f32) -> f32 {
+        (-self.absorption * d).exp()
+    }
+
+    /// Intensity averaged over the next `d` mm, which is how bright that stretch is drawn.
+    pub fn mean_intensity(&self, d: f32) -> f32 {
+        let depth = self.absorption * d;
+        if depth < 1e-6 {
+            return self.i
+        }
+        self.i * (1. - (-depth).exp()) / depth
+    }
+}
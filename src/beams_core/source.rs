@@ -0,0 +1,382 @@
+use bevy::{ecs::component::Component, math::Vec2};
+use itertools_num::linspace;
+use serde::{Deserialize, Serialize};
+
+use super::{cross2, Profile, ProfileAxis, Ray, Stokes};
+use crate::units::{Degrees, Millimeters, Nanometers};
+
+// Rays start within this distance (mm) of their source's waist line
+const SOURCE_TOLERANCE: f32 = 1e-3;
+// Adaptive sampling: halvings of the spacing at most, the most rays it may add up to as a
+// multiple of the evenly spaced ones, and how much farther apart than they started two
+// neighbours' paths may get before a ray is added between them
+const REFINE_PASSES: usize = 3;
+const MAX_REFINEMENT: usize = 4;
+const MAX_SPREAD: f32 = 4.;
+
+/// Fraction of the whole each sample in an evenly or unevenly spaced set stands for, from
+/// the trapezoid rule. A single sample stands for everything.
+pub fn trapezoid_weights(samples: &[f32]) -> Vec<f32> {
+    let n = samples.len();
+    if n < 2 {
+        return vec![1.; n]
+    }
+    let span = samples[n - 1] - samples[0];
+    if span.abs() < f32::EPSILON {
+        return vec![1. / n as f32; n]
+    }
+    (0..n).map(|k| {
+        let lo = samples[k.saturating_sub(1)];
+        let hi = samples[(k + 1).min(n - 1)];
+        (hi - lo) / (2. * span)
+    }).collect()
+}
+
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BeamSource {
+    pub pos: Vec2,
+    pub direction: Vec2,
+    pub waist: Millimeters,
+    pub w: Nanometers,
+    pub index: f32,
+    /// Emitted power (mW)
+    pub power: f32,
+    /// Full angle over which the rays fan out, as if from a point behind the waist
+    pub divergence: Degrees,
+    /// Polarization of the emitted rays, when the scene tracks it
+    pub polarization: Stokes,
+    pub emission: Emission,
+    /// Emitted lines as (wavelength, relative power); empty for a single line at `w`
+    pub spectrum: Vec<(Nanometers, f32)>,
+    /// Intensity profile across the waist
+    pub shape: BeamShape,
+    /// Measured intensity profile weighting the rays
+    pub profile: Option<Profile>,
+    /// Whether the source emits; a switched off one stays on the table but sends no rays
+    pub enabled: bool
+}
+
+/// Intensity profile across a source's waist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum BeamShape {
+    /// Uniform across the waist
+    #[default]
+    TopHat,
+    /// Falling to 1/e² at `radius` from the center
+    Gaussian { radius: Millimeters },
+    /// Dark center with a bright ring at `radius / √2`, like a Laguerre-Gaussian donut mode
+    Donut { radius: Millimeters }
+}
+
+impl BeamShape {
+    /// Relative intensity at offset `x` (mm) from the center, 1 at the peak.
+    pub fn intensity(&self, x: f32) -> f32 {
+        match self {
+            BeamShape::TopHat => 1.,
+            BeamShape::Gaussian { radius } => (-2. * x * x / (radius.0 * radius.0)).exp(),
+            BeamShape::Donut { radius } => {
+                let r = 2. * x * x / (radius.0 * radius.0);
+                r * (1. - r).exp()
+            }
+        }
+    }
+
+    /// The next shape in the cycle, with its radius fitted to a waist of `waist`.
+    pub fn next(&self, waist: Millimeters) -> Self {
+        let radius = waist / 3.;
+        match self {
+            BeamShape::TopHat => BeamShape::Gaussian { radius },
+            BeamShape::Gaussian { .. } => BeamShape::Donut { radius },
+            BeamShape::Donut { .. } => BeamShape::TopHat
+        }
+    }
+}
+
+/// How a source spreads its rays in angle.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Emission {
+    /// A beam fanned out over the source's divergence, as if from a point behind the waist
+    Beam,
+    /// Every point of the waist radiates with the same radiance into the half space ahead, like
+    /// an LED die or a lamp filament, sampled by `angles` rays
+    Lambertian { angles: usize },
+    /// Every ray leaves the source's origin, `rays` of them spread evenly over the full angle
+    /// `spread` around its direction, like a point of an object being imaged. The waist is unused
+    Point { rays: usize, spread: Degrees }
+}
+
+impl Default for BeamSource {
+    /// A 1 mm beam along x at the origin, which scene files fill in.
+    fn default() -> Self {
+        Self::new(Vec2::ZERO, Vec2::X, Millimeters(1.))
+    }
+}
+
+impl BeamSource {
+    pub fn new(
+        pos: Vec2,
+        direction: Vec2,
+        waist: Millimeters
+    ) -> Self {
+        Self {
+            pos,
+            direction,
+            waist,
+            w: Nanometers(532.),
+            index: 1.0,
+            power: 1.0,
+            divergence: Degrees(0.),
+            polarization: Stokes::UNPOLARIZED,
+            emission: Emission::Beam,
+            spectrum: Vec::new(),
+            shape: BeamShape::TopHat,
+            profile: None,
+            enabled: true
+        }
+    }
+
+    /// Extended incoherent source, e.g. an LED or lamp: Lambertian over its emitting `length`,
+    /// with the given spectrum.
+    pub fn extended(
+        pos: Vec2,
+        direction: Vec2,
+        length: Millimeters,
+        spectrum: &[(Nanometers, f32)]
+    ) -> Self {
+        Self::new(pos, direction, length).lambertian(16).spectrum(spectrum)
+    }
+
+    pub fn wavelength(mut self, w: Nanometers) -> Self {
+        self.w = w;
+        self
+    }
+
+    pub fn power(mut self, power: f32) -> Self {
+        self.power = power;
+        self
+    }
+
+    pub fn divergence(mut self, divergence: Degrees) -> Self {
+        self.divergence = divergence;
+        self
+    }
+
+    pub fn polarized(mut self, polarization: Stokes) -> Self {
+        self.polarization = polarization;
+        self
+    }
+
+    /// Emits from every point of the waist with constant radiance, sampled by `angles` rays.
+    pub fn lambertian(mut self, angles: usize) -> Self {
+        self.emission = Emission::Lambertian { angles };
+        self
+    }
+
+    /// Emits from the origin alone, `rays` rays spread over the full angle `spread`.
+    pub fn point(mut self, rays: usize, spread: Degrees) -> Self {
+        self.emission = Emission::Point { rays, spread };
+        self
+    }
+
+    /// Emits several lines, as (wavelength, relative power). `w` becomes the strongest line.
+    pub fn spectrum(mut self, spectrum: &[(Nanometers, f32)]) -> Self {
+        if let Some((w, _)) = spectrum.iter().max_by(|a, b| a.1.total_cmp(&b.1)) {
+            self.w = *w;
+        }
+        self.spectrum = spectrum.to_vec();
+        self
+    }
+
+    /// Emits a continuous band from `from` to `to`, such as white light, sampled by `lines`
+    /// evenly spaced lines of equal power.
+    pub fn band(self, from: Nanometers, to: Nanometers, lines: usize) -> Self {
+        let spectrum: Vec<(Nanometers, f32)> = linspace(from.0, to.0, lines).map(|w| (Nanometers(w), 1.)).collect();
+        self.spectrum(&spectrum)
+    }
+
+    /// Weights the rays by an intensity profile across the waist.
+    pub fn shape(mut self, shape: BeamShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    /// Weights the rays by a measured profile. A position profile sets the waist and an
+    /// angular one the divergence, to cover the measured range.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        match profile.axis {
+            ProfileAxis::Position => self.waist = Millimeters(2. * profile.extent()),
+            ProfileAxis::Angle => self.divergence = Degrees(2. * profile.extent())
+        }
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Emission directions as (direction, share of power) for rays leaving offset `x` across
+    /// the waist. Lambertian rays are spaced evenly in sin θ, so they carry equal power and
+    /// their density follows cos θ; those of a point source are spaced evenly in angle.
+    fn directions(&self, x: f32) -> Vec<(Vec2, f32)> {
+        let half_waist = self.waist.0 / 2.;
+        match self.emission {
+            Emission::Beam => {
+                let half_angle = self.divergence.to_radians() / 2.;
+                let angle = if half_waist > 0. { half_angle * x / half_waist } else { 0. };
+                vec![(Vec2::from_angle(angle).rotate(self.direction), 1.)]
+            }
+            Emission::Lambertian { angles } => (0..angles).map(|k| {
+                let sin = -1. + (2 * k + 1) as f32 / angles as f32;
+                (Vec2::from_angle(sin.asin()).rotate(self.direction), 1. / angles as f32)
+            }).collect(),
+            Emission::Point { rays, spread } => (0..rays).map(|k| {
+                let angle = spread.to_radians() * ((k as f32 + 0.5) / rays as f32 - 0.5);
+                (Vec2::from_angle(angle).rotate(self.direction), 1. / rays as f32)
+            }).collect()
+        }
+    }
+
+    /// Emitted lines with their share of the power.
+    fn lines(&self) -> Vec<(Nanometers, f32)> {
+        let total: f32 = self.spectrum.iter().map(|(_, power)| power).sum();
+        if self.spectrum.is_empty() || total <= 0. {
+            return vec![(self.w, 1.)]
+        }
+        self.spectrum.iter().map(|(w, power)| (*w, power / total)).collect()
+    }
+
+    /// Rays spread across the waist, `density` per mm, fanned out over the divergence or,
+    /// for Lambertian sources, over the half space, at each line of the spectrum. A point
+    /// source emits its fan from the origin alone. The beam
+    /// shape and a measured profile reweight them without changing the total power.
+    ///
+    /// Each ray carries the share of the source power in the slice of the beam (or, when
+    /// divergent, of the emission angle) it samples. Since a divergent fan spreads linearly
+    /// with distance, the power per unit length landing on a detector then falls as 1/r,
+    /// as it should for a 2D source, independent of how many rays are traced. A switched off
+    /// source gives none.
+    pub fn rays(&self, density: f32) -> Vec<Ray> {
+        if !self.enabled {
+            return Vec::new()
+        }
+        self.rays_at(&self.samples(density))
+    }
+
+    /// Like [`BeamSource::rays`], but with rays added where evenly spaced ones leave fine
+    /// features unresolved. `path` gives the points where a ray meets the surfaces along its
+    /// way. Where two neighbours' paths meet different surfaces, as at the edge of a lens, or
+    /// end up much farther apart than they started, as past a caustic, a ray is added halfway
+    /// between them, up to three times over. The outermost gaps are always halved once, since
+    /// clipping and aberrations show first at the edges of a beam. A point source's fan is
+    /// left as it is. The rays still carry the source's total power between them.
+    pub fn adaptive_rays(&self, density: f32, path: impl Fn(&Ray) -> Vec<Vec2>) -> Vec<Ray> {
+        if !self.enabled {
+            return Vec::new()
+        }
+        let mut samples = self.samples(density);
+        if samples.len() < 2 {
+            return self.rays_at(&samples)
+        }
+        let budget = samples.len() * MAX_REFINEMENT;
+        for pass in 0..REFINE_PASSES {
+            let paths: Vec<Vec<Vec2>> = samples.iter().map(|x| path(&self.probe(*x))).collect();
+            let last = samples.len() - 1;
+            let mut refined = vec![samples[0]];
+            for k in 1..=last {
+                let (a, b) = (samples[k - 1], samples[k]);
+                let edge = pass == 0 && (k == 1 || k == last);
+                let (p, q) = (&paths[k - 1], &paths[k]);
+                let parted = p.len() != q.len() || p.iter().zip(q).any(|(p, q)| p.distance(*q) > MAX_SPREAD * (b - a));
+                if edge || parted {
+                    refined.push((a + b) / 2.);
+                }
+                refined.push(b);
+            }
+            if refined.len() == samples.len() || refined.len() > budget {
+                break
+            }
+            samples = refined;
+        }
+        self.rays_at(&samples)
+    }
+
+    /// Offsets (mm) across the waist that rays start from, `density` per mm and evenly
+    /// spaced, or just the origin for a point source.
+    fn samples(&self, density: f32) -> Vec<f32> {
+        let half_waist = self.waist.0 / 2.;
+        match self.emission {
+            Emission::Point { .. } => vec![0.],
+            _ => linspace(-half_waist, half_waist, (self.waist.0 * density) as usize).collect()
+        }
+    }
+
+    /// A single ray from offset `x` across the waist, along the middle of its emission and at
+    /// the first line, standing for where the rays from there go.
+    fn probe(&self, x: f32) -> Ray {
+        let l = match self.emission {
+            Emission::Beam => self.directions(x)[0].0,
+            _ => self.direction.normalize_or_zero()
+        };
+        let mut ray = Ray::new(self.pos + x * self.direction.perp(), l, self.index);
+        ray.w = self.lines()[0].0;
+        ray
+    }
+
+    /// Rays from each of `samples` across the waist, evenly spaced or not, each carrying the
+    /// share of the power in the slice of the beam around it.
+    fn rays_at(&self, samples: &[f32]) -> Vec<Ray> {
+        let weights = trapezoid_weights(samples);
+        let lines = self.lines();
+        let mut rays = Vec::new();
+        for (x, weight) in samples.iter().zip(weights) {
+            for (l, share) in self.directions(*x) {
+                for (w, line_share) in lines.iter() {
+                    let mut ray = Ray::new(self.pos + *x * self.direction.perp(), l, self.index);
+                    ray.w = *w;
+                    ray.power = self.power * weight * share * line_share;
+                    rays.push(ray);
+                }
+            }
+        }
+        if self.shape != BeamShape::TopHat || self.profile.is_some() {
+            let before: f32 = rays.iter().map(|ray| ray.power).sum();
+            let direction = self.direction.normalize_or_zero();
+            for ray in rays.iter_mut() {
+                let x = (ray.p - self.pos).dot(direction.perp());
+                ray.power *= self.shape.intensity(x) * match &self.profile {
+                    Some(profile) if profile.axis == ProfileAxis::Position => profile.at(x),
+                    Some(profile) => profile.at(cross2(direction, ray.l).asin().to_degrees()),
+                    None => 1.
+                };
+            }
+            let after: f32 = rays.iter().map(|ray| ray.power).sum();
+            if after > 0. {
+                rays.iter_mut().for_each(|ray| ray.power *= before / after);
+            }
+        }
+        rays
+    }
+
+    /// Where across the waist `ray` was emitted, from -1 to 1, if it came from this source.
+    pub fn pupil(&self, ray: &Ray) -> Option<f32> {
+        let direction = self.direction.normalize_or_zero();
+        let offset = ray.p - self.pos;
+        let half_waist = self.waist.0 / 2.;
+        if offset.dot(direction).abs() > SOURCE_TOLERANCE || offset.length() > half_waist + SOURCE_TOLERANCE {
+            return None
+        }
+        Some(if half_waist > 0. { offset.dot(direction.perp()) / half_waist } else { 0. })
+    }
+
+    /// The ray from the middle of the waist along the source's direction, at its wavelength,
+    /// that the paraxial and alignment tools follow through the scene.
+    pub fn chief_ray(&self) -> Ray {
+        let mut ray = Ray::new(self.pos, self.direction.normalize_or_zero(), self.index);
+        ray.w = self.w;
+        ray
+    }
+
+    /// Refractive index of the medium the source sits in.
+    pub fn index(mut self, index: f32) -> Self {
+        self.index = index;
+        self
+    }
+}
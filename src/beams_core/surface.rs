@@ -0,0 +1,1063 @@
+use std::f32::consts::PI;
+
+use bevy::{ecs::component::Component, math::Vec2};
+use serde::{Deserialize, Serialize};
+
+use super::{fresnel, reflect, refract, Incidence, Mueller, Ray, Stokes};
+use crate::{coating::Coating, material::Material, units::{Degrees, Millimeters, Nanometers}};
+
+// Infinite surfaces are drawn as segments this long (mm)
+pub const INFINITE_DRAW_LENGTH: f32 = 1e4;
+// Straight pieces a curved surface is drawn with
+const ARC_DRAW_POINTS: usize = 32;
+
+// Orders of a new grating with their share of the light, most of it in the zeroth
+const GRATING_ORDERS: [(i32, f32); 5] = [(-2, 0.05), (-1, 0.2), (0, 0.5), (1, 0.2), (2, 0.05)];
+
+const THERMAL_BINS: usize = 64;
+pub const THERMAL_TOLERANCE: f32 = 1e-3;
+
+/// Absorbs light in an excitation band and re-emits it isotropically at a longer wavelength.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fluorescence {
+    pub excitation: (Nanometers, Nanometers),
+    pub emission: Nanometers,
+    pub quantum_yield: f32,
+    pub rays: usize
+}
+
+impl Fluorescence {
+    pub fn excited_by(&self, w: Nanometers) -> bool {
+        w >= self.excitation.0 && w <= self.excitation.1
+    }
+
+    pub fn emit(&self, ray: &Ray, p: Vec2) -> Vec<Ray> {
+        let i = ray.i * self.quantum_yield / self.rays as f32;
+        (0..self.rays).map(|k| {
+            let mut emitted = Ray::new(
+                p,
+                Vec2::from_angle(2. * PI * (k as f32 + 0.5) / self.rays as f32),
+                ray.index
+            );
+            emitted.i = i;
+            emitted.w = self.emission;
+            // fluorescence forgets the excitation's polarization
+            emitted.stokes = ray.stokes.map(|_| Stokes::UNPOLARIZED);
+            emitted
+        }).collect()
+    }
+}
+
+/// Thin nonlinear crystal converting part of the incident light to its second harmonic.
+/// Conversion falls off as sinc² away from the phase-matching angle (measured from the normal).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HarmonicGeneration {
+    pub efficiency: f32,
+    pub phase_match: Degrees,
+    pub acceptance: Degrees
+}
+
+impl HarmonicGeneration {
+    pub fn conversion(&self, ray: &Ray, normal: Vec2) -> f32 {
+        let normal = if normal.dot(ray.l) < 0. { -normal } else { normal };
+        // sinc²(x) = 1/2 at x ≈ 1.3916, so `acceptance` is the full width at half maximum
+        let x = 2.7832 * (normal.angle_between(ray.l) - self.phase_match.to_radians()) / self.acceptance.to_radians();
+        let sinc = if x.abs() < 1e-6 { 1. } else { x.sin() / x };
+        self.efficiency * sinc * sinc
+    }
+}
+
+/// Microscopic surface roughness. Total integrated scatter (from the RMS height `rms`)
+/// is diverted into a Lambertian diffuse component and the specular remainder is spread
+/// over a narrow lobe of half-width `lobe`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Roughness {
+    pub rms: Nanometers,
+    pub lobe: Degrees,
+    pub diffuse_rays: usize
+}
+
+impl Roughness {
+    /// Fraction of the outgoing power scattered diffusely.
+    pub fn total_integrated_scatter(&self, incident: &Ray, out: &Ray, normal: Vec2) -> f32 {
+        let cos_i = incident.l.dot(normal).abs();
+        let k_rms = 2. * PI * (self.rms / incident.w);
+        let reflected = incident.l.dot(normal).signum() != out.l.dot(normal).signum();
+        let phase = if reflected {
+            2. * k_rms * cos_i * incident.index
+        } else {
+            k_rms * cos_i * (out.index - incident.index).abs()
+        };
+        (phase * phase).min(1.)
+    }
+
+    pub fn scatter(&self, incident: &Ray, out: &Ray, normal: Vec2) -> Vec<Ray> {
+        let tis = self.total_integrated_scatter(incident, out, normal);
+        let specular = out.i * (1. - tis);
+        let lobe = self.lobe.to_radians();
+        let mut rays: Vec<Ray> = [(-lobe, 0.25), (0., 0.5), (lobe, 0.25)].iter().map(|(angle, weight)| {
+            let mut ray = out.clone();
+            ray.l = Vec2::from_angle(*angle).rotate(out.l);
+            ray.i = specular * weight;
+            ray
+        }).collect();
+        if tis > 0. {
+            let n = if out.l.dot(normal) > 0. { normal } else { -normal };
+            for k in 0..self.diffuse_rays {
+                // uniform in sin θ gives a cosine (Lambertian) distribution
+                let sin = -1. + (2. * k as f32 + 1.) / self.diffuse_rays as f32;
+                let mut ray = out.clone();
+                ray.l = (n * (1. - sin * sin).sqrt() + n.perp() * sin).normalize();
+                ray.i = out.i * tis / self.diffuse_rays as f32;
+                ray.depolarize();
+                rays.push(ray);
+            }
+        }
+        rays
+    }
+}
+
+/// Rotating chopper wheel. The surface spans one blade and one gap of its rim, which sweep
+/// across it `blades` times per turn, so a narrow beam is gated by a square wave at
+/// `blades * frequency` and a wider one is cut off gradually.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Chopper {
+    pub blades: u32,
+    /// Turns per second
+    pub frequency: f32,
+    /// Fraction of a turn, set from the scene clock
+    pub angle: f32
+}
+
+impl Chopper {
+    /// Whether the gap is in front of fraction `t` along the surface.
+    pub fn open(&self, t: f32) -> bool {
+        (self.blades as f32 * self.angle + t).rem_euclid(1.) < 0.5
+    }
+}
+
+/// Ruled grating that splits light into diffraction orders by the grating equation,
+/// n sin θₘ = n sin θᵢ + m λ / d, measured along the grating in the medium the ray is in.
+/// Orders that would leave at grazing incidence or beyond are evanescent and carry nothing.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Grating {
+    /// Line spacing d
+    pub spacing: Nanometers,
+    /// Diffraction orders with the share of the incident intensity each takes
+    pub orders: Vec<(i32, f32)>,
+    /// Diffracts back off the surface rather than through it
+    pub reflective: bool
+}
+
+impl Grating {
+    /// Transmission grating ruled with `lines_per_mm`, sending light into orders -2 to 2.
+    pub fn new(lines_per_mm: f32) -> Self {
+        Self {
+            spacing: Nanometers(1e6 / lines_per_mm),
+            orders: GRATING_ORDERS.to_vec(),
+            reflective: false
+        }
+    }
+
+    /// Sets the diffracted orders as (m, share of the incident intensity).
+    pub fn orders(mut self, orders: &[(i32, f32)]) -> Self {
+        self.orders = orders.to_vec();
+        self
+    }
+
+    pub fn reflective(mut self) -> Self {
+        self.reflective = true;
+        self
+    }
+
+    pub fn lines_per_mm(&self) -> f32 {
+        1e6 / self.spacing.0
+    }
+
+    /// Rays of each propagating order for `ray` meeting the grating at `p`, where the surface
+    /// has unit tangent `along` and normal `normal`.
+    fn diffract(&self, ray: &Ray, p: Vec2, along: Vec2, normal: Vec2) -> Vec<Ray> {
+        let side = ray.l.dot(normal).signum() * if self.reflective { -1. } else { 1. };
+        self.orders.iter().filter_map(|(m, share)| {
+            let sin = ray.l.dot(along) + *m as f32 * ray.w.0 / (ray.index * self.spacing.0);
+            if sin.abs() >= 1. {
+                return None
+            }
+            let mut out = ray.clone();
+            out.p = p;
+            out.l = along * sin + normal * side * (1. - sin * sin).sqrt();
+            out.i = ray.i * share;
+            Some(out)
+        }).collect()
+    }
+}
+
+/// Black-box Faraday isolator. Light travelling forward passes untouched; light travelling
+/// backward is let through only by the isolation ratio, the rest absorbed or, with a dump
+/// angle, sent off sideways as from the rejection port of its polarizer.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Isolator {
+    /// Isolation (dB) of backward-travelling light
+    pub isolation: f32,
+    /// Angle from the backward direction at which rejected light leaves, if it isn't absorbed
+    pub dump: Option<Degrees>
+}
+
+impl Isolator {
+    /// Fraction of backward-travelling light let through.
+    pub fn leakage(&self) -> f32 {
+        10f32.powf(-self.isolation / 10.)
+    }
+}
+
+/// How far a surface extends beyond its defining points.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Extent {
+    /// Only between `p1` and `p2`
+    #[default]
+    Segment,
+    /// From `p1` through `p2` to infinity
+    Ray,
+    /// Infinite line through `p1` and `p2`
+    Line
+}
+
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Surface {
+    pub p1: Vec2,
+    pub p2: Vec2,
+    pub extent: Extent,
+    /// Curvature (1/mm) of a circular arc from `p1` to `p2`, bulging towards the normal when
+    /// positive; 0 for a straight surface. The arc is never more than a half circle.
+    pub curvature: f32,
+    pub label: Option<String>,
+    pub index: f32,
+    pub reflection: f32,
+    pub absorption: f32,
+    pub passband: Option<(Nanometers, Nanometers)>,
+    pub fluorescence: Option<Fluorescence>,
+    pub harmonic: Option<HarmonicGeneration>,
+    pub coating: Option<Coating>,
+    pub roughness: Option<Roughness>,
+    /// Polarizing action on transmitted light, e.g. a polarizer or waveplate
+    pub mueller: Option<Mueller>,
+    /// Numerical aperture accepted by an aperture or fiber face
+    pub numerical_aperture: Option<f32>,
+    /// Focal length of an ideal thin lens
+    pub focal_length: Option<Millimeters>,
+    /// Optical density of a neutral attenuator, transmitting 10^-OD
+    pub optical_density: Option<f32>,
+    pub chopper: Option<Chopper>,
+    pub isolator: Option<Isolator>,
+    pub grating: Option<Grating>,
+    /// Fraction of a D-shaped pickoff mirror's length, from `p1`, that is mirrored; light
+    /// passing beside the cut edge goes on untouched
+    pub pickoff: Option<f32>,
+    /// Index inside the closed body this surface bounds, which lies away from its normal; rays
+    /// crossing against the normal go into it and rays crossing along it go out into `index`
+    pub interior: Option<f32>,
+    /// Absorption coefficient (1/mm) inside the closed body this surface bounds, as in tinted
+    /// glass; rays going in decay by e^(-αd) along their path until they come back out
+    pub interior_absorption: f32,
+    /// Dispersive glass inside the body the surface bounds, whose index at each ray's
+    /// wavelength replaces `interior`. Ignored on surfaces that bound no body, which can't tell
+    /// a ray going into the glass from one coming out of it
+    pub dispersion: Option<Material>
+}
+
+impl Default for Surface {
+    /// A unit glass segment along x at the origin, which scene files fill in.
+    fn default() -> Self {
+        Self::glass(Vec2::ZERO, Vec2::X)
+    }
+}
+
+impl Surface {
+    /// Uncoated glass interface, n = 1.5 unless set with [`Surface::index`].
+    pub fn glass(
+        p1: Vec2,
+        p2: Vec2
+    ) -> Self {
+        Self {
+            p1,
+            p2,
+            extent: Extent::Segment,
+            curvature: 0.,
+            label: None,
+            index: 1.5,
+            reflection: 0.0,
+            absorption: 0.0,
+            passband: None,
+            fluorescence: None,
+            harmonic: None,
+            coating: None,
+            roughness: None,
+            mueller: None,
+            numerical_aperture: None,
+            focal_length: None,
+            optical_density: None,
+            chopper: None,
+            isolator: None,
+            grating: None,
+            pickoff: None,
+            interior: None,
+            interior_absorption: 0.,
+            dispersion: None
+        }
+    }
+    pub fn blocker(
+        p1: Vec2,
+        p2: Vec2,
+    ) -> Self {
+        Self::glass(p1, p2).index(1.0).absorption(1.0)
+    }
+    /// Transmits wavelengths inside `band` and absorbs everything else.
+    pub fn filter(
+        p1: Vec2,
+        p2: Vec2,
+        band: (Nanometers, Nanometers)
+    ) -> Self {
+        Self::glass(p1, p2).index(1.0).passband(band)
+    }
+    /// Long-pass dichroic: transmits wavelengths above `cutoff` and reflects the rest.
+    pub fn dichroic(
+        p1: Vec2,
+        p2: Vec2,
+        cutoff: Nanometers
+    ) -> Self {
+        Self::glass(p1, p2)
+            .index(1.0)
+            .reflectivity(1.0)
+            .passband((cutoff, Nanometers(f32::INFINITY)))
+    }
+    /// Thin fluorescent layer that re-emits rays at `emission` for every excited hit.
+    /// Unexcited wavelengths pass straight through.
+    pub fn fluorescent(
+        p1: Vec2,
+        p2: Vec2,
+        excitation: (Nanometers, Nanometers),
+        emission: Nanometers,
+        quantum_yield: f32
+    ) -> Self {
+        Self {
+            fluorescence: Some(Fluorescence {
+                excitation,
+                emission,
+                quantum_yield,
+                rays: 16
+            }),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+    /// Second-harmonic generation crystal. `acceptance` is the full width of the phase-matching curve.
+    pub fn shg(
+        p1: Vec2,
+        p2: Vec2,
+        efficiency: f32,
+        phase_match: Degrees,
+        acceptance: Degrees
+    ) -> Self {
+        Self {
+            harmonic: Some(HarmonicGeneration {
+                efficiency,
+                phase_match,
+                acceptance
+            }),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+
+    /// Mirror reflecting fraction `reflectivity` of every wavelength and absorbing the rest.
+    /// Lower the absorption, e.g. `.absorption(0.)`, to transmit the remainder instead.
+    pub fn mirror(
+        p1: Vec2,
+        p2: Vec2,
+        reflectivity: f32
+    ) -> Self {
+        let reflectivity = reflectivity.clamp(0., 1.);
+        Self::glass(p1, p2)
+            .index(1.0)
+            .reflectivity(reflectivity)
+            .absorption(1. - reflectivity)
+    }
+
+    /// Lossless beam splitter reflecting `split_ratio` of the light and letting the rest
+    /// through undeviated, e.g. for Michelson interferometers.
+    pub fn beamsplitter(
+        p1: Vec2,
+        p2: Vec2,
+        split_ratio: f32
+    ) -> Self {
+        Self::mirror(p1, p2, split_ratio).absorption(0.)
+    }
+
+    /// Metal mirror whose reflectance follows from the coating's complex index.
+    /// Light that is not reflected is absorbed.
+    pub fn metal(
+        p1: Vec2,
+        p2: Vec2,
+        coating: Coating
+    ) -> Self {
+        Self::blocker(p1, p2).coating(coating)
+    }
+
+    /// Fiber face that takes in light within its numerical aperture `na`.
+    pub fn fiber(
+        p1: Vec2,
+        p2: Vec2,
+        na: f32
+    ) -> Self {
+        Self::blocker(p1, p2).numerical_aperture(na)
+    }
+
+    /// Ideal thin lens of focal length `f`, free of aberrations at any height and angle.
+    pub fn thin_lens(
+        p1: Vec2,
+        p2: Vec2,
+        f: Millimeters
+    ) -> Self {
+        Self {
+            focal_length: Some(f),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+
+    /// Ideal linear polarizer passing light polarized at `axis` from s, which scalar rays
+    /// treat as passing half of the light.
+    pub fn polarizer(
+        p1: Vec2,
+        p2: Vec2,
+        axis: Degrees
+    ) -> Self {
+        Self::glass(p1, p2).index(1.0).mueller(Mueller::polarizer(axis))
+    }
+
+    /// Neutral density attenuator transmitting 10^-`od` of every wavelength.
+    pub fn attenuator(
+        p1: Vec2,
+        p2: Vec2,
+        od: f32
+    ) -> Self {
+        Self {
+            optical_density: Some(od),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+
+    /// Chopper wheel with `blades` blades turning at `frequency` Hz with the scene clock.
+    pub fn chopper(
+        p1: Vec2,
+        p2: Vec2,
+        blades: u32,
+        frequency: f32
+    ) -> Self {
+        Self {
+            chopper: Some(Chopper {
+                blades,
+                frequency,
+                angle: 0.
+            }),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+
+    /// Diffraction grating, see [`Grating`].
+    pub fn grating(
+        p1: Vec2,
+        p2: Vec2,
+        grating: Grating
+    ) -> Self {
+        Self {
+            grating: Some(grating),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+
+    /// Faraday isolator passing light that travels forward, to the left when looking from
+    /// `p1` to `p2`, and suppressing backward light by `isolation` dB.
+    pub fn isolator(
+        p1: Vec2,
+        p2: Vec2,
+        isolation: f32
+    ) -> Self {
+        Self {
+            isolator: Some(Isolator {
+                isolation,
+                dump: None
+            }),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+
+    /// D-shaped pickoff mirror, mirrored from `p1` to its middle and cut away beyond, so it
+    /// splits off the part of a beam that hits the mirrored half.
+    pub fn pickoff(
+        p1: Vec2,
+        p2: Vec2
+    ) -> Self {
+        Self {
+            pickoff: Some(0.5),
+            ..Self::glass(p1, p2).index(1.0)
+        }
+    }
+
+    /// Uncoated glass along the circle of `radius` about `center`, counterclockwise from angle
+    /// `from` to `to`, sweeping at most 180°; 350° to 10° sweeps 20° through 0°. Chain [`Surface::index`], [`Surface::coating`]
+    /// and the like as for straight surfaces to make curved lens faces and mirrors.
+    pub fn arc(
+        center: Vec2,
+        radius: Millimeters,
+        from: Degrees,
+        to: Degrees
+    ) -> Self {
+        let to = from.0 + (to.0 - from.0).rem_euclid(360.).min(180.);
+        let point = |angle: f32| center + Vec2::from_angle(angle.to_radians()) * radius.0;
+        // the center is to the left of p1 -> p2, on the normal's side, so the arc bulges away
+        Self::glass(point(from.0), point(to)).curvature(-1. / radius.0)
+    }
+
+    /// Absorbing infinite line through `p` with direction `along`, e.g. a table edge or backdrop.
+    pub fn backdrop(
+        p: Vec2,
+        along: Vec2
+    ) -> Self {
+        Self::blocker(p, p + along.normalize()).infinite()
+    }
+
+    pub fn dp(&self) -> Vec2 {
+        self.p2 - self.p1
+    }
+
+    /// Fraction an attenuator passes, 1 for everything else.
+    pub fn transmittance(&self) -> f32 {
+        self.optical_density.map_or(1., |od| 10f32.powf(-od))
+    }
+
+    /// Unit normal, or zero for a degenerate surface. For a curved surface it is the normal at
+    /// the middle of the chord; see [`Surface::normal_at`].
+    pub fn normal(&self) -> Vec2 {
+        self.dp().normalize_or_zero().perp()
+    }
+
+    /// Center and radius of the circle a curved surface follows, `None` when it is straight.
+    /// A radius too small to span the chord is taken as half the chord.
+    pub fn circle(&self) -> Option<(Vec2, f32)> {
+        if self.curvature == 0. || self.extent != Extent::Segment {
+            return None
+        }
+        let half = self.dp().length() / 2.;
+        let radius = (1. / self.curvature.abs()).max(half);
+        let rise = (radius * radius - half * half).sqrt();
+        Some(((self.p1 + self.p2) / 2. - self.normal() * self.curvature.signum() * rise, radius))
+    }
+
+    /// Unit normal at `point` on the surface, on the same side as [`Surface::normal`].
+    pub fn normal_at(&self, point: Vec2) -> Vec2 {
+        match self.circle() {
+            Some((center, _)) => (point - center).normalize_or_zero() * self.curvature.signum(),
+            None => self.normal()
+        }
+    }
+
+    /// Offset of the middle of the arc from the middle of the chord, zero when straight.
+    pub fn sag(&self) -> Vec2 {
+        match self.circle() {
+            Some((center, radius)) => center + self.normal() * self.curvature.signum() * radius - (self.p1 + self.p2) / 2.,
+            None => Vec2::ZERO
+        }
+    }
+
+    /// Corners of a box holding a bounded surface, its arc included.
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        let sag = self.sag();
+        let corners = [self.p1, self.p2, self.p1 + sag, self.p2 + sag];
+        (
+            corners.iter().copied().fold(Vec2::splat(f32::INFINITY), Vec2::min),
+            corners.iter().copied().fold(Vec2::splat(f32::NEG_INFINITY), Vec2::max)
+        )
+    }
+
+    /// Transmission axis of a polarizer, see [`Surface::polarizer`].
+    pub fn polarizer_axis(&self) -> Option<Degrees> {
+        self.mueller.and_then(|mueller| mueller.polarizer_axis())
+    }
+
+    pub fn length(&self) -> Millimeters {
+        Millimeters(self.dp().length())
+    }
+
+    /// Element type, named after the constructor that usually builds it.
+    pub fn kind(&self) -> &'static str {
+        if self.numerical_aperture.is_some() {
+            "fiber"
+        } else if self.focal_length.is_some() {
+            "lens"
+        } else if self.optical_density.is_some() {
+            "attenuator"
+        } else if self.chopper.is_some() {
+            "chopper"
+        } else if self.isolator.is_some() {
+            "isolator"
+        } else if self.grating.is_some() {
+            "grating"
+        } else if self.pickoff.is_some() {
+            "pickoff"
+        } else if self.fluorescence.is_some() {
+            "fluorescent"
+        } else if self.harmonic.is_some() {
+            "shg"
+        } else if self.coating.is_some() {
+            "metal"
+        } else if self.passband.is_some() {
+            "filter"
+        } else if self.polarizer_axis().is_some() {
+            "polarizer"
+        } else if self.reflection > 0. {
+            if self.reflection + self.absorption < 1. { "beamsplitter" } else { "mirror" }
+        } else if self.absorption >= 1. {
+            if self.extent == Extent::Line { "backdrop" } else { "blocker" }
+        } else {
+            "glass"
+        }
+    }
+
+    /// Coating, passband or refractive index; empty for plain absorbers.
+    pub fn material(&self) -> String {
+        if let Some(na) = self.numerical_aperture {
+            return format!("NA {}", na)
+        }
+        if let Some(f) = self.focal_length {
+            return format!("f = {}", f)
+        }
+        if let Some(od) = self.optical_density {
+            return format!("OD {:.2}", od)
+        }
+        if let Some(chopper) = &self.chopper {
+            return format!("{} × {} Hz", chopper.blades, chopper.frequency)
+        }
+        if let Some(isolator) = &self.isolator {
+            return format!("{} dB", isolator.isolation)
+        }
+        if let Some(grating) = &self.grating {
+            return format!("{:.0} lines/mm", grating.lines_per_mm())
+        }
+        if let Some(mirrored) = self.pickoff {
+            return format!("{:.0}% mirrored", mirrored * 100.)
+        }
+        if let Some(axis) = self.polarizer_axis() {
+            return format!("axis {:.0}°", axis.0)
+        }
+        if self.reflection > 0. && self.coating.is_none() && self.passband.is_none() {
+            return format!("R = {:.0}%", self.reflection * 100.)
+        }
+        match (&self.coating, self.passband) {
+            (Some(coating), _) => format!("{:?}", coating),
+            (None, Some((lo, hi))) => format!("{}-{}", lo, hi),
+            (None, None) if self.absorption >= 1. => String::new(),
+            (None, None) => match self.dispersion.filter(|_| self.interior.is_some()) {
+                Some(material) => material.name().unwrap_or("n(λ)").to_string(),
+                None => format!("n = {}", self.index)
+            }
+        }
+    }
+
+    /// Bends the surface into an arc of curvature `curvature` (1/mm) through its endpoints.
+    pub fn curvature(mut self, curvature: f32) -> Self {
+        self.curvature = curvature;
+        self
+    }
+
+    /// Refractive index of the medium behind the surface.
+    pub fn index(mut self, index: f32) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Makes the surface a face of a closed body of index `index`, see [`Surface::interior`].
+    pub fn interior(mut self, index: f32) -> Self {
+        self.interior = Some(index);
+        self
+    }
+
+    /// Makes the body this face bounds absorb, see [`Surface::interior_absorption`].
+    pub fn interior_absorption(mut self, absorption: f32) -> Self {
+        self.interior_absorption = absorption;
+        self
+    }
+
+    /// Makes the body this face bounds of dispersive glass, see [`Surface::dispersion`].
+    pub fn dispersion(mut self, material: Material) -> Self {
+        self.dispersion = Some(material);
+        self
+    }
+
+    /// Fraction of light reflected: all of it for a mirror, out of band for a filter.
+    pub fn reflectivity(mut self, reflection: f32) -> Self {
+        self.reflection = reflection;
+        self
+    }
+
+    pub fn absorption(mut self, absorption: f32) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn passband(mut self, band: (Nanometers, Nanometers)) -> Self {
+        self.passband = Some(band);
+        self
+    }
+
+    pub fn coating(mut self, coating: Coating) -> Self {
+        self.coating = Some(coating);
+        self
+    }
+
+    /// Acts on the polarization of transmitted rays; only felt when the scene tracks it.
+    pub fn mueller(mut self, mueller: Mueller) -> Self {
+        self.mueller = Some(mueller);
+        self
+    }
+
+    /// Marks the surface as an aperture or fiber face accepting light within `na`.
+    pub fn numerical_aperture(mut self, na: f32) -> Self {
+        self.numerical_aperture = Some(na);
+        self
+    }
+
+    /// Moves a pickoff mirror's cut edge to fraction `mirrored` of its length from `p1`.
+    pub fn mirrored(mut self, mirrored: f32) -> Self {
+        self.pickoff = Some(mirrored.clamp(0., 1.));
+        self
+    }
+
+    /// Sends the light an isolator rejects off at `angle` from the backward direction
+    /// instead of absorbing it.
+    pub fn dump(mut self, angle: Degrees) -> Self {
+        if let Some(isolator) = &mut self.isolator {
+            isolator.dump = Some(angle);
+        }
+        self
+    }
+
+    /// Roughens the surface, see [`Roughness`].
+    pub fn roughness(mut self, rms: Nanometers, lobe: Degrees) -> Self {
+        self.roughness = Some(Roughness {
+            rms,
+            lobe,
+            diffuse_rays: 8
+        });
+        self
+    }
+
+    /// Extends the surface to an infinite line through its endpoints.
+    pub fn infinite(mut self) -> Self {
+        self.extent = Extent::Line;
+        self
+    }
+
+    /// Extends the surface from `p1` through `p2` to infinity.
+    pub fn semi_infinite(mut self) -> Self {
+        self.extent = Extent::Ray;
+        self
+    }
+
+    /// Points to draw the surface through: its endpoints, or points along its arc.
+    pub fn outline(&self) -> Vec<Vec2> {
+        let Some((center, _)) = self.circle() else {
+            let (p1, p2) = self.drawn_endpoints();
+            return vec![p1, p2]
+        };
+        let (a, b) = (self.p1 - center, self.p2 - center);
+        // counterclockwise when bulging to the right of p1 -> p2; the angle alone can't tell
+        // which way round a half circle goes
+        let span = a.angle_between(b).abs() * -self.curvature.signum();
+        (0..=ARC_DRAW_POINTS)
+            .map(|k| center + Vec2::from_angle(span * k as f32 / ARC_DRAW_POINTS as f32).rotate(a))
+            .collect()
+    }
+
+    /// Endpoints to draw, with infinite directions cut off far outside the scene.
+    pub fn drawn_endpoints(&self) -> (Vec2, Vec2) {
+        let far = INFINITE_DRAW_LENGTH * self.dp().normalize_or_zero();
+        match self.extent {
+            Extent::Segment => (self.p1, self.p2),
+            Extent::Ray => (self.p1, self.p1 + far),
+            Extent::Line => (self.p1 - far, self.p1 + far)
+        }
+    }
+
+    /// Index of the medium a ray of wavelength `w` going along `l` through the surface, with
+    /// normal `normal` where it crosses, ends up in.
+    fn index_beyond(&self, l: Vec2, normal: Vec2, w: Nanometers) -> f32 {
+        match self.interior {
+            Some(inside) if l.dot(normal) < 0. => self.dispersion.map_or(inside, |material| material.index(w)),
+            _ => self.index
+        }
+    }
+
+    /// Absorption coefficient of the medium a ray going along `l` crosses into: the body's
+    /// going in, clear going out, and unchanged through surfaces that bound no body.
+    fn absorption_beyond(&self, l: Vec2, normal: Vec2, current: f32) -> f32 {
+        match self.interior {
+            Some(_) if l.dot(normal) < 0. => self.interior_absorption,
+            Some(_) => 0.,
+            None => current
+        }
+    }
+
+    /// Angles at the hit when the surface refracts `ray` at an index step, None when it
+    /// reflects, absorbs or diverts the ray some other way, or the index doesn't change.
+    pub fn incidence(&self, ray: &Ray, normal: Vec2) -> Option<Incidence> {
+        let refracts = self.fluorescence.is_none() && self.coating.is_none() && self.absorption < 1.
+            && self.grating.is_none() && self.pickoff.is_none() && self.isolator.is_none()
+            && self.passband.is_none() && self.reflection <= 0.;
+        let beyond = self.index_beyond(ray.l, normal, ray.w);
+        (refracts && beyond != ray.index).then(|| Incidence::new(ray.l, normal, ray.index, beyond))
+    }
+
+    /// Returns the rays leaving this surface when `ray` hits it at distance `d`.
+    pub fn interact(&self, ray: &Ray, d: f32) -> Vec<Ray> {
+        let out = self.interact_smooth(ray, d);
+        match &self.roughness {
+            Some(roughness) if self.fluorescence.is_none() => out.iter()
+                .flat_map(|o| roughness.scatter(ray, o, self.normal_at(ray.p + ray.l * d)))
+                .collect(),
+            _ => out
+        }
+    }
+
+    fn interact_smooth(&self, ray: &Ray, d: f32) -> Vec<Ray> {
+        let p = ray.p + ray.l * d;
+        let normal = self.normal_at(p);
+        if let Some(fluorescence) = &self.fluorescence {
+            if fluorescence.excited_by(ray.w) {
+                return fluorescence.emit(ray, p)
+            }
+        }
+        if let Some(coating) = &self.coating {
+            let cos_i = ray.l.dot(normal);
+            let (rs, rp) = coating.reflectance(ray.w, ray.index, cos_i);
+            // metal delays p against s, so linear light comes back elliptical
+            let (phase_s, phase_p) = coating.phase(ray.w, ray.index, cos_i);
+            let retarder = Mueller::retarder(Degrees((phase_p - phase_s).to_degrees()), Degrees(0.));
+            let mut reflected = ray.clone();
+            reflected.p = p;
+            reflected.l = reflect(ray.l, normal);
+            reflected.through(&Mueller(retarder.0 * Mueller::diattenuator(rs, rp).0), (rs + rp) / 2.);
+            return vec![reflected]
+        }
+        if self.absorption >= 1.0 {
+            return Vec::new()
+        }
+        if let Some(grating) = &self.grating {
+            let mut rays = grating.diffract(ray, p, -normal.perp(), normal);
+            rays.iter_mut().for_each(|out| out.i *= 1. - self.absorption);
+            return rays
+        }
+        if let Some(mirrored) = self.pickoff {
+            let t = (p - self.p1).dot(self.dp()) / self.dp().length_squared().max(f32::EPSILON);
+            let mut out = ray.clone();
+            out.p = p;
+            if t <= mirrored {
+                out.l = reflect(ray.l, normal);
+            }
+            return vec![out]
+        }
+        if let Some(isolator) = &self.isolator {
+            if ray.l.dot(normal) < 0. {
+                // backward: normal() points forward, to the left of p1 -> p2
+                let mut leaked = ray.clone();
+                leaked.p = p;
+                leaked.i = ray.i * isolator.leakage();
+                let mut rays = vec![leaked];
+                if let Some(angle) = isolator.dump {
+                    let mut dumped = ray.clone();
+                    dumped.p = p;
+                    dumped.l = Vec2::from_angle(angle.to_radians()).rotate(ray.l);
+                    dumped.i = ray.i * (1. - isolator.leakage());
+                    rays.push(dumped);
+                }
+                return rays
+            }
+        }
+        if let Some(chopper) = &self.chopper {
+            let t = (p - self.p1).dot(self.dp()) / self.dp().length_squared().max(f32::EPSILON);
+            if !chopper.open(t) {
+                return Vec::new()
+            }
+        }
+        if let Some((lo, hi)) = self.passband {
+            if ray.w < lo || ray.w > hi {
+                if self.reflection > 0.0 {
+                    let mut reflected = ray.clone();
+                    reflected.p = p;
+                    reflected.l = reflect(ray.l, normal);
+                    reflected.i = ray.i * self.reflection;
+                    return vec![reflected]
+                }
+                return Vec::new()
+            }
+        }
+        if self.reflection > 0.0 && self.passband.is_none() {
+            let mut reflected = ray.clone();
+            reflected.p = p;
+            reflected.l = reflect(ray.l, normal);
+            reflected.i = ray.i * self.reflection;
+            let mut rays = vec![reflected];
+            // what is neither reflected nor absorbed goes straight on
+            let transmitted = 1.0 - self.reflection - self.absorption;
+            if transmitted > 0.0 {
+                let mut out = ray.clone();
+                out.p = p;
+                out.i = ray.i * transmitted;
+                rays.push(out);
+            }
+            return rays
+        }
+        let mut out = ray.clone();
+        out.p = p;
+        out.i = ray.i * (1.0 - self.absorption) * self.transmittance();
+        // an index step splits the ray by the Fresnel equations; the reflected part leaves as
+        // its own branch and stays in the incident medium
+        let mut rays = Vec::new();
+        let beyond = self.index_beyond(ray.l, normal, ray.w);
+        let transmitted = match refract(ray.l, normal, ray.index, beyond) {
+            Some(l) => {
+                let (rs, rp) = fresnel(ray.l.dot(normal), ray.index, beyond);
+                if rs + rp > 0. {
+                    let mut reflected = out.clone();
+                    reflected.l = reflect(ray.l, normal);
+                    reflected.ghost = true;
+                    reflected.through(&Mueller::diattenuator(rs, rp), (rs + rp) / 2.);
+                    rays.push(reflected);
+                    out.through(&Mueller::diattenuator(1. - rs, 1. - rp), 1. - (rs + rp) / 2.);
+                }
+                out.l = l;
+                out.index = beyond;
+                out.absorption = self.absorption_beyond(ray.l, normal, ray.absorption);
+                true
+            }
+            None => {
+                out.l = reflect(ray.l, normal);
+                false
+            }
+        };
+        if let Some(f) = self.focal_length {
+            // tan θ' = tan θ - h / f, with θ measured from the lens axis
+            let along = self.dp().normalize_or_zero();
+            let h = (p - (self.p1 + self.p2) / 2.).dot(along);
+            let axis = normal * out.l.dot(normal).signum();
+            let tangent = out.l.dot(along) / out.l.dot(axis) - h / f.0;
+            out.l = (axis + along * tangent).normalize();
+        }
+        // a totally reflected ray never passes the polarizing layer
+        if let Some(mueller) = self.mueller.as_ref().filter(|_| transmitted) {
+            out.through(mueller, mueller.0.x_axis.x);
+        }
+        if let Some(harmonic) = &self.harmonic {
+            let eta = harmonic.conversion(ray, normal);
+            if eta > 0.0 {
+                let mut doubled = out.clone();
+                doubled.w = ray.w / 2.;
+                doubled.i = out.i * eta;
+                out.i *= 1.0 - eta;
+                rays.push(doubled);
+            }
+        }
+        rays.insert(0, out);
+        rays
+    }
+}
+
+/// Temperature profile across an absorbing surface, heated by the power it absorbs while tracing.
+/// The resulting index gradient deflects transmitted rays like a thin (usually positive) lens.
+#[derive(Component, Clone, Serialize, Deserialize)]
+pub struct ThermalLens {
+    pub dn_dt: f32,
+    pub thickness: Millimeters,
+    pub conductance: f32,
+    pub time_constant: f32,
+    #[serde(skip_serializing, default = "thermal_bins")]
+    pub deposited: Vec<f32>,
+    #[serde(skip_serializing, default = "thermal_bins")]
+    pub temperature: Vec<f32>
+}
+
+/// A cold profile, for thermal lenses read from a scene file.
+fn thermal_bins() -> Vec<f32> {
+    vec![0.; THERMAL_BINS]
+}
+
+impl ThermalLens {
+    pub fn new(dn_dt: f32, thickness: Millimeters) -> Self {
+        Self {
+            dn_dt,
+            thickness,
+            conductance: 1.0,
+            time_constant: 1.0,
+            deposited: thermal_bins(),
+            temperature: thermal_bins()
+        }
+    }
+
+    /// Steady-state temperature per unit of absorbed intensity.
+    pub fn conductance(mut self, conductance: f32) -> Self {
+        self.conductance = conductance;
+        self
+    }
+
+    /// Seconds for the temperature to settle after the absorbed power changes.
+    pub fn time_constant(mut self, time_constant: f32) -> Self {
+        self.time_constant = time_constant;
+        self
+    }
+
+    fn bin(&self, t: f32) -> usize {
+        ((t * THERMAL_BINS as f32) as usize).min(THERMAL_BINS - 1)
+    }
+
+    /// Adds power absorbed at fraction `t` along the surface.
+    pub fn deposit(&mut self, t: f32, power: f32) {
+        let bin = self.bin(t);
+        self.deposited[bin] += power;
+    }
+
+    /// Transverse deflection (radians, along the surface direction) at fraction `t` of a surface of `length`.
+    pub fn deflection(&self, t: f32, length: Millimeters) -> f32 {
+        let bin = self.bin(t);
+        let lo = bin.saturating_sub(1);
+        let hi = (bin + 1).min(THERMAL_BINS - 1);
+        if hi == lo {
+            return 0.
+        }
+        let dx = length * ((hi - lo) as f32 / THERMAL_BINS as f32);
+        self.dn_dt * (self.thickness / dx) * (self.temperature[hi] - self.temperature[lo])
+    }
+
+    /// Steps the temperature towards the diffused steady state of the deposited power.
+    /// Returns the largest change in temperature.
+    pub fn relax(&mut self, dt: f32) -> f32 {
+        let mut target: Vec<f32> = self.deposited.iter().map(|p| p * self.conductance).collect();
+        for _ in 0..4 {
+            target = (0..THERMAL_BINS).map(|k| {
+                let lo = target[k.saturating_sub(1)];
+                let hi = target[(k + 1).min(THERMAL_BINS - 1)];
+                0.25 * lo + 0.5 * target[k] + 0.25 * hi
+            }).collect();
+        }
+        let alpha = (dt / self.time_constant).min(1.);
+        let mut change: f32 = 0.;
+        for (temperature, target) in self.temperature.iter_mut().zip(target) {
+            let dtemp = alpha * (target - *temperature);
+            *temperature += dtemp;
+            change = change.max(dtemp.abs());
+        }
+        change
+    }
+}
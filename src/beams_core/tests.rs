@@ -0,0 +1,343 @@
+use bevy::{ecs::entity::Entity, math::{Vec2, Vec4}};
+
+use super::*;
+use crate::{coating::Coating, material::SF11, units::{Degrees, Millimeters, Nanometers}};
+
+fn entities(surfaces: &[Surface]) -> Vec<(Entity, &Surface)> {
+    surfaces.iter().enumerate().map(|(k, surface)| (Entity::from_raw(k as u32), surface)).collect()
+}
+
+fn along(degrees: f32) -> Vec2 {
+    Vec2::from_angle(degrees.to_radians())
+}
+
+#[test]
+fn segment_is_hit_between_its_ends_only() {
+    let surface = Surface::glass(Vec2::new(10., -1.), Vec2::new(10., 1.));
+    assert_eq!(intersect(&Ray::new(Vec2::ZERO, Vec2::X, 1.), &surface), 10.);
+    // past an end, behind the ray and parallel to the surface
+    assert!(intersect(&Ray::new(Vec2::new(0., 2.), Vec2::X, 1.), &surface).is_infinite());
+    assert!(intersect(&Ray::new(Vec2::ZERO, -Vec2::X, 1.), &surface).is_infinite());
+    assert!(intersect(&Ray::new(Vec2::ZERO, Vec2::Y, 1.), &surface).is_infinite());
+}
+
+#[test]
+fn unbounded_surfaces_reach_past_their_points() {
+    let surface = Surface::glass(Vec2::new(10., 0.), Vec2::new(10., 1.));
+    let above = Ray::new(Vec2::new(0., 5.), Vec2::X, 1.);
+    let below = Ray::new(Vec2::new(0., -5.), Vec2::X, 1.);
+    assert_eq!(intersect(&above, &surface.clone().infinite()), 10.);
+    assert_eq!(intersect(&below, &surface.clone().infinite()), 10.);
+    // from p1 on through p2
+    assert_eq!(intersect(&above, &surface.clone().semi_infinite()), 10.);
+    assert!(intersect(&below, &surface.semi_infinite()).is_infinite());
+}
+
+#[test]
+fn arc_is_hit_on_its_own_half_of_the_circle() {
+    // the right half of the unit circle
+    let arc = Surface::arc(Vec2::ZERO, Millimeters(1.), Degrees(-90.), Degrees(90.));
+    let d = intersect(&Ray::new(Vec2::new(-5., 0.), Vec2::X, 1.), &arc);
+    assert!((d - 6.).abs() < 1e-4, "{d}");
+    assert!(intersect(&Ray::new(Vec2::new(5., 0.), Vec2::X, 1.), &arc).is_infinite());
+    let normal = arc.normal_at(Vec2::X);
+    assert!(normal.abs().distance(Vec2::X) < 1e-5);
+}
+
+#[test]
+fn reflection_flips_the_normal_component() {
+    assert_eq!(reflect(Vec2::new(1., -1.), Vec2::Y), Vec2::new(1., 1.));
+    assert_eq!(reflect(Vec2::new(1., -1.), -Vec2::Y), Vec2::new(1., 1.));
+}
+
+#[test]
+fn refraction_follows_snells_law() {
+    let l = along(-60.);
+    let refracted = refract(l, Vec2::Y, 1., 1.5).unwrap();
+    assert!((refracted.x - 30f32.to_radians().sin() / 1.5).abs() < 1e-5);
+    assert!(refracted.y < 0.);
+    // either side of the normal
+    assert!(refract(l, -Vec2::Y, 1., 1.5).unwrap().distance(refracted) < 1e-6);
+    assert!(refract(Vec2::NEG_Y, Vec2::Y, 1., 1.5).unwrap().distance(Vec2::NEG_Y) < 1e-6);
+}
+
+#[test]
+fn light_is_totally_reflected_past_the_critical_angle() {
+    let critical = Incidence::new(Vec2::NEG_Y, Vec2::Y, 1.5, 1.).critical().unwrap();
+    assert!((critical - (1f32 / 1.5).asin()).abs() < 1e-6);
+    let tilted = |degrees: f32| Vec2::new(degrees.to_radians().sin(), -degrees.to_radians().cos());
+    assert!(refract(tilted(41.), Vec2::Y, 1.5, 1.).is_some());
+    assert!(refract(tilted(43.), Vec2::Y, 1.5, 1.).is_none());
+    assert_eq!(fresnel(43f32.to_radians().cos(), 1.5, 1.), (1., 1.));
+}
+
+#[test]
+fn fresnel_reflectance_at_normal_and_brewster_incidence() {
+    let (rs, rp) = fresnel(1., 1., 1.5);
+    assert!((rs - 0.04).abs() < 1e-6 && (rp - 0.04).abs() < 1e-6);
+    let brewster = Incidence::new(Vec2::NEG_Y, Vec2::Y, 1., 1.5).brewster();
+    let (rs, rp) = fresnel(brewster.cos(), 1., 1.5);
+    assert!(rp < 1e-6 && rs > 0.1, "{rs} {rp}");
+}
+
+#[test]
+fn slab_shifts_a_beam_without_turning_it() {
+    let surfaces = [
+        Surface::glass(Vec2::new(10., -20.), Vec2::new(10., 20.)).index(1.5),
+        Surface::glass(Vec2::new(20., -20.), Vec2::new(20., 20.)).index(1.)
+    ];
+    let surfaces = entities(&surfaces);
+    let ray = Ray::new(Vec2::ZERO, along(20.), 1.);
+    let traced = trace_limited(&ray, &surfaces, |_| None, TraceLimits::sequential());
+    assert_eq!(traced.segments.len(), 2);
+    let out = traced.branches.last().unwrap();
+    assert!(out.l.distance(ray.l) < 1e-5);
+    // the beam runs at a steeper angle, so less high, inside the glass
+    let inside = refract(ray.l, Vec2::X, 1., 1.5).unwrap();
+    let expected = 10. * (20f32.to_radians().tan() + inside.y / inside.x);
+    assert!((out.p.y - expected).abs() < 1e-3, "{} {}", out.p.y, expected);
+    assert!((out.medium_index() - 1.).abs() < 1e-6);
+}
+
+#[test]
+fn mirror_sends_a_beam_back() {
+    let surfaces = [Surface::mirror(Vec2::new(10., -5.), Vec2::new(10., 5.), 1.)];
+    let surfaces = entities(&surfaces);
+    let traced = trace(&Ray::new(Vec2::ZERO, Vec2::X, 1.), &surfaces, |_| None);
+    assert_eq!(traced.segments.len(), 1);
+    assert_eq!(traced.branches.len(), 1);
+    assert!(traced.branches[0].l.distance(-Vec2::X) < 1e-6);
+    assert!((traced.branches[0].i - 1.).abs() < 1e-6);
+}
+
+#[test]
+fn trace_stops_at_the_depth_limit() {
+    // a cavity between two mirrors, crossed at a slant
+    let surfaces = [
+        Surface::mirror(Vec2::new(0., -100.), Vec2::new(0., 100.), 1.),
+        Surface::mirror(Vec2::new(10., -100.), Vec2::new(10., 100.), 1.)
+    ];
+    let surfaces = entities(&surfaces);
+    let limits = TraceLimits {
+        max_depth: 5,
+        ..TraceLimits::default()
+    };
+    let traced = trace_limited(&Ray::new(Vec2::new(5., 0.), along(10.), 1.), &surfaces, |_| None, limits);
+    assert_eq!(traced.segments.len(), 6);
+    assert_eq!(traced.segments.iter().map(|segment| segment.depth).max(), Some(5));
+}
+
+#[test]
+fn concave_arc_reflects_a_ray_more_than_once() {
+    // the right half of the unit circle, mirrored, with a ray going up inside it at x = 0.9
+    let surfaces = [Surface::arc(Vec2::ZERO, Millimeters(1.), Degrees(-90.), Degrees(90.)).index(1.).reflectivity(1.)];
+    let surfaces = entities(&surfaces);
+    let traced = trace(&Ray::new(Vec2::new(0.9, 0.), Vec2::Y, 1.), &surfaces, |_| None);
+    assert!(traced.segments.len() >= 2, "{} bounces", traced.segments.len());
+    let second = &traced.segments[1];
+    assert_eq!(second.surface, surfaces[0].0);
+    // the chord between the bounces is 2 sin of the grazing angle
+    let expected = 2. * 0.19f32.sqrt();
+    assert!((second.hit.distance - expected).abs() < 1e-3, "{}", second.hit.distance);
+    assert!((second.hit.point.length() - 1.).abs() < 1e-4);
+}
+
+#[test]
+fn arc_sweeps_wrap_through_zero() {
+    let arc = Surface::arc(Vec2::ZERO, Millimeters(1.), Degrees(350.), Degrees(10.));
+    assert!((arc.length().0 - 2. * 10f32.to_radians().sin()).abs() < 1e-5);
+    let outline = arc.outline();
+    assert!(outline[outline.len() / 2].distance(Vec2::X) < 1e-4);
+}
+
+#[test]
+fn half_circles_are_drawn_on_their_own_side() {
+    for (from, to, middle) in [(-90., 90., Vec2::X), (90., 270., -Vec2::X), (0., 180., Vec2::Y), (180., 0., -Vec2::Y)] {
+        let arc = Surface::arc(Vec2::ZERO, Millimeters(1.), Degrees(from), Degrees(to));
+        let outline = arc.outline();
+        assert!(outline[outline.len() / 2].distance(middle) < 1e-4, "{from} to {to}");
+        assert!((intersect(&Ray::new(Vec2::ZERO, middle, 1.), &arc) - 1.).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn dispersive_slab_lets_every_wavelength_back_out() {
+    // faces of a slab of SF11 from x = 10 to 20, normals pointing out of it
+    let face = |p1: Vec2, p2: Vec2| Surface::glass(p1, p2).index(1.).interior(1.5).dispersion(SF11);
+    let surfaces = [
+        face(Vec2::new(10., -20.), Vec2::new(10., 20.)),
+        face(Vec2::new(20., 20.), Vec2::new(20., -20.))
+    ];
+    let surfaces = entities(&surfaces);
+    let mut exits = Vec::new();
+    for w in [Nanometers(450.), Nanometers(650.)] {
+        let mut ray = Ray::new(Vec2::ZERO, along(30.), 1.);
+        ray.w = w;
+        let traced = trace_limited(&ray, &surfaces, |_| None, TraceLimits::sequential());
+        assert_eq!(traced.segments.len(), 2);
+        let inside = &traced.branches[0];
+        assert!((inside.medium_index() - SF11.index(w)).abs() < 1e-6);
+        let out = traced.branches.last().unwrap();
+        assert!((out.medium_index() - 1.).abs() < 1e-6);
+        assert!(out.l.distance(ray.l) < 1e-5);
+        exits.push(out.p.y);
+    }
+    // blue is bent more, so it comes out lower
+    assert!(exits[0] < exits[1] - 0.01, "{exits:?}");
+    // lone surfaces can't be dispersive, so a slab of them doesn't keep rays in glass
+    let surfaces = [
+        Surface::glass(Vec2::new(10., -20.), Vec2::new(10., 20.)).dispersion(SF11),
+        Surface::glass(Vec2::new(20., -20.), Vec2::new(20., 20.)).index(1.).dispersion(SF11)
+    ];
+    let surfaces = entities(&surfaces);
+    let traced = trace_limited(&Ray::new(Vec2::ZERO, along(30.), 1.), &surfaces, |_| None, TraceLimits::sequential());
+    assert!((traced.branches.last().unwrap().medium_index() - 1.).abs() < 1e-6);
+}
+
+#[test]
+fn polarizer_leaves_totally_reflected_light_alone() {
+    // a polarizer on the face of a glass block, crossed with the p-polarized light inside it
+    let polarizer = Surface::polarizer(Vec2::new(10., -20.), Vec2::new(10., 20.), Degrees(0.)).index(1.);
+    let polarized = |l: Vec2| {
+        let mut ray = Ray::new(Vec2::ZERO, l, 1.5);
+        ray.stokes = Some(Stokes::P);
+        ray
+    };
+    let ray = polarized(along(20.));
+    let through = polarizer.interact(&ray, intersect(&ray, &polarizer));
+    assert!(through[0].l.x > 0. && through[0].i < 1e-4, "{}", through[0].i);
+    // past the critical angle all of it comes back, still p-polarized
+    let ray = polarized(along(60.));
+    let reflected = polarizer.interact(&ray, intersect(&ray, &polarizer));
+    assert_eq!(reflected.len(), 1);
+    assert!(reflected[0].l.x < 0.);
+    assert!((reflected[0].i - ray.i).abs() < 1e-5, "{}", reflected[0].i);
+    assert_eq!(reflected[0].stokes, ray.stokes);
+}
+
+#[test]
+fn concave_mirror_focuses_at_half_its_radius() {
+    // a mirror on the circle of radius 100 about the origin, its vertex at x = -100
+    let arc = Surface::arc(Vec2::ZERO, Millimeters(100.), Degrees(150.), Degrees(210.));
+    let surfaces = [Surface::mirror(arc.p1, arc.p2, 1.).curvature(arc.curvature)];
+    let surfaces = entities(&surfaces);
+    for h in [-1., 1.] {
+        let traced = trace(&Ray::new(Vec2::new(0., h), -Vec2::X, 1.), &surfaces, |_| None);
+        assert_eq!(traced.branches.len(), 1);
+        let back = &traced.branches[0];
+        // where the reflected ray crosses the axis
+        let focus = back.p.x - back.p.y * back.l.x / back.l.y;
+        assert!((focus + 50.).abs() < 0.01, "{focus}");
+    }
+}
+
+#[test]
+fn partial_mirrors_reflect_their_share() {
+    let (p1, p2) = (Vec2::new(10., -5.), Vec2::new(10., 5.));
+    let ray = Ray::new(Vec2::ZERO, Vec2::X, 1.);
+    // a mirror absorbs what it doesn't reflect
+    let surfaces = [Surface::mirror(p1, p2, 0.3)];
+    let traced = trace(&ray, &entities(&surfaces), |_| None);
+    assert_eq!(traced.branches.len(), 1);
+    assert!(traced.branches[0].l.distance(-Vec2::X) < 1e-6);
+    assert!((traced.branches[0].i - 0.3).abs() < 1e-6, "{}", traced.branches[0].i);
+    // a beam splitter lets it through instead
+    let surfaces = [Surface::beamsplitter(p1, p2, 0.3)];
+    let traced = trace(&ray, &entities(&surfaces), |_| None);
+    assert_eq!(traced.branches.len(), 2);
+    let reflected = traced.branches.iter().find(|branch| branch.l.x < 0.).unwrap();
+    let through = traced.branches.iter().find(|branch| branch.l.x > 0.).unwrap();
+    assert!((reflected.i - 0.3).abs() < 1e-6, "{}", reflected.i);
+    assert!((through.i - 0.7).abs() < 1e-6, "{}", through.i);
+    assert!(through.l.distance(Vec2::X) < 1e-6);
+}
+
+#[test]
+fn index_step_splits_a_ray_by_the_fresnel_equations() {
+    let ray = Ray::new(Vec2::ZERO, along(40.), 1.);
+    let (rs, rp) = fresnel(ray.l.dot(Vec2::X), 1., 1.5);
+    let r = (rs + rp) / 2.;
+    let limits = TraceLimits {
+        min_intensity: 0.,
+        ..TraceLimits::default()
+    };
+    for absorption in [0., 0.1] {
+        let surfaces = [Surface::glass(Vec2::new(10., -20.), Vec2::new(10., 20.)).index(1.5).absorption(absorption)];
+        let traced = trace_limited(&ray, &entities(&surfaces), |_| None, limits);
+        assert_eq!(traced.branches.len(), 2);
+        let reflected = traced.branches.iter().find(|branch| branch.ghost).unwrap();
+        let refracted = traced.branches.iter().find(|branch| !branch.ghost).unwrap();
+        assert!(reflected.l.distance(reflect(ray.l, Vec2::X)) < 1e-6);
+        assert!(refracted.l.distance(refract(ray.l, Vec2::X, 1., 1.5).unwrap()) < 1e-6);
+        assert!((reflected.i - (1. - absorption) * r).abs() < 1e-6, "{} {r}", reflected.i);
+        assert!((refracted.i - (1. - absorption) * (1. - r)).abs() < 1e-6, "{}", refracted.i);
+        // nothing is lost but what the surface absorbs
+        assert!((reflected.i + refracted.i + absorption - 1.).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn sibling_rays_each_keep_their_own_reach() {
+    // grating orders all leave from the same point but meet a slanted screen at different distances
+    let surfaces = [
+        Surface::grating(Vec2::new(10., -20.), Vec2::new(10., 20.), Grating::new(600.)),
+        Surface::blocker(Vec2::new(20., -50.), Vec2::new(40., 50.))
+    ];
+    let surfaces = entities(&surfaces);
+    let traced = trace(&Ray::new(Vec2::ZERO, Vec2::X, 1.), &surfaces, |_| None);
+    assert_eq!(traced.reaches.len(), traced.branches.len() + 1);
+    assert!((traced.reaches[0] - 10.).abs() < 1e-4);
+    let screen = surfaces[1].1;
+    let mut reaches: Vec<f32> = traced.branches.iter().zip(&traced.reaches[1..]).map(|(branch, reach)| {
+        assert_eq!(branch.p, traced.branches[0].p);
+        assert!((reach - intersect(branch, screen)).abs() < 1e-4, "{reach}");
+        *reach
+    }).collect();
+    reaches.dedup_by(|a, b| (*a - *b).abs() < 1.);
+    assert!(reaches.len() > 1, "{reaches:?}");
+}
+
+#[test]
+fn metal_mirror_turns_linear_light_elliptical() {
+    let silver = Surface::metal(Vec2::new(10., -20.), Vec2::new(10., 20.), Coating::Silver);
+    let mut ray = Ray::new(Vec2::ZERO, along(45.), 1.);
+    // linear, halfway between s and p
+    ray.stokes = Some(Stokes(Vec4::new(1., 0., 1., 0.)));
+    let reflected = silver.interact(&ray, intersect(&ray, &silver));
+    assert_eq!(reflected.len(), 1);
+    let stokes = reflected[0].stokes.unwrap().0;
+    assert!(stokes.w.abs() > 0.01, "{stokes}");
+    // still fully polarized
+    assert!((Vec2::new(stokes.y, stokes.z).length_squared() + stokes.w * stokes.w - 1.).abs() < 1e-4, "{stokes}");
+}
+
+#[test]
+fn grating_sends_each_order_along_the_grating_equation() {
+    let grating = Surface::grating(Vec2::new(10., -20.), Vec2::new(10., 20.), Grating::new(600.));
+    let mut ray = Ray::new(Vec2::ZERO, along(20.), 1.);
+    ray.w = Nanometers(633.);
+    let orders = grating.interact(&ray, intersect(&ray, &grating));
+    // sin θ is 0.34 and λ/d 0.38, so order 2 would leave at sin θ = 1.1 and doesn't
+    let step = 633. * 600. / 1e6;
+    assert_eq!(orders.len(), 4);
+    for (out, m) in orders.iter().zip([-2., -1., 0., 1.]) {
+        assert!((out.l.y - (ray.l.y + m * step)).abs() < 1e-5, "order {m}: {}", out.l.y);
+        assert!(out.l.x > 0.);
+    }
+}
+
+#[test]
+fn isolator_passes_forward_light_and_leaks_backward_by_its_isolation() {
+    // forward is to the left of p1 -> p2, here along +x
+    let isolator = Surface::isolator(Vec2::new(10., 20.), Vec2::new(10., -20.), 30.);
+    let forward = Ray::new(Vec2::ZERO, Vec2::X, 1.);
+    let out = isolator.interact(&forward, intersect(&forward, &isolator));
+    assert_eq!(out.len(), 1);
+    assert!((out[0].i - 1.).abs() < 1e-6, "{}", out[0].i);
+    assert!(out[0].l.distance(Vec2::X) < 1e-6);
+    let backward = Ray::new(Vec2::new(20., 0.), -Vec2::X, 1.);
+    let out = isolator.interact(&backward, intersect(&backward, &isolator));
+    assert_eq!(out.len(), 1);
+    assert!((out[0].i - 1e-3).abs() < 1e-7, "{}", out[0].i);
+    assert!(out[0].l.distance(-Vec2::X) < 1e-6);
+}
@@ -0,0 +1,165 @@
+use bevy::ecs::entity::Entity;
+
+use super::{nearest_hit, offset_from_surface, Hit, Incidence, Ray, Surface, ThermalLens};
+
+// Default trace limits, see `TraceLimits`
+pub const MAX_DEPTH: usize = 32;
+pub const MIN_INTENSITY: f32 = 1e-3;
+// Segments per traced tree; beyond this the least important pending branches are dropped
+const MAX_BRANCHES: usize = 4096;
+
+/// One straight piece of a traced ray tree, ending on `surface`.
+#[derive(Clone)]
+pub struct TraceSegment {
+    pub ray: Ray,
+    pub depth: usize,
+    pub surface: Entity,
+    pub hit: Hit,
+    /// Angles at the hit, when the surface refracts the ray
+    pub incidence: Option<Incidence>
+}
+
+#[derive(Default)]
+pub struct Trace {
+    pub segments: Vec<TraceSegment>,
+    pub branches: Vec<Ray>,
+    /// How far the root and then each branch go before they hit something, infinite for the
+    /// rays that escape
+    pub reaches: Vec<f32>,
+    /// Rays that hit nothing, with their depth in the tree
+    pub escaped: Vec<(Ray, usize)>,
+    /// Power absorbed by thermal lenses, as (surface, fraction along it, power)
+    pub deposits: Vec<(Entity, f32, f32)>
+}
+
+/// How much a pending branch is worth tracing: its power, discounted by how many surfaces
+/// it has already split at.
+fn importance(ray: &Ray, depth: usize) -> f32 {
+    ray.i / (1 + depth) as f32
+}
+
+/// Drops the least important pending branches until at most `budget` are left. The rest keep
+/// their order, so traces stay deterministic.
+fn prune(stack: &mut Vec<(Ray, usize, Option<Entity>)>, budget: usize) {
+    while stack.len() > budget {
+        let weakest = stack.iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| importance(&a.0, a.1).total_cmp(&importance(&b.0, b.1)))
+            .map(|(k, _)| k);
+        let Some(weakest) = weakest else {
+            return
+        };
+        stack.remove(weakest);
+    }
+}
+
+/// How far child rays are followed: rays past `max_depth` interactions or weaker than
+/// `min_intensity` are not traced further.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceLimits {
+    pub max_depth: usize,
+    pub min_intensity: f32,
+    /// Whether Fresnel reflections at index steps are followed; see [`Ray::ghost`]
+    pub ghosts: bool
+}
+
+impl Default for TraceLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: MAX_DEPTH,
+            min_intensity: MIN_INTENSITY,
+            ghosts: true
+        }
+    }
+}
+
+impl TraceLimits {
+    /// Follows only the beam's own path, for analyses that expect one ray out of each
+    /// transmitting surface. The transmitted rays still lose the reflected power.
+    pub fn sequential() -> Self {
+        Self {
+            ghosts: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// Traces `root` and all its children through `surfaces` with the default limits. The
+/// analyses use this so their results don't depend on the user's settings.
+pub fn trace<'a>(
+    root: &Ray,
+    surfaces: &[(Entity, &Surface)],
+    lenses: impl Fn(Entity) -> Option<&'a ThermalLens>
+) -> Trace {
+    trace_limited(root, surfaces, lenses, TraceLimits::default())
+}
+
+/// Traces `root` and all its children through `surfaces`. Ray order is depth-first and only
+/// depends on the order of `surfaces`, so the result is deterministic for a given scene. A tree
+/// never has more than [`MAX_BRANCHES`] segments, keeping the strongest branches when it would.
+pub fn trace_limited<'a>(
+    root: &Ray,
+    surfaces: &[(Entity, &Surface)],
+    lenses: impl Fn(Entity) -> Option<&'a ThermalLens>,
+    limits: TraceLimits
+) -> Trace {
+    trace_with(root, |ray, exclude| nearest_hit(ray, surfaces.iter().copied(), exclude), lenses, limits)
+}
+
+/// Traces `root` and all its children, finding the next surface of each ray with `hit`, which
+/// is given the ray and the surface it leaves.
+pub fn trace_with<'a, 's>(
+    root: &Ray,
+    hit: impl Fn(&Ray, Option<Entity>) -> Option<(f32, Entity, &'s Surface)>,
+    lenses: impl Fn(Entity) -> Option<&'a ThermalLens>,
+    limits: TraceLimits
+) -> Trace {
+    let mut trace = Trace::default();
+    let mut stack = vec![(root.clone(), 0, None)];
+    while let Some((ray, depth, exclude)) = stack.pop() {
+        if depth > 0 {
+            trace.branches.push(ray.clone());
+        }
+        if let Some((d, entity, surface)) = hit(&ray, exclude) {
+            trace.reaches.push(d);
+            let hit = Hit {
+                point: ray.p + ray.l * d,
+                distance: d,
+                normal: surface.normal_at(ray.p + ray.l * d)
+            };
+            // light in an absorbing medium arrives weaker than it set out
+            let mut arriving = ray.clone();
+            arriving.i *= ray.transmission(d);
+            let incidence = surface.incidence(&ray, hit.normal);
+            let mut children = surface.interact(&arriving, d);
+            if let Some(lens) = lenses(entity) {
+                let dp = surface.dp();
+                let t = (hit.point - surface.p1).dot(dp) / dp.length_squared();
+                trace.deposits.push((entity, t, arriving.i * surface.absorption));
+                let deflection = lens.deflection(t, surface.length());
+                for child in children.iter_mut() {
+                    child.l = (child.l + deflection * dp.normalize()).normalize();
+                }
+            }
+            if depth < limits.max_depth {
+                for mut child in children.into_iter().filter(|c| c.i >= limits.min_intensity && (limits.ghosts || !c.ghost)) {
+                    child.opl = ray.opl + ray.index * d;
+                    offset_from_surface(&mut child, hit.normal);
+                    stack.push((child, depth + 1, Some(entity)));
+                }
+                prune(&mut stack, MAX_BRANCHES.saturating_sub(trace.segments.len() + 1));
+            }
+            trace.segments.push(TraceSegment {
+                ray,
+                depth,
+                surface: entity,
+                hit,
+                incidence
+            });
+        } else {
+            trace.reaches.push(f32::INFINITY);
+            trace.escaped.push((ray, depth));
+        }
+    }
+    trace
+}
@@ -0,0 +1,346 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{cross2, material::Material, units::Millimeters, Surface};
+
+// Face edits smaller than this (mm) are the body's own updates, not the body being moved
+const FACE_TOLERANCE: f32 = 1e-4;
+// Thickness (mm) of symmetric lenses where they are thinnest
+const MIN_THICKNESS: f32 = 1.;
+// Steps of the fixed-point solve for the radii of symmetric lenses
+const LENS_SOLVE_STEPS: usize = 8;
+
+/// Closed piece of glass, such as a prism or a lens, bounded by straight or curved edges
+/// between its corners. Each edge is spawned as a face whose normal points out of the body
+/// and that knows the index inside, so a ray crossing it goes into the glass or back out into
+/// the surrounding medium depending on which way it crosses. Rays therefore keep track of the
+/// medium they are in without the faces having to be ordered along the beam.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Body {
+    /// Corners in order around the body, either way round
+    pub corners: Vec<Vec2>,
+    /// Curvature (1/mm) of the edge from each corner to the next, bulging outwards when positive
+    pub bulges: Vec<f32>,
+    pub index: f32,
+    /// Index of the medium around the body
+    pub medium: f32,
+    /// Dispersive glass replacing `index`
+    #[serde(default)]
+    pub dispersion: Option<Material>,
+    /// Absorption coefficient (1/mm) of tinted glass, 0 for clear
+    #[serde(default)]
+    pub absorption: f32
+}
+
+/// Spherical singlet lens, built as a [`Body`] with curved front and back faces joined by flat
+/// edges. Radii follow the usual sign convention along the axis: positive when the center of
+/// curvature lies behind the surface, so `r1 > 0 > r2` is biconvex; an infinite radius is flat.
+#[derive(Clone, Debug)]
+pub struct Lens {
+    pub center: Vec2,
+    /// Direction light goes through the lens in, from the front face to the back
+    pub axis: Vec2,
+    pub diameter: Millimeters,
+    pub r1: Millimeters,
+    pub r2: Millimeters,
+    /// Thickness on the axis
+    pub thickness: Millimeters,
+    pub index: f32
+}
+
+/// Sag (mm, along the axis) of a spherical surface of radius `r` at height `h` from its vertex.
+fn sag(r: f32, h: f32) -> f32 {
+    if r.is_infinite() {
+        return 0.
+    }
+    r - r.signum() * (r * r - h * h).max(0.).sqrt()
+}
+
+impl Lens {
+    pub fn new(
+        center: Vec2,
+        diameter: Millimeters,
+        r1: Millimeters,
+        r2: Millimeters,
+        thickness: Millimeters,
+        index: f32
+    ) -> Self {
+        Self {
+            center,
+            axis: Vec2::X,
+            diameter,
+            r1,
+            r2,
+            thickness,
+            index
+        }
+    }
+
+    /// Equiconvex lens of focal length `f`, or equiconcave for negative `f`, 1 mm thick where
+    /// it is thinnest.
+    pub fn symmetric(center: Vec2, diameter: Millimeters, f: Millimeters, index: f32) -> Self {
+        let h = diameter.0 / 2.;
+        let mut lens = Self::new(center, diameter, Millimeters(0.), Millimeters(0.), Millimeters(MIN_THICKNESS), index);
+        // thin-lens radii, then scaled while the thickness they need shifts the focus
+        let mut r = 2. * (index - 1.) * f.0;
+        for _ in 0..LENS_SOLVE_STEPS {
+            lens.r1 = Millimeters(r);
+            lens.r2 = Millimeters(-r);
+            lens.thickness = Millimeters(MIN_THICKNESS + 2. * sag(r, h).max(0.));
+            r *= f.0 / lens.focal_length().0;
+        }
+        lens
+    }
+
+    /// Plano-convex lens of focal length `f`, curved side first, 1 mm thick at the rim.
+    pub fn plano_convex(center: Vec2, diameter: Millimeters, f: Millimeters, index: f32) -> Self {
+        let r = (index - 1.) * f.0;
+        let thickness = MIN_THICKNESS + sag(r, diameter.0 / 2.).max(0.);
+        Self::new(center, diameter, Millimeters(r), Millimeters(f32::INFINITY), Millimeters(thickness), index)
+    }
+
+    /// Turns the lens so light goes through it along `axis`.
+    pub fn axis(mut self, axis: Vec2) -> Self {
+        self.axis = axis.normalize_or_zero();
+        self
+    }
+
+    /// Thickness at the rim, negative when the faces cross before the full diameter.
+    pub fn edge_thickness(&self) -> f32 {
+        let h = self.diameter.0 / 2.;
+        self.thickness.0 + sag(self.r2.0, h) - sag(self.r1.0, h)
+    }
+
+    /// Effective focal length from the thick-lens lensmaker's equation, in air.
+    pub fn focal_length(&self) -> Millimeters {
+        let (c1, c2, n) = (1. / self.r1.0, 1. / self.r2.0, self.index);
+        let power = (n - 1.) * (c1 - c2 + (n - 1.) * self.thickness.0 * c1 * c2 / n);
+        Millimeters(1. / power)
+    }
+
+    /// The lens as a body, or `None` when it can't be made: a radius smaller than the half
+    /// diameter, or faces that meet inside the rim.
+    pub fn body(&self) -> Option<Body> {
+        let h = self.diameter.0 / 2.;
+        if self.r1.0.abs() < h || self.r2.0.abs() < h || self.edge_thickness() < 0. {
+            return None
+        }
+        let (front, back) = (-self.thickness.0 / 2. + sag(self.r1.0, h), self.thickness.0 / 2. + sag(self.r2.0, h));
+        let place = |x: f32, y: f32| self.center + self.axis.rotate(Vec2::new(x, y));
+        // counterclockwise in lens coordinates: bottom edge, back face, top edge, front face
+        let corners = vec![place(front, -h), place(back, -h), place(back, h), place(front, h)];
+        // outward, the front face bulges against the axis and the back face along it
+        Some(Body::polygon(corners, self.index)
+            .bulge(1, -1. / self.r2.0)
+            .bulge(3, 1. / self.r1.0))
+    }
+}
+
+/// The face of a body along the edge from corner `edge` to the next.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct BodyFace {
+    pub body: Entity,
+    pub edge: usize
+}
+
+impl Body {
+    /// Polygon of glass with index `index` in air.
+    pub fn polygon(corners: Vec<Vec2>, index: f32) -> Self {
+        Self {
+            bulges: vec![0.; corners.len()],
+            corners,
+            index,
+            medium: 1.0,
+            dispersion: None,
+            absorption: 0.
+        }
+    }
+
+    /// Tints the glass so light going through decays as e^(-αd) over its path of `d` mm.
+    pub fn absorbing(mut self, absorption: f32) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    /// Bends the edge from corner `edge` to the next into an arc, convex for positive
+    /// `curvature` (1/mm).
+    pub fn bulge(mut self, edge: usize, curvature: f32) -> Self {
+        if let Some(bulge) = self.bulges.get_mut(edge) {
+            *bulge = curvature;
+        }
+        self
+    }
+
+    /// Equilateral prism standing on the base from `p1` to `p2`, with its apex to the left.
+    pub fn prism(p1: Vec2, p2: Vec2, index: f32) -> Self {
+        let apex = (p1 + p2) / 2. + (p2 - p1).perp() * 3f32.sqrt() / 2.;
+        Self::polygon(vec![p1, p2, apex], index)
+    }
+
+    /// Twice the signed area of the polygon through the corners, positive counterclockwise.
+    fn winding(&self) -> f32 {
+        self.corners.iter()
+            .zip(self.corners.iter().cycle().skip(1))
+            .map(|(a, b)| cross2(*a, *b))
+            .sum()
+    }
+
+    /// Closed outline through the corners, along the curved edges as they are drawn.
+    pub fn outline(&self) -> Vec<Vec2> {
+        let clockwise = self.winding() < 0.;
+        self.faces().iter().flat_map(|face| {
+            let mut points = face.outline();
+            // faces of a counterclockwise body run from each edge's far corner
+            if !clockwise {
+                points.reverse();
+            }
+            points.pop();
+            points
+        }).collect()
+    }
+
+    /// One face per edge, in corner order. Going round clockwise puts the left-hand normal of
+    /// each face outside the body.
+    pub fn faces(&self) -> Vec<Surface> {
+        let clockwise = self.winding() < 0.;
+        let count = self.corners.len();
+        (0..count).map(|edge| {
+            let (a, b) = (self.corners[edge], self.corners[(edge + 1) % count]);
+            let (p1, p2) = if clockwise { (a, b) } else { (b, a) };
+            Surface {
+                dispersion: self.dispersion,
+                ..Surface::glass(p1, p2)
+                    .index(self.medium)
+                    .interior(self.index)
+                    .interior_absorption(self.absorption)
+                    .curvature(self.bulges.get(edge).copied().unwrap_or(0.))
+            }
+        }).collect()
+    }
+}
+
+/// Spawns the faces of new bodies as their children, so they go when the body is despawned.
+pub fn body_spawn_system(
+    mut commands: Commands,
+    body_query: Query<(Entity, &Body), Added<Body>>
+) {
+    for (entity, body) in body_query.iter() {
+        if body.corners.len() < 2 {
+            warn!("a body needs at least two corners");
+            continue
+        }
+        commands.entity(entity).with_children(|parent| {
+            for (edge, surface) in body.faces().into_iter().enumerate() {
+                parent.spawn((surface, BodyFace {
+                    body: entity,
+                    edge
+                }));
+            }
+        });
+    }
+}
+
+/// Keeps the faces of a body together. Moving or turning a face moves the whole body with it,
+/// and giving it another glass or tint changes that of the body; stretching a face or changing its
+/// curvature or indices is undone, as those belong to the body. Faces follow edits of the body
+/// itself.
+pub fn body_coupling_system(
+    mut body_query: Query<(Entity, &mut Body, ChangeTrackers<Body>)>,
+    mut face_query: Query<(&BodyFace, &mut Surface, ChangeTrackers<Surface>)>
+) {
+    let edits: Vec<(BodyFace, Surface)> = face_query.iter()
+        .filter(|(_, _, changes)| changes.is_changed() && !changes.is_added())
+        .map(|(face, surface, _)| (*face, surface.clone()))
+        .collect();
+    let mut touched: Vec<Entity> = Vec::new();
+    for (face, surface) in edits {
+        let Ok((_, mut body, _)) = body_query.get_mut(face.body) else {
+            continue
+        };
+        let Some(expected) = body.faces().into_iter().nth(face.edge) else {
+            continue
+        };
+        if expected.dispersion != surface.dispersion || expected.interior_absorption != surface.interior_absorption {
+            body.dispersion = surface.dispersion;
+            body.absorption = surface.interior_absorption;
+            touched.push(face.body);
+        }
+        if expected.p1.distance(surface.p1) < FACE_TOLERANCE && expected.p2.distance(surface.p2) < FACE_TOLERANCE {
+            continue
+        }
+        // rigid motion carrying the expected face onto the edited one
+        let rotation = Vec2::from_angle(expected.dp().angle_between(surface.dp()));
+        let (from, to) = ((expected.p1 + expected.p2) / 2., (surface.p1 + surface.p2) / 2.);
+        for corner in body.corners.iter_mut() {
+            *corner = to + rotation.rotate(*corner - from);
+        }
+        touched.push(face.body);
+    }
+    touched.extend(body_query.iter()
+        .filter(|(_, _, changes)| changes.is_changed() && !changes.is_added())
+        .map(|(entity, ..)| entity));
+    for (face, mut surface, changes) in face_query.iter_mut() {
+        let edited = changes.is_changed() && !changes.is_added();
+        if !edited && !touched.contains(&face.body) {
+            continue
+        }
+        let Some(expected) = body_query.get(face.body).ok().and_then(|(_, body, _)| body.faces().into_iter().nth(face.edge)) else {
+            continue
+        };
+        let moved = expected.p1.distance(surface.p1) >= FACE_TOLERANCE || expected.p2.distance(surface.p2) >= FACE_TOLERANCE;
+        let optics = (expected.index, expected.interior, expected.curvature, expected.dispersion);
+        if moved || optics != (surface.index, surface.interior, surface.curvature, surface.dispersion) {
+            surface.p1 = expected.p1;
+            surface.p2 = expected.p2;
+            surface.index = expected.index;
+            surface.interior = expected.interior;
+            surface.curvature = expected.curvature;
+            surface.dispersion = expected.dispersion;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{trace_limited, Ray, TraceLimits};
+
+    /// Traces `ray` through the faces of `body` along the beam's own path, returning the ray
+    /// that leaves it.
+    fn through(body: &Body, ray: &Ray) -> Ray {
+        let faces = body.faces();
+        let surfaces: Vec<_> = faces.iter().enumerate().map(|(k, face)| (Entity::from_raw(k as u32), face)).collect();
+        let traced = trace_limited(ray, &surfaces, |_| None, TraceLimits::sequential());
+        traced.branches.last().cloned().expect("the ray goes through the body")
+    }
+
+    #[test]
+    fn symmetric_lens_focuses_near_axis_rays_at_its_focal_length() {
+        for f in [50., -50.] {
+            let lens = Lens::symmetric(Vec2::ZERO, Millimeters(20.), Millimeters(f), 1.5);
+            let body = lens.body().expect("the lens can be made");
+            let h = 0.5;
+            let out = through(&body, &Ray::new(Vec2::new(-30., h), Vec2::X, 1.));
+            assert!((out.medium_index() - 1.).abs() < 1e-6);
+            // the effective focal length is the height over the slope it leaves at
+            let measured = h * out.l.x / -out.l.y;
+            assert!((measured - f).abs() < 0.01 * f.abs(), "{measured} for {f}");
+        }
+    }
+
+    #[test]
+    fn prism_deviates_least_by_the_textbook_angle() {
+        let (index, apex) = (1.5f32, 60f32.to_radians());
+        let prism = Body::prism(Vec2::new(-10., 0.), Vec2::new(10., 0.), index);
+        // the middle of the left face, whose inward normal points 30° below the base
+        let target = Vec2::new(-5., 5. * 3f32.sqrt());
+        let deviation = (350..700).map(|tenths| {
+            let incidence = (tenths as f32 / 10.).to_radians();
+            let l = Vec2::from_angle(incidence - apex / 2.);
+            let out = through(&prism, &Ray::new(target - 20. * l, l, 1.));
+            l.angle_between(out.l).abs()
+        }).fold(f32::INFINITY, f32::min);
+        let expected = 2. * (index * (apex / 2.).sin()).asin() - apex;
+        assert!((deviation - expected).abs() < 0.01f32.to_radians(), "{} {}", deviation.to_degrees(), expected.to_degrees());
+    }
+}
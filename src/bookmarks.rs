@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+
+use crate::{fit_scale, keyboard::Palette, scene_tree::SceneFilter, viewer::ReadOnly, viewports::MainCamera, SCENE_H, SCENE_W};
+
+const DIGITS: [KeyCode; 9] = [
+    KeyCode::Key1, KeyCode::Key2, KeyCode::Key3,
+    KeyCode::Key4, KeyCode::Key5, KeyCode::Key6,
+    KeyCode::Key7, KeyCode::Key8, KeyCode::Key9
+];
+
+/// A saved camera view. `zoom` is relative to the scale that fits the table into the window,
+/// so bookmarks stay valid when the window is resized.
+#[derive(Clone, Debug)]
+pub struct Bookmark {
+    pub name: String,
+    pub center: Vec2,
+    pub zoom: f32
+}
+
+/// Views bound to the number keys 1-9.
+#[derive(Resource)]
+pub struct Bookmarks(pub [Option<Bookmark>; 9]);
+
+impl Default for Bookmarks {
+    fn default() -> Self {
+        let mut bookmarks = Self(Default::default());
+        bookmarks.set(0, "full table", Vec2::new(SCENE_W, SCENE_H) / 2., 1.);
+        bookmarks
+    }
+}
+
+impl Bookmarks {
+    pub fn set(&mut self, slot: usize, name: &str, center: Vec2, zoom: f32) {
+        self.0[slot] = Some(Bookmark {
+            name: name.to_string(),
+            center,
+            zoom
+        });
+    }
+}
+
+/// Center and zoom of the main camera's view, as bookmarks keep them.
+pub fn current_view(windows: &Windows, camera_query: &Query<(&Transform, &OrthographicProjection), MainCamera>) -> Option<(Vec2, f32)> {
+    let window = windows.get_primary()?;
+    let (transform, projection) = camera_query.get_single().ok()?;
+    Some((transform.translation.truncate(), fit_scale(window.width(), window.height()) / projection.scale))
+}
+
+/// 1-9 jumps to a bookmarked view, Ctrl+1-9 bookmarks the current one and opens the palette
+/// to name it. Digits go to the scene search box instead while it is open.
+pub fn bookmark_system(
+    keys: Res<Input<KeyCode>>,
+    (filter, read_only): (Res<SceneFilter>, Res<ReadOnly>),
+    windows: Res<Windows>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut palette: ResMut<Palette>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), MainCamera>
+) {
+    if filter.open {
+        return
+    }
+    let Some(slot) = DIGITS.iter().position(|key| keys.just_pressed(*key)) else {
+        return
+    };
+    let Some(window) = windows.get_primary() else {
+        return
+    };
+    let fit = fit_scale(window.width(), window.height());
+    let Ok((mut transform, mut projection)) = camera_query.get_single_mut() else {
+        return
+    };
+    if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        let name = format!("view {}", slot + 1);
+        bookmarks.set(slot, &name, transform.translation.truncate(), fit / projection.scale);
+        info!("bookmarked {} on {}", name, slot + 1);
+        // Escape keeps the default name
+        if !read_only.0 {
+            palette.open = true;
+            palette.entry = format!("rename {} ", slot + 1);
+            palette.error = None;
+        }
+    } else if let Some(bookmark) = &bookmarks.0[slot] {
+        transform.translation.x = bookmark.center.x;
+        transform.translation.y = bookmark.center.y;
+        projection.scale = fit / bookmark.zoom;
+        info!("jumped to {}", bookmark.name);
+    }
+}
@@ -0,0 +1,73 @@
+use bevy::prelude::*;
+
+use crate::{cross2, TraceSegment};
+
+/// A ray where it crosses a plane.
+#[derive(Clone, Copy, Debug)]
+pub struct Crossing {
+    pub point: Vec2,
+    /// Unit direction of the ray
+    pub direction: Vec2,
+    /// Power the ray carries, which weights it in [`BundleStats`]
+    pub power: f32
+}
+
+impl Crossing {
+    /// A traced segment where it ends on its surface.
+    pub fn of_segment(segment: &TraceSegment) -> Self {
+        Self {
+            point: segment.hit.point,
+            direction: segment.ray.l.normalize_or_zero(),
+            power: segment.ray.power * segment.ray.i
+        }
+    }
+}
+
+/// Power-weighted statistics of a bundle of rays crossing a plane.
+#[derive(Clone, Copy, Debug)]
+pub struct BundleStats {
+    /// Total power of the bundle
+    pub power: f32,
+    pub centroid: Vec2,
+    /// RMS distance (mm) of the crossings from the centroid
+    pub rms_radius: f32,
+    /// Principal axes of the spot, the unit directions of its largest and smallest spread
+    pub axes: [Vec2; 2],
+    /// RMS spread (mm) along each principal axis
+    pub extents: [f32; 2],
+    /// Mean direction of the rays
+    pub direction: Vec2,
+    /// RMS angle (radians) of the rays about their mean direction
+    pub angular_spread: f32
+}
+
+impl BundleStats {
+    /// Statistics of `crossings`, or `None` when they carry no power.
+    pub fn of(crossings: &[Crossing]) -> Option<Self> {
+        let power: f32 = crossings.iter().map(|crossing| crossing.power).sum();
+        if power <= 0. {
+            return None
+        }
+        let mean = |f: &dyn Fn(&Crossing) -> f32| crossings.iter().map(|crossing| f(crossing) * crossing.power).sum::<f32>() / power;
+        let centroid = Vec2::new(mean(&|c| c.point.x), mean(&|c| c.point.y));
+        let (xx, xy, yy) = (
+            mean(&|c| (c.point.x - centroid.x).powi(2)),
+            mean(&|c| (c.point.x - centroid.x) * (c.point.y - centroid.y)),
+            mean(&|c| (c.point.y - centroid.y).powi(2))
+        );
+        // eigenvectors of the covariance matrix
+        let major = Vec2::from_angle(0.5 * (2. * xy).atan2(xx - yy));
+        let (half_sum, half_gap) = ((xx + yy) / 2., (((xx - yy) / 2.).powi(2) + xy * xy).sqrt());
+        let direction = Vec2::new(mean(&|c| c.direction.x), mean(&|c| c.direction.y)).normalize_or_zero();
+        let angular_spread = mean(&|c| cross2(direction, c.direction).atan2(direction.dot(c.direction)).powi(2)).sqrt();
+        Some(Self {
+            power,
+            centroid,
+            rms_radius: (xx + yy).sqrt(),
+            axes: [major, major.perp()],
+            extents: [(half_sum + half_gap).sqrt(), (half_sum - half_gap).max(0.).sqrt()],
+            direction,
+            angular_spread
+        })
+    }
+}
@@ -0,0 +1,204 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    config::Config, locale::Locale, ui::{self, UiFont}, units::{Nanometers, Quantity}, BeamSource, RaySegment, RayTree, Scale
+};
+
+// Sources closer than this (mm, and in direction) form one multi-wavelength bundle
+const BUNDLE_TOLERANCE: f32 = 1e-3;
+// Below this, the final legs are too close to parallel to have a focus
+const MIN_CONVERGENCE: f32 = 1e-6;
+// Half size (mm) of the focus markers
+const MARKER_SIZE: f32 = 0.3;
+
+type Annotations = Or<(With<ChromaticPanel>, With<FocusMarker>)>;
+
+/// Where one wavelength of a bundle comes to focus, relative to the bundle's reference wavelength.
+#[derive(Clone, Debug)]
+pub struct FocalShift {
+    pub wavelength: Nanometers,
+    pub focus: Vec2,
+    /// Shift (mm) along the reference wavelength's final direction
+    pub longitudinal: f32,
+    /// Shift (mm) across it
+    pub lateral: f32
+}
+
+/// Focal shifts of every source bundle that's traced at more than one wavelength. C toggles
+/// the report.
+#[derive(Resource, Default)]
+pub struct ChromaticReport {
+    pub enabled: bool,
+    pub bundles: Vec<(Nanometers, Vec<FocalShift>)>
+}
+
+#[derive(Component)]
+pub struct ChromaticPanel;
+
+#[derive(Component)]
+pub struct FocusMarker;
+
+/// Least-squares meeting point of lines through `a` along unit `d`, if they converge.
+pub fn focus(legs: &[(Vec2, Vec2)]) -> Option<Vec2> {
+    let mut m = Mat2::ZERO;
+    let mut b = Vec2::ZERO;
+    for (a, d) in legs {
+        let projector = Mat2::IDENTITY - Mat2::from_cols(*d * d.x, *d * d.y);
+        m += projector;
+        b += projector * *a;
+    }
+    if legs.len() < 2 || m.determinant().abs() < MIN_CONVERGENCE {
+        return None
+    }
+    Some(m.inverse() * b)
+}
+
+/// Last leg of a ray tree's own path: the deepest of its segments that isn't a ghost, and the
+/// brightest of those where a beamsplitter leaves several. Trees are traced depth first, and a
+/// beamsplitter's reflection after the light it lets through, so the tree's last segment may
+/// be on a side branch.
+pub fn final_leg<'a>(segments: impl Iterator<Item = &'a RaySegment>) -> Option<&'a RaySegment> {
+    segments.filter(|segment| !segment.ghost)
+        .max_by(|a, b| a.depth.cmp(&b.depth).then(a.intensity.total_cmp(&b.intensity)))
+}
+
+/// Focal shifts per wavelength for a bundle's final legs, grouped by wavelength. The median
+/// wavelength is the reference.
+pub fn focal_shifts(legs: &[(Nanometers, Vec2, Vec2)]) -> Option<(Nanometers, Vec<FocalShift>)> {
+    let mut wavelengths: Vec<Nanometers> = legs.iter().map(|(w, _, _)| *w).collect();
+    wavelengths.sort_by(|a, b| a.0.total_cmp(&b.0));
+    wavelengths.dedup();
+    if wavelengths.len() < 2 {
+        return None
+    }
+    let foci: Vec<(Nanometers, Vec2, Vec2)> = wavelengths.iter().filter_map(|w| {
+        let lines: Vec<(Vec2, Vec2)> = legs.iter()
+            .filter(|(lw, _, _)| lw == w)
+            .map(|(_, a, d)| (*a, *d))
+            .collect();
+        let axis = lines.iter().map(|(_, d)| *d).sum::<Vec2>().normalize_or_zero();
+        focus(&lines).map(|point| (*w, point, axis))
+    }).collect();
+    let (reference, reference_focus, axis) = *foci.get(foci.len() / 2)?;
+    let shifts = foci.iter().map(|(w, point, _)| {
+        let shift = *point - reference_focus;
+        FocalShift {
+            wavelength: *w,
+            focus: *point,
+            longitudinal: shift.dot(axis),
+            lateral: shift.dot(axis.perp())
+        }
+    }).collect();
+    Some((reference, shifts))
+}
+
+pub fn chromatic_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut report: ResMut<ChromaticReport>
+) {
+    // Ctrl+C copies surfaces between tabs
+    if keys.just_pressed(KeyCode::C) && !keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        report.enabled = !report.enabled;
+    }
+}
+
+/// Recomputes the report from the last leg of every ray tree whenever rays are retraced.
+pub fn chromatic_report_system(
+    mut report: ResMut<ChromaticReport>,
+    changed: Query<(), Changed<RaySegment>>,
+    source_query: Query<&BeamSource>,
+    tree_query: Query<(&RayTree, &Children)>,
+    segment_query: Query<&RaySegment>
+) {
+    if !report.enabled || (!report.is_changed() && changed.is_empty()) {
+        return
+    }
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    // first source at the same place and heading stands for the bundle
+    let bundle_of = |source: &BeamSource| sources.iter().position(|other| {
+        other.pos.distance(source.pos) < BUNDLE_TOLERANCE
+            && other.direction.normalize_or_zero().distance(source.direction.normalize_or_zero()) < BUNDLE_TOLERANCE
+    });
+    let emitted_by = |tree: &RayTree| sources.iter().copied().find(|source| source.pupil(&tree.root).is_some());
+    let mut legs: Vec<Vec<(Nanometers, Vec2, Vec2)>> = vec![Vec::new(); sources.len()];
+    for (tree, children) in tree_query.iter() {
+        let Some(bundle) = emitted_by(tree).and_then(bundle_of) else {
+            continue
+        };
+        let Some(last) = final_leg(segment_query.iter_many(children)) else {
+            continue
+        };
+        legs[bundle].push((last.wavelength, last.from, (last.to - last.from).normalize_or_zero()));
+    }
+    report.bundles = legs.iter().filter_map(|legs| focal_shifts(legs)).collect();
+}
+
+pub fn chromatic_panel_system(
+    mut commands: Commands,
+    (config, scale): (Res<Config>, Res<Scale>),
+    report: Res<ChromaticReport>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    annotation_query: Query<Entity, Annotations>
+) {
+    if !report.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for entity in annotation_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !report.enabled {
+        return
+    }
+    for (_, shifts) in report.bundles.iter() {
+        for shift in shifts {
+            let mut path_builder = PathBuilder::new();
+            for (from, to) in [(Vec2::new(-1., -1.), Vec2::new(1., 1.)), (Vec2::new(-1., 1.), Vec2::new(1., -1.))] {
+                path_builder.move_to(shift.focus + from * MARKER_SIZE);
+                path_builder.line_to(shift.focus + to * MARKER_SIZE);
+            }
+            commands.spawn((GeometryBuilder::build_as(
+                &path_builder.build(),
+                DrawMode::Stroke(StrokeMode::new(config.palette.color(shift.wavelength), scale.px(2.))),
+                Transform::from_xyz(0., 0., 2.)
+            ), FocusMarker));
+        }
+    }
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.),
+                top: Val::Percent(40.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }).insert(ChromaticPanel).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("chromatic.title"), ui::text_style(&font, ui::TEXT_COLOR)));
+        if report.bundles.is_empty() {
+            panel.spawn(TextBundle::from_section(locale.text("chromatic.none"), ui::text_style(&font, ui::TEXT_COLOR)));
+        }
+        for (reference, shifts) in report.bundles.iter() {
+            panel.spawn(TextBundle::from_section(
+                locale.format("chromatic.reference", &[("w", &config.units.show(Quantity::Wavelength, reference.0))]),
+                ui::text_style(&font, ui::TEXT_COLOR)
+            ));
+            for shift in shifts {
+                panel.spawn(TextBundle::from_section(
+                    locale.format("chromatic.shift", &[
+                        ("w", &config.units.show(Quantity::Wavelength, shift.wavelength.0)),
+                        ("longitudinal", &format!("{:+}", config.units.show(Quantity::SmallLength, shift.longitudinal))),
+                        ("lateral", &format!("{:+}", config.units.show(Quantity::SmallLength, shift.lateral)))
+                    ]),
+                    ui::text_style(&font, config.palette.color(shift.wavelength))
+                ));
+            }
+        }
+    });
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{complex::Complex, units::Nanometers};
+
+/// Metal mirror coatings, described by their tabulated complex refractive index n + ik.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Coating {
+    Aluminium,
+    Silver,
+    Gold
+}
+
+// (wavelength nm, n, k)
+const ALUMINIUM: [(f32, f32, f32); 7] = [
+    (400., 0.49, 4.86),
+    (500., 0.77, 6.08),
+    (600., 1.20, 7.26),
+    (700., 1.83, 8.31),
+    (800., 2.80, 8.45),
+    (900., 2.06, 8.30),
+    (1000., 1.35, 9.58)
+];
+
+const SILVER: [(f32, f32, f32); 7] = [
+    (400., 0.05, 2.10),
+    (500., 0.05, 3.13),
+    (600., 0.06, 4.15),
+    (700., 0.04, 4.84),
+    (800., 0.04, 5.40),
+    (900., 0.04, 6.20),
+    (1000., 0.04, 6.99)
+];
+
+const GOLD: [(f32, f32, f32); 7] = [
+    (400., 1.47, 1.95),
+    (500., 0.97, 1.87),
+    (600., 0.25, 2.98),
+    (700., 0.16, 3.95),
+    (800., 0.15, 4.91),
+    (900., 0.19, 5.85),
+    (1000., 0.26, 6.82)
+];
+
+impl Coating {
+    fn table(&self) -> &'static [(f32, f32, f32)] {
+        match self {
+            Coating::Aluminium => &ALUMINIUM,
+            Coating::Silver => &SILVER,
+            Coating::Gold => &GOLD
+        }
+    }
+
+    /// Complex index at wavelength `w`, linearly interpolated and clamped to the table range.
+    pub fn index(&self, w: Nanometers) -> Complex {
+        let w = w.0;
+        let table = self.table();
+        let (first, last) = (table[0], table[table.len() - 1]);
+        if w <= first.0 {
+            return Complex::new(first.1, first.2)
+        }
+        if w >= last.0 {
+            return Complex::new(last.1, last.2)
+        }
+        let k = table.iter().position(|row| row.0 > w).unwrap();
+        let (a, b) = (table[k - 1], table[k]);
+        let t = (w - a.0) / (b.0 - a.0);
+        Complex::new(a.1 + t * (b.1 - a.1), a.2 + t * (b.2 - a.2))
+    }
+
+    /// Complex s and p amplitude reflection coefficients for light at wavelength `w`
+    /// arriving from a medium of index `n1` with the given cosine of incidence.
+    pub fn fresnel(&self, w: Nanometers, n1: f32, cos_i: f32) -> (Complex, Complex) {
+        let n1 = Complex::from(n1);
+        let n2 = self.index(w);
+        let cos_i = Complex::from(cos_i.abs().min(1.));
+        let sin2_i = Complex::ONE - cos_i * cos_i;
+        let ratio = n1 / n2;
+        let cos_t = (Complex::ONE - ratio * ratio * sin2_i).sqrt();
+        let rs = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+        let rp = (n2 * cos_i - n1 * cos_t) / (n2 * cos_i + n1 * cos_t);
+        (rs, rp)
+    }
+
+    /// s and p power reflectances.
+    pub fn reflectance(&self, w: Nanometers, n1: f32, cos_i: f32) -> (f32, f32) {
+        let (rs, rp) = self.fresnel(w, n1, cos_i);
+        (rs.norm_sqr(), rp.norm_sqr())
+    }
+
+    /// s and p phase shifts on reflection, in radians.
+    pub fn phase(&self, w: Nanometers, n1: f32, cos_i: f32) -> (f32, f32) {
+        let (rs, rp) = self.fresnel(w, n1, cos_i);
+        (rs.arg(), rp.arg())
+    }
+}
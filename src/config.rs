@@ -0,0 +1,384 @@
+use std::{env, fs, path::PathBuf};
+
+use bevy::{prelude::*, window::PresentMode, winit::WinitSettings};
+use bevy_prototype_lyon::prelude::DrawMode;
+use serde::{Deserialize, Serialize};
+
+use crate::{locale::{self, Locale}, palette::Palette, snapping, ui::{self, UiFont}, units::UnitSystem, GridLine, TraceLimits, MAX_DEPTH, MIN_INTENSITY, PX_PER_MM, RAY_DENSITY};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Dark,
+    Light
+}
+
+/// How the GPU fluence views (F6, Shift+F6) color what they accumulate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FluenceMap {
+    /// Each ray's own color, so wavelengths stay apart
+    #[default]
+    Rays,
+    /// Power alone, through the inferno colormap, for reading off focal spots and caustics
+    Inferno
+}
+
+impl Theme {
+    pub fn background(&self) -> Color {
+        match self {
+            Theme::Dark => Color::BLACK,
+            Theme::Light => Color::rgb(0.95, 0.95, 0.92)
+        }
+    }
+
+    pub fn grid(&self) -> Color {
+        match self {
+            Theme::Dark => Color::rgb(0.5, 0.5, 0.5),
+            Theme::Light => Color::rgb(0.75, 0.75, 0.75)
+        }
+    }
+
+    pub fn surface(&self) -> Color {
+        match self {
+            Theme::Dark => Color::WHITE,
+            Theme::Light => Color::rgb(0.1, 0.1, 0.1)
+        }
+    }
+}
+
+/// User settings, read from `config.toml` in the platform config directory at startup.
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub window_width: f32,
+    pub window_height: f32,
+    pub vsync: bool,
+    /// Only update and redraw on input or scene changes
+    pub power_saving: bool,
+    /// Pixels a millimeter covers at the default zoom, and the resolution of the GPU views.
+    /// Read at startup.
+    pub px_per_mm: f32,
+    /// Rays per mm of beam waist
+    pub ray_density: f32,
+    /// Add rays where evenly spaced ones leave lens edges and caustics unresolved, see
+    /// `BeamSource::adaptive_rays`
+    pub adaptive_rays: bool,
+    /// Interactions a ray is followed through before its children are dropped
+    pub max_depth: usize,
+    /// Intensity below which a child ray is dropped
+    pub min_intensity: f32,
+    /// Grid (mm) that endpoints and sources snap to while dragging with Alt
+    pub snap_grid: f32,
+    /// Angle (degrees) that turns snap to while dragging with Alt
+    pub snap_angle: f32,
+    pub theme: Theme,
+    pub palette: Palette,
+    pub fluence_map: FluenceMap,
+    /// Units of quantities in panels and exports
+    pub units: UnitSystem,
+    /// UI language, see `assets/locales`
+    pub language: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_scene: Option<PathBuf>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            window_width: 1080.,
+            window_height: 920.,
+            vsync: false,
+            power_saving: false,
+            px_per_mm: PX_PER_MM,
+            ray_density: RAY_DENSITY,
+            adaptive_rays: false,
+            max_depth: MAX_DEPTH,
+            min_intensity: MIN_INTENSITY,
+            snap_grid: 1.,
+            snap_angle: 15.,
+            theme: Theme::Dark,
+            palette: Palette::Spectral,
+            fluence_map: FluenceMap::Rays,
+            units: UnitSystem::Lab,
+            language: "en".to_string(),
+            last_scene: None
+        }
+    }
+}
+
+impl Config {
+    pub fn limits(&self) -> TraceLimits {
+        TraceLimits {
+            max_depth: self.max_depth,
+            min_intensity: self.min_intensity,
+            ghosts: true
+        }
+    }
+
+    /// `$BEAMS_CONFIG`, or `beams/config.toml` under the platform's config directory.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("BEAMS_CONFIG") {
+            return Some(PathBuf::from(path))
+        }
+        let dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(dir.join("beams").join("config.toml"))
+    }
+
+    /// Loads the config file, falling back to defaults if it is missing or invalid.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default()
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|err| {
+                eprintln!("ignoring invalid config {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default()
+        }
+    }
+
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let path = Self::path().ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let text = toml::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&path, text).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    pub fn winit_settings(&self) -> WinitSettings {
+        if self.power_saving {
+            WinitSettings::desktop_app()
+        } else {
+            WinitSettings::game()
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct SettingsPanel;
+
+#[derive(Component, Clone, Copy)]
+pub enum SettingsButton {
+    Vsync,
+    PowerSaving,
+    Theme,
+    Palette,
+    FluenceMap,
+    Units,
+    Language,
+    DensityDown,
+    DensityUp,
+    Adaptive,
+    DepthDown,
+    DepthUp,
+    SnapGrid,
+    SnapAngle,
+    Save
+}
+
+/// Applies config changes that don't need a restart.
+pub fn apply_config_system(
+    config: Res<Config>,
+    mut clear_color: ResMut<ClearColor>,
+    mut windows: ResMut<Windows>,
+    mut winit: ResMut<WinitSettings>,
+    mut grid_query: Query<&mut DrawMode, With<GridLine>>
+) {
+    if !config.is_changed() {
+        return
+    }
+    clear_color.0 = config.theme.background();
+    *winit = config.winit_settings();
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_present_mode(config.present_mode());
+    }
+    for mut draw_mode in grid_query.iter_mut() {
+        if let DrawMode::Stroke(stroke) = draw_mode.as_mut() {
+            stroke.color = config.theme.grid();
+        }
+    }
+}
+
+/// F2 opens and closes the settings dialog.
+pub fn settings_toggle_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    panel_query: Query<Entity, With<SettingsPanel>>
+) {
+    if keys.just_pressed(KeyCode::F2) {
+        match panel_query.get_single() {
+            Ok(panel) => commands.entity(panel).despawn_recursive(),
+            Err(_) => {
+                commands.spawn((NodeBundle::default(), SettingsPanel));
+            }
+        }
+    }
+}
+
+/// Rebuilds the settings dialog contents when it opens or the config changes.
+pub fn settings_panel_system(
+    mut commands: Commands,
+    config: Res<Config>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<(Entity, Option<&Children>), With<SettingsPanel>>,
+    added: Query<(), Added<SettingsPanel>>
+) {
+    let Ok((panel, children)) = panel_query.get_single() else {
+        return
+    };
+    if !config.is_changed() && !locale.is_changed() && added.is_empty() {
+        return
+    }
+    if let Some(children) = children {
+        for child in children.iter() {
+            commands.entity(*child).despawn_recursive();
+        }
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    let state = |on: bool| locale.text(if on { "common.on" } else { "common.off" });
+    let toggle = locale.text("common.toggle");
+    let theme = locale.text(match config.theme {
+        Theme::Dark => "theme.dark",
+        Theme::Light => "theme.light"
+    });
+    let palette = locale.text(match config.palette {
+        Palette::Standard => "palette.standard",
+        Palette::Colorblind => "palette.colorblind",
+        Palette::Spectral => "palette.spectral"
+    });
+    let fluence_map = locale.text(match config.fluence_map {
+        FluenceMap::Rays => "fluence-map.rays",
+        FluenceMap::Inferno => "fluence-map.inferno"
+    });
+    let units = locale.text(match config.units {
+        UnitSystem::Lab => "units.lab",
+        UnitSystem::Si => "units.si"
+    });
+    let rows = [
+        (locale.format("settings.vsync", &[("state", &state(config.vsync))]), vec![(toggle, SettingsButton::Vsync)]),
+        (locale.format("settings.power-saving", &[("state", &state(config.power_saving))]), vec![(toggle, SettingsButton::PowerSaving)]),
+        (locale.format("settings.theme", &[("theme", &theme)]), vec![(toggle, SettingsButton::Theme)]),
+        (locale.format("settings.palette", &[("palette", &palette)]), vec![(toggle, SettingsButton::Palette)]),
+        (locale.format("settings.fluence-map", &[("map", &fluence_map)]), vec![(toggle, SettingsButton::FluenceMap)]),
+        (locale.format("settings.units", &[("units", &units)]), vec![(toggle, SettingsButton::Units)]),
+        (locale.format("settings.language", &[("language", &config.language)]), vec![(toggle, SettingsButton::Language)]),
+        (locale.format("settings.density", &[("density", &config.ray_density)]), vec![("-", SettingsButton::DensityDown), ("+", SettingsButton::DensityUp)]),
+        (locale.format("settings.adaptive", &[("state", &state(config.adaptive_rays))]), vec![(toggle, SettingsButton::Adaptive)]),
+        (locale.format("settings.depth", &[("depth", &config.max_depth)]), vec![("-", SettingsButton::DepthDown), ("+", SettingsButton::DepthUp)]),
+        (locale.format("settings.snap-grid", &[("step", &config.snap_grid)]), vec![(toggle, SettingsButton::SnapGrid)]),
+        (locale.format("settings.snap-angle", &[("step", &config.snap_angle)]), vec![(toggle, SettingsButton::SnapAngle)]),
+        (locale.format("settings.window", &[("width", &config.window_width), ("height", &config.window_height)]), vec![]),
+        (String::new(), vec![(locale.text("common.save"), SettingsButton::Save)])
+    ];
+    commands.entity(panel).insert(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.),
+                top: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("settings.title"), style.clone()));
+        for (label, buttons) in rows {
+            panel.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                row.spawn(TextBundle::from_section(label, style.clone()));
+                for (text, button) in buttons {
+                    row.spawn(ButtonBundle {
+                        style: Style {
+                            margin: UiRect::left(Val::Px(8.)),
+                            padding: UiRect::horizontal(Val::Px(4.)),
+                            ..default()
+                        },
+                        background_color: Color::rgb(0.25, 0.25, 0.25).into(),
+                        ..default()
+                    }).insert(button).with_children(|button| {
+                        button.spawn(TextBundle::from_section(text, style.clone()));
+                    });
+                }
+            });
+        }
+    });
+}
+
+pub fn settings_button_system(
+    mut config: ResMut<Config>,
+    windows: Res<Windows>,
+    button_query: Query<(&Interaction, &SettingsButton), Changed<Interaction>>
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue
+        }
+        match button {
+            SettingsButton::Vsync => config.vsync = !config.vsync,
+            SettingsButton::PowerSaving => config.power_saving = !config.power_saving,
+            SettingsButton::Theme => config.theme = match config.theme {
+                Theme::Dark => Theme::Light,
+                Theme::Light => Theme::Dark
+            },
+            SettingsButton::Palette => config.palette = match config.palette {
+                Palette::Standard => Palette::Colorblind,
+                Palette::Colorblind => Palette::Spectral,
+                Palette::Spectral => Palette::Standard
+            },
+            SettingsButton::FluenceMap => config.fluence_map = match config.fluence_map {
+                FluenceMap::Rays => FluenceMap::Inferno,
+                FluenceMap::Inferno => FluenceMap::Rays
+            },
+            SettingsButton::Units => config.units = match config.units {
+                UnitSystem::Lab => UnitSystem::Si,
+                UnitSystem::Si => UnitSystem::Lab
+            },
+            SettingsButton::Language => {
+                let languages: Vec<&str> = locale::languages().collect();
+                let next = languages.iter().position(|language| *language == config.language).map_or(0, |k| k + 1);
+                config.language = languages[next % languages.len()].to_string();
+            }
+            SettingsButton::DensityDown => config.ray_density = (config.ray_density / 2.).max(0.25),
+            SettingsButton::DensityUp => config.ray_density = (config.ray_density * 2.).min(256.),
+            SettingsButton::Adaptive => config.adaptive_rays = !config.adaptive_rays,
+            SettingsButton::DepthDown => config.max_depth = config.max_depth.saturating_sub(4).max(1),
+            SettingsButton::DepthUp => config.max_depth = (config.max_depth + 4).min(256),
+            SettingsButton::SnapGrid => config.snap_grid = snapping::next_step(&snapping::GRID_STEPS, config.snap_grid),
+            SettingsButton::SnapAngle => config.snap_angle = snapping::next_step(&snapping::ANGLE_STEPS, config.snap_angle),
+            SettingsButton::Save => {
+                if let Some(window) = windows.get_primary() {
+                    config.window_width = window.width();
+                    config.window_height = window.height();
+                }
+                match config.save() {
+                    Ok(path) => info!("saved settings to {}", path.display()),
+                    Err(err) => error!("could not save settings: {}", err)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+
+use crate::Surface;
+
+const MAX_SUBDIVISION: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct CubicBezier {
+    pub p0: Vec2,
+    pub p1: Vec2,
+    pub p2: Vec2,
+    pub p3: Vec2
+}
+
+impl CubicBezier {
+    pub fn new(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2) -> Self {
+        Self { p0, p1, p2, p3 }
+    }
+
+    /// Splits the curve at t = 0.5 (de Casteljau).
+    pub fn split(&self) -> (Self, Self) {
+        let p01 = (self.p0 + self.p1) / 2.;
+        let p12 = (self.p1 + self.p2) / 2.;
+        let p23 = (self.p2 + self.p3) / 2.;
+        let p012 = (p01 + p12) / 2.;
+        let p123 = (p12 + p23) / 2.;
+        let mid = (p012 + p123) / 2.;
+        (Self::new(self.p0, p01, p012, mid), Self::new(mid, p123, p23, self.p3))
+    }
+
+    /// Largest distance of the control points from the chord p0-p3.
+    fn flatness(&self) -> f32 {
+        let chord = self.p3 - self.p0;
+        let length = chord.length();
+        if length < f32::EPSILON {
+            return (self.p1 - self.p0).length().max((self.p2 - self.p0).length())
+        }
+        let distance = |p: Vec2| chord.perp_dot(p - self.p0).abs() / length;
+        distance(self.p1).max(distance(self.p2))
+    }
+
+    /// Polyline approximating the curve to within `tolerance`, subdividing only where it bends.
+    pub fn flatten(&self, tolerance: f32) -> Vec<Vec2> {
+        let mut points = vec![self.p0];
+        self.flatten_into(tolerance, 0, &mut points);
+        points
+    }
+
+    fn flatten_into(&self, tolerance: f32, depth: usize, points: &mut Vec<Vec2>) {
+        if depth >= MAX_SUBDIVISION || self.flatness() <= tolerance {
+            points.push(self.p3);
+        } else {
+            let (a, b) = self.split();
+            a.flatten_into(tolerance, depth + 1, points);
+            b.flatten_into(tolerance, depth + 1, points);
+        }
+    }
+}
+
+/// One surface per polyline edge, built by `surface` from the edge endpoints, e.g.
+/// `polyline(&points, |p1, p2| Surface::glass(p1, p2).index(1.52))`.
+pub fn polyline(points: &[Vec2], surface: impl Fn(Vec2, Vec2) -> Surface) -> Vec<Surface> {
+    points.windows(2)
+        .filter(|edge| edge[0] != edge[1])
+        .map(|edge| surface(edge[0], edge[1]))
+        .collect()
+}
+
+/// Surfaces along a cubic Bézier curve, flattened to within `tolerance` (mm).
+pub fn bezier(curve: &CubicBezier, tolerance: f32, surface: impl Fn(Vec2, Vec2) -> Surface) -> Vec<Surface> {
+    polyline(&curve.flatten(tolerance), surface)
+}
@@ -0,0 +1,514 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+
+use crate::{
+    animation::{Clock, Track}, config::Config, links::Param, locale::Locale, scene_tree::Selected, trace,
+    ui::{self, UiFont}, units::{Nanometers, Quantity}, viewports::{self, Analysis}, BeamSource, RaySegment, Surface
+};
+
+// Half the swing (degrees) of a galvo scan added with G
+const GALVO_AMPLITUDE: f32 = 5.;
+// Seconds per galvo scan cycle
+const GALVO_PERIOD: f32 = 4.;
+// Recording length (s) when no scanner sets the cycle
+const DEFAULT_CYCLE: f32 = 4.;
+const SERIES_COLOR: Color = Color::rgb(0.5, 0.9, 0.5);
+// Bins of the intensity profile across a detector
+const PROFILE_BINS: usize = 32;
+const PROFILE_COLOR: Color = Color::rgb(0.9, 0.7, 0.4);
+// Wavelengths (nm) the spectrometer reads, and its bins across them
+const SPECTRUM_RANGE: (f32, f32) = (350., 1100.);
+const SPECTRUM_BINS: usize = 75;
+const SPECTRUM_COLOR: Color = Color::rgb(0.6, 0.7, 1.0);
+
+type SelectedSurface<'a> = (Entity, &'a Surface, Option<&'a Detector>, Option<&'a Track>);
+type ProfileChanged = Or<(Changed<RaySegment>, Added<Selected>, Added<Detector>)>;
+type SelectedDetector = (With<Selected>, With<Detector>);
+
+/// Surface recording the power that reaches it over time, like a photodiode or PMT.
+#[derive(Component)]
+pub struct Detector;
+
+/// Power one detector received during a recording.
+#[derive(Clone, Debug)]
+pub struct Series {
+    pub name: String,
+    pub detector: Entity,
+    /// (seconds since the recording started, scanner value, power)
+    pub samples: Vec<(f32, f32, f32)>
+}
+
+/// What scanned during a recording, for plotting the acquired image line against it.
+#[derive(Clone, Copy, Debug)]
+pub struct Scan {
+    pub param: Param,
+    pub from: f32,
+    pub to: f32
+}
+
+/// Detector time series over one scan cycle. T records one cycle of the slowest animation
+/// track while the clock plays, tracing every source at each frame; Shift+T makes the
+/// selected surfaces detectors and G scans the selected surfaces like galvo mirrors.
+#[derive(Resource, Default)]
+pub struct Recording {
+    pub enabled: bool,
+    /// Clock time the recording started, while it runs
+    pub start: Option<f32>,
+    pub cycle: f32,
+    pub scan: Option<Scan>,
+    pub series: Vec<Series>
+}
+
+#[derive(Component)]
+pub struct RecordingPanel;
+
+/// Power that the traced rays bring onto the selected detector, binned across it from `p1`
+/// to `p2`. It updates whenever the rays are retraced.
+#[derive(Resource, Default)]
+pub struct DetectorProfile {
+    pub detector: Option<Entity>,
+    pub name: String,
+    /// Length (mm) of the detector
+    pub width: f32,
+    /// Power per bin, from `p1`
+    pub bins: Vec<f32>,
+    /// Power-weighted mean position (mm) of the hits from the middle of the detector
+    pub centroid: f32,
+    /// RMS width (mm) of the hits about the centroid
+    pub rms: f32,
+    /// Power per bin of wavelength across the spectrometer's range, from the shortest
+    pub spectrum: Vec<f32>
+}
+
+#[derive(Component)]
+pub struct ProfilePanel;
+
+/// Bins `hits`, as (point, power), by where they fall along `surface` from `p1` to `p2`.
+/// Hits beyond the ends go into the end bins.
+pub fn profile(surface: &Surface, hits: impl Iterator<Item = (Vec2, f32)>, bins: usize) -> Vec<f32> {
+    let mut profile = vec![0.; bins];
+    let dp = surface.dp();
+    for (point, power) in hits {
+        let t = (point - surface.p1).dot(dp) / dp.length_squared().max(f32::EPSILON);
+        let bin = ((t * bins as f32) as usize).min(bins.saturating_sub(1));
+        if let Some(total) = profile.get_mut(bin) {
+            *total += power;
+        }
+    }
+    profile
+}
+
+/// Bins `hits`, as (wavelength, power), over the spectrometer's range. Wavelengths outside it
+/// go into the end bins.
+pub fn spectrum(hits: impl Iterator<Item = (Nanometers, f32)>, bins: usize) -> Vec<f32> {
+    let (from, to) = SPECTRUM_RANGE;
+    let mut spectrum = vec![0.; bins];
+    for (w, power) in hits {
+        let bin = (((w.0 - from) / (to - from)).max(0.) * bins as f32) as usize;
+        if let Some(total) = spectrum.get_mut(bin.min(bins.saturating_sub(1))) {
+            *total += power;
+        }
+    }
+    spectrum
+}
+
+/// Plotted points of a spectrum, at the middle of each bin.
+fn spectrum_points(spectrum: &[f32]) -> Vec<Vec2> {
+    let (from, to) = SPECTRUM_RANGE;
+    let bin_width = (to - from) / spectrum.len() as f32;
+    spectrum.iter()
+        .enumerate()
+        .map(|(k, power)| Vec2::new(from + (k as f32 + 0.5) * bin_width, *power))
+        .collect()
+}
+
+/// T starts a recording or closes it, Shift+T toggles detectors and G toggles galvo scans on the selection.
+pub fn detector_input_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut clock: ResMut<Clock>,
+    mut recording: ResMut<Recording>,
+    selected_query: Query<SelectedSurface, With<Selected>>,
+    detector_query: Query<(Entity, &Surface), With<Detector>>,
+    track_query: Query<&Track>
+) {
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if keys.just_pressed(KeyCode::G) {
+        for (entity, surface, _, track) in selected_query.iter() {
+            match (track, Param::Angle.get(surface)) {
+                (Some(_), _) => {
+                    commands.entity(entity).remove::<Track>();
+                }
+                (None, Some(angle)) => {
+                    commands.entity(entity).insert(Track {
+                        param: Param::Angle,
+                        from: angle - GALVO_AMPLITUDE,
+                        to: angle + GALVO_AMPLITUDE,
+                        period: GALVO_PERIOD
+                    });
+                }
+                (None, None) => {}
+            }
+        }
+    }
+    if !keys.just_pressed(KeyCode::T) {
+        return
+    }
+    if shift {
+        for (entity, _, detector, _) in selected_query.iter() {
+            if detector.is_some() {
+                commands.entity(entity).remove::<Detector>();
+            } else {
+                commands.entity(entity).insert(Detector);
+            }
+        }
+        return
+    }
+    if recording.enabled {
+        recording.enabled = false;
+        recording.start = None;
+        return
+    }
+    let slowest = track_query.iter().max_by(|a, b| a.period.total_cmp(&b.period));
+    recording.enabled = true;
+    recording.cycle = slowest.map_or(DEFAULT_CYCLE, |track| track.period);
+    recording.scan = slowest.map(|track| Scan {
+        param: track.param,
+        from: track.from,
+        to: track.to
+    });
+    recording.series = detector_query.iter().map(|(entity, surface)| Series {
+        name: surface.label.clone().unwrap_or_else(|| surface.kind().to_string()),
+        detector: entity,
+        samples: Vec::new()
+    }).collect();
+    if !recording.series.is_empty() {
+        recording.start = Some(clock.time);
+        clock.playing = true;
+    }
+}
+
+/// Adds a sample to every series each frame the clock advances during a recording.
+pub fn recording_system(
+    clock: Res<Clock>,
+    config: Res<Config>,
+    mut recording: ResMut<Recording>,
+    source_query: Query<&BeamSource>,
+    surface_query: Query<(Entity, &Surface)>,
+    track_query: Query<&Track>
+) {
+    let Some(start) = recording.start else {
+        return
+    };
+    let t = clock.time - start;
+    if t >= recording.cycle {
+        recording.start = None;
+        return
+    }
+    if recording.series.first().and_then(|series| series.samples.last()).is_some_and(|(last, _, _)| *last >= t) {
+        return
+    }
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    let segments: Vec<_> = source_query.iter()
+        .flat_map(|source| source.rays(config.ray_density))
+        .flat_map(|ray| trace(&ray, &surfaces, |_| None).segments)
+        .collect();
+    let scan = track_query.iter().max_by(|a, b| a.period.total_cmp(&b.period)).map_or(t, |track| track.at(clock.time));
+    for series in recording.series.iter_mut() {
+        let power: f32 = segments.iter()
+            .filter(|segment| segment.surface == series.detector)
+            .map(|segment| segment.ray.power * segment.ray.i)
+            .sum();
+        series.samples.push((t, scan, power));
+    }
+}
+
+/// Shows the recording in the main window, unless the analysis views are detached.
+pub fn recording_panel_system(
+    mut commands: Commands,
+    recording: Res<Recording>,
+    (config, locale, font): (Res<Config>, Res<Locale>, Res<UiFont>),
+    panel_query: Query<Entity, With<RecordingPanel>>,
+    analysis_query: Analysis,
+    mut detached: Local<bool>
+) {
+    let detaching = viewports::analysis_window(&analysis_query).is_some();
+    if !recording.is_changed() && !config.is_changed() && !locale.is_changed() && detaching == *detached {
+        return
+    }
+    *detached = detaching;
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !recording.enabled || detaching {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(60.),
+                bottom: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, RecordingPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("detectors.title"), style.clone()));
+        if recording.series.is_empty() {
+            panel.spawn(TextBundle::from_section(locale.text("detectors.none"), style.clone()));
+            return
+        }
+        if recording.start.is_some() {
+            panel.spawn(TextBundle::from_section(locale.text("detectors.recording"), style.clone()));
+        }
+        for series in recording.series.iter() {
+            let peak = series.samples.iter().fold(0., |peak: f32, (_, _, power)| peak.max(*power));
+            let power_range = (0., peak.max(f32::EPSILON));
+            let time: Vec<Vec2> = series.samples.iter().map(|(t, _, power)| Vec2::new(*t, *power)).collect();
+            panel.spawn(TextBundle::from_section(
+                locale.format("detectors.series", &[
+                    ("name", &series.name),
+                    ("cycle", &format!("{:.1}", recording.cycle)),
+                    ("peak", &config.units.show(Quantity::Power, peak))
+                ]),
+                style.clone()
+            ));
+            ui::plot(panel, &time, (0., recording.cycle), power_range, SERIES_COLOR);
+            let Some(scan) = recording.scan else {
+                continue
+            };
+            let line: Vec<Vec2> = series.samples.iter().map(|(_, value, power)| Vec2::new(*value, *power)).collect();
+            panel.spawn(TextBundle::from_section(
+                locale.format("detectors.line", &[
+                    ("param", &scan.param),
+                    ("from", &scan.param.show(config.units, scan.from)),
+                    ("to", &scan.param.show(config.units, scan.to))
+                ]),
+                style.clone()
+            ));
+            ui::plot(panel, &line, (scan.from.min(scan.to), scan.from.max(scan.to)), power_range, SERIES_COLOR);
+        }
+        if recording.scan.is_none() {
+            panel.spawn(TextBundle::from_section(locale.text("detectors.no-scan"), style.clone()));
+        }
+    });
+}
+
+/// Bins the rays ending on the selected detector whenever they are retraced or cleared, or
+/// another detector is selected.
+pub fn detector_profile_system(
+    mut report: ResMut<DetectorProfile>,
+    changed: Query<(), ProfileChanged>,
+    (deselected, removed, cleared): (RemovedComponents<Selected>, RemovedComponents<Detector>, RemovedComponents<RaySegment>),
+    selected_query: Query<(Entity, &Surface), SelectedDetector>,
+    segment_query: Query<&RaySegment>
+) {
+    if changed.is_empty() && deselected.iter().next().is_none() && removed.iter().next().is_none() && cleared.iter().next().is_none() {
+        return
+    }
+    let Some((entity, surface)) = selected_query.iter().next() else {
+        if report.detector.is_some() {
+            *report = DetectorProfile::default();
+        }
+        return
+    };
+    let arriving: Vec<&RaySegment> = segment_query.iter()
+        .filter(|segment| segment.surface == entity)
+        .collect();
+    let hits: Vec<(Vec2, f32)> = arriving.iter().map(|segment| (segment.to, segment.power)).collect();
+    let width = surface.length().0;
+    let along = |point: Vec2| (point - (surface.p1 + surface.p2) / 2.).dot(surface.dp().normalize_or_zero());
+    let total: f32 = hits.iter().map(|(_, power)| power).sum();
+    let centroid = hits.iter().map(|(point, power)| along(*point) * power).sum::<f32>() / total.max(f32::EPSILON);
+    let variance = hits.iter().map(|(point, power)| (along(*point) - centroid).powi(2) * power).sum::<f32>() / total.max(f32::EPSILON);
+    *report = DetectorProfile {
+        detector: Some(entity),
+        name: surface.label.clone().unwrap_or_else(|| surface.kind().to_string()),
+        width,
+        bins: profile(surface, hits.into_iter(), PROFILE_BINS),
+        centroid,
+        rms: variance.sqrt(),
+        spectrum: spectrum(arriving.iter().map(|segment| (segment.wavelength, segment.power)), SPECTRUM_BINS)
+    };
+}
+
+/// Plots the profile and spectrum while a detector is selected, in the main window unless the
+/// analysis views are detached.
+pub fn profile_panel_system(
+    mut commands: Commands,
+    report: Res<DetectorProfile>,
+    (config, locale, font): (Res<Config>, Res<Locale>, Res<UiFont>),
+    panel_query: Query<Entity, With<ProfilePanel>>,
+    analysis_query: Analysis,
+    mut detached: Local<bool>
+) {
+    let detaching = viewports::analysis_window(&analysis_query).is_some();
+    if !report.is_changed() && !config.is_changed() && !locale.is_changed() && detaching == *detached {
+        return
+    }
+    *detached = detaching;
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if report.detector.is_none() || detaching {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    let units = config.units;
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(10.),
+                top: Val::Percent(40.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, ProfilePanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.format("detectors.profile", &[("name", &report.name)]), style.clone()));
+        let total: f32 = report.bins.iter().sum();
+        if total <= 0. {
+            panel.spawn(TextBundle::from_section(locale.text("detectors.dark"), style.clone()));
+            return
+        }
+        panel.spawn(TextBundle::from_section(
+            locale.format("detectors.spot", &[
+                ("power", &units.show(Quantity::Power, total)),
+                ("centroid", &units.show(Quantity::Length, report.centroid)),
+                ("rms", &units.show(Quantity::Length, report.rms))
+            ]),
+            style.clone()
+        ));
+        let bin_width = report.width / report.bins.len() as f32;
+        let points: Vec<Vec2> = report.bins.iter()
+            .enumerate()
+            .map(|(k, power)| Vec2::new((k as f32 + 0.5) * bin_width - report.width / 2., *power))
+            .collect();
+        let peak = report.bins.iter().fold(0., |peak: f32, power| peak.max(*power));
+        ui::plot(panel, &points, (-report.width / 2., report.width / 2.), (0., peak), PROFILE_COLOR);
+        panel.spawn(TextBundle::from_section(spectrum_label(&locale), style.clone()));
+        let peak = report.spectrum.iter().fold(0., |peak: f32, power| peak.max(*power));
+        ui::plot(panel, &spectrum_points(&report.spectrum), SPECTRUM_RANGE, (0., peak), SPECTRUM_COLOR);
+    });
+}
+
+fn spectrum_label(locale: &Locale) -> String {
+    locale.format("detectors.spectrum", &[("from", &SPECTRUM_RANGE.0), ("to", &SPECTRUM_RANGE.1)])
+}
+
+/// Draws the recording, the profile and the spectrum of the selected detector in the analysis
+/// window while one is open.
+pub fn detector_view_system(
+    mut egui_context: ResMut<EguiContext>,
+    analysis_query: Analysis,
+    (recording, report): (Res<Recording>, Res<DetectorProfile>),
+    (config, locale): (Res<Config>, Res<Locale>)
+) {
+    let Some(ctx) = viewports::analysis_window(&analysis_query).and_then(|window| egui_context.try_ctx_for_window_mut(window)) else {
+        return
+    };
+    let units = config.units;
+    egui::CentralPanel::default().show(ctx, |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if !recording.enabled && report.detector.is_none() {
+                ui.label(locale.text("views.idle"));
+            }
+            if recording.enabled {
+                ui.heading(locale.text("detectors.title"));
+                if recording.series.is_empty() {
+                    ui.label(locale.text("detectors.none"));
+                }
+                if recording.start.is_some() {
+                    ui.label(locale.text("detectors.recording"));
+                }
+                for series in recording.series.iter() {
+                    let peak = series.samples.iter().fold(0., |peak: f32, (_, _, power)| peak.max(*power));
+                    let power_range = (0., peak.max(f32::EPSILON));
+                    let time: Vec<Vec2> = series.samples.iter().map(|(t, _, power)| Vec2::new(*t, *power)).collect();
+                    ui.label(locale.format("detectors.series", &[
+                        ("name", &series.name),
+                        ("cycle", &format!("{:.1}", recording.cycle)),
+                        ("peak", &units.show(Quantity::Power, peak))
+                    ]));
+                    ui::egui_plot(ui, ("time", series.detector), &time, (0., recording.cycle), power_range, SERIES_COLOR);
+                    let Some(scan) = recording.scan else {
+                        continue
+                    };
+                    let line: Vec<Vec2> = series.samples.iter().map(|(_, value, power)| Vec2::new(*value, *power)).collect();
+                    ui.label(locale.format("detectors.line", &[
+                        ("param", &scan.param),
+                        ("from", &scan.param.show(units, scan.from)),
+                        ("to", &scan.param.show(units, scan.to))
+                    ]));
+                    ui::egui_plot(ui, ("line", series.detector), &line, (scan.from.min(scan.to), scan.from.max(scan.to)), power_range, SERIES_COLOR);
+                }
+                if recording.scan.is_none() && !recording.series.is_empty() {
+                    ui.label(locale.text("detectors.no-scan"));
+                }
+            }
+            if report.detector.is_none() {
+                return
+            }
+            ui.heading(locale.format("detectors.profile", &[("name", &report.name)]));
+            let total: f32 = report.bins.iter().sum();
+            if total <= 0. {
+                ui.label(locale.text("detectors.dark"));
+                return
+            }
+            ui.label(locale.format("detectors.spot", &[
+                ("power", &units.show(Quantity::Power, total)),
+                ("centroid", &units.show(Quantity::Length, report.centroid)),
+                ("rms", &units.show(Quantity::Length, report.rms))
+            ]));
+            let bin_width = report.width / report.bins.len() as f32;
+            let points: Vec<Vec2> = report.bins.iter()
+                .enumerate()
+                .map(|(k, power)| Vec2::new((k as f32 + 0.5) * bin_width - report.width / 2., *power))
+                .collect();
+            let peak = report.bins.iter().fold(0., |peak: f32, power| peak.max(*power));
+            ui::egui_plot(ui, "profile", &points, (-report.width / 2., report.width / 2.), (0., peak), PROFILE_COLOR);
+            ui.label(spectrum_label(&locale));
+            let peak = report.spectrum.iter().fold(0., |peak: f32, power| peak.max(*power));
+            ui::egui_plot(ui, "spectrum", &spectrum_points(&report.spectrum), SPECTRUM_RANGE, (0., peak), SPECTRUM_COLOR);
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_bins_hits_along_the_detector() {
+        let detector = Surface::blocker(Vec2::ZERO, Vec2::new(10., 0.));
+        let hits = [(1., 1.), (5., 2.), (9.9, 3.), (-3., 4.), (15., 5.), (5.5, 0.5)];
+        let bins = profile(&detector, hits.iter().map(|(x, power)| (Vec2::new(*x, 0.), *power)), 5);
+        // hits past the ends count in the end bins
+        assert_eq!(bins, vec![5., 0., 2.5, 0., 8.]);
+        assert_eq!(profile(&detector, std::iter::empty(), 3), vec![0.; 3]);
+    }
+
+    #[test]
+    fn spectrum_bins_hits_by_wavelength() {
+        let hits = [(400., 1.), (633., 2.), (640., 0.5), (200., 4.), (1550., 3.)];
+        let bins = spectrum(hits.iter().map(|(w, power)| (Nanometers(*w), *power)), 75);
+        // 10 nm bins from 350 nm, with wavelengths past the ends in the end bins
+        assert_eq!(bins[5], 1.);
+        assert_eq!(bins[28], 2.);
+        assert_eq!(bins[29], 0.5);
+        assert_eq!(bins[0], 4.);
+        assert_eq!(bins[74], 3.);
+        assert_eq!(bins.iter().sum::<f32>(), 10.5);
+    }
+}
@@ -0,0 +1,323 @@
+use std::fmt::Display;
+
+use bevy::prelude::*;
+
+use crate::{
+    bodies::Body, config::Config, cross2, locale::Locale, nearest_hit, ui::{self, UiFont}, units::{Quantity, UnitSystem},
+    viewports::MainCamera, BeamSource, Extent, Ray, Scale, Surface
+};
+
+// Distance (mm) below which points are considered coincident
+const POINT_TOLERANCE: f32 = 1e-3;
+
+// Zoom applied when jumping to a diagnostic
+const ZOOM_TO_ISSUE: f32 = 4.;
+
+type SceneChanged = Or<(Changed<Surface>, Changed<BeamSource>, Changed<Body>)>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+/// Value filled into a diagnostic message.
+#[derive(Clone, Debug)]
+pub enum Arg {
+    Text(String),
+    /// Shown in the configured units
+    Value(Quantity, f32)
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Locale key of the message, filled in from `args`
+    pub message: &'static str,
+    pub args: Vec<(&'static str, Arg)>,
+    pub location: Vec2
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: &'static str, location: Vec2) -> Self {
+        Self {
+            severity,
+            message,
+            args: vec![("x", Arg::Value(Quantity::Length, location.x)), ("y", Arg::Value(Quantity::Length, location.y))],
+            location
+        }
+    }
+
+    fn arg(mut self, name: &'static str, value: Arg) -> Self {
+        self.args.push((name, value));
+        self
+    }
+
+    pub fn text(&self, locale: &Locale, units: UnitSystem) -> String {
+        let values: Vec<String> = self.args.iter().map(|(_, value)| match value {
+            Arg::Text(text) => text.clone(),
+            Arg::Value(quantity, value) => units.show(*quantity, *value).to_string()
+        }).collect();
+        let args: Vec<(&str, &dyn Display)> = self.args.iter().zip(values.iter())
+            .map(|((name, _), value)| (*name, value as &dyn Display))
+            .collect();
+        locale.format(self.message, &args)
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+#[derive(Component)]
+pub struct DiagnosticsPanel;
+
+#[derive(Component)]
+pub struct DiagnosticButton(pub Vec2);
+
+/// Parameter range covered by `surface` along the line through its own endpoints.
+fn parameter_range(surface: &Surface) -> (f32, f32) {
+    match surface.extent {
+        Extent::Segment => (0., 1.),
+        Extent::Ray => (0., f32::INFINITY),
+        Extent::Line => (f32::NEG_INFINITY, f32::INFINITY)
+    }
+}
+
+/// Length (mm) over which two collinear surfaces lie on top of each other, if they do.
+fn overlap(a: &Surface, b: &Surface) -> Option<f32> {
+    let length = a.length().0;
+    let dp = a.dp();
+    let off_line = |p: Vec2| dp.perp_dot(p - a.p1).abs() / length;
+    if off_line(b.p1) > POINT_TOLERANCE || off_line(b.p2) > POINT_TOLERANCE {
+        return None
+    }
+    let t = |p: Vec2| (p - a.p1).dot(dp) / (length * length);
+    let (t1, t2) = (t(b.p1), t(b.p2));
+    let (b_lo, b_hi) = match b.extent {
+        Extent::Segment => (t1.min(t2), t1.max(t2)),
+        Extent::Ray if t2 > t1 => (t1, f32::INFINITY),
+        Extent::Ray => (f32::NEG_INFINITY, t1),
+        Extent::Line => (f32::NEG_INFINITY, f32::INFINITY)
+    };
+    let (a_lo, a_hi) = parameter_range(a);
+    let shared = (a_hi.min(b_hi) - a_lo.max(b_lo)) * length;
+    (shared > POINT_TOLERANCE).then_some(shared)
+}
+
+/// Where edges `p -> q` and `r -> s` cross, away from their ends.
+fn crossing(p: Vec2, q: Vec2, r: Vec2, s: Vec2) -> Option<Vec2> {
+    let denominator = cross2(q - p, s - r);
+    if denominator.abs() < POINT_TOLERANCE * POINT_TOLERANCE {
+        return None
+    }
+    let t = cross2(r - p, s - r) / denominator;
+    let u = cross2(r - p, q - p) / denominator;
+    let clear = |t: f32, length: f32| t * length > POINT_TOLERANCE && (1. - t) * length > POINT_TOLERANCE;
+    (clear(t, p.distance(q)) && clear(u, r.distance(s))).then(|| p + (q - p) * t)
+}
+
+/// Whether `point` lies inside `polygon`, clear of its edges.
+fn inside(polygon: &[Vec2], point: Vec2) -> bool {
+    let edges = polygon.iter().zip(polygon.iter().cycle().skip(1));
+    let mut odd = false;
+    for (a, b) in edges {
+        let t = ((point - *a).dot(*b - *a) / a.distance_squared(*b)).clamp(0., 1.);
+        if point.distance(*a + (*b - *a) * t) < POINT_TOLERANCE {
+            return false
+        }
+        if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            odd = !odd;
+        }
+    }
+    odd
+}
+
+/// A point where the bodies with outlines `a` and `b` overlap, if they do: where their edges
+/// cross, or a corner or the middle of one inside the other. Bodies that only touch, such as
+/// the two halves of a cemented doublet, don't overlap.
+fn bodies_overlap(a: &[Vec2], b: &[Vec2]) -> Option<Vec2> {
+    let edges = |polygon: &[Vec2]| polygon.iter().copied().zip(polygon.iter().copied().cycle().skip(1)).collect::<Vec<_>>();
+    let b_edges = edges(b);
+    let crossed = edges(a).into_iter()
+        .find_map(|(p, q)| b_edges.iter().find_map(|(r, s)| crossing(p, q, *r, *s)));
+    let middle = |polygon: &[Vec2]| polygon.iter().sum::<Vec2>() / polygon.len() as f32;
+    crossed
+        .or_else(|| a.iter().copied().find(|p| inside(b, *p)))
+        .or_else(|| b.iter().copied().find(|p| inside(a, *p)))
+        // bodies on top of each other share every corner and edge
+        .or_else(|| [middle(a), middle(b)].into_iter().find(|p| inside(a, *p) && inside(b, *p)))
+}
+
+fn is_medium_boundary(surface: &Surface) -> bool {
+    surface.absorption < 1.0 && surface.coating.is_none() && surface.index != 1.0
+}
+
+/// `density` is the number of rays emitted per mm of beam waist.
+pub fn validate(surfaces: &[(Entity, &Surface)], bodies: &[&Body], sources: &[&BeamSource], density: f32) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let degenerate = |surface: &Surface| !surface.p1.is_finite() || !surface.p2.is_finite() || surface.length().0 < POINT_TOLERANCE;
+
+    for (k, (_, surface)) in surfaces.iter().enumerate() {
+        if degenerate(surface) {
+            diagnostics.push(Diagnostic::new(Severity::Error, "diagnostics.degenerate", surface.p1));
+            continue
+        }
+        for (_, other) in surfaces[k + 1..].iter().filter(|(_, other)| !degenerate(other)) {
+            if let Some(shared) = overlap(surface, other) {
+                diagnostics.push(Diagnostic::new(Severity::Warning, "diagnostics.overlap", surface.p1)
+                    .arg("length", Arg::Value(Quantity::Length, shared)));
+            }
+        }
+        if is_medium_boundary(surface) && surface.extent == Extent::Segment {
+            for end in [surface.p1, surface.p2] {
+                let closed = surfaces.iter().enumerate().any(|(j, (_, other))| {
+                    j != k && other.absorption < 1.0 && (other.p1.distance(end) < POINT_TOLERANCE || other.p2.distance(end) < POINT_TOLERANCE)
+                });
+                if !closed {
+                    diagnostics.push(Diagnostic::new(Severity::Warning, "diagnostics.unclosed", end)
+                        .arg("index", Arg::Text(surface.index.to_string())));
+                }
+            }
+        }
+    }
+
+    let outlines: Vec<Vec<Vec2>> = bodies.iter().map(|body| body.outline()).collect();
+    for (k, outline) in outlines.iter().enumerate() {
+        for other in outlines[k + 1..].iter() {
+            if let Some(location) = bodies_overlap(outline, other) {
+                diagnostics.push(Diagnostic::new(Severity::Error, "diagnostics.bodies-overlap", location));
+            }
+        }
+    }
+
+    for source in sources {
+        let location = source.pos;
+        if !source.direction.is_finite() || source.direction.length() < POINT_TOLERANCE {
+            diagnostics.push(Diagnostic::new(Severity::Error, "diagnostics.no-direction", location));
+            continue
+        }
+        if (source.waist.0 * density) as usize == 0 {
+            diagnostics.push(Diagnostic::new(Severity::Warning, "diagnostics.no-rays", location)
+                .arg("waist", Arg::Value(Quantity::Length, source.waist.0)));
+            continue
+        }
+        let ray = Ray::new(source.pos, source.direction.normalize(), source.index);
+        let reaches_optic = match nearest_hit(&ray, surfaces.iter().copied(), None) {
+            Some((_, _, surface)) => surface.absorption < 1.0 || surface.coating.is_some() || surface.fluorescence.is_some(),
+            None => false
+        };
+        if !reaches_optic {
+            diagnostics.push(Diagnostic::new(Severity::Warning, "diagnostics.unreached", location));
+        }
+    }
+    diagnostics
+}
+
+pub fn validation_system(
+    mut diagnostics: ResMut<Diagnostics>,
+    config: Res<Config>,
+    changed: Query<(), SceneChanged>,
+    removed_surfaces: RemovedComponents<Surface>,
+    removed_sources: RemovedComponents<BeamSource>,
+    (surface_query, body_query): (Query<(Entity, &Surface)>, Query<&Body>),
+    source_query: Query<&BeamSource>
+) {
+    if !config.is_changed() && changed.is_empty() && removed_surfaces.iter().next().is_none() && removed_sources.iter().next().is_none() {
+        return
+    }
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    let bodies: Vec<&Body> = body_query.iter().collect();
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    diagnostics.0 = validate(&surfaces, &bodies, &sources, config.ray_density);
+}
+
+pub fn diagnostics_panel_system(
+    mut commands: Commands,
+    diagnostics: Res<Diagnostics>,
+    config: Res<Config>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<DiagnosticsPanel>>
+) {
+    if !diagnostics.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if diagnostics.0.is_empty() {
+        return
+    }
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(10.),
+                bottom: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }).insert(DiagnosticsPanel).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(
+            locale.format("diagnostics.title", &[("count", &diagnostics.0.len())]),
+            ui::text_style(&font, ui::TEXT_COLOR)
+        ));
+        for diagnostic in diagnostics.0.iter() {
+            let color = match diagnostic.severity {
+                Severity::Warning => Color::rgb(1.0, 0.8, 0.2),
+                Severity::Error => Color::rgb(1.0, 0.3, 0.3)
+            };
+            panel.spawn(ButtonBundle {
+                background_color: Color::NONE.into(),
+                ..default()
+            }).insert(DiagnosticButton(diagnostic.location)).with_children(|button| {
+                button.spawn(TextBundle::from_section(diagnostic.text(&locale, config.units), ui::text_style(&font, color)));
+            });
+        }
+    });
+}
+
+/// Centers and zooms the camera on a diagnostic when its entry is clicked.
+pub fn diagnostic_click_system(
+    button_query: Query<(&Interaction, &DiagnosticButton), Changed<Interaction>>,
+    scale: Res<Scale>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), MainCamera>
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction == Interaction::Clicked {
+            for (mut transform, mut projection) in camera_query.iter_mut() {
+                transform.translation.x = button.0.x;
+                transform.translation.y = button.0.y;
+                projection.scale = scale.px(1.) / ZOOM_TO_ISSUE;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlapping(a: &Body, b: &Body) -> bool {
+        validate(&[], &[a, b], &[], 10.).iter().any(|diagnostic| diagnostic.message == "diagnostics.bodies-overlap")
+    }
+
+    #[test]
+    fn bodies_overlap_where_they_share_glass_only() {
+        let square = |x: f32, y: f32| Body::polygon(vec![Vec2::new(x, y), Vec2::new(x + 10., y), Vec2::new(x + 10., y + 10.), Vec2::new(x, y + 10.)], 1.5);
+        // edges crossing, one inside the other, and the same body twice
+        assert!(overlapping(&square(0., 0.), &square(5., 5.)));
+        assert!(overlapping(&square(0., 0.), &Body::polygon(vec![Vec2::new(2., 2.), Vec2::new(4., 2.), Vec2::new(3., 4.)], 1.5)));
+        assert!(overlapping(&square(0., 0.), &square(0., 0.)));
+        // apart, and cemented along an edge
+        assert!(!overlapping(&square(0., 0.), &square(20., 0.)));
+        assert!(!overlapping(&square(0., 0.), &square(10., 0.)));
+        // a lens face bulging into the body next to it
+        assert!(overlapping(&square(0., 0.), &square(10., 0.).bulge(3, 0.15)));
+    }
+}
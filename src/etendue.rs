@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+
+use crate::{
+    bundle::{BundleStats, Crossing}, config::Config, cross2, fans::RayFan, locale::Locale, scene_tree::Selected, trace_limited,
+    ui::{self, UiFont}, units::Quantity, BeamSource, RaySegment, Surface, TraceLimits
+};
+
+const WARNING_COLOR: Color = Color::rgb(1.0, 0.8, 0.2);
+
+type EtendueChanged = Or<(Changed<RaySegment>, Added<Selected>)>;
+
+/// An aperture or fiber downstream of the plane that the bundle's étendue does not fit through.
+#[derive(Clone, Debug)]
+pub struct Overfilled {
+    pub name: String,
+    /// Étendue (mm) the surface accepts: its length times its full NA range
+    pub capacity: f32
+}
+
+/// The bundle's extent on the plane in (y, n·sin θ) phase space, about its mean ray.
+#[derive(Clone, Debug)]
+pub struct Invariant {
+    pub index: f32,
+    /// Half width (mm) across the mean direction
+    pub half_width: f32,
+    /// Half range of n·sin θ about the mean direction
+    pub numerical_aperture: f32,
+    /// Power-weighted statistics of the crossings
+    pub spot: BundleStats,
+    pub overfilled: Vec<Overfilled>
+}
+
+impl Invariant {
+    /// Lagrange invariant: marginal height times marginal n·sin θ.
+    pub fn lagrange(&self) -> f32 {
+        self.half_width * self.numerical_aperture
+    }
+
+    /// 2D étendue (mm), the area of the bundle's box in phase space.
+    pub fn etendue(&self) -> f32 {
+        4. * self.lagrange()
+    }
+}
+
+/// Lagrange invariant and étendue of the fan's source (Shift+F picks it) on the selected
+/// surface. E toggles the panel.
+#[derive(Resource, Default)]
+pub struct EtendueReport {
+    pub enabled: bool,
+    pub invariant: Option<Invariant>
+}
+
+#[derive(Component)]
+pub struct EtenduePanel;
+
+pub fn etendue_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut report: ResMut<EtendueReport>
+) {
+    if keys.just_pressed(KeyCode::E) {
+        report.enabled = !report.enabled;
+    }
+}
+
+/// Rays of the bundle as they reach `plane`, with the index they travel in, and the
+/// apertures hit downstream of it.
+fn crossings(
+    source: &BeamSource,
+    plane: Entity,
+    surfaces: &[(Entity, &Surface)],
+    density: f32
+) -> (Vec<(Crossing, f32)>, Vec<Entity>) {
+    let mut rays = Vec::new();
+    let mut downstream = Vec::new();
+    for ray in source.rays(density) {
+        let traced = trace_limited(&ray, surfaces, |_| None, TraceLimits::sequential());
+        let Some(at) = traced.segments.iter().position(|segment| segment.surface == plane) else {
+            continue
+        };
+        let crossing = &traced.segments[at];
+        rays.push((Crossing::of_segment(crossing), crossing.ray.medium_index()));
+        // descendants of the crossing follow it until the trace backs up past its depth
+        let after = traced.segments[at + 1..].iter().take_while(|segment| segment.depth > crossing.depth);
+        for segment in after {
+            if !downstream.contains(&segment.surface) {
+                downstream.push(segment.surface);
+            }
+        }
+    }
+    (rays, downstream)
+}
+
+/// Recomputes the invariant whenever rays are retraced, the selection changes or another source is picked.
+pub fn etendue_system(
+    config: Res<Config>,
+    fan: Res<RayFan>,
+    mut report: ResMut<EtendueReport>,
+    changed: Query<(), EtendueChanged>,
+    source_query: Query<&BeamSource>,
+    selected_query: Query<Entity, (With<Selected>, With<Surface>)>,
+    surface_query: Query<(Entity, &Surface)>
+) {
+    if !report.enabled || (!report.is_changed() && !fan.is_changed() && changed.is_empty()) {
+        return
+    }
+    report.invariant = None;
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    let (false, Some(plane)) = (sources.is_empty(), selected_query.iter().next()) else {
+        return
+    };
+    let source = sources[fan.source % sources.len()];
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    let (rays, downstream) = crossings(source, plane, &surfaces, config.ray_density);
+    if rays.is_empty() {
+        return
+    }
+    let bundle: Vec<Crossing> = rays.iter().map(|(crossing, _)| *crossing).collect();
+    let Some(spot) = BundleStats::of(&bundle) else {
+        return
+    };
+    let (axis, center) = (spot.direction, spot.centroid);
+    let index = rays.iter().map(|(_, n)| *n).sum::<f32>() / rays.len() as f32;
+    let half_range = |values: Vec<f32>| {
+        let (lo, hi) = values.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+        (hi - lo) / 2.
+    };
+    let half_width = half_range(rays.iter().map(|(crossing, _)| (crossing.point - center).dot(axis.perp())).collect());
+    let numerical_aperture = half_range(rays.iter().map(|(crossing, n)| n * cross2(axis, crossing.direction)).collect());
+    let mut invariant = Invariant {
+        index,
+        half_width,
+        numerical_aperture,
+        spot,
+        overfilled: Vec::new()
+    };
+    for (entity, surface) in surfaces.iter() {
+        let Some(na) = surface.numerical_aperture else {
+            continue
+        };
+        let capacity = surface.length().0 * 2. * na;
+        if downstream.contains(entity) && invariant.etendue() > capacity {
+            invariant.overfilled.push(Overfilled {
+                name: surface.label.clone().unwrap_or_else(|| surface.kind().to_string()),
+                capacity
+            });
+        }
+    }
+    report.invariant = Some(invariant);
+}
+
+pub fn etendue_panel_system(
+    mut commands: Commands,
+    report: Res<EtendueReport>,
+    config: Res<Config>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<EtenduePanel>>
+) {
+    if !report.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !report.enabled {
+        return
+    }
+    let units = config.units;
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.),
+                top: Val::Percent(20.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, EtenduePanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("etendue.title"), ui::text_style(&font, ui::TEXT_COLOR)));
+        let Some(invariant) = &report.invariant else {
+            panel.spawn(TextBundle::from_section(locale.text("etendue.none"), ui::text_style(&font, ui::TEXT_COLOR)));
+            return
+        };
+        let lines = [
+            locale.format("etendue.bundle", &[
+                ("width", &units.show(Quantity::Length, 2. * invariant.half_width)),
+                ("na", &format!("{:.4}", invariant.numerical_aperture)),
+                ("n", &invariant.index)
+            ]),
+            locale.format("etendue.rms", &[
+                ("major", &units.show(Quantity::Length, invariant.spot.extents[0])),
+                ("minor", &units.show(Quantity::Length, invariant.spot.extents[1])),
+                ("angle", &units.show(Quantity::Angle, invariant.spot.axes[0].y.atan2(invariant.spot.axes[0].x))),
+                ("spread", &units.show(Quantity::BeamAngle, invariant.spot.angular_spread))
+            ]),
+            locale.format("etendue.lagrange", &[("h", &units.show(Quantity::Length, invariant.lagrange()))]),
+            locale.format("etendue.etendue", &[("g", &units.show(Quantity::Length, invariant.etendue()))])
+        ];
+        for line in lines {
+            panel.spawn(TextBundle::from_section(line, ui::text_style(&font, ui::TEXT_COLOR)));
+        }
+        for overfilled in invariant.overfilled.iter() {
+            panel.spawn(TextBundle::from_section(
+                locale.format("etendue.overfilled", &[
+                    ("name", &overfilled.name),
+                    ("capacity", &units.show(Quantity::Length, overfilled.capacity))
+                ]),
+                ui::text_style(&font, WARNING_COLOR)
+            ));
+        }
+    });
+}
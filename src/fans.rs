@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+
+use crate::{
+    chromatic, config::Config, cross2, locale::Locale, scene_tree::Selected, ui::{self, UiFont}, units::{Nanometers, Quantity},
+    BeamSource, RaySegment, RayTree, Surface
+};
+
+// Below this, a ray's last leg is taken as parallel to the chief ray
+const PARALLEL_TOLERANCE: f32 = 1e-6;
+
+type FanChanged = Or<(Changed<RaySegment>, Added<Selected>)>;
+
+/// One ray of a fan: where it left the pupil and how far it misses the chief ray.
+#[derive(Clone, Copy, Debug)]
+pub struct FanSample {
+    /// Position across the source waist, -1 to 1
+    pub pupil: f32,
+    /// Miss distance (mm) on the image plane, across the axis
+    pub transverse: f32,
+    /// Distance (mm) along the axis from the image plane to where the ray crosses the chief ray
+    pub longitudinal: Option<f32>
+}
+
+/// Ray fan of one source at its wavelength. F toggles the plots and Shift+F steps to the next
+/// source. The image plane is the selected surface, or else the plane across the axis at the
+/// best focus.
+#[derive(Resource, Default)]
+pub struct RayFan {
+    pub enabled: bool,
+    pub source: usize,
+    pub wavelength: Option<Nanometers>,
+    pub samples: Vec<FanSample>
+}
+
+#[derive(Component)]
+pub struct FanPanel;
+
+pub fn fan_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut fan: ResMut<RayFan>
+) {
+    if !keys.just_pressed(KeyCode::F) {
+        return
+    }
+    if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        fan.source += 1;
+    } else {
+        fan.enabled = !fan.enabled;
+    }
+}
+
+/// Fan of `legs`, the last leg of each ray as (pupil, start, unit direction), on the plane
+/// through `plane` across `axis`.
+pub fn samples(legs: &[(f32, Vec2, Vec2)], plane: Vec2, axis: Vec2) -> Vec<FanSample> {
+    let Some(&(_, chief_start, chief_direction)) = legs.iter().min_by(|a, b| a.0.abs().total_cmp(&b.0.abs())) else {
+        return Vec::new()
+    };
+    let on_plane = |start: Vec2, direction: Vec2| {
+        let along = direction.dot(axis);
+        (along.abs() > PARALLEL_TOLERANCE).then(|| start + direction * (plane - start).dot(axis) / along)
+    };
+    let Some(chief_hit) = on_plane(chief_start, chief_direction) else {
+        return Vec::new()
+    };
+    let mut samples: Vec<FanSample> = legs.iter().filter_map(|&(pupil, start, direction)| {
+        let hit = on_plane(start, direction)?;
+        let crossing = cross2(direction, chief_direction);
+        let longitudinal = (crossing.abs() > PARALLEL_TOLERANCE).then(|| {
+            let t = cross2(chief_start - start, chief_direction) / crossing;
+            (start + direction * t - plane).dot(axis)
+        });
+        Some(FanSample {
+            pupil,
+            transverse: (hit - chief_hit).dot(axis.perp()),
+            longitudinal
+        })
+    }).collect();
+    samples.sort_by(|a, b| a.pupil.total_cmp(&b.pupil));
+    samples
+}
+
+/// Rebuilds the fan of the chosen source whenever rays are retraced or the selection changes.
+pub fn fan_system(
+    mut fan: ResMut<RayFan>,
+    changed: Query<(), FanChanged>,
+    source_query: Query<&BeamSource>,
+    selected_query: Query<&Surface, With<Selected>>,
+    tree_query: Query<(&RayTree, &Children)>,
+    segment_query: Query<&RaySegment>
+) {
+    if !fan.enabled || (!fan.is_changed() && changed.is_empty()) {
+        return
+    }
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    if sources.is_empty() {
+        fan.samples.clear();
+        return
+    }
+    fan.source %= sources.len();
+    let source = sources[fan.source];
+    let legs: Vec<(f32, Vec2, Vec2)> = tree_query.iter().filter_map(|(tree, children)| {
+        // one line of a source's spectrum
+        if tree.root.wavelength() != source.w {
+            return None
+        }
+        let pupil = source.pupil(&tree.root)?;
+        let last = chromatic::final_leg(segment_query.iter_many(children))?;
+        Some((pupil, last.from, (last.to - last.from).normalize_or_zero()))
+    }).collect();
+    let axis = legs.iter().map(|(_, _, d)| *d).sum::<Vec2>().normalize_or_zero();
+    let lines: Vec<(Vec2, Vec2)> = legs.iter().map(|(_, a, d)| (*a, *d)).collect();
+    let image = match selected_query.iter().next() {
+        Some(surface) => Some(((surface.p1 + surface.p2) / 2., surface.normal())),
+        None => chromatic::focus(&lines).map(|focus| (focus, axis))
+    };
+    fan.wavelength = Some(source.w);
+    fan.samples = match image {
+        Some((plane, normal)) => {
+            let normal = if normal.dot(axis) < 0. { -normal } else { normal };
+            samples(&legs, plane, normal)
+        }
+        None => Vec::new()
+    };
+}
+
+/// Symmetric range covering `values`, never narrower than `floor`.
+fn symmetric(values: impl Iterator<Item = f32>, floor: f32) -> (f32, f32) {
+    let extent = values.fold(floor, |extent, v| extent.max(v.abs()));
+    (-extent, extent)
+}
+
+pub fn fan_panel_system(
+    mut commands: Commands,
+    config: Res<Config>,
+    fan: Res<RayFan>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<FanPanel>>
+) {
+    if !fan.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    let (true, Some(wavelength)) = (fan.enabled, fan.wavelength) else {
+        return
+    };
+    let color = config.palette.color(wavelength);
+    // µm against pupil
+    let transverse: Vec<Vec2> = fan.samples.iter().map(|s| Vec2::new(s.pupil, s.transverse * 1e3)).collect();
+    let longitudinal: Vec<Vec2> = fan.samples.iter()
+        .filter_map(|s| s.longitudinal.map(|z| Vec2::new(s.pupil, z * 1e3)))
+        .collect();
+    let transverse_range = symmetric(transverse.iter().map(|p| p.y), 1.);
+    let longitudinal_range = symmetric(longitudinal.iter().map(|p| p.y), 1.);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(10.),
+                bottom: Val::Percent(30.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, FanPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(
+            locale.format("fans.title", &[("w", &config.units.show(Quantity::Wavelength, wavelength.0)), ("source", &(fan.source + 1))]),
+            ui::text_style(&font, ui::TEXT_COLOR)
+        ));
+        panel.spawn(TextBundle::from_section(
+            locale.format("fans.transverse", &[("range", &config.units.show(Quantity::SmallLength, transverse_range.1 * 1e-3))]),
+            ui::text_style(&font, ui::TEXT_COLOR)
+        ));
+        ui::plot(panel, &transverse, (-1., 1.), transverse_range, color);
+        panel.spawn(TextBundle::from_section(
+            locale.format("fans.longitudinal", &[("range", &config.units.show(Quantity::SmallLength, longitudinal_range.1 * 1e-3))]),
+            ui::text_style(&font, ui::TEXT_COLOR)
+        ));
+        ui::plot(panel, &longitudinal, (-1., 1.), longitudinal_range, color);
+    });
+}
@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+use itertools_num::linspace;
+
+use crate::{
+    chromatic, config::Config, fans::RayFan, locale::Locale, scene_tree::Selected, trace_limited,
+    ui::{self, UiFont}, units::{Degrees, Quantity}, BeamSource, RaySegment, Surface, TraceLimits
+};
+
+// Largest field angle of the sweep, either side of the source's direction
+const MAX_FIELD: Degrees = Degrees(10.);
+// Field angles traced, including the axis
+const FIELD_STEPS: usize = 21;
+// Below this, an image height is too small to compare against the ideal one
+const MIN_HEIGHT: f32 = 1e-6;
+
+type SweepChanged = Or<(Changed<RaySegment>, Added<Selected>)>;
+
+/// Where a collimated bundle at one field angle comes to focus.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldPoint {
+    pub angle: Degrees,
+    /// Height (mm) of the chief ray on the image plane, from the axis
+    pub height: f32,
+    /// Distance (mm) along the axis from the image plane to the bundle's focus
+    pub curvature: Option<f32>,
+    /// Height error against f·tan(angle), in percent of the ideal height
+    pub distortion: Option<f32>
+}
+
+/// Field curvature and distortion of the fan's source (Shift+F picks it). The source's bundle
+/// is tilted across the field and traced without drawing; D toggles the plots. The image plane
+/// is the selected surface, or else the plane across the axis at the on-axis focus.
+#[derive(Resource, Default)]
+pub struct FieldSweep {
+    pub enabled: bool,
+    pub points: Vec<FieldPoint>
+}
+
+#[derive(Component)]
+pub struct FieldPanel;
+
+pub fn field_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut sweep: ResMut<FieldSweep>
+) {
+    if keys.just_pressed(KeyCode::D) {
+        sweep.enabled = !sweep.enabled;
+    }
+}
+
+/// Last leg of every ray of `source`, as (pupil, start, unit direction), traced through `surfaces`.
+fn legs(source: &BeamSource, surfaces: &[(Entity, &Surface)], density: f32) -> Vec<(f32, Vec2, Vec2)> {
+    source.rays(density).iter().filter_map(|ray| {
+        let pupil = source.pupil(ray)?;
+        let last = trace_limited(ray, surfaces, |_| None, TraceLimits::sequential()).segments.pop()?;
+        Some((pupil, last.ray.p, last.ray.l))
+    }).collect()
+}
+
+/// Chief ray (closest to the middle of the pupil) of `legs`.
+fn chief(legs: &[(f32, Vec2, Vec2)]) -> Option<(Vec2, Vec2)> {
+    legs.iter()
+        .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+        .map(|(_, start, direction)| (*start, *direction))
+}
+
+/// Retraces the sweep whenever rays are retraced, the selection changes or another source is picked.
+pub fn field_sweep_system(
+    config: Res<Config>,
+    fan: Res<RayFan>,
+    mut sweep: ResMut<FieldSweep>,
+    changed: Query<(), SweepChanged>,
+    source_query: Query<&BeamSource>,
+    selected_query: Query<&Surface, With<Selected>>,
+    surface_query: Query<(Entity, &Surface)>
+) {
+    if !sweep.enabled || (!sweep.is_changed() && !fan.is_changed() && changed.is_empty()) {
+        return
+    }
+    sweep.points.clear();
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    if sources.is_empty() {
+        return
+    }
+    let source = sources[fan.source % sources.len()];
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    let collimated = source.clone().divergence(Degrees(0.));
+    let on_axis = legs(&collimated, &surfaces, config.ray_density);
+    let lines = |legs: &[(f32, Vec2, Vec2)]| -> Vec<(Vec2, Vec2)> { legs.iter().map(|(_, a, d)| (*a, *d)).collect() };
+    let axis = on_axis.iter().map(|(_, _, d)| *d).sum::<Vec2>().normalize_or_zero();
+    let image = match selected_query.iter().next() {
+        Some(surface) => Some(((surface.p1 + surface.p2) / 2., surface.normal())),
+        None => chromatic::focus(&lines(&on_axis)).map(|focus| (focus, axis))
+    };
+    let (Some((plane, normal)), Some((axis_start, axis_direction))) = (image, chief(&on_axis)) else {
+        return
+    };
+    let normal = if normal.dot(axis) < 0. { -normal } else { normal };
+    let on_plane = |start: Vec2, direction: Vec2| {
+        let along = direction.dot(normal);
+        (along.abs() > MIN_HEIGHT).then(|| start + direction * (plane - start).dot(normal) / along)
+    };
+    let Some(center) = on_plane(axis_start, axis_direction) else {
+        return
+    };
+    let half_field = MAX_FIELD.to_radians();
+    let mut points: Vec<FieldPoint> = linspace(-half_field, half_field, FIELD_STEPS).filter_map(|theta| {
+        let mut tilted = collimated.clone();
+        tilted.direction = Vec2::from_angle(theta).rotate(collimated.direction);
+        let legs = legs(&tilted, &surfaces, config.ray_density);
+        let (start, direction) = chief(&legs)?;
+        let height = (on_plane(start, direction)? - center).dot(normal.perp());
+        let curvature = chromatic::focus(&lines(&legs)).map(|focus| (focus - plane).dot(normal));
+        Some(FieldPoint {
+            angle: Degrees(theta.to_degrees()),
+            height,
+            curvature,
+            distortion: None
+        })
+    }).collect();
+    // the smallest field off the axis sets the paraxial focal length
+    let paraxial = points.iter()
+        .filter(|p| p.height.abs() > MIN_HEIGHT)
+        .min_by(|a, b| a.angle.0.abs().total_cmp(&b.angle.0.abs()))
+        .map(|p| p.height / p.angle.to_radians().tan());
+    if let Some(focal_length) = paraxial {
+        for point in points.iter_mut() {
+            let ideal = focal_length * point.angle.to_radians().tan();
+            if ideal.abs() > MIN_HEIGHT {
+                point.distortion = Some((point.height - ideal) / ideal * 100.);
+            }
+        }
+    }
+    sweep.points = points;
+}
+
+pub fn field_panel_system(
+    mut commands: Commands,
+    sweep: Res<FieldSweep>,
+    config: Res<Config>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<FieldPanel>>
+) {
+    if !sweep.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !sweep.enabled {
+        return
+    }
+    let curvature: Vec<Vec2> = sweep.points.iter()
+        .filter_map(|p| p.curvature.map(|z| Vec2::new(p.angle.0, z)))
+        .collect();
+    let distortion: Vec<Vec2> = sweep.points.iter()
+        .filter_map(|p| p.distortion.map(|d| Vec2::new(p.angle.0, d)))
+        .collect();
+    let extent = |points: &[Vec2], floor: f32| points.iter().fold(floor, |extent, p| extent.max(p.y.abs()));
+    let (curvature_range, distortion_range) = (extent(&curvature, 0.1), extent(&distortion, 1.));
+    let field = (-MAX_FIELD.0, MAX_FIELD.0);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(30.),
+                bottom: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, FieldPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(
+            locale.format("field.title", &[("field", &config.units.show(Quantity::Angle, MAX_FIELD.to_radians()))]),
+            ui::text_style(&font, ui::TEXT_COLOR)
+        ));
+        if sweep.points.is_empty() {
+            panel.spawn(TextBundle::from_section(locale.text("field.none"), ui::text_style(&font, ui::TEXT_COLOR)));
+            return
+        }
+        panel.spawn(TextBundle::from_section(
+            locale.format("field.curvature", &[("range", &config.units.show(Quantity::Length, curvature_range))]),
+            ui::text_style(&font, ui::TEXT_COLOR)
+        ));
+        ui::plot(panel, &curvature, field, (-curvature_range, curvature_range), ui::TEXT_COLOR);
+        panel.spawn(TextBundle::from_section(
+            locale.format("field.distortion", &[("range", &format!("{:.1}", distortion_range))]),
+            ui::text_style(&font, ui::TEXT_COLOR)
+        ));
+        ui::plot(panel, &distortion, field, (-distortion_range, distortion_range), ui::TEXT_COLOR);
+    });
+}
@@ -0,0 +1,238 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    complex::Complex, config::Config, locale::Locale, paraxial::{self, Abcd},
+    ui::{self, UiFont}, units::{Nanometers, Quantity}, BeamShape, BeamSource, Emission, RaySegment, Surface,
+    INFINITE_DRAW_LENGTH
+};
+
+// Legs followed along the chief ray before giving up, as for a resonator that never lets go
+const MAX_LEGS: usize = 64;
+// Points along each edge of a leg's envelope
+const ENVELOPE_SAMPLES: usize = 48;
+const ENVELOPE_ALPHA: f32 = 0.25;
+
+/// Straight part of a Gaussian beam between two surfaces, with the beam parameter at its start.
+#[derive(Clone, Debug)]
+pub struct Leg {
+    pub from: Vec2,
+    pub to: Vec2,
+    /// Complex beam parameter q = z + i z_R (mm) at `from`, z measured from the waist
+    pub q: Complex,
+    pub index: f32
+}
+
+impl Leg {
+    pub fn length(&self) -> f32 {
+        self.from.distance(self.to)
+    }
+}
+
+/// Gaussian beam launched from a source's waist and carried along its chief ray.
+#[derive(Clone, Debug)]
+pub struct GaussianBeam {
+    pub wavelength: Nanometers,
+    pub legs: Vec<Leg>
+}
+
+/// 1/e² radius (mm) of a beam of vacuum wavelength `w` with parameter `q` in index `n`.
+pub fn radius(q: Complex, w: Nanometers, n: f32) -> f32 {
+    let inverse = Complex::from(1.) / q;
+    (-w.0 * 1e-6 / (PI * n * inverse.im)).sqrt()
+}
+
+/// Waist of the beam with parameter `q`: (distance to it along the beam, 1/e² radius), both mm.
+/// The distance is negative when the waist lies behind.
+pub fn waist(q: Complex, w: Nanometers, n: f32) -> (f32, f32) {
+    (-q.re, (q.im * w.0 * 1e-6 / (PI * n)).sqrt())
+}
+
+/// Far-field half angle (radians) of the beam with parameter `q`.
+pub fn divergence(q: Complex, w: Nanometers, n: f32) -> f32 {
+    waist(q, w, n).1 / q.im
+}
+
+/// Follows the strongest ray out of each surface from `source`, carrying the beam parameter
+/// through the surfaces' ABCD matrices. The waist sits at the source, with the source's
+/// Gaussian radius or else half its width; Lambertian and point sources have no Gaussian beam.
+pub fn propagate(source: &BeamSource, surfaces: &[(Entity, &Surface)]) -> Option<GaussianBeam> {
+    if source.emission != Emission::Beam {
+        return None
+    }
+    let w0 = match source.shape {
+        BeamShape::Gaussian { radius } => radius.0,
+        _ => source.waist.0 / 2.
+    };
+    if w0 <= 0. {
+        return None
+    }
+    let ray = source.chief_ray();
+    let mut q = Complex::new(0., PI * w0 * w0 * source.index / (source.w.0 * 1e-6));
+    let (elements, last) = paraxial::chief_path(&ray, surfaces, MAX_LEGS);
+    let mut legs = Vec::new();
+    let mut from = source.pos;
+    for element in elements.iter() {
+        legs.push(Leg {
+            from,
+            to: element.at,
+            q,
+            index: element.index
+        });
+        q = Abcd::propagation(element.distance).then(element.matrix).apply(q);
+        from = element.at;
+    }
+    if let Some(ray) = last {
+        legs.push(Leg {
+            from,
+            to: from + ray.l * INFINITE_DRAW_LENGTH,
+            q,
+            index: ray.medium_index()
+        });
+    }
+    Some(GaussianBeam {
+        wavelength: source.w,
+        legs
+    })
+}
+
+/// Gaussian beams of the sources, drawn as their 1/e² envelopes. J toggles the mode.
+#[derive(Resource, Default)]
+pub struct GaussianBeams {
+    pub enabled: bool,
+    pub beams: Vec<GaussianBeam>
+}
+
+#[derive(Component)]
+pub struct Envelope;
+
+#[derive(Component)]
+pub struct GaussianPanel;
+
+type Drawn = Or<(With<Envelope>, With<GaussianPanel>)>;
+
+pub fn gaussian_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut beams: ResMut<GaussianBeams>
+) {
+    if keys.just_pressed(KeyCode::J) {
+        beams.enabled = !beams.enabled;
+    }
+}
+
+/// Propagates the beams again whenever rays are retraced.
+pub fn gaussian_beam_system(
+    mut beams: ResMut<GaussianBeams>,
+    changed: Query<(), Changed<RaySegment>>,
+    source_query: Query<&BeamSource>,
+    surface_query: Query<(Entity, &Surface)>
+) {
+    if !beams.enabled || (!beams.is_changed() && changed.is_empty()) {
+        return
+    }
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    beams.beams = source_query.iter().filter_map(|source| propagate(source, &surfaces)).collect();
+}
+
+/// Outline of a leg's envelope at ±w(z), sampled evenly in asinh of the distance from the
+/// waist so that a tight focus is as smooth as the far field.
+fn envelope(leg: &Leg, w: Nanometers) -> Path {
+    let axis = (leg.to - leg.from).normalize_or_zero();
+    let (to_waist, _) = waist(leg.q, w, leg.index);
+    let z_r = leg.q.im;
+    let (u0, u1) = (-to_waist / z_r, (leg.length() - to_waist) / z_r);
+    let (u0, u1) = (u0.asinh(), u1.asinh());
+    let edge: Vec<(Vec2, f32)> = (0..ENVELOPE_SAMPLES).map(|k| {
+        let u = u0 + (u1 - u0) * k as f32 / (ENVELOPE_SAMPLES - 1) as f32;
+        let z = (to_waist + z_r * u.sinh()).clamp(0., leg.length());
+        (leg.from + axis * z, radius(leg.q + Complex::from(z), w, leg.index))
+    }).collect();
+    let mut path_builder = PathBuilder::new();
+    path_builder.move_to(edge[0].0 + axis.perp() * edge[0].1);
+    for (p, r) in edge.iter().skip(1) {
+        path_builder.line_to(*p + axis.perp() * *r);
+    }
+    for (p, r) in edge.iter().rev() {
+        path_builder.line_to(*p - axis.perp() * *r);
+    }
+    path_builder.close();
+    path_builder.build()
+}
+
+pub fn gaussian_draw_system(
+    mut commands: Commands,
+    config: Res<Config>,
+    beams: Res<GaussianBeams>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    drawn_query: Query<Entity, Drawn>
+) {
+    if !beams.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for entity in drawn_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !beams.enabled {
+        return
+    }
+    for beam in beams.beams.iter() {
+        let color = *config.palette.color(beam.wavelength).set_a(ENVELOPE_ALPHA);
+        for leg in beam.legs.iter().filter(|leg| leg.length() > 0.) {
+            commands.spawn((GeometryBuilder::build_as(
+                &envelope(leg, beam.wavelength),
+                DrawMode::Fill(FillMode::color(color)),
+                Transform::from_xyz(0., 0., 0.25)
+            ), Envelope));
+        }
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.),
+                top: Val::Percent(80.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, GaussianPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("gaussian.title"), style.clone()));
+        if beams.beams.is_empty() {
+            panel.spawn(TextBundle::from_section(locale.text("gaussian.none"), style.clone()));
+        }
+        for beam in beams.beams.iter() {
+            let (Some(first), Some(last)) = (beam.legs.first(), beam.legs.last()) else {
+                continue
+            };
+            let (_, w0) = waist(first.q, beam.wavelength, first.index);
+            let (to_waist, w1) = waist(last.q, beam.wavelength, last.index);
+            panel.spawn(TextBundle::from_section(
+                locale.format("gaussian.beam", &[
+                    ("w", &config.units.show(Quantity::Wavelength, beam.wavelength.0)),
+                    ("waist", &config.units.show(Quantity::SmallLength, w0)),
+                    ("rayleigh", &config.units.show(Quantity::Length, first.q.im)),
+                    ("divergence", &config.units.show(Quantity::BeamAngle, divergence(first.q, beam.wavelength, first.index)))
+                ]),
+                ui::text_style(&font, config.palette.color(beam.wavelength))
+            ));
+            if beam.legs.len() > 1 {
+                panel.spawn(TextBundle::from_section(
+                    locale.format("gaussian.output", &[
+                        ("waist", &config.units.show(Quantity::SmallLength, w1)),
+                        ("at", &format!("{:+}", config.units.show(Quantity::Length, to_waist))),
+                        ("divergence", &config.units.show(Quantity::BeamAngle, divergence(last.q, beam.wavelength, last.index)))
+                    ]),
+                    style.clone()
+                ));
+            }
+        }
+    });
+}
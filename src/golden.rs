@@ -0,0 +1,123 @@
+use std::fs;
+
+use bevy::prelude::*;
+
+use crate::{
+    config::Config, demo_scene, launch, polarization::Formalism, raycaster::Raycaster, trace, BeamSource, Surface, TraceSegment,
+    RAY_DENSITY
+};
+
+// Decimal places kept in dumps: 0.1 µm for positions
+const DECIMALS: usize = 4;
+
+const HEADER: &str = "# ray depth surface x0 y0 x1 y1 wavelength intensity";
+// Starts the rays sampled adaptively, see `Config::adaptive_rays`
+const ADAPTIVE: &str = "# adaptive";
+
+fn rounded(x: f32) -> String {
+    let s = format!("{:.*}", DECIMALS, x);
+    // don't let sign noise around zero show up as a diff
+    match s.strip_prefix('-') {
+        Some(magnitude) if magnitude.chars().all(|c| c == '0' || c == '.') => magnitude.to_string(),
+        _ => s
+    }
+}
+
+/// Canonical trace of every source through `surfaces`, one line per segment. Surfaces are
+/// identified by their position in `surfaces`, rays by `source.ray`. Sources are sampled at the
+/// default ray density, evenly and then adaptively, so the dump doesn't depend on user settings.
+pub fn dump(sources: &[BeamSource], surfaces: &[Surface]) -> String {
+    let surfaces: Vec<(Entity, &Surface)> = surfaces.iter()
+        .enumerate()
+        .map(|(k, surface)| (Entity::from_raw(k as u32), surface))
+        .collect();
+    let raycaster = Raycaster::new(&surfaces);
+    let mut lines = vec![HEADER.to_string()];
+    for adaptive_rays in [false, true] {
+        if adaptive_rays {
+            lines.push(ADAPTIVE.to_string());
+        }
+        let config = Config {
+            ray_density: RAY_DENSITY,
+            adaptive_rays,
+            ..Config::default()
+        };
+        for (s, source) in sources.iter().enumerate() {
+            for (r, ray) in launch(source, &config, Formalism::Scalar, &raycaster).iter().enumerate() {
+                lines.extend(trace(ray, &surfaces, |_| None).segments.iter().map(|segment| line(s, r, segment)));
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// One segment of ray `r` of source `s`.
+fn line(s: usize, r: usize, segment: &TraceSegment) -> String {
+    format!(
+        "{}.{} {} {} {} {} {} {} {} {}",
+        s, r,
+        segment.depth,
+        segment.surface.index(),
+        rounded(segment.ray.p.x),
+        rounded(segment.ray.p.y),
+        rounded(segment.hit.point.x),
+        rounded(segment.hit.point.y),
+        rounded(segment.ray.wavelength().0),
+        rounded(segment.ray.i)
+    )
+}
+
+/// Describes the first difference between a dump and its reference.
+pub fn compare(dump: &str, reference: &str) -> Result<(), String> {
+    let (mut actual, mut expected) = (dump.lines(), reference.lines());
+    for line in 1.. {
+        match (actual.next(), expected.next()) {
+            (None, None) => return Ok(()),
+            (a, e) if a == e => continue,
+            (a, e) => return Err(format!(
+                "line {}:\n  expected: {}\n  actual:   {}",
+                line,
+                e.unwrap_or("<end of trace>"),
+                a.unwrap_or("<end of trace>")
+            ))
+        }
+    }
+    unreachable!()
+}
+
+/// Handles `--golden <file>` (write a dump of the demo scene) and `--golden-check <file>`
+/// (compare against a stored dump). Returns the process exit code if one of them was given.
+pub fn run(args: &[String]) -> Option<i32> {
+    let (mode, path) = match args {
+        [mode, path, ..] if mode == "--golden" || mode == "--golden-check" => (mode.as_str(), path),
+        _ => return None
+    };
+    let (sources, surfaces) = demo_scene();
+    let dump = dump(&sources, &surfaces);
+    if mode == "--golden" {
+        return Some(match fs::write(path, dump) {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("could not write {}: {}", path, err);
+                2
+            }
+        })
+    }
+    let reference = match fs::read_to_string(path) {
+        Ok(reference) => reference,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            return Some(2)
+        }
+    };
+    Some(match compare(&dump, &reference) {
+        Ok(()) => {
+            println!("trace matches {}", path);
+            0
+        }
+        Err(difference) => {
+            eprintln!("trace differs from {} at {}", path, difference);
+            1
+        }
+    })
+}
@@ -0,0 +1,355 @@
+use std::{borrow::Cow, f32::consts::PI};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage
+    }
+};
+
+use super::{handoff, medium::{medium_map, FieldWindow}, storage_image, types::BpmParams};
+use crate::{config::Config, scene_tree::Selected, BeamSource, RaySegment, Surface, SCENE_H, SCENE_W};
+
+// Transverse samples; must match the workgroup array in bpm.wgsl
+pub const BPM_N: u32 = 512;
+// Steps along the propagation axis
+pub const BPM_COLUMNS: u32 = 1024;
+
+// Window width in beam waists
+const WINDOW_WAISTS: f32 = 4.;
+
+type SceneChanged = Or<(Changed<Surface>, Changed<BeamSource>, Changed<RaySegment>, Added<Selected>)>;
+
+/// Distance from `p` along `axis` to the edge of the table.
+fn table_exit(p: Vec2, axis: Vec2) -> f32 {
+    let exit = |p: f32, l: f32, size: f32| match l {
+        l if l > 0. => (size - p) / l,
+        l if l < 0. => -p / l,
+        _ => f32::INFINITY
+    };
+    exit(p.x, axis.x, SCENE_W).min(exit(p.y, axis.y, SCENE_H)).clamp(0., SCENE_W.hypot(SCENE_H))
+}
+
+/// Strip along `source` to where its axis leaves the table, a few waists wide. The paraxial
+/// propagator resolves angles up to about λ / (2 dy) from the axis.
+pub fn window_along(source: &BeamSource) -> FieldWindow {
+    let axis = source.direction.normalize_or_zero();
+    FieldWindow {
+        origin: source.pos,
+        axis,
+        length: table_exit(source.pos, axis),
+        width: WINDOW_WAISTS * source.waist.0,
+        columns: BPM_COLUMNS,
+        rows: BPM_N
+    }
+}
+
+/// Gaussian field of `source` across the window's first column, with the phase tilt of its
+/// direction relative to the window axis.
+fn launch(window: &FieldWindow, source: &BeamSource) -> Vec<Vec2> {
+    let k0 = 2. * PI / source.w.to_mm().0;
+    let radius = (source.waist.0 / 2.).max(window.dy());
+    let center = (source.pos - window.origin).dot(window.axis.perp());
+    let tilt = source.direction.normalize_or_zero().dot(window.axis.perp());
+    (0..BPM_N).map(|i| {
+        let y = window.offset(i) - center;
+        let amplitude = (-(y * y) / (radius * radius)).exp();
+        Vec2::from_angle(k0 * source.index * tilt * y) * amplitude
+    }).collect()
+}
+
+/// Where the propagated field comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BpmInput {
+    /// Gaussian beam of the first source, from the source onwards
+    Source,
+    /// Rays traced up to the selected surface, from that surface onwards
+    Handoff
+}
+
+/// Inputs of the BPM kernel, rebuilt whenever the scene changes while the view is on.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct BpmField {
+    pub image: Handle<Image>,
+    pub input_mode: BpmInput,
+    pub window: Option<FieldWindow>,
+    pub medium: Vec<Vec2>,
+    pub input: Vec<Vec2>,
+    pub params: BpmParams,
+    pub enabled: bool
+}
+
+#[derive(Component)]
+pub struct BpmSprite;
+
+pub struct BpmPlugin;
+
+impl Plugin for BpmPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<BpmField>::default())
+            .add_startup_system(setup_bpm_system)
+            .add_system(bpm_toggle_system)
+            .add_system(bpm_scene_system.after(bpm_toggle_system))
+            .add_system(bpm_sprite_system.after(bpm_scene_system));
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<BpmPipeline>()
+            .add_system_to_stage(RenderStage::Queue, queue_bpm_bind_group);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("bpm", BpmNode);
+        render_graph.add_node_edge("bpm", CAMERA_DRIVER).unwrap();
+    }
+}
+
+fn setup_bpm_system(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>
+) {
+    let image = images.add(storage_image(BPM_COLUMNS, BPM_N, TextureFormat::Rgba8Unorm));
+    commands.spawn((SpriteBundle {
+        texture: image.clone(),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    }, BpmSprite));
+    commands.insert_resource(BpmField {
+        image,
+        input_mode: BpmInput::Source,
+        window: None,
+        medium: Vec::new(),
+        input: Vec::new(),
+        params: BpmParams::default(),
+        enabled: false
+    });
+}
+
+/// F7 switches the wave-optics view on and off. Shift+F7 switches its input between the first
+/// source and the rays handed off at the selected surface.
+fn bpm_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut field: ResMut<BpmField>
+) {
+    if !keys.just_pressed(KeyCode::F7) {
+        return
+    }
+    if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        field.input_mode = match field.input_mode {
+            BpmInput::Source => BpmInput::Handoff,
+            BpmInput::Handoff => BpmInput::Source
+        };
+        field.enabled = true;
+    } else {
+        field.enabled = !field.enabled;
+    }
+    // rebuilt from the current scene
+    field.window = None;
+}
+
+fn bpm_scene_system(
+    config: Res<Config>,
+    mut field: ResMut<BpmField>,
+    changed: Query<(), SceneChanged>,
+    removed: RemovedComponents<Surface>,
+    surface_query: Query<(&Surface, Option<&Selected>)>,
+    source_query: Query<&BeamSource>,
+    segment_query: Query<&RaySegment>
+) {
+    let toggled_on = field.window.is_none();
+    if !field.enabled || !(toggled_on || !changed.is_empty() || removed.iter().next().is_some()) {
+        return
+    }
+    let input = match field.input_mode {
+        BpmInput::Source => source_query.iter().next().map(|source| {
+            let window = window_along(source);
+            (window, launch(&window, source), source.w, source.index)
+        }),
+        BpmInput::Handoff => surface_query.iter().find(|(_, selected)| selected.is_some()).and_then(|(plane, _)| {
+            let crossings = handoff::crossings(plane, segment_query.iter());
+            let brightest = crossings.iter().max_by(|a, b| a.amplitude.total_cmp(&b.amplitude))?;
+            let window = handoff::window_from(plane, &crossings, table_exit, BPM_COLUMNS, BPM_N);
+            let input = handoff::field(&window, &crossings, brightest.wavelength, 1. / config.ray_density);
+            Some((window, input, brightest.wavelength, brightest.index))
+        })
+    };
+    let Some((window, input, w, n0)) = input else {
+        return
+    };
+    let surfaces: Vec<&Surface> = surface_query.iter().map(|(surface, _)| surface).collect();
+    field.medium = medium_map(&window, &surfaces, w, n0);
+    field.input = input;
+    field.params = BpmParams {
+        k0: 2. * PI / w.to_mm().0,
+        n0,
+        dz: window.dz(),
+        dy: window.dy(),
+        columns: BPM_COLUMNS,
+        color: Vec4::from(config.palette.color(w).as_linear_rgba_f32())
+    };
+    field.window = Some(window);
+}
+
+/// Lays the field texture over its window on the table.
+fn bpm_sprite_system(
+    field: Res<BpmField>,
+    mut sprite_query: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<BpmSprite>>
+) {
+    if !field.is_changed() {
+        return
+    }
+    for (mut sprite, mut transform, mut visibility) in sprite_query.iter_mut() {
+        visibility.is_visible = field.enabled && field.window.is_some();
+        if let Some(window) = field.window {
+            sprite.custom_size = Some(Vec2::new(window.length, window.width));
+            *transform = window.transform(1.);
+        }
+    }
+}
+
+#[derive(Resource)]
+struct BpmPipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId
+}
+
+fn storage_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None
+        },
+        count: None
+    }
+}
+
+impl FromWorld for BpmPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("bpm_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba8Unorm,
+                        view_dimension: TextureViewDimension::D2
+                    },
+                    count: None
+                },
+                storage_entry(1),
+                storage_entry(2),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(BpmParams::min_size())
+                    },
+                    count: None
+                }
+            ]
+        });
+        let shader = world.resource::<AssetServer>().load("bpm.wgsl");
+        let pipeline = world.resource_mut::<PipelineCache>().queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("bpm")),
+            layout: Some(vec![layout.clone()]),
+            shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("propagate")
+        });
+        Self { layout, pipeline }
+    }
+}
+
+#[derive(Resource)]
+struct BpmBindGroup(BindGroup);
+
+/// Queues one propagation per scene change, once the texture and pipeline are ready.
+#[allow(clippy::too_many_arguments)]
+fn queue_bpm_bind_group(
+    mut commands: Commands,
+    pipeline: Res<BpmPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<Image>>,
+    field: Res<BpmField>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut pending: Local<bool>
+) {
+    commands.remove_resource::<BpmBindGroup>();
+    *pending |= field.is_changed();
+    if !*pending || !field.enabled || field.window.is_none() || pipeline_cache.get_compute_pipeline(pipeline.pipeline).is_none() {
+        return
+    }
+    let Some(image) = gpu_images.get(&field.image) else {
+        return
+    };
+    let mut medium = StorageBuffer::from(field.medium.clone());
+    medium.write_buffer(&device, &queue);
+    let mut input = StorageBuffer::from(field.input.clone());
+    input.write_buffer(&device, &queue);
+    let mut params = UniformBuffer::from(field.params);
+    params.write_buffer(&device, &queue);
+    let (Some(medium), Some(input), Some(params)) = (medium.binding(), input.binding(), params.binding()) else {
+        return
+    };
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("bpm"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view)
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: medium
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: input
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: params
+            }
+        ]
+    });
+    commands.insert_resource(BpmBindGroup(bind_group));
+    *pending = false;
+}
+
+struct BpmNode;
+
+impl render_graph::Node for BpmNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(bind_group) = world.get_resource::<BpmBindGroup>() else {
+            return Ok(())
+        };
+        let pipeline_id = world.resource::<BpmPipeline>().pipeline;
+        let Some(pipeline) = world.resource::<PipelineCache>().get_compute_pipeline(pipeline_id) else {
+            return Ok(())
+        };
+        let mut pass = render_context.command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("bpm")
+        });
+        pass.set_bind_group(0, &bind_group.0, &[]);
+        pass.set_pipeline(pipeline);
+        // a single workgroup owns the whole transverse field
+        pass.dispatch_workgroups(1, 1, 1);
+        Ok(())
+    }
+}
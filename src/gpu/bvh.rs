@@ -0,0 +1,322 @@
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        RenderApp, RenderStage
+    }
+};
+
+use super::types::{BvhInfo, BvhNode, BvhSurface};
+use crate::{Extent, Surface};
+
+// Morton code bits per axis
+const MORTON_BITS: u32 = 16;
+
+/// Scene surfaces as a flattened LBVH, rebuilt whenever geometry changes. Kernels that trace
+/// on the GPU bind it as group 1 and `#import beams::bvh` for `nearest_hit`.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct SurfaceBvh {
+    pub nodes: Vec<BvhNode>,
+    /// Bounded surfaces in tree order, then the unbounded ones
+    pub surfaces: Vec<BvhSurface>,
+    /// Entity of each surface in `surfaces`, to map GPU hits back
+    pub entities: Vec<Entity>,
+    pub bounded: u32
+}
+
+/// Bind group over the uploaded BVH, in the render world.
+#[derive(Resource)]
+pub struct GpuBvh {
+    pub bind_group: BindGroup
+}
+
+#[derive(Resource)]
+pub struct BvhLayout {
+    pub layout: BindGroupLayout,
+    // keeps the import module loaded for the kernels that use it
+    _shader: Handle<Shader>
+}
+
+pub struct BvhPlugin;
+
+impl Plugin for BvhPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<SurfaceBvh>::default())
+            .init_resource::<SurfaceBvh>()
+            .add_system_to_stage(CoreStage::PostUpdate, surface_bvh_system);
+        app.sub_app_mut(RenderApp)
+            .init_resource::<BvhLayout>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_bvh_system);
+    }
+}
+
+pub(super) fn surface_bvh_system(
+    mut bvh: ResMut<SurfaceBvh>,
+    changed: Query<(), Changed<Surface>>,
+    removed: RemovedComponents<Surface>,
+    surface_query: Query<(Entity, &Surface)>
+) {
+    if changed.is_empty() && removed.iter().next().is_none() {
+        return
+    }
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    *bvh = SurfaceBvh::build(&surfaces);
+}
+
+fn morton(x: u32) -> u32 {
+    let x = (x | (x << 8)) & 0x00FF00FF;
+    let x = (x | (x << 4)) & 0x0F0F0F0F;
+    let x = (x | (x << 2)) & 0x33333333;
+    (x | (x << 1)) & 0x55555555
+}
+
+/// Split point and the first and last keys covered by internal node `i`, over sorted,
+/// distinct `keys` (Karras 2012).
+fn node_range(keys: &[u64], i: usize) -> (usize, usize, usize) {
+    let delta = |j: isize| -> i32 {
+        if j < 0 || j >= keys.len() as isize {
+            -1
+        } else {
+            (keys[i] ^ keys[j as usize]).leading_zeros() as i32
+        }
+    };
+    let i = i as isize;
+    let d: isize = if delta(i + 1) > delta(i - 1) { 1 } else { -1 };
+    let shortest = delta(i - d);
+    let mut reach = 2;
+    while delta(i + reach * d) > shortest {
+        reach *= 2;
+    }
+    let mut length = 0;
+    let mut step = reach / 2;
+    while step >= 1 {
+        if delta(i + (length + step) * d) > shortest {
+            length += step;
+        }
+        step /= 2;
+    }
+    let j = i + length * d;
+    let common = delta(j);
+    let mut split = 0;
+    let mut divisor = 2;
+    loop {
+        let step = (length + divisor - 1) / divisor;
+        if delta(i + (split + step) * d) > common {
+            split += step;
+        }
+        if step <= 1 {
+            break
+        }
+        divisor *= 2;
+    }
+    let gamma = i + split * d + d.min(0);
+    (gamma as usize, i.min(j) as usize, i.max(j) as usize)
+}
+
+impl SurfaceBvh {
+    pub fn build(surfaces: &[(Entity, &Surface)]) -> Self {
+        let gpu = |surface: &Surface| BvhSurface {
+            p1: surface.p1,
+            p2: surface.p2,
+            extent: match surface.extent {
+                Extent::Segment => 0,
+                Extent::Ray => 1,
+                Extent::Line => 2
+            },
+            curvature: surface.curvature
+        };
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = surfaces.iter()
+            .partition(|(_, surface)| surface.extent == Extent::Segment);
+        let centers: Vec<Vec2> = bounded.iter().map(|(_, s)| (s.p1 + s.p2) / 2.).collect();
+        let lo = centers.iter().copied().fold(Vec2::splat(f32::INFINITY), Vec2::min);
+        let hi = centers.iter().copied().fold(Vec2::splat(f32::NEG_INFINITY), Vec2::max);
+        let scale = ((1 << MORTON_BITS) - 1) as f32 / (hi - lo).max(Vec2::splat(f32::EPSILON));
+        // the index in the low bits keeps keys distinct
+        let mut keys: Vec<u64> = centers.iter().enumerate().map(|(k, center)| {
+            let q = ((*center - lo) * scale).as_uvec2();
+            ((morton(q.x) | (morton(q.y) << 1)) as u64) << 32 | k as u64
+        }).collect();
+        keys.sort_unstable();
+
+        let mut bvh = Self::default();
+        let mut bounds = Vec::with_capacity(keys.len());
+        for key in keys.iter() {
+            let (entity, surface) = bounded[(key & 0xFFFF_FFFF) as usize];
+            bvh.surfaces.push(gpu(surface));
+            bvh.entities.push(entity);
+            bounds.push(surface.bounds());
+        }
+        bvh.bounded = bvh.surfaces.len() as u32;
+        if !keys.is_empty() {
+            let root = if keys.len() == 1 { Node::Leaf(0) } else { Node::Internal(0) };
+            bvh.flatten(&keys, &bounds, root);
+        }
+        for (entity, surface) in unbounded {
+            bvh.surfaces.push(gpu(surface));
+            bvh.entities.push(entity);
+        }
+        bvh
+    }
+
+    /// Appends `node` and its subtree depth-first, returning its bounds. `bounds` holds those
+    /// of each surface in the tree.
+    fn flatten(&mut self, keys: &[u64], bounds: &[(Vec2, Vec2)], node: Node) -> (Vec2, Vec2) {
+        let index = self.nodes.len();
+        self.nodes.push(BvhNode::default());
+        let (surface, min, max) = match node {
+            Node::Leaf(k) => (k as i32, bounds[k].0, bounds[k].1),
+            Node::Internal(i) => {
+                let (gamma, first, last) = node_range(keys, i);
+                let left = if gamma == first { Node::Leaf(gamma) } else { Node::Internal(gamma) };
+                let right = if gamma + 1 == last { Node::Leaf(gamma + 1) } else { Node::Internal(gamma + 1) };
+                let (left_min, left_max) = self.flatten(keys, bounds, left);
+                let (right_min, right_max) = self.flatten(keys, bounds, right);
+                (-1, left_min.min(right_min), left_max.max(right_max))
+            }
+        };
+        self.nodes[index] = BvhNode {
+            min,
+            max,
+            surface,
+            skip: self.nodes.len() as u32
+        };
+        (min, max)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Node {
+    Leaf(usize),
+    Internal(usize)
+}
+
+impl FromWorld for BvhLayout {
+    fn from_world(world: &mut World) -> Self {
+        let storage = |binding: u32| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None
+        };
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("bvh_layout"),
+            entries: &[
+                storage(0),
+                storage(1),
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(BvhInfo::min_size())
+                    },
+                    count: None
+                }
+            ]
+        });
+        Self {
+            layout,
+            _shader: world.resource::<AssetServer>().load("bvh.wgsl")
+        }
+    }
+}
+
+/// Uploads the BVH when it changes; the buffers stay on the GPU in between.
+fn prepare_bvh_system(
+    mut commands: Commands,
+    bvh: Res<SurfaceBvh>,
+    layout: Res<BvhLayout>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>
+) {
+    if !bvh.is_changed() {
+        return
+    }
+    // storage bindings can't be empty
+    let mut nodes = StorageBuffer::from(if bvh.nodes.is_empty() { vec![BvhNode::default()] } else { bvh.nodes.clone() });
+    nodes.write_buffer(&device, &queue);
+    let mut surfaces = StorageBuffer::from(if bvh.surfaces.is_empty() { vec![BvhSurface::default()] } else { bvh.surfaces.clone() });
+    surfaces.write_buffer(&device, &queue);
+    let mut info = UniformBuffer::from(BvhInfo {
+        nodes: bvh.nodes.len() as u32,
+        bounded: bvh.bounded,
+        surfaces: bvh.surfaces.len() as u32
+    });
+    info.write_buffer(&device, &queue);
+    let (Some(nodes), Some(surfaces), Some(info)) = (nodes.binding(), surfaces.binding(), info.binding()) else {
+        return
+    };
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("bvh"),
+        layout: &layout.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: nodes
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: surfaces
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: info
+            }
+        ]
+    });
+    commands.insert_resource(GpuBvh { bind_group });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Surfaces scattered over the table, and a few sharing a center so their Morton codes tie.
+    fn scattered(count: usize) -> Vec<Surface> {
+        (0..count).map(|k| {
+            let p = if k % 5 == 4 { Vec2::splat(20.) } else { Vec2::new((k * 7 % 50) as f32, (k * 13 % 40) as f32) };
+            Surface::glass(p, p + Vec2::from_angle(k as f32) * 3.)
+        }).collect()
+    }
+
+    /// Leaves the GPU walk reaches when every box is hit: into internal nodes, past leaves.
+    fn walked_leaves(bvh: &SurfaceBvh) -> Vec<i32> {
+        let (mut leaves, mut k) = (Vec::new(), 0);
+        while k < bvh.nodes.len() {
+            let node = &bvh.nodes[k];
+            if node.surface >= 0 {
+                leaves.push(node.surface);
+                k = node.skip as usize;
+            } else {
+                k += 1;
+            }
+        }
+        leaves
+    }
+
+    #[test]
+    fn tree_covers_every_leaf_exactly_once() {
+        for count in [1, 2, 3, 5, 6, 7, 12, 31, 33, 100] {
+            let surfaces = scattered(count);
+            let listed: Vec<(Entity, &Surface)> = surfaces.iter().enumerate().map(|(k, s)| (Entity::from_raw(k as u32), s)).collect();
+            let bvh = SurfaceBvh::build(&listed);
+            assert_eq!(bvh.nodes.len(), 2 * count - 1, "{count} leaves");
+            assert_eq!(bvh.nodes[0].skip as usize, bvh.nodes.len());
+            let mut leaves = walked_leaves(&bvh);
+            leaves.sort_unstable();
+            assert_eq!(leaves, (0..count as i32).collect::<Vec<_>>(), "{count} leaves");
+            // every box holds its surface
+            for node in bvh.nodes.iter().filter(|node| node.surface >= 0) {
+                let (min, max) = surfaces[bvh.entities[node.surface as usize].index() as usize].bounds();
+                assert!(node.min.cmple(min).all() && node.max.cmpge(max).all());
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::{borrow::Cow, f32::consts::PI};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage
+    }
+};
+
+use super::{medium::{medium_map, FieldWindow}, storage_image, types::FdtdParams, workgroups};
+use crate::{
+    config::Config,
+    locale::Locale,
+    scene_tree::Selected,
+    ui::{self, UiFont},
+    units::{Nanometers, Quantity},
+    viewports::MainCamera,
+    BeamSource, RaySegment, Surface
+};
+
+// Grid cells along each side, PML cells included
+pub const FDTD_N: u32 = 256;
+// Absorbing layer thickness (cells) on each edge
+const PML: u32 = 24;
+// Grid resolution, in vacuum wavelengths
+const CELLS_PER_WAVELENGTH: f32 = 16.;
+// c dt / dx; 2D Yee grids are stable up to 1/sqrt(2)
+const COURANT: f32 = 0.5;
+// Time steps run per frame
+const STEPS_PER_FRAME: u32 = 4;
+// On-screen size (px) of the field inset
+const PANEL_SIZE: f32 = 320.;
+
+type RegionChanged = Or<(Changed<RaySegment>, Added<Selected>)>;
+
+/// Small square of the table simulated with FDTD, seeded by the ray passing closest to its
+/// center. The grid is aligned with that ray, which enters as a plane wave from the left.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct FdtdRegion {
+    pub image: Handle<Image>,
+    pub window: Option<FieldWindow>,
+    pub medium: Vec<Vec2>,
+    pub wavelength: Nanometers,
+    pub params: FdtdParams,
+    pub enabled: bool
+}
+
+/// Time steps taken since the region was last rebuilt.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct FdtdClock(pub u32);
+
+#[derive(Component)]
+pub struct FdtdPanel;
+
+pub struct FdtdPlugin;
+
+impl Plugin for FdtdPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<FdtdRegion>::default())
+            .add_plugin(ExtractResourcePlugin::<FdtdClock>::default())
+            .init_resource::<FdtdClock>()
+            .add_startup_system(setup_fdtd_system)
+            .add_system(fdtd_toggle_system)
+            .add_system(fdtd_region_system.after(fdtd_toggle_system))
+            .add_system(fdtd_clock_system.after(fdtd_region_system))
+            .add_system(fdtd_panel_system.after(fdtd_region_system));
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<FdtdPipeline>()
+            .add_system_to_stage(RenderStage::Queue, queue_fdtd_bind_groups);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("fdtd", FdtdNode);
+        render_graph.add_node_edge("fdtd", CAMERA_DRIVER).unwrap();
+    }
+}
+
+fn setup_fdtd_system(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>
+) {
+    commands.insert_resource(FdtdRegion {
+        image: images.add(storage_image(FDTD_N, FDTD_N, TextureFormat::Rgba8Unorm)),
+        window: None,
+        medium: Vec::new(),
+        wavelength: Nanometers(0.),
+        params: FdtdParams::default(),
+        enabled: false
+    });
+}
+
+/// F8 starts the FDTD region at the selected surface, or at the view center, and stops it.
+fn fdtd_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut region: ResMut<FdtdRegion>
+) {
+    if keys.just_pressed(KeyCode::F8) {
+        region.enabled = !region.enabled;
+        region.window = None;
+    }
+}
+
+/// Distance from `p` to the segment between `a` and `b`.
+fn distance_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let d = b - a;
+    let t = ((p - a).dot(d) / d.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+    p.distance(a + t * d)
+}
+
+fn fdtd_region_system(
+    config: Res<Config>,
+    mut region: ResMut<FdtdRegion>,
+    changed: Query<(), RegionChanged>,
+    surface_query: Query<(&Surface, Option<&Selected>)>,
+    segment_query: Query<&RaySegment>,
+    source_query: Query<&BeamSource>,
+    camera_query: Query<&Transform, MainCamera>
+) {
+    if !region.enabled || (region.window.is_some() && changed.is_empty()) {
+        return
+    }
+    let center = match surface_query.iter().find(|(_, selected)| selected.is_some()) {
+        Some((surface, _)) => (surface.p1 + surface.p2) / 2.,
+        None => match camera_query.get_single() {
+            Ok(transform) => transform.translation.truncate(),
+            Err(_) => return
+        }
+    };
+    // seed from the closest ray, or the first source if no ray comes near
+    let closest = segment_query.iter()
+        .min_by(|a, b| distance_to_segment(center, a.from, a.to).total_cmp(&distance_to_segment(center, b.from, b.to)));
+    let (axis, wavelength) = match (closest, source_query.iter().next()) {
+        (Some(segment), _) => ((segment.to - segment.from).normalize_or_zero(), segment.wavelength),
+        (None, Some(source)) => (source.direction.normalize_or_zero(), source.w),
+        (None, None) => return
+    };
+    let side = FDTD_N as f32 * wavelength.to_mm().0 / CELLS_PER_WAVELENGTH;
+    let window = FieldWindow {
+        origin: center - axis * side / 2.,
+        axis,
+        length: side,
+        width: side,
+        columns: FDTD_N,
+        rows: FDTD_N
+    };
+    let surfaces: Vec<&Surface> = surface_query.iter().map(|(surface, _)| surface).collect();
+    region.medium = medium_map(&window, &surfaces, wavelength, 1.);
+    region.wavelength = wavelength;
+    region.params = FdtdParams {
+        courant: COURANT,
+        omega: 2. * PI * COURANT / CELLS_PER_WAVELENGTH,
+        step: 0,
+        size: FDTD_N,
+        pml: PML,
+        source_column: PML + 2,
+        color: Vec4::from(config.palette.color(wavelength).as_linear_rgba_f32())
+    };
+    region.window = Some(window);
+}
+
+fn fdtd_clock_system(
+    region: Res<FdtdRegion>,
+    mut clock: ResMut<FdtdClock>
+) {
+    if region.is_changed() {
+        clock.0 = 0;
+    } else if region.enabled {
+        clock.0 += STEPS_PER_FRAME;
+    }
+}
+
+/// Inset showing the simulated field, since the region is only micrometres across.
+fn fdtd_panel_system(
+    mut commands: Commands,
+    region: Res<FdtdRegion>,
+    config: Res<Config>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    mut panel: Local<Option<Entity>>
+) {
+    if !region.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    if let Some(entity) = panel.take() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let (true, Some(window)) = (region.enabled, region.window) else {
+        return
+    };
+    let center = window.center();
+    let caption = locale.format("gpu.fdtd", &[
+        ("x", &config.units.show(Quantity::Length, center.x)),
+        ("y", &config.units.show(Quantity::Length, center.y)),
+        ("w", &config.units.show(Quantity::Wavelength, region.wavelength.0)),
+        ("size", &config.units.show(Quantity::SmallLength, window.length))
+    ]);
+    let entity = commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.),
+                bottom: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, FdtdPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(caption, ui::text_style(&font, ui::TEXT_COLOR)));
+        panel.spawn(ImageBundle {
+            style: Style {
+                size: Size::new(Val::Px(PANEL_SIZE), Val::Px(PANEL_SIZE)),
+                ..default()
+            },
+            image: UiImage(region.image.clone()),
+            ..default()
+        });
+    }).id();
+    *panel = Some(entity);
+}
+
+#[derive(Resource)]
+struct FdtdPipeline {
+    layout: BindGroupLayout,
+    update_h: CachedComputePipelineId,
+    update_e: CachedComputePipelineId,
+    draw: CachedComputePipelineId
+}
+
+impl FromWorld for FdtdPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let storage = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None
+        };
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("fdtd_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba8Unorm,
+                        view_dimension: TextureViewDimension::D2
+                    },
+                    count: None
+                },
+                storage(1, false),
+                storage(2, true),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(FdtdParams::min_size())
+                    },
+                    count: None
+                }
+            ]
+        });
+        let shader = world.resource::<AssetServer>().load("fdtd.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue = |entry_point: &'static str| pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from(entry_point)),
+            layout: Some(vec![layout.clone()]),
+            shader: shader.clone(),
+            shader_defs: vec![],
+            entry_point: Cow::from(entry_point)
+        });
+        Self {
+            update_h: queue("update_h"),
+            update_e: queue("update_e"),
+            draw: queue("draw"),
+            layout
+        }
+    }
+}
+
+/// Field and medium buffers of the current region; the field lives on the GPU between frames.
+struct FdtdBuffers {
+    cells: StorageBuffer<Vec<Vec4>>,
+    medium: StorageBuffer<Vec<Vec2>>
+}
+
+/// One bind group per time step this frame, each with its own step number.
+#[derive(Resource)]
+struct FdtdBindGroups(Vec<BindGroup>);
+
+/// Rebuilds the field buffers when the region changes and binds this frame's time steps.
+#[allow(clippy::too_many_arguments)]
+fn queue_fdtd_bind_groups(
+    mut commands: Commands,
+    pipeline: Res<FdtdPipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    region: Res<FdtdRegion>,
+    clock: Res<FdtdClock>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut buffers: Local<Option<FdtdBuffers>>
+) {
+    commands.remove_resource::<FdtdBindGroups>();
+    if region.is_changed() {
+        *buffers = None;
+    }
+    if !region.enabled || region.window.is_none() {
+        return
+    }
+    let Some(image) = gpu_images.get(&region.image) else {
+        return
+    };
+    let buffers = buffers.get_or_insert_with(|| {
+        let mut cells = StorageBuffer::from(vec![Vec4::ZERO; (FDTD_N * FDTD_N) as usize]);
+        cells.write_buffer(&device, &queue);
+        let mut medium = StorageBuffer::from(region.medium.clone());
+        medium.write_buffer(&device, &queue);
+        FdtdBuffers { cells, medium }
+    });
+    let (Some(cells), Some(medium)) = (buffers.cells.binding(), buffers.medium.binding()) else {
+        return
+    };
+    let bind_groups = (0..STEPS_PER_FRAME).filter_map(|k| {
+        let mut params = UniformBuffer::from(FdtdParams {
+            step: clock.0 + k,
+            ..region.params
+        });
+        params.write_buffer(&device, &queue);
+        Some(device.create_bind_group(&BindGroupDescriptor {
+            label: Some("fdtd"),
+            layout: &pipeline.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&image.texture_view)
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: cells.clone()
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: medium.clone()
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: params.binding()?
+                }
+            ]
+        }))
+    }).collect();
+    commands.insert_resource(FdtdBindGroups(bind_groups));
+}
+
+struct FdtdNode;
+
+impl render_graph::Node for FdtdNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(bind_groups) = world.get_resource::<FdtdBindGroups>() else {
+            return Ok(())
+        };
+        let pipeline = world.resource::<FdtdPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let (Some(update_h), Some(update_e), Some(draw)) = (
+            pipeline_cache.get_compute_pipeline(pipeline.update_h),
+            pipeline_cache.get_compute_pipeline(pipeline.update_e),
+            pipeline_cache.get_compute_pipeline(pipeline.draw)
+        ) else {
+            return Ok(())
+        };
+        let mut pass = render_context.command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("fdtd")
+        });
+        for bind_group in bind_groups.0.iter() {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.set_pipeline(update_h);
+            pass.dispatch_workgroups(workgroups(FDTD_N), workgroups(FDTD_N), 1);
+            pass.set_pipeline(update_e);
+            pass.dispatch_workgroups(workgroups(FDTD_N), workgroups(FDTD_N), 1);
+        }
+        if let Some(bind_group) = bind_groups.0.last() {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.set_pipeline(draw);
+            pass.dispatch_workgroups(workgroups(FDTD_N), workgroups(FDTD_N), 1);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use super::medium::FieldWindow;
+use crate::{intersect, units::Nanometers, Ray, RaySegment, Surface, SURFACE_OFFSET};
+
+// Segments ending this close (mm) past the plane still count as crossing it, since traced
+// segments stop exactly on the surface they hit
+const CROSSING_TOLERANCE: f32 = 1e-3;
+
+/// A traced ray where it meets the handoff plane.
+#[derive(Clone, Debug)]
+pub struct Crossing {
+    pub point: Vec2,
+    pub direction: Vec2,
+    pub amplitude: f32,
+    /// Phase (rad) accumulated along the optical path up to `point`
+    pub phase: f32,
+    pub index: f32,
+    pub wavelength: Nanometers
+}
+
+/// Every traced segment that crosses `plane`.
+pub fn crossings<'a>(plane: &Surface, segments: impl Iterator<Item = &'a RaySegment>) -> Vec<Crossing> {
+    segments.filter_map(|segment| {
+        let length = segment.from.distance(segment.to);
+        let direction = (segment.to - segment.from).normalize_or_zero();
+        let d = intersect(&Ray::new(segment.from, direction, segment.index), plane);
+        if !d.is_finite() || d > length + CROSSING_TOLERANCE {
+            return None
+        }
+        let k0 = TAU / segment.wavelength.to_mm().0;
+        Some(Crossing {
+            point: segment.from + direction * d,
+            direction,
+            amplitude: segment.intensity.max(0.).sqrt(),
+            // wrapped here, where there's precision to spare
+            phase: (k0 as f64 * (segment.opl + segment.index * d) as f64).rem_euclid(TAU as f64) as f32,
+            index: segment.index,
+            wavelength: segment.wavelength
+        })
+    }).collect()
+}
+
+/// Window starting on `plane` and running along its normal, in the direction the crossing
+/// rays travel, for `length`. It starts just short of the plane so the plane itself is part
+/// of the medium.
+pub fn window_from(plane: &Surface, crossings: &[Crossing], length: impl Fn(Vec2, Vec2) -> f32, columns: u32, rows: u32) -> FieldWindow {
+    let normal = plane.normal();
+    let heading: Vec2 = crossings.iter().map(|crossing| crossing.direction).sum();
+    let axis = if heading.dot(normal) < 0. { -normal } else { normal };
+    let origin = (plane.p1 + plane.p2) / 2. - axis * SURFACE_OFFSET;
+    FieldWindow {
+        origin,
+        axis,
+        length: length(origin, axis),
+        width: plane.length().0,
+        columns,
+        rows
+    }
+}
+
+/// Field across the window's first column, as the sum of a Gaussian beamlet per crossing
+/// with the crossing's phase and tilt. Only crossings at `wavelength` take part; the result
+/// is scaled to a peak amplitude of 1.
+pub fn field(window: &FieldWindow, crossings: &[Crossing], wavelength: Nanometers, beamlet: f32) -> Vec<Vec2> {
+    let k0 = TAU / wavelength.to_mm().0;
+    let across = window.axis.perp();
+    let beamlet = beamlet.max(window.dy());
+    let coherent: Vec<&Crossing> = crossings.iter().filter(|crossing| crossing.wavelength == wavelength).collect();
+    let field: Vec<Vec2> = (0..window.rows).map(|i| {
+        let y = window.offset(i);
+        coherent.iter().map(|crossing| {
+            let dy = y - (crossing.point - window.origin).dot(across);
+            let tilt = crossing.direction.dot(across);
+            let amplitude = crossing.amplitude * (-(dy * dy) / (beamlet * beamlet)).exp();
+            Vec2::from_angle(crossing.phase + k0 * crossing.index * tilt * dy) * amplitude
+        }).sum()
+    }).collect();
+    let peak = field.iter().map(|e| e.length()).fold(0., f32::max);
+    if peak > 0. {
+        field.iter().map(|e| *e / peak).collect()
+    } else {
+        field
+    }
+}
@@ -0,0 +1,414 @@
+use std::{borrow::Cow, f32::consts::TAU};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage
+    }
+};
+
+use super::{storage_image, types::{CoherentSegment, InterferenceParams}, workgroups};
+use crate::{config::Config, viewports::MainCamera, RaySegment};
+
+// Scales summed intensity before tonemapping
+const EXPOSURE: f32 = 0.5;
+// Side (texels) of the square tiles segments are binned into, as TILE in the kernel
+const TILE: u32 = 16;
+// Beamlets are cut off this many 1/e half widths across, as in the kernel
+const BEAMLET_REACH: f32 = 3.;
+
+type SpriteFilter = (With<InterferenceSprite>, Without<Camera2d>);
+
+/// Coherent rays and the screen-sized texture their interference pattern is resolved into.
+/// Rays of one wavelength are treated as coherent, whichever source they come from.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct InterferenceTexture {
+    pub image: Handle<Image>,
+    pub segments: Vec<CoherentSegment>,
+    /// Start and length in `indices` of each tile's segments, row by row
+    pub tiles: Vec<UVec2>,
+    /// Segments that reach into each tile, in the order of `segments`
+    pub indices: Vec<u32>,
+    pub params: InterferenceParams,
+    pub enabled: bool
+}
+
+#[derive(Component)]
+pub struct InterferenceSprite;
+
+pub struct InterferencePlugin;
+
+impl Plugin for InterferencePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<InterferenceTexture>::default())
+            .add_startup_system(setup_interference_system)
+            .add_system(interference_toggle_system)
+            .add_system(collect_coherent_system)
+            .add_system(interference_view_system.after(interference_toggle_system).after(collect_coherent_system));
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<InterferencePipeline>()
+            .add_system_to_stage(RenderStage::Queue, queue_interference_bind_group);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("interference", InterferenceNode);
+        render_graph.add_node_edge("interference", CAMERA_DRIVER).unwrap();
+    }
+}
+
+fn setup_interference_system(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>
+) {
+    // resized to the window once switched on
+    let image = images.add(storage_image(1, 1, TextureFormat::Rgba8Unorm));
+    commands.spawn((SpriteBundle {
+        texture: image.clone(),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    }, InterferenceSprite));
+    commands.insert_resource(InterferenceTexture {
+        image,
+        segments: Vec::new(),
+        tiles: Vec::new(),
+        indices: Vec::new(),
+        params: InterferenceParams::default(),
+        enabled: false
+    });
+}
+
+/// F9 switches the interference view on and off.
+fn interference_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut texture: ResMut<InterferenceTexture>,
+    mut sprite_query: Query<&mut Visibility, With<InterferenceSprite>>
+) {
+    if keys.just_pressed(KeyCode::F9) {
+        texture.enabled = !texture.enabled;
+        for mut visibility in sprite_query.iter_mut() {
+            visibility.is_visible = texture.enabled;
+        }
+    }
+}
+
+fn collect_coherent_system(
+    config: Res<Config>,
+    mut texture: ResMut<InterferenceTexture>,
+    changed: Query<(), Changed<RaySegment>>,
+    removed: RemovedComponents<RaySegment>,
+    segment_query: Query<&RaySegment>
+) {
+    if !config.is_changed() && changed.is_empty() && removed.iter().next().is_none() {
+        return
+    }
+    let mut segments: Vec<CoherentSegment> = segment_query.iter().map(|segment| {
+        let k0 = TAU / segment.wavelength.to_mm().0;
+        CoherentSegment {
+            p0: segment.from,
+            p1: segment.to,
+            // wrapped here, where there's precision to spare
+            phase: (k0 as f64 * segment.opl as f64).rem_euclid(TAU as f64) as f32,
+            k: k0 * segment.index,
+            amplitude: segment.intensity.max(0.).sqrt(),
+            group: segment.wavelength.0.to_bits(),
+            color: Vec4::from(config.palette.color(segment.wavelength).as_linear_rgba_f32())
+        }
+    }).collect();
+    segments.sort_by_key(|segment| segment.group);
+    texture.params.beamlet = 1. / config.ray_density;
+    texture.params.exposure = EXPOSURE;
+    texture.segments = segments;
+}
+
+/// Keeps the texture one texel per logical pixel and its sprite covering the main view, and
+/// bins the segments into its tiles whenever they or the view change.
+fn interference_view_system(
+    windows: Res<Windows>,
+    mut images: ResMut<Assets<Image>>,
+    mut texture: ResMut<InterferenceTexture>,
+    camera_query: Query<(&Transform, &OrthographicProjection), MainCamera>,
+    mut sprite_query: Query<(&mut Sprite, &mut Transform), SpriteFilter>
+) {
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    if !texture.enabled || window.width() < 1. || window.height() < 1. {
+        return
+    }
+    let size = Extent3d {
+        width: window.width() as u32,
+        height: window.height() as u32,
+        depth_or_array_layers: 1
+    };
+    // get_mut re-uploads the image, so only take it when the size changed
+    if images.get(&texture.image).is_some_and(|image| image.texture_descriptor.size != size) {
+        if let Some(image) = images.get_mut(&texture.image) {
+            image.resize(size);
+        }
+    }
+    let extent = Vec2::new(size.width as f32, size.height as f32) * projection.scale;
+    let center = camera.translation.truncate();
+    for (mut sprite, mut transform) in sprite_query.iter_mut() {
+        sprite.custom_size = Some(extent);
+        *transform = Transform::from_xyz(center.x, center.y, 1.);
+    }
+    let params = InterferenceParams {
+        origin: center + Vec2::new(-extent.x, extent.y) / 2.,
+        mm_per_texel: projection.scale,
+        tiles: UVec2::new(size.width.div_ceil(TILE), size.height.div_ceil(TILE)),
+        ..texture.params
+    };
+    // the toggle and new segments change the texture too
+    if params != texture.params || texture.is_changed() {
+        texture.params = params;
+        (texture.tiles, texture.indices) = bin(&texture.segments, &params);
+    }
+}
+
+/// Lists, for every tile of the texture, the segments whose beamlets reach into it, so each
+/// texel only sums the segments near it. A segment goes into every tile within reach of its
+/// line, keeping the segments' order, and so their groups, within each tile.
+fn bin(segments: &[CoherentSegment], params: &InterferenceParams) -> (Vec<UVec2>, Vec<u32>) {
+    let columns = params.tiles.x as usize;
+    let mut lists = vec![Vec::new(); columns * params.tiles.y as usize];
+    let reach = BEAMLET_REACH * params.beamlet / params.mm_per_texel;
+    // from the tile's center to its corners
+    let corner = TILE as f32 / std::f32::consts::SQRT_2;
+    let texel = |p: Vec2| Vec2::new(p.x - params.origin.x, params.origin.y - p.y) / params.mm_per_texel;
+    let last = params.tiles.as_vec2() - 1.;
+    for (k, segment) in segments.iter().enumerate() {
+        let (a, b) = (texel(segment.p0), texel(segment.p1));
+        let from = ((a.min(b) - reach) / TILE as f32).floor().max(Vec2::ZERO);
+        let to = ((a.max(b) + reach) / TILE as f32).floor().min(last);
+        // the kernel skips segments of no length
+        if a == b || from.cmpgt(to).any() {
+            continue
+        }
+        for row in from.y as usize..=to.y as usize {
+            for column in from.x as usize..=to.x as usize {
+                let center = (Vec2::new(column as f32, row as f32) + 0.5) * TILE as f32;
+                let t = ((center - a).dot(b - a) / (b - a).length_squared()).clamp(0., 1.);
+                if center.distance(a + (b - a) * t) <= corner + reach {
+                    lists[row * columns + column].push(k as u32);
+                }
+            }
+        }
+    }
+    let mut tiles = Vec::with_capacity(lists.len());
+    let mut indices = Vec::new();
+    for list in lists {
+        tiles.push(UVec2::new(indices.len() as u32, list.len() as u32));
+        indices.extend(list);
+    }
+    (tiles, indices)
+}
+
+#[derive(Resource)]
+struct InterferencePipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId
+}
+
+impl FromWorld for InterferencePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let storage = |binding: u32, min_binding_size| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: Some(min_binding_size)
+            },
+            count: None
+        };
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("interference_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba8Unorm,
+                        view_dimension: TextureViewDimension::D2
+                    },
+                    count: None
+                },
+                storage(1, CoherentSegment::min_size()),
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(InterferenceParams::min_size())
+                    },
+                    count: None
+                },
+                storage(3, UVec2::min_size()),
+                storage(4, u32::min_size())
+            ]
+        });
+        let shader = world.resource::<AssetServer>().load("interference.wgsl");
+        let pipeline = world.resource_mut::<PipelineCache>().queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from("interference")),
+            layout: Some(vec![layout.clone()]),
+            shader,
+            shader_defs: vec![],
+            entry_point: Cow::from("accumulate")
+        });
+        Self { layout, pipeline }
+    }
+}
+
+/// Bind group and the texture size to dispatch over.
+#[derive(Resource)]
+struct InterferenceBindGroup(BindGroup, UVec2);
+
+fn queue_interference_bind_group(
+    mut commands: Commands,
+    pipeline: Res<InterferencePipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    texture: Res<InterferenceTexture>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>
+) {
+    commands.remove_resource::<InterferenceBindGroup>();
+    let Some(image) = gpu_images.get(&texture.image) else {
+        return
+    };
+    if !texture.enabled {
+        return
+    }
+    // storage bindings can't be empty
+    let mut segments = texture.segments.clone();
+    if segments.is_empty() {
+        segments.push(CoherentSegment::default());
+    }
+    let mut segments = StorageBuffer::from(segments);
+    segments.write_buffer(&device, &queue);
+    let mut params = UniformBuffer::from(texture.params);
+    params.write_buffer(&device, &queue);
+    let mut tiles = StorageBuffer::from(texture.tiles.clone());
+    if texture.tiles.is_empty() {
+        tiles.get_mut().push(UVec2::ZERO);
+    }
+    tiles.write_buffer(&device, &queue);
+    let mut indices = StorageBuffer::from(texture.indices.clone());
+    if texture.indices.is_empty() {
+        indices.get_mut().push(0);
+    }
+    indices.write_buffer(&device, &queue);
+    let (Some(segments), Some(params), Some(tiles), Some(indices)) = (segments.binding(), params.binding(), tiles.binding(), indices.binding()) else {
+        return
+    };
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("interference"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view)
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: segments
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: params
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: tiles
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: indices
+            }
+        ]
+    });
+    commands.insert_resource(InterferenceBindGroup(bind_group, image.size.as_uvec2()));
+}
+
+struct InterferenceNode;
+
+impl render_graph::Node for InterferenceNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(InterferenceBindGroup(bind_group, size)) = world.get_resource::<InterferenceBindGroup>() else {
+            return Ok(())
+        };
+        let pipeline_id = world.resource::<InterferencePipeline>().pipeline;
+        let Some(pipeline) = world.resource::<PipelineCache>().get_compute_pipeline(pipeline_id) else {
+            return Ok(())
+        };
+        let mut pass = render_context.command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("interference")
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_pipeline(pipeline);
+        pass.dispatch_workgroups(workgroups(size.x), workgroups(size.y), 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the kernel adds the segment's beamlet into the texel, as `accumulate` decides.
+    fn reaches(segment: &CoherentSegment, params: &InterferenceParams, x: u32, y: u32) -> bool {
+        let p = params.origin + Vec2::new(x as f32 + 0.5, -(y as f32 + 0.5)) * params.mm_per_texel;
+        let d = segment.p1 - segment.p0;
+        let len = d.length();
+        let along = (p - segment.p0).dot(d) / len;
+        let across = (p - segment.p0).dot(d.perp()).abs() / len;
+        len > 0. && (0. ..=len).contains(&along) && across <= BEAMLET_REACH * params.beamlet
+    }
+
+    #[test]
+    fn every_texel_sums_the_segments_that_reach_it_in_order() {
+        let params = InterferenceParams {
+            origin: Vec2::new(-5., 30.),
+            mm_per_texel: 0.5,
+            beamlet: 0.4,
+            tiles: UVec2::new(6, 5),
+            ..default()
+        };
+        // a fan of segments from inside the view out past its edges, and one of no length
+        let segments: Vec<CoherentSegment> = (0..24).map(|k| {
+            let p0 = Vec2::new(10., 10.) + Vec2::new((k % 5) as f32, (k % 3) as f32);
+            CoherentSegment {
+                p0,
+                p1: p0 + Vec2::from_angle(k as f32 * 0.7) * (k as f32 * 3.),
+                group: k / 8,
+                ..default()
+            }
+        }).collect();
+        let (tiles, indices) = bin(&segments, &params);
+        assert_eq!(tiles.len(), 30);
+        for y in 0..params.tiles.y * TILE {
+            for x in 0..params.tiles.x * TILE {
+                let tile = tiles[((y / TILE) * params.tiles.x + x / TILE) as usize];
+                let listed = &indices[tile.x as usize..(tile.x + tile.y) as usize];
+                assert!(listed.windows(2).all(|pair| pair[0] < pair[1]));
+                for (k, segment) in segments.iter().enumerate() {
+                    if reaches(segment, &params, x, y) {
+                        assert!(listed.contains(&(k as u32)), "segment {k} misses texel ({x}, {y})");
+                    }
+                }
+            }
+        }
+        // tiles keep out of the way of segments that pass far from them
+        assert!(indices.len() < segments.len() * tiles.len() / 2, "{} listed", indices.len());
+    }
+}
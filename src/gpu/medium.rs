@@ -0,0 +1,74 @@
+use bevy::prelude::*;
+
+use crate::{intersect, units::Nanometers, Ray, Surface};
+
+/// Rectangle of the table a wave kernel works in: from `origin` along `axis` for `length`,
+/// `width` across and centered on the axis, sampled in `columns` steps by `rows` samples.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldWindow {
+    pub origin: Vec2,
+    pub axis: Vec2,
+    pub length: f32,
+    pub width: f32,
+    pub columns: u32,
+    pub rows: u32
+}
+
+impl FieldWindow {
+    pub fn dz(&self) -> f32 {
+        self.length / self.columns as f32
+    }
+
+    pub fn dy(&self) -> f32 {
+        self.width / self.rows as f32
+    }
+
+    /// Transverse offset of sample row `i` from the axis.
+    pub fn offset(&self, i: u32) -> f32 {
+        (i as f32 + 0.5) * self.dy() - self.width / 2.
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.origin + self.axis * self.length / 2.
+    }
+
+    /// Transform of a sprite of size (length, width) laid over the window.
+    pub fn transform(&self, z: f32) -> Transform {
+        let center = self.center();
+        Transform::from_xyz(center.x, center.y, z)
+            .with_rotation(Quat::from_rotation_z(self.axis.y.atan2(self.axis.x)))
+    }
+}
+
+/// (index, fraction absorbed) per cell, column-major. Index changes where a row crosses a
+/// transmissive surface, the same rule the ray tracer uses; mirrors and blockers absorb, since
+/// the wave kernels don't model metals.
+pub fn medium_map(window: &FieldWindow, surfaces: &[&Surface], w: Nanometers, n0: f32) -> Vec<Vec2> {
+    let (rows, columns) = (window.rows as usize, window.columns as usize);
+    let mut cells = vec![Vec2::new(n0, 0.); rows * columns];
+    for i in 0..window.rows {
+        let row = Ray::new(window.origin + window.offset(i) * window.axis.perp(), window.axis, n0);
+        let mut crossings: Vec<(f32, &Surface)> = surfaces.iter()
+            .map(|surface| (intersect(&row, surface), *surface))
+            .filter(|(d, _)| *d < window.length)
+            .collect();
+        crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let (mut n, mut next) = (n0, 0);
+        for column in 0..columns {
+            let mut loss = 0.;
+            while next < crossings.len() && crossings[next].0 < (column + 1) as f32 * window.dz() {
+                let surface = crossings[next].1;
+                let blocked = surface.passband.is_some_and(|(lo, hi)| w < lo || w > hi);
+                if surface.coating.is_some() || surface.absorption >= 1. || blocked {
+                    loss = 1.;
+                } else {
+                    n = surface.index;
+                    loss = surface.absorption.max(loss);
+                }
+                next += 1;
+            }
+            cells[column * rows + i as usize] = Vec2::new(n, loss);
+        }
+    }
+    cells
+}
@@ -0,0 +1,85 @@
+use bevy::{prelude::*, render::{render_resource::*, RenderApp}};
+
+use crate::{Scale, SCENE_H, SCENE_W};
+
+mod bpm;
+mod bvh;
+mod fdtd;
+mod handoff;
+mod interference;
+mod medium;
+mod monte_carlo;
+mod rays;
+mod readback;
+mod shaders;
+mod tracer;
+mod types;
+
+// Invocations per workgroup along each axis of the 2D kernels
+pub const WORKGROUP_SIZE: u32 = 8;
+
+/// Compute-shader subsystems. Each one renders into a storage texture shown on a sprite
+/// covering the table; F6 shows and hides them. Needs the `Scale` resource inserted first.
+pub struct GpuPlugin;
+
+impl Plugin for GpuPlugin {
+    fn build(&self, app: &mut App) {
+        // WebGL2 has no compute shaders
+        if cfg!(target_arch = "wasm32") {
+            return
+        }
+        // the render world sizes its buffers from the scale too
+        let scale = *app.world.resource::<Scale>();
+        app.sub_app_mut(RenderApp).insert_resource(scale);
+        app.add_plugin(bvh::BvhPlugin)
+            .add_plugin(rays::RayTexturePlugin)
+            .add_plugin(tracer::GpuTracerPlugin)
+            .add_plugin(bpm::BpmPlugin)
+            .add_plugin(fdtd::FdtdPlugin)
+            .add_plugin(interference::InterferencePlugin)
+            .add_plugin(monte_carlo::MonteCarloPlugin)
+            .add_plugin(readback::ReadbackPlugin)
+            .add_plugin(shaders::ShaderReloadPlugin);
+    }
+}
+
+/// Size of the GPU textures, which cover the table at the default zoom's resolution.
+pub fn texture_size(scale: &Scale) -> UVec2 {
+    (Vec2::new(SCENE_W, SCENE_H) * scale.px_per_mm).as_uvec2()
+}
+
+/// Workgroups needed to cover `size` invocations.
+pub fn workgroups(size: u32) -> u32 {
+    size.div_ceil(WORKGROUP_SIZE)
+}
+
+/// Transparent texture a compute shader can write and a sprite can sample.
+pub fn storage_image(width: u32, height: u32, format: TextureFormat) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1
+        },
+        TextureDimension::D2,
+        &vec![0; format.describe().block_size as usize],
+        format
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    image
+}
+
+/// Sprite showing `image` stretched over the table, hidden until toggled.
+pub fn table_sprite(image: Handle<Image>) -> SpriteBundle {
+    SpriteBundle {
+        sprite: Sprite {
+            custom_size: Some(Vec2::new(SCENE_W, SCENE_H)),
+            ..default()
+        },
+        texture: image,
+        // above the grid and lyon shapes
+        transform: Transform::from_xyz(SCENE_W / 2., SCENE_H / 2., 1.),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    }
+}
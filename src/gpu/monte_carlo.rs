@@ -0,0 +1,339 @@
+use std::borrow::Cow;
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage
+    }
+};
+
+use super::{storage_image, types::{MonteCarloParams, PhotonLaunch}, workgroups};
+use crate::{config::Config, turbid::TurbidMedium, RaySegment};
+
+// Fluence grid cells along each side of the medium
+pub const FLUENCE_N: u32 = 256;
+// Photon packets launched per frame; a multiple of the simulate kernel's workgroup size
+const PHOTONS_PER_FRAME: u32 = 1 << 16;
+const PACKET_WORKGROUP: u32 = 64;
+// Stop once this many packets have run; the image has converged by then
+const MAX_PHOTONS: u32 = 1 << 26;
+
+type MediumChanged = Or<(Changed<TurbidMedium>, Changed<RaySegment>)>;
+
+/// Photon launches into the first turbid medium, taken from the rays that enter it.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct MonteCarlo {
+    pub image: Handle<Image>,
+    pub medium: Option<TurbidMedium>,
+    pub launches: Vec<PhotonLaunch>,
+    pub params: MonteCarloParams,
+    pub enabled: bool
+}
+
+/// Packets launched since the launches last changed, counting this frame's.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct PhotonCount(pub u32);
+
+#[derive(Component)]
+pub struct MonteCarloSprite;
+
+pub struct MonteCarloPlugin;
+
+impl Plugin for MonteCarloPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<MonteCarlo>::default())
+            .add_plugin(ExtractResourcePlugin::<PhotonCount>::default())
+            .init_resource::<PhotonCount>()
+            .add_startup_system(setup_monte_carlo_system)
+            .add_system(monte_carlo_toggle_system)
+            .add_system(monte_carlo_scene_system.after(monte_carlo_toggle_system))
+            .add_system(photon_count_system.after(monte_carlo_scene_system))
+            .add_system(monte_carlo_sprite_system.after(monte_carlo_scene_system));
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<MonteCarloPipeline>()
+            .add_system_to_stage(RenderStage::Queue, queue_monte_carlo_bind_group);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("monte_carlo", MonteCarloNode);
+        render_graph.add_node_edge("monte_carlo", CAMERA_DRIVER).unwrap();
+    }
+}
+
+fn setup_monte_carlo_system(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>
+) {
+    let image = images.add(storage_image(FLUENCE_N, FLUENCE_N, TextureFormat::Rgba8Unorm));
+    commands.spawn((SpriteBundle {
+        texture: image.clone(),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    }, MonteCarloSprite));
+    commands.insert_resource(MonteCarlo {
+        image,
+        medium: None,
+        launches: Vec::new(),
+        params: MonteCarloParams::default(),
+        enabled: false
+    });
+}
+
+/// F11 switches the Monte Carlo fluence view on and off.
+fn monte_carlo_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut monte_carlo: ResMut<MonteCarlo>
+) {
+    if keys.just_pressed(KeyCode::F11) {
+        monte_carlo.enabled = !monte_carlo.enabled;
+        // rebuilt from the current scene when switched back on
+        monte_carlo.medium = None;
+    }
+}
+
+fn monte_carlo_scene_system(
+    config: Res<Config>,
+    mut monte_carlo: ResMut<MonteCarlo>,
+    changed: Query<(), MediumChanged>,
+    medium_query: Query<&TurbidMedium>,
+    segment_query: Query<&RaySegment>
+) {
+    if !monte_carlo.enabled || (monte_carlo.medium.is_some() && changed.is_empty()) {
+        return
+    }
+    let Some(medium) = medium_query.iter().next() else {
+        return
+    };
+    let entries = medium.entries(segment_query.iter());
+    let color = match entries.first() {
+        Some(entry) => config.palette.color(entry.wavelength),
+        None => config.theme.surface()
+    };
+    monte_carlo.launches = entries.iter().map(|entry| PhotonLaunch {
+        p: entry.point,
+        l: entry.direction,
+        weight: entry.weight
+    }).collect();
+    monte_carlo.params = MonteCarloParams {
+        min: medium.min,
+        max: medium.max,
+        mu_a: medium.mu_a,
+        mu_s: medium.mu_s,
+        g: medium.g,
+        launches: entries.len() as u32,
+        photons: 0,
+        seed: 0,
+        size: FLUENCE_N,
+        color: Vec4::from(color.as_linear_rgba_f32())
+    };
+    monte_carlo.medium = Some(medium.clone());
+}
+
+fn photon_count_system(
+    monte_carlo: Res<MonteCarlo>,
+    mut count: ResMut<PhotonCount>
+) {
+    if monte_carlo.is_changed() {
+        count.0 = PHOTONS_PER_FRAME;
+    } else if monte_carlo.enabled && count.0 < MAX_PHOTONS {
+        count.0 += PHOTONS_PER_FRAME;
+    }
+}
+
+/// Lays the fluence texture over the medium.
+fn monte_carlo_sprite_system(
+    monte_carlo: Res<MonteCarlo>,
+    mut sprite_query: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<MonteCarloSprite>>
+) {
+    if !monte_carlo.is_changed() {
+        return
+    }
+    for (mut sprite, mut transform, mut visibility) in sprite_query.iter_mut() {
+        visibility.is_visible = monte_carlo.enabled && monte_carlo.medium.is_some();
+        if let Some(medium) = &monte_carlo.medium {
+            let center = (medium.min + medium.max) / 2.;
+            sprite.custom_size = Some(medium.size());
+            *transform = Transform::from_xyz(center.x, center.y, 1.);
+        }
+    }
+}
+
+#[derive(Resource)]
+struct MonteCarloPipeline {
+    layout: BindGroupLayout,
+    simulate: CachedComputePipelineId,
+    resolve: CachedComputePipelineId
+}
+
+impl FromWorld for MonteCarloPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let storage = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None
+        };
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("monte_carlo_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba8Unorm,
+                        view_dimension: TextureViewDimension::D2
+                    },
+                    count: None
+                },
+                storage(1, true),
+                storage(2, false),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(MonteCarloParams::min_size())
+                    },
+                    count: None
+                }
+            ]
+        });
+        let shader = world.resource::<AssetServer>().load("monte_carlo.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue = |entry_point: &'static str| pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from(entry_point)),
+            layout: Some(vec![layout.clone()]),
+            shader: shader.clone(),
+            shader_defs: vec![],
+            entry_point: Cow::from(entry_point)
+        });
+        Self {
+            simulate: queue("simulate"),
+            resolve: queue("resolve"),
+            layout
+        }
+    }
+}
+
+/// Launch and fluence buffers of the current scene; the fluence keeps accumulating on the
+/// GPU until the launches change.
+struct MonteCarloBuffers {
+    launches: StorageBuffer<Vec<PhotonLaunch>>,
+    fluence: StorageBuffer<Vec<u32>>
+}
+
+/// Bind group, and whether this frame launches new packets.
+#[derive(Resource)]
+struct MonteCarloBindGroup(BindGroup, bool);
+
+/// Rebuilds the buffers when the launches change and binds this frame's packet count.
+#[allow(clippy::too_many_arguments)]
+fn queue_monte_carlo_bind_group(
+    mut commands: Commands,
+    pipeline: Res<MonteCarloPipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    monte_carlo: Res<MonteCarlo>,
+    count: Res<PhotonCount>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut buffers: Local<Option<MonteCarloBuffers>>
+) {
+    commands.remove_resource::<MonteCarloBindGroup>();
+    if monte_carlo.is_changed() {
+        *buffers = None;
+    }
+    if !monte_carlo.enabled || monte_carlo.medium.is_none() {
+        return
+    }
+    let Some(image) = gpu_images.get(&monte_carlo.image) else {
+        return
+    };
+    let buffers = buffers.get_or_insert_with(|| {
+        // storage bindings can't be empty
+        let mut launches = monte_carlo.launches.clone();
+        if launches.is_empty() {
+            launches.push(PhotonLaunch::default());
+        }
+        let mut launches = StorageBuffer::from(launches);
+        launches.write_buffer(&device, &queue);
+        let mut fluence = StorageBuffer::from(vec![0u32; (FLUENCE_N * FLUENCE_N) as usize]);
+        fluence.write_buffer(&device, &queue);
+        MonteCarloBuffers { launches, fluence }
+    });
+    let mut params = UniformBuffer::from(MonteCarloParams {
+        photons: count.0,
+        seed: count.0 / PHOTONS_PER_FRAME,
+        ..monte_carlo.params
+    });
+    params.write_buffer(&device, &queue);
+    let (Some(launches), Some(fluence), Some(params)) = (buffers.launches.binding(), buffers.fluence.binding(), params.binding()) else {
+        return
+    };
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("monte_carlo"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view)
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: launches
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: fluence
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: params
+            }
+        ]
+    });
+    commands.insert_resource(MonteCarloBindGroup(bind_group, count.is_changed()));
+}
+
+struct MonteCarloNode;
+
+impl render_graph::Node for MonteCarloNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(MonteCarloBindGroup(bind_group, launch)) = world.get_resource::<MonteCarloBindGroup>() else {
+            return Ok(())
+        };
+        let pipeline = world.resource::<MonteCarloPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let (Some(simulate), Some(resolve)) = (
+            pipeline_cache.get_compute_pipeline(pipeline.simulate),
+            pipeline_cache.get_compute_pipeline(pipeline.resolve)
+        ) else {
+            return Ok(())
+        };
+        let mut pass = render_context.command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("monte_carlo")
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+        if *launch {
+            pass.set_pipeline(simulate);
+            pass.dispatch_workgroups(PHOTONS_PER_FRAME / PACKET_WORKGROUP, 1, 1);
+        }
+        pass.set_pipeline(resolve);
+        pass.dispatch_workgroups(workgroups(FLUENCE_N), workgroups(FLUENCE_N), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use std::borrow::Cow;
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage
+    }
+};
+
+use super::{storage_image, table_sprite, texture_size, types::{GpuSegment, RayParams}, workgroups};
+use crate::{config::{Config, FluenceMap}, RaySegment, Scale, SCENE_H, SCENE_W};
+
+// Fluence, in full-intensity texel crossings, that maps to 1 - 1/e of full brightness
+const EXPOSURE: f32 = 1.;
+// Invocations per workgroup of the accumulate kernel, one per segment
+const SEGMENT_WORKGROUP: u32 = 64;
+
+/// Ray segments the kernel histograms whenever they change, and the texture the tonemapped
+/// fluence is drawn into.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct RayTexture {
+    pub image: Handle<Image>,
+    pub segments: Vec<GpuSegment>,
+    pub exposure: f32,
+    pub map: FluenceMap,
+    pub enabled: bool
+}
+
+#[derive(Component)]
+pub struct RayTextureSprite;
+
+pub struct RayTexturePlugin;
+
+impl Plugin for RayTexturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<RayTexture>::default())
+            .add_startup_system(setup_ray_texture_system)
+            .add_system(ray_texture_toggle_system)
+            .add_system(collect_segments_system);
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<RayPipeline>()
+            .add_system_to_stage(RenderStage::Queue, queue_ray_bind_group);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("ray_texture", RayNode);
+        render_graph.add_node_edge("ray_texture", CAMERA_DRIVER).unwrap();
+    }
+}
+
+fn setup_ray_texture_system(
+    mut commands: Commands,
+    scale: Res<Scale>,
+    mut images: ResMut<Assets<Image>>
+) {
+    let size = texture_size(&scale);
+    let image = images.add(storage_image(size.x, size.y, TextureFormat::Rgba8Unorm));
+    commands.spawn((table_sprite(image.clone()), RayTextureSprite));
+    commands.insert_resource(RayTexture {
+        image,
+        segments: Vec::new(),
+        exposure: EXPOSURE,
+        map: FluenceMap::default(),
+        enabled: false
+    });
+}
+
+/// F6 switches between the vector rays and the GPU-histogrammed ray texture; Shift+F6 is the
+/// GPU tracer's.
+fn ray_texture_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut texture: ResMut<RayTexture>,
+    mut sprite_query: Query<&mut Visibility, With<RayTextureSprite>>
+) {
+    if keys.just_pressed(KeyCode::F6) && !keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        texture.enabled = !texture.enabled;
+        for mut visibility in sprite_query.iter_mut() {
+            visibility.is_visible = texture.enabled;
+        }
+    }
+}
+
+fn collect_segments_system(
+    config: Res<Config>,
+    mut texture: ResMut<RayTexture>,
+    changed: Query<(), Changed<RaySegment>>,
+    removed: RemovedComponents<RaySegment>,
+    segment_query: Query<&RaySegment>
+) {
+    if !config.is_changed() && changed.is_empty() && removed.iter().next().is_none() {
+        return
+    }
+    texture.map = config.fluence_map;
+    texture.segments = segment_query.iter().map(|segment| GpuSegment {
+        p0: segment.from,
+        p1: segment.to,
+        color: Vec4::from(config.palette.color(segment.wavelength).as_linear_rgba_f32()) * segment.intensity
+    }).collect();
+}
+
+#[derive(Resource)]
+struct RayPipeline {
+    layout: BindGroupLayout,
+    accumulate: CachedComputePipelineId,
+    resolve: CachedComputePipelineId,
+    /// RGB and power fluence per texel, as fixed-point atomics
+    fluence: Buffer,
+    // keeps the colormap import loaded
+    _colormap: Handle<Shader>
+}
+
+impl FromWorld for RayPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let size = texture_size(world.resource::<Scale>());
+        let device = world.resource::<RenderDevice>();
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ray_texture_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba8Unorm,
+                        view_dimension: TextureViewDimension::D2
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(GpuSegment::min_size())
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(RayParams::min_size())
+                    },
+                    count: None
+                }
+            ]
+        });
+        let fluence = device.create_buffer(&BufferDescriptor {
+            label: Some("ray_fluence"),
+            size: 4 * 4 * size.x as u64 * size.y as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+        let asset_server = world.resource::<AssetServer>();
+        let (shader, colormap) = (asset_server.load("rays.wgsl"), asset_server.load("colormap.wgsl"));
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue = |entry_point: &'static str| pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from(format!("ray_texture_{entry_point}"))),
+            layout: Some(vec![layout.clone()]),
+            shader: shader.clone(),
+            shader_defs: vec![],
+            entry_point: Cow::from(entry_point)
+        });
+        Self {
+            accumulate: queue("accumulate"),
+            resolve: queue("resolve"),
+            layout,
+            fluence,
+            _colormap: colormap
+        }
+    }
+}
+
+/// Bind group, and whether the fluence is cleared and re-accumulated this frame.
+#[derive(Resource)]
+struct RayBindGroup(BindGroup, bool);
+
+/// Uploads the segments once per change; they stay on the GPU until the next one.
+#[allow(clippy::too_many_arguments)]
+fn queue_ray_bind_group(
+    mut commands: Commands,
+    pipeline: Res<RayPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<Image>>,
+    texture: Res<RayTexture>,
+    scale: Res<Scale>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut segments: Local<Option<StorageBuffer<Vec<GpuSegment>>>>,
+    mut pending: Local<bool>
+) {
+    commands.remove_resource::<RayBindGroup>();
+    if texture.is_changed() {
+        *segments = None;
+        *pending = true;
+    }
+    let Some(image) = gpu_images.get(&texture.image) else {
+        return
+    };
+    if !texture.enabled {
+        return
+    }
+    let segments = segments.get_or_insert_with(|| {
+        // storage bindings can't be empty
+        let mut segments = texture.segments.clone();
+        if segments.is_empty() {
+            segments.push(GpuSegment::default());
+        }
+        let mut segments = StorageBuffer::from(segments);
+        segments.write_buffer(&device, &queue);
+        segments
+    });
+    let size = texture_size(&scale);
+    let mut params = UniformBuffer::from(RayParams {
+        origin: Vec2::new(0., SCENE_H),
+        mm_per_texel: SCENE_W / size.x as f32,
+        exposure: texture.exposure,
+        count: texture.segments.len() as u32,
+        size,
+        colormap: (texture.map == FluenceMap::Inferno) as u32
+    });
+    params.write_buffer(&device, &queue);
+    let (Some(segments), Some(params)) = (segments.binding(), params.binding()) else {
+        return
+    };
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("ray_texture"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view)
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: segments
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: pipeline.fluence.as_entire_binding()
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: params
+            }
+        ]
+    });
+    // the node runs neither pass until both have compiled, so keep the segments pending till then
+    let ready = pipeline_cache.get_compute_pipeline(pipeline.accumulate).is_some()
+        && pipeline_cache.get_compute_pipeline(pipeline.resolve).is_some();
+    commands.insert_resource(RayBindGroup(bind_group, *pending && ready));
+    if ready {
+        *pending = false;
+    }
+}
+
+struct RayNode;
+
+impl render_graph::Node for RayNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(RayBindGroup(bind_group, accumulate)) = world.get_resource::<RayBindGroup>() else {
+            return Ok(())
+        };
+        let pipeline = world.resource::<RayPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let (Some(accumulate_pipeline), Some(resolve)) = (
+            pipeline_cache.get_compute_pipeline(pipeline.accumulate),
+            pipeline_cache.get_compute_pipeline(pipeline.resolve)
+        ) else {
+            return Ok(())
+        };
+        if *accumulate {
+            render_context.command_encoder.clear_buffer(&pipeline.fluence, 0, None);
+        }
+        let mut pass = render_context.command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("ray_texture")
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+        if *accumulate {
+            let count = world.resource::<RayTexture>().segments.len() as u32;
+            pass.set_pipeline(accumulate_pipeline);
+            pass.dispatch_workgroups(count.div_ceil(SEGMENT_WORKGROUP).max(1), 1, 1);
+        }
+        pass.set_pipeline(resolve);
+        let size = texture_size(world.resource::<Scale>());
+        pass.dispatch_workgroups(workgroups(size.x), workgroups(size.y), 1);
+        Ok(())
+    }
+}
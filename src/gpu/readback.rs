@@ -0,0 +1,266 @@
+use std::{
+    num::NonZeroU32,
+    sync::{mpsc::{channel, Receiver, Sender}, Mutex}
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        RenderApp, RenderStage
+    }
+};
+
+use super::{bpm::BpmField, fdtd::FdtdRegion, interference::InterferenceTexture, monte_carlo::MonteCarlo, rays::RayTexture, tracer::GpuTracer};
+
+// Rows of a texture copied into a buffer must start on this alignment (bytes)
+const ROW_ALIGNMENT: u32 = 256;
+
+/// Asks for a CPU copy of a GPU texture. The answer comes back as a [`ReadbackEvent`] with
+/// the same label, usually a frame or two later.
+pub struct ReadbackRequest {
+    pub label: &'static str,
+    pub image: Handle<Image>
+}
+
+/// Pixels of a texture read back from the GPU.
+pub struct ReadbackEvent {
+    pub label: &'static str,
+    pub image: Image
+}
+
+/// Requests made this frame, handed to the render world.
+#[derive(Resource, Clone, Default, ExtractResource)]
+struct ReadbackQueue(Vec<(&'static str, Handle<Image>)>);
+
+/// Texture copy in flight: queued in the render world, copied by the render graph, mapped after
+/// the frame is submitted and finally read on the main world once the map completes.
+struct ReadbackCopy {
+    label: &'static str,
+    texture: Texture,
+    buffer: Buffer,
+    size: Extent3d,
+    format: TextureFormat,
+    padded_row: u32
+}
+
+#[derive(Resource, Default)]
+struct ReadbackCopies(Vec<ReadbackCopy>);
+
+#[derive(Resource)]
+struct ReadbackSender(Sender<ReadbackCopy>);
+
+#[derive(Resource)]
+struct ReadbackReceiver(Mutex<Receiver<ReadbackCopy>>);
+
+pub struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = channel();
+        app.add_event::<ReadbackRequest>()
+            .add_event::<ReadbackEvent>()
+            .add_plugin(ExtractResourcePlugin::<ReadbackQueue>::default())
+            .init_resource::<ReadbackQueue>()
+            .insert_resource(ReadbackReceiver(Mutex::new(receiver)))
+            .add_system(export_request_system)
+            .add_system(readback_request_system.after(export_request_system))
+            .add_system(readback_receive_system)
+            .add_system(export_save_system.after(readback_receive_system));
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<ReadbackCopies>()
+            .insert_resource(ReadbackSender(sender))
+            .add_system_to_stage(RenderStage::Queue, queue_readbacks)
+            .add_system_to_stage(RenderStage::Cleanup, map_readbacks);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("readback", ReadbackNode);
+        // copy after every compute view has drawn
+        for node in ["ray_texture", "bpm", "fdtd", "interference", "monte_carlo"] {
+            render_graph.add_node_edge(node, "readback").unwrap();
+        }
+        render_graph.add_node_edge("readback", CAMERA_DRIVER).unwrap();
+    }
+}
+
+fn readback_request_system(
+    mut reader: EventReader<ReadbackRequest>,
+    mut queue: ResMut<ReadbackQueue>
+) {
+    let requests: Vec<_> = reader.iter().map(|request| (request.label, request.image.clone())).collect();
+    // only touch the queue when needed, since every change is extracted
+    if !requests.is_empty() || !queue.0.is_empty() {
+        queue.0 = requests;
+    }
+}
+
+fn readback_receive_system(
+    receiver: Res<ReadbackReceiver>,
+    mut writer: EventWriter<ReadbackEvent>
+) {
+    let Ok(receiver) = receiver.0.lock() else {
+        return
+    };
+    for copy in receiver.try_iter() {
+        let row = copy.size.width * copy.format.describe().block_size as u32;
+        let data = {
+            let mapped = copy.buffer.slice(..).get_mapped_range();
+            mapped.chunks(copy.padded_row as usize)
+                .take(copy.size.height as usize)
+                .flat_map(|padded| &padded[..row as usize])
+                .copied()
+                .collect()
+        };
+        copy.buffer.unmap();
+        writer.send(ReadbackEvent {
+            label: copy.label,
+            image: Image::new(copy.size, TextureDimension::D2, data, copy.format)
+        });
+    }
+}
+
+/// F10 reads back every GPU view that's switched on, for [`export_save_system`] to save.
+#[allow(clippy::too_many_arguments)]
+fn export_request_system(
+    keys: Res<Input<KeyCode>>,
+    rays: Res<RayTexture>,
+    bpm: Res<BpmField>,
+    fdtd: Res<FdtdRegion>,
+    interference: Res<InterferenceTexture>,
+    monte_carlo: Res<MonteCarlo>,
+    tracer: Res<GpuTracer>,
+    mut writer: EventWriter<ReadbackRequest>
+) {
+    if !keys.just_pressed(KeyCode::F10) {
+        return
+    }
+    let views = [
+        ("rays", rays.enabled, &rays.image),
+        ("bpm", bpm.enabled, &bpm.image),
+        ("fdtd", fdtd.enabled, &fdtd.image),
+        ("interference", interference.enabled, &interference.image),
+        ("monte_carlo", monte_carlo.enabled, &monte_carlo.image),
+        ("tracer", tracer.enabled, &tracer.image)
+    ];
+    for (label, enabled, image) in views {
+        if enabled {
+            writer.send(ReadbackRequest {
+                label,
+                image: image.clone()
+            });
+        }
+    }
+}
+
+/// Saves read-back views to `beams-<view>.png` in the working directory.
+fn export_save_system(
+    mut reader: EventReader<ReadbackEvent>
+) {
+    for event in reader.iter() {
+        let path = format!("beams-{}.png", event.label);
+        let mut image = event.image.clone();
+        // same bytes; try_into_dynamic only knows the sRGB variant
+        if image.texture_descriptor.format == TextureFormat::Rgba8Unorm {
+            image.texture_descriptor.format = TextureFormat::Rgba8UnormSrgb;
+        }
+        match image.try_into_dynamic() {
+            Ok(image) => match image.save(&path) {
+                Ok(()) => info!("exported {}", path),
+                Err(err) => error!("could not write {}: {}", path, err)
+            },
+            Err(err) => error!("could not convert {} view: {}", event.label, err)
+        }
+    }
+}
+
+/// Allocates a staging buffer for each texture asked for this frame.
+fn queue_readbacks(
+    queue: Res<ReadbackQueue>,
+    gpu_images: Res<RenderAssets<Image>>,
+    device: Res<RenderDevice>,
+    mut copies: ResMut<ReadbackCopies>
+) {
+    if !queue.is_changed() {
+        return
+    }
+    for (label, handle) in queue.0.iter() {
+        let Some(image) = gpu_images.get(handle) else {
+            continue
+        };
+        let size = Extent3d {
+            width: image.size.x as u32,
+            height: image.size.y as u32,
+            depth_or_array_layers: 1
+        };
+        let row = size.width * image.texture_format.describe().block_size as u32;
+        let padded_row = row.div_ceil(ROW_ALIGNMENT) * ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("readback"),
+            size: (padded_row * size.height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+        copies.0.push(ReadbackCopy {
+            label,
+            texture: image.texture.clone(),
+            buffer,
+            size,
+            format: image.texture_format,
+            padded_row
+        });
+    }
+}
+
+/// Maps this frame's staging buffers once the frame is submitted. wgpu completes the map on a
+/// later submit, and the callback passes the copy on to the main world.
+fn map_readbacks(
+    sender: Res<ReadbackSender>,
+    mut copies: ResMut<ReadbackCopies>
+) {
+    for copy in copies.0.drain(..) {
+        let sender = sender.0.clone();
+        let buffer = copy.buffer.clone();
+        buffer.slice(..).map_async(MapMode::Read, move |result| match result {
+            Ok(()) => {
+                sender.send(copy).ok();
+            }
+            Err(err) => error!("could not read back {}: {}", copy.label, err)
+        });
+    }
+}
+
+struct ReadbackNode;
+
+impl render_graph::Node for ReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World
+    ) -> Result<(), render_graph::NodeRunError> {
+        for copy in world.resource::<ReadbackCopies>().0.iter() {
+            render_context.command_encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture: &copy.texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All
+                },
+                ImageCopyBuffer {
+                    buffer: &copy.buffer,
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: NonZeroU32::new(copy.padded_row),
+                        rows_per_image: None
+                    }
+                },
+                copy.size
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::{mpsc::{channel, Receiver, Sender}, Mutex};
+
+use bevy::{
+    prelude::*,
+    render::{render_resource::*, RenderApp, RenderStage}
+};
+
+use super::{bpm::BpmField, fdtd::FdtdRegion, monte_carlo::MonteCarlo, tracer::GpuTracer};
+use crate::{locale::Locale, ui::{self, UiFont}};
+
+/// Compute pipelines that failed to build, as (pipeline, message). Cleared once the shader is
+/// fixed and reloads.
+#[derive(Resource, Default, PartialEq)]
+pub struct ShaderErrors(pub Vec<(String, String)>);
+
+#[derive(Resource)]
+struct ShaderErrorSender(Sender<Vec<(String, String)>>);
+
+#[derive(Resource)]
+struct ShaderErrorReceiver(Mutex<Receiver<Vec<(String, String)>>>);
+
+#[derive(Component)]
+struct ShaderErrorPanel;
+
+/// Surfaces WGSL compile errors in-app. The pipeline cache already rebuilds pipelines when
+/// a watched shader file changes; this restarts the kernels that only run on demand.
+pub struct ShaderReloadPlugin;
+
+impl Plugin for ShaderReloadPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = channel();
+        app.init_resource::<ShaderErrors>()
+            .insert_resource(ShaderErrorReceiver(Mutex::new(receiver)))
+            .add_system(shader_reload_system)
+            .add_system(shader_error_receive_system)
+            .add_system(shader_error_panel_system.after(shader_error_receive_system));
+        app.sub_app_mut(RenderApp)
+            .insert_resource(ShaderErrorSender(sender))
+            .add_system_to_stage(RenderStage::Cleanup, collect_shader_errors);
+    }
+}
+
+/// Reruns the BPM and restarts the FDTD region, Monte Carlo and GPU tracer when any shader
+/// changes on disk.
+fn shader_reload_system(
+    mut events: EventReader<AssetEvent<Shader>>,
+    mut bpm: ResMut<BpmField>,
+    mut fdtd: ResMut<FdtdRegion>,
+    mut monte_carlo: ResMut<MonteCarlo>,
+    mut tracer: ResMut<GpuTracer>
+) {
+    let mut modified = false;
+    for event in events.iter() {
+        if let AssetEvent::Modified { .. } = event {
+            modified = true;
+        }
+    }
+    if modified {
+        info!("shader changed, reloading");
+        bpm.set_changed();
+        fdtd.window = None;
+        monte_carlo.medium = None;
+        tracer.set_changed();
+    }
+}
+
+/// Message for a failed pipeline, with the offending WGSL line when there is one.
+fn describe(error: &PipelineCacheError) -> String {
+    match error {
+        PipelineCacheError::AsModuleDescriptorError(
+            AsModuleDescriptorError::ShaderReflectError(ShaderReflectError::WgslParse(error)),
+            source
+        ) => error.emit_to_string(source.get_wgsl_source().unwrap_or_default()),
+        error => error.to_string()
+    }
+}
+
+/// Sends the current compute pipeline errors to the main world whenever they change.
+fn collect_shader_errors(
+    pipeline_cache: Res<PipelineCache>,
+    sender: Res<ShaderErrorSender>,
+    mut last: Local<Vec<(String, String)>>
+) {
+    let errors: Vec<(String, String)> = pipeline_cache.pipelines().filter_map(|pipeline| {
+        let (PipelineDescriptor::ComputePipelineDescriptor(descriptor), CachedPipelineState::Err(error)) = (&pipeline.descriptor, &pipeline.state) else {
+            return None
+        };
+        if matches!(error, PipelineCacheError::ShaderNotLoaded(_) | PipelineCacheError::ShaderImportNotYetAvailable) {
+            return None
+        }
+        Some((descriptor.label.as_deref().unwrap_or_default().to_string(), describe(error)))
+    }).collect();
+    if errors != *last {
+        sender.0.send(errors.clone()).ok();
+        *last = errors;
+    }
+}
+
+fn shader_error_receive_system(
+    receiver: Res<ShaderErrorReceiver>,
+    mut errors: ResMut<ShaderErrors>
+) {
+    let Ok(receiver) = receiver.0.lock() else {
+        return
+    };
+    if let Some(latest) = receiver.try_iter().last() {
+        errors.0 = latest;
+    }
+}
+
+fn shader_error_panel_system(
+    mut commands: Commands,
+    errors: Res<ShaderErrors>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<ShaderErrorPanel>>
+) {
+    if !errors.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if errors.0.is_empty() {
+        return
+    }
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(30.),
+                top: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }).insert(ShaderErrorPanel).with_children(|panel| {
+        for (pipeline, message) in errors.0.iter() {
+            panel.spawn(TextBundle::from_section(
+                locale.format("gpu.shader-error", &[("pipeline", pipeline)]),
+                ui::text_style(&font, ui::TEXT_COLOR)
+            ));
+            panel.spawn(TextBundle::from_section(message.clone(), ui::text_style(&font, Color::rgb(1.0, 0.3, 0.3))));
+        }
+    });
+}
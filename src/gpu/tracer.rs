@@ -0,0 +1,387 @@
+use std::borrow::Cow;
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage
+    }
+};
+
+use super::{
+    bvh::{self, BvhLayout, GpuBvh, SurfaceBvh}, storage_image, table_sprite, texture_size,
+    types::{TracerLaunch, TracerMaterial, TracerParams}, workgroups
+};
+use crate::{config::{Config, FluenceMap}, material::Material, units::Nanometers, BeamSource, Emission, Surface, Scale, SCENE_H, SCENE_W};
+
+// Launch rays per mm of beam waist; the kernel spreads the rays it traces between them
+const LAUNCH_DENSITY: f32 = 32.;
+// Fluence (mW/mm) that maps to 1 - 1/e of full brightness
+const EXPOSURE: f32 = 1.;
+// Rays traced per frame, as RAYS_PER_FRAME in the kernel, and invocations per workgroup
+const RAYS_PER_FRAME: u32 = 1 << 16;
+const RAY_WORKGROUP: u32 = 64;
+// Stop once this many rays have run; the image has converged by then
+const MAX_RAYS: u32 = 1 << 24;
+// Wavelength (nm) at which a coating's reflectance stands for all of them
+const COATING_WAVELENGTH: Nanometers = Nanometers(550.);
+
+/// Dense ray launches from every source and the optics of every surface, which the kernel
+/// traces through the BVH on the GPU, and the texture the fluence is drawn into.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct GpuTracer {
+    pub image: Handle<Image>,
+    pub launches: Vec<TracerLaunch>,
+    /// One per surface, in the BVH's order
+    pub materials: Vec<TracerMaterial>,
+    pub exposure: f32,
+    pub map: FluenceMap,
+    pub enabled: bool
+}
+
+#[derive(Component)]
+pub struct GpuTracerSprite;
+
+pub struct GpuTracerPlugin;
+
+impl Plugin for GpuTracerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ExtractResourcePlugin::<GpuTracer>::default())
+            .add_startup_system(setup_tracer_system)
+            .add_system(tracer_toggle_system)
+            .add_system_to_stage(CoreStage::PostUpdate, tracer_scene_system.after(bvh::surface_bvh_system));
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<TracerPipeline>()
+            .add_system_to_stage(RenderStage::Queue, queue_tracer_bind_group);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node("gpu_tracer", TracerNode);
+        render_graph.add_node_edge("gpu_tracer", CAMERA_DRIVER).unwrap();
+    }
+}
+
+fn setup_tracer_system(
+    mut commands: Commands,
+    scale: Res<Scale>,
+    mut images: ResMut<Assets<Image>>
+) {
+    let size = texture_size(&scale);
+    let image = images.add(storage_image(size.x, size.y, TextureFormat::Rgba8Unorm));
+    commands.spawn((table_sprite(image.clone()), GpuTracerSprite));
+    commands.insert_resource(GpuTracer {
+        image,
+        launches: Vec::new(),
+        materials: Vec::new(),
+        exposure: EXPOSURE,
+        map: FluenceMap::default(),
+        enabled: false
+    });
+}
+
+/// Shift+F6 switches the GPU-traced intensity field on and off.
+fn tracer_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut tracer: ResMut<GpuTracer>,
+    mut sprite_query: Query<&mut Visibility, With<GpuTracerSprite>>
+) {
+    if keys.just_pressed(KeyCode::F6) && keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        tracer.enabled = !tracer.enabled;
+        for mut visibility in sprite_query.iter_mut() {
+            visibility.is_visible = tracer.enabled;
+        }
+    }
+}
+
+/// What the kernel knows of a surface: index steps, dispersive bodies, partial reflection,
+/// absorption and thin lenses. Coatings reflect as they do at normal incidence; fluorescent
+/// layers and gratings, which send light off at other wavelengths or angles, block it instead.
+/// Polarizers and waveplates pass their share of unpolarized light, whichever way it goes, and
+/// roughness, harmonic generation, pickoffs, isolators and thermal lenses are left out.
+fn material(surface: &Surface) -> TracerMaterial {
+    let blocks = surface.fluorescence.is_some() || surface.grating.is_some();
+    let reflection = match &surface.coating {
+        Some(coating) => {
+            let (rs, rp) = coating.reflectance(COATING_WAVELENGTH, surface.index, -1.);
+            (rs + rp) / 2.
+        }
+        None => surface.reflection
+    };
+    // as on the CPU, only the glass of a body disperses
+    let (dispersion, b, c) = match surface.dispersion.filter(|_| surface.interior.is_some()) {
+        Some(Material::Sellmeier { b, c }) => (1, Vec3::from(b), Vec3::from(c)),
+        Some(Material::Cauchy { a, b, c }) => (2, Vec3::new(a, b, c), Vec3::ZERO),
+        None => (0, Vec3::ZERO, Vec3::ZERO)
+    };
+    TracerMaterial {
+        index: surface.index,
+        interior: surface.interior.unwrap_or(0.),
+        dispersion,
+        b,
+        c,
+        interior_absorption: surface.interior_absorption,
+        reflection,
+        absorption: if blocks { 1. } else if surface.coating.is_some() { 1. - reflection } else { surface.absorption },
+        transmittance: surface.transmittance() * surface.mueller.as_ref().map_or(1., |mueller| mueller.0.x_axis.x),
+        focal_length: surface.focal_length.map_or(0., |f| f.0),
+        passband: surface.passband.map_or(Vec2::ZERO, |(lo, hi)| Vec2::new(lo.0, hi.0.min(f32::MAX)))
+    }
+}
+
+/// Rebuilds the launches and materials whenever the sources or surfaces change, once the BVH
+/// they index into has been rebuilt.
+fn tracer_scene_system(
+    config: Res<Config>,
+    bvh: Res<SurfaceBvh>,
+    mut tracer: ResMut<GpuTracer>,
+    changed: Query<(), Changed<BeamSource>>,
+    removed: RemovedComponents<BeamSource>,
+    source_query: Query<&BeamSource>,
+    surface_query: Query<&Surface>
+) {
+    let scene_changed = config.is_changed() || bvh.is_changed() || !changed.is_empty() || removed.iter().next().is_some();
+    if !tracer.enabled || !(tracer.is_changed() || scene_changed) {
+        return
+    }
+    let mut launches = Vec::new();
+    for source in source_query.iter() {
+        let (jitter, turn) = match source.emission {
+            Emission::Point { rays, spread } => (Vec2::ZERO, spread.to_radians() / rays.max(1) as f32),
+            _ => (source.direction.normalize_or_zero().perp() / LAUNCH_DENSITY, 0.)
+        };
+        launches.extend(source.rays(LAUNCH_DENSITY).iter().map(|ray| TracerLaunch {
+            p: ray.p,
+            l: ray.l,
+            jitter,
+            turn,
+            index: ray.medium_index(),
+            w: ray.wavelength().0,
+            color: Vec4::from(config.palette.color(ray.wavelength()).as_linear_rgba_f32()) * ray.power
+        }));
+    }
+    tracer.launches = launches;
+    tracer.map = config.fluence_map;
+    tracer.materials = bvh.entities.iter()
+        .map(|entity| surface_query.get(*entity).map_or(TracerMaterial { absorption: 1., ..default() }, material))
+        .collect();
+}
+
+#[derive(Resource)]
+struct TracerPipeline {
+    layout: BindGroupLayout,
+    trace: CachedComputePipelineId,
+    resolve: CachedComputePipelineId,
+    /// RGB and power fluence per texel, as fixed-point atomics
+    fluence: Buffer,
+    // keeps the colormap import loaded
+    _colormap: Handle<Shader>
+}
+
+impl FromWorld for TracerPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let storage = |binding: u32, read_only: bool| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None
+            },
+            count: None
+        };
+        let size = texture_size(world.resource::<Scale>());
+        let device = world.resource::<RenderDevice>();
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("gpu_tracer_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba8Unorm,
+                        view_dimension: TextureViewDimension::D2
+                    },
+                    count: None
+                },
+                storage(1, true),
+                storage(2, true),
+                storage(3, false),
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(TracerParams::min_size())
+                    },
+                    count: None
+                }
+            ]
+        });
+        let fluence = device.create_buffer(&BufferDescriptor {
+            label: Some("gpu_tracer_fluence"),
+            size: 4 * 4 * size.x as u64 * size.y as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+        let bvh_layout = world.resource::<BvhLayout>().layout.clone();
+        let asset_server = world.resource::<AssetServer>();
+        let (shader, colormap) = (asset_server.load("tracer.wgsl"), asset_server.load("colormap.wgsl"));
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue = |entry_point: &'static str| pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(Cow::from(format!("gpu_tracer_{entry_point}"))),
+            layout: Some(vec![layout.clone(), bvh_layout.clone()]),
+            shader: shader.clone(),
+            shader_defs: vec![],
+            entry_point: Cow::from(entry_point)
+        });
+        Self {
+            trace: queue("trace"),
+            resolve: queue("resolve"),
+            layout,
+            fluence,
+            _colormap: colormap
+        }
+    }
+}
+
+/// Launch and material buffers of the current scene; the fluence keeps accumulating on the GPU
+/// until they change.
+struct TracerBuffers {
+    launches: StorageBuffer<Vec<TracerLaunch>>,
+    materials: StorageBuffer<Vec<TracerMaterial>>
+}
+
+/// Bind group, whether this frame traces new rays and whether it first clears the fluence.
+#[derive(Resource)]
+struct TracerBindGroup(BindGroup, bool, bool);
+
+/// Uploads the scene once per change and counts the rays traced since, so the fluence can be
+/// averaged over them.
+#[allow(clippy::too_many_arguments)]
+fn queue_tracer_bind_group(
+    mut commands: Commands,
+    (pipeline, pipeline_cache, scale): (Res<TracerPipeline>, Res<PipelineCache>, Res<Scale>),
+    gpu_images: Res<RenderAssets<Image>>,
+    tracer: Res<GpuTracer>,
+    bvh: Option<Res<GpuBvh>>,
+    (device, queue): (Res<RenderDevice>, Res<RenderQueue>),
+    mut buffers: Local<Option<TracerBuffers>>,
+    mut rays: Local<u32>
+) {
+    commands.remove_resource::<TracerBindGroup>();
+    if tracer.is_changed() {
+        *buffers = None;
+        *rays = 0;
+    }
+    let (true, Some(image), Some(_)) = (tracer.enabled && !tracer.launches.is_empty(), gpu_images.get(&tracer.image), bvh) else {
+        return
+    };
+    let buffers = buffers.get_or_insert_with(|| {
+        // storage bindings can't be empty
+        let mut materials = tracer.materials.clone();
+        if materials.is_empty() {
+            materials.push(TracerMaterial::default());
+        }
+        let mut launches = StorageBuffer::from(tracer.launches.clone());
+        launches.write_buffer(&device, &queue);
+        let mut materials = StorageBuffer::from(materials);
+        materials.write_buffer(&device, &queue);
+        TracerBuffers { launches, materials }
+    });
+    // the node runs neither pass until both have compiled, so count no rays till then
+    let ready = pipeline_cache.get_compute_pipeline(pipeline.trace).is_some()
+        && pipeline_cache.get_compute_pipeline(pipeline.resolve).is_some();
+    let launch = ready && *rays < MAX_RAYS;
+    if launch {
+        *rays += RAYS_PER_FRAME;
+    }
+    // nothing to average until the first rays are traced
+    if *rays == 0 {
+        return
+    }
+    let size = texture_size(&scale);
+    let mut params = UniformBuffer::from(TracerParams {
+        origin: Vec2::new(0., SCENE_H),
+        mm_per_texel: SCENE_W / size.x as f32,
+        exposure: tracer.exposure,
+        launches: tracer.launches.len() as u32,
+        rays: *rays,
+        size,
+        colormap: (tracer.map == FluenceMap::Inferno) as u32
+    });
+    params.write_buffer(&device, &queue);
+    let (Some(launches), Some(materials), Some(params)) = (buffers.launches.binding(), buffers.materials.binding(), params.binding()) else {
+        return
+    };
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("gpu_tracer"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view)
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: launches
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: materials
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: pipeline.fluence.as_entire_binding()
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: params
+            }
+        ]
+    });
+    commands.insert_resource(TracerBindGroup(bind_group, launch, launch && *rays == RAYS_PER_FRAME));
+}
+
+struct TracerNode;
+
+impl render_graph::Node for TracerNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World
+    ) -> Result<(), render_graph::NodeRunError> {
+        let (Some(TracerBindGroup(bind_group, launch, clear)), Some(bvh)) = (world.get_resource::<TracerBindGroup>(), world.get_resource::<GpuBvh>()) else {
+            return Ok(())
+        };
+        let pipeline = world.resource::<TracerPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let (Some(trace), Some(resolve)) = (
+            pipeline_cache.get_compute_pipeline(pipeline.trace),
+            pipeline_cache.get_compute_pipeline(pipeline.resolve)
+        ) else {
+            return Ok(())
+        };
+        if *clear {
+            render_context.command_encoder.clear_buffer(&pipeline.fluence, 0, None);
+        }
+        let mut pass = render_context.command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("gpu_tracer")
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_bind_group(1, &bvh.bind_group, &[]);
+        if *launch {
+            pass.set_pipeline(trace);
+            pass.dispatch_workgroups(RAYS_PER_FRAME / RAY_WORKGROUP, 1, 1);
+        }
+        pass.set_pipeline(resolve);
+        let size = texture_size(world.resource::<Scale>());
+        pass.dispatch_workgroups(workgroups(size.x), workgroups(size.y), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+// CPU-side mirrors of the structs the WGSL kernels read. encase's ShaderType derive expands
+// to a layout check per field that is never called, in items of its own that no attribute on
+// a struct or field reaches. The expectation fails the build once encase stops emitting them.
+#![expect(dead_code, reason = "never-called layout checks from #[derive(ShaderType)]")]
+
+use bevy::{prelude::*, render::render_resource::ShaderType};
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct GpuSegment {
+    pub p0: Vec2,
+    pub p1: Vec2,
+    /// Linear RGB and alpha, scaled by intensity
+    pub color: Vec4
+}
+
+#[derive(ShaderType, Clone, Copy)]
+pub struct RayParams {
+    /// Scene position (mm) of the texture's top-left corner
+    pub origin: Vec2,
+    pub mm_per_texel: f32,
+    /// Fluence that maps to 1 - 1/e of full brightness
+    pub exposure: f32,
+    pub count: u32,
+    pub size: UVec2,
+    /// 1 to draw the power through the inferno colormap, 0 in the rays' colors
+    pub colormap: u32
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct BpmParams {
+    /// Vacuum wavenumber (rad/mm)
+    pub k0: f32,
+    /// Reference index of the paraxial propagator
+    pub n0: f32,
+    /// Step along the axis and transverse sample spacing (mm)
+    pub dz: f32,
+    pub dy: f32,
+    pub columns: u32,
+    /// Linear RGB the intensity is drawn in
+    pub color: Vec4
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct FdtdParams {
+    /// c dt / dx
+    pub courant: f32,
+    /// Source angular frequency (rad per step)
+    pub omega: f32,
+    pub step: u32,
+    pub size: u32,
+    pub pml: u32,
+    pub source_column: u32,
+    /// Linear RGB positive Ez is drawn in
+    pub color: Vec4
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct CoherentSegment {
+    pub p0: Vec2,
+    pub p1: Vec2,
+    /// Phase (rad) at `p0`
+    pub phase: f32,
+    /// Wavenumber in the medium (rad/mm)
+    pub k: f32,
+    pub amplitude: f32,
+    /// Segments of one group are coherent with each other; groups add in intensity
+    pub group: u32,
+    /// Linear RGB of the group
+    pub color: Vec4
+}
+
+#[derive(ShaderType, Clone, Copy, Default, PartialEq)]
+pub struct InterferenceParams {
+    /// Scene position (mm) of the top-left texel corner
+    pub origin: Vec2,
+    pub mm_per_texel: f32,
+    /// 1/e half width (mm) of the beamlet each ray stands for
+    pub beamlet: f32,
+    pub exposure: f32,
+    /// Columns and rows of the tiles segments are binned into
+    pub tiles: UVec2
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct PhotonLaunch {
+    pub p: Vec2,
+    pub l: Vec2,
+    pub weight: f32
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct MonteCarloParams {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub mu_a: f32,
+    pub mu_s: f32,
+    pub g: f32,
+    pub launches: u32,
+    /// Packets launched so far, counting this frame's
+    pub photons: u32,
+    pub seed: u32,
+    pub size: u32,
+    /// Linear RGB the fluence is drawn in
+    pub color: Vec4
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct BvhNode {
+    pub min: Vec2,
+    pub max: Vec2,
+    /// Surface index for leaves, -1 for internal nodes
+    pub surface: i32,
+    /// Next node once this one's subtree is done or missed
+    pub skip: u32
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct BvhSurface {
+    pub p1: Vec2,
+    pub p2: Vec2,
+    /// 0 segment, 1 ray, 2 line
+    pub extent: u32,
+    /// As [`crate::Surface::curvature`]
+    pub curvature: f32
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct BvhInfo {
+    pub nodes: u32,
+    /// Surfaces in the tree; the rest are unbounded and tested one by one
+    pub bounded: u32,
+    pub surfaces: u32
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct TracerLaunch {
+    pub p: Vec2,
+    pub l: Vec2,
+    /// Span (mm) across which the start point is spread, centered on `p`
+    pub jitter: Vec2,
+    /// Full angle (rad) over which the direction is spread around `l`
+    pub turn: f32,
+    pub index: f32,
+    /// Wavelength (nm)
+    pub w: f32,
+    /// Linear RGB and alpha, scaled by power (mW)
+    pub color: Vec4
+}
+
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct TracerMaterial {
+    /// As [`crate::Surface::index`]
+    pub index: f32,
+    /// Index inside the body the surface bounds, 0 when it bounds none
+    pub interior: f32,
+    /// Dispersion model of the body's glass: 0 for none, 1 for Sellmeier, 2 for Cauchy
+    pub dispersion: u32,
+    /// Sellmeier B coefficients, or Cauchy's A, B and C
+    pub b: Vec3,
+    /// Sellmeier C coefficients (µm²)
+    pub c: Vec3,
+    pub interior_absorption: f32,
+    pub reflection: f32,
+    pub absorption: f32,
+    /// Fraction passed by an attenuator or polarizer, for unpolarized light
+    pub transmittance: f32,
+    /// Focal length (mm) of an ideal thin lens, 0 for none
+    pub focal_length: f32,
+    /// Transmitted band (nm), both 0 for none
+    pub passband: Vec2
+}
+
+#[derive(ShaderType, Clone, Copy)]
+pub struct TracerParams {
+    /// Scene position (mm) of the texture's top-left corner
+    pub origin: Vec2,
+    pub mm_per_texel: f32,
+    /// Fluence (mW/mm) that maps to 1 - 1/e of full brightness
+    pub exposure: f32,
+    pub launches: u32,
+    /// Rays traced so far, counting this frame's
+    pub rays: u32,
+    pub size: UVec2,
+    /// As [`RayParams::colormap`]
+    pub colormap: u32
+}
@@ -0,0 +1,161 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    config::Config, launch, polarization::Formalism, raycaster::Raycaster, scene::Scene, trace_limited, units::{Quantity, UnitSystem},
+    Ray, Surface, TraceSegment
+};
+
+/// One traced segment as written by `--headless`, in the configured units.
+#[derive(Serialize)]
+struct SegmentRecord<'a> {
+    /// Index of the source in the scene
+    source: usize,
+    /// Index of the ray among its source's
+    ray: usize,
+    depth: usize,
+    x: f32,
+    y: f32,
+    dx: f32,
+    dy: f32,
+    /// None for rays that hit nothing and leave the table
+    length: Option<f32>,
+    wavelength: f32,
+    /// Fraction of the emitted power left
+    intensity: f32,
+    power: f32,
+    /// Index of the surface the segment ends on, see [`Scene::surfaces`]
+    surface: Option<usize>,
+    label: Option<&'a str>
+}
+
+impl<'a> SegmentRecord<'a> {
+    fn of(source: usize, ray: usize, segment: &TraceSegment, surfaces: &'a [Surface], units: UnitSystem) -> Self {
+        let surface = segment.surface.index() as usize;
+        Self {
+            length: Some(segment.hit.distance * units.export_unit(Quantity::Length).0),
+            surface: Some(surface),
+            label: surfaces[surface].label.as_deref(),
+            ..Self::escaped(source, ray, &segment.ray, segment.depth, units)
+        }
+    }
+
+    /// An open-ended segment, of a ray that leaves the table without hitting anything.
+    fn escaped(source: usize, ray: usize, traced: &Ray, depth: usize, units: UnitSystem) -> Self {
+        let export = |quantity: Quantity, value: f32| value * units.export_unit(quantity).0;
+        Self {
+            source,
+            ray,
+            depth,
+            x: export(Quantity::Length, traced.p.x),
+            y: export(Quantity::Length, traced.p.y),
+            dx: traced.l.x,
+            dy: traced.l.y,
+            length: None,
+            wavelength: export(Quantity::Wavelength, traced.wavelength().0),
+            intensity: traced.i,
+            power: export(Quantity::Power, traced.power * traced.i),
+            surface: None,
+            label: None
+        }
+    }
+
+    fn csv_header(units: UnitSystem) -> String {
+        let unit = |quantity: Quantity| units.export_unit(quantity).1;
+        format!(
+            "source,ray,depth,x [{0}],y [{0}],dx,dy,length [{0}],wavelength [{1}],intensity,power [{2}],surface,label",
+            unit(Quantity::Length), unit(Quantity::Wavelength), unit(Quantity::Power)
+        )
+    }
+
+    fn csv_row(&self) -> String {
+        // open-ended segments leave the length and surface blank
+        let length = self.length.map_or(String::new(), |length| length.to_string());
+        let surface = self.surface.map_or(String::new(), |surface| surface.to_string());
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.source, self.ray, self.depth, self.x, self.y, self.dx, self.dy, length,
+            self.wavelength, self.intensity, self.power, surface, self.label.unwrap_or("")
+        )
+    }
+}
+
+/// Units of the values in a JSON dump.
+#[derive(Serialize)]
+struct Units {
+    length: &'static str,
+    wavelength: &'static str,
+    power: &'static str
+}
+
+#[derive(Serialize)]
+struct Dump<'a> {
+    units: Units,
+    segments: Vec<SegmentRecord<'a>>
+}
+
+/// Traces every source of `scene` to completion, emitted as the window emits them with the
+/// configured ray density, sampling and limits, and writes each segment to `out` as CSV or
+/// JSON, by its extension. Rays that hit nothing end their ray's segments as open-ended ones.
+/// Rays are scalar, as the window starts out. Turbid media only show as their boundaries, as
+/// their scattering is simulated in the window.
+pub fn trace_scene(scene: &Scene, out: &Path, config: &Config) -> Result<(), String> {
+    let surfaces = scene.surfaces();
+    let listed: Vec<(Entity, &Surface)> = surfaces.iter()
+        .enumerate()
+        .map(|(k, surface)| (Entity::from_raw(k as u32), surface))
+        .collect();
+    // only elements carry thermal lenses, and they come first
+    let lenses = |entity: Entity| scene.elements.get(entity.index() as usize).and_then(|element| element.thermal_lens.as_ref());
+    let raycaster = Raycaster::new(&listed);
+    let mut records: Vec<SegmentRecord> = Vec::new();
+    for (s, source) in scene.sources.iter().enumerate() {
+        for (r, ray) in launch(source, config, Formalism::Scalar, &raycaster).iter().enumerate() {
+            let trace = trace_limited(ray, &listed, lenses, config.limits());
+            records.extend(trace.segments.iter().map(|segment| SegmentRecord::of(s, r, segment, &surfaces, config.units)));
+            records.extend(trace.escaped.iter().map(|(escaped, depth)| SegmentRecord::escaped(s, r, escaped, *depth, config.units)));
+        }
+    }
+    let text = match out.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => {
+            let mut lines = vec![SegmentRecord::csv_header(config.units)];
+            lines.extend(records.iter().map(|record| record.csv_row()));
+            lines.join("\n") + "\n"
+        }
+        Some("json") => {
+            let unit = |quantity: Quantity| config.units.export_unit(quantity).1;
+            let dump = Dump {
+                units: Units {
+                    length: unit(Quantity::Length),
+                    wavelength: unit(Quantity::Wavelength),
+                    power: unit(Quantity::Power)
+                },
+                segments: records
+            };
+            serde_json::to_string_pretty(&dump).map_err(|err| err.to_string())?
+        }
+        _ => return Err("ray dumps end in .csv or .json".to_string())
+    };
+    fs::write(out, text).map_err(|err| err.to_string())
+}
+
+/// Handles `--headless <scene> <out>`: traces the scene file without a window and writes every
+/// ray segment to `out`. Returns the process exit code if it was given.
+pub fn run(args: &[String]) -> Option<i32> {
+    let [mode, scene, out, ..] = args else {
+        return None
+    };
+    if mode != "--headless" {
+        return None
+    }
+    let result = Scene::load(Path::new(scene)).and_then(|scene| trace_scene(&scene, Path::new(out), &Config::load()));
+    Some(match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("headless trace failed: {}", err);
+            1
+        }
+    })
+}
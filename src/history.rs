@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+
+use crate::{
+    animation::{Clock, Track}, aperture::Aperture, bodies::Body, config::Config, polarization::Formalism, prisms::AnamorphicPair,
+    scene::{Extras, Scene, SceneEntity, SceneItem}, tabs::Tabs, turbid::TurbidMedium, BeamSource, RaycastEvent, Surface
+};
+
+// Edits kept to undo; older ones are forgotten
+const MAX_UNDO: usize = 100;
+
+type SceneChanged = Or<(
+    Changed<Surface>, Changed<BeamSource>, Changed<AnamorphicPair>, Changed<Body>, Changed<Aperture>, Changed<TurbidMedium>
+)>;
+type Removed<'w, 's> = (RemovedComponents<'w, Surface>, RemovedComponents<'w, BeamSource>);
+/// Changed scene items, with what an animation could be moving
+type Changes<'w, 's> = Query<'w, 's, (Option<&'static Surface>, Option<&'static Track>), SceneChanged>;
+
+/// Undo and redo of scene edits, as snapshots of the scene before and after each one. An edit
+/// is whatever changes the scene between two moments it is at rest: a drag counts once it is
+/// let go, a placed element, a deleted one or a value set in the palette or inspector as soon
+/// as it is done. Ctrl+Z undoes the last edit and Ctrl+Shift+Z redoes it. Each tab starts a
+/// new history when it is shown.
+#[derive(Resource, Default)]
+pub struct History {
+    undo: Vec<Scene>,
+    redo: Vec<Scene>,
+    /// The scene when it was last at rest, or None until it is next captured
+    settled: Option<Scene>,
+    /// Whether the scene changed since it was last at rest
+    pending: bool,
+    /// Tab the history is of
+    tab: usize
+}
+
+impl History {
+    /// Records the edit from the settled scene to `shown`.
+    fn settle(&mut self, shown: Scene) {
+        self.pending = false;
+        if let Some(before) = self.settled.replace(shown) {
+            self.undo.push(before);
+            if self.undo.len() > MAX_UNDO {
+                self.undo.remove(0);
+            }
+            self.redo.clear();
+        }
+    }
+}
+
+/// Notes changes to the scene and records them as an edit once the scene is at rest, with
+/// no changes in a frame and no mouse button or finger down. Surfaces that tracks and
+/// choppers move while the clock plays aren't edits, so a running animation doesn't hold
+/// other edits back.
+pub fn history_record_system(
+    (buttons, touches, clock): (Res<Input<MouseButton>>, Res<Touches>, Res<Clock>),
+    tabs: Res<Tabs>,
+    mut history: ResMut<History>,
+    (changed, removed): (Changes, Removed),
+    scene_query: Query<SceneItem, SceneEntity>,
+    extras_query: Query<Extras>
+) {
+    if tabs.current != history.tab {
+        *history = History {
+            tab: tabs.current,
+            ..default()
+        };
+    }
+    let animated = |(surface, track): (Option<&Surface>, Option<&Track>)| {
+        clock.playing && (track.is_some() || surface.is_some_and(|surface| surface.chopper.is_some()))
+    };
+    if changed.iter().any(|item| !animated(item)) || removed.0.iter().next().is_some() || removed.1.iter().next().is_some() {
+        history.pending = true;
+        return
+    }
+    if !history.pending || buttons.get_pressed().next().is_some() || touches.iter().next().is_some() {
+        return
+    }
+    history.settle(Scene::capture(&scene_query, &extras_query));
+}
+
+/// Ctrl+Z brings back the scene before the last edit, Ctrl+Shift+Z the one after an undone
+/// edit. The selection is cleared, as the scene is spawned anew.
+pub fn history_keys_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    (config, formalism): (Res<Config>, Res<Formalism>),
+    mut history: ResMut<History>,
+    mut writer: EventWriter<RaycastEvent>,
+    (scene_query, child_query): (Query<SceneItem, SceneEntity>, Query<(), With<Parent>>),
+    extras_query: Query<Extras>
+) {
+    if !keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) || !keys.just_pressed(KeyCode::Z) {
+        return
+    }
+    let redo = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    let shown = Scene::capture(&scene_query, &extras_query);
+    // an edit still going on is finished first, so it is the one undone
+    if history.pending {
+        history.settle(shown.clone());
+    }
+    let Some(scene) = (if redo { history.redo.pop() } else { history.undo.pop() }) else {
+        return
+    };
+    if redo {
+        history.undo.push(shown);
+    } else {
+        history.redo.push(shown);
+    }
+    history.settled = None;
+    info!("{} ({} to undo, {} to redo)", if redo { "redo" } else { "undo" }, history.undo.len(), history.redo.len());
+    // faces and blades go with the element they belong to
+    for (entity, ..) in scene_query.iter().filter(|(entity, ..)| !child_query.contains(*entity)) {
+        commands.entity(entity).despawn_recursive();
+    }
+    scene.spawn(&mut commands, &config, &formalism, &mut writer);
+}
@@ -0,0 +1,222 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+
+use crate::{
+    links::Param, locale::Locale, presentation::Presentation, scene_tree::{Selected, SELECTED_COLOR}, ui::{color32, TEXT_COLOR}, units::{Millimeters, Nanometers},
+    viewer::ReadOnly, viewports::{self, Analysis}, BeamSource, Surface
+};
+
+// Tallest the list of rows gets (px) before it scrolls
+const LIST_HEIGHT: f32 = 320.;
+// Stands in for the value in a field's label, to split the label around the value
+const VALUE_MARK: &str = "\u{0}";
+
+/// A parameter the inspector shows and edits. Positions are of a source's origin or of a
+/// surface's center, in mm; wavelengths are in nm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    X,
+    Y,
+    /// Refractive index the source emits into, or behind the surface
+    Index,
+    /// Fraction of the light a surface reflects
+    Reflection,
+    Wavelength,
+    /// Beam width (mm)
+    Waist
+}
+
+const SOURCE_FIELDS: [Field; 5] = [Field::X, Field::Y, Field::Index, Field::Wavelength, Field::Waist];
+const SURFACE_FIELDS: [Field; 4] = [Field::X, Field::Y, Field::Index, Field::Reflection];
+
+impl Field {
+    fn key(self) -> &'static str {
+        match self {
+            Field::X => "x",
+            Field::Y => "y",
+            Field::Index => "n",
+            Field::Reflection => "reflection",
+            Field::Wavelength => "wavelength",
+            Field::Waist => "waist"
+        }
+    }
+
+    /// How much the value changes per pixel the field is dragged.
+    fn speed(self) -> f64 {
+        match self {
+            Field::X | Field::Y => 0.1,
+            Field::Index | Field::Reflection => 0.001,
+            Field::Wavelength => 1.,
+            Field::Waist => 0.01
+        }
+    }
+
+    fn source(self, source: &BeamSource) -> Option<f32> {
+        match self {
+            Field::X => Some(source.pos.x),
+            Field::Y => Some(source.pos.y),
+            Field::Index => Some(source.index),
+            Field::Wavelength => Some(source.w.0),
+            Field::Waist => Some(source.waist.0),
+            Field::Reflection => None
+        }
+    }
+
+    fn set_source(self, source: &mut BeamSource, value: f32) -> Result<(), String> {
+        match self {
+            Field::X => source.pos.x = value,
+            Field::Y => source.pos.y = value,
+            Field::Index if value >= 1. => source.index = value,
+            Field::Wavelength if value > 0. => source.w = Nanometers(value),
+            Field::Waist if value >= 0. => source.waist = Millimeters(value),
+            Field::Index => return Err("an index is at least 1".to_string()),
+            Field::Wavelength => return Err("a wavelength is positive".to_string()),
+            Field::Waist => return Err("a waist isn't negative".to_string()),
+            Field::Reflection => return Err("sources don't reflect".to_string())
+        }
+        Ok(())
+    }
+
+    fn surface(self, surface: &Surface) -> Option<f32> {
+        match self {
+            Field::X => Param::X.get(surface),
+            Field::Y => Param::Y.get(surface),
+            Field::Index => Param::N.get(surface),
+            Field::Reflection => Some(surface.reflection),
+            Field::Wavelength | Field::Waist => None
+        }
+    }
+
+    fn set_surface(self, surface: &mut Surface, value: f32) -> Result<(), String> {
+        match self {
+            Field::X => Param::X.set(surface, value),
+            Field::Y => Param::Y.set(surface, value),
+            Field::Index => Param::N.set(surface, value),
+            Field::Reflection if (0. ..=1.).contains(&value) => {
+                surface.reflection = value;
+                Ok(())
+            }
+            Field::Reflection => Err("a reflection is between 0 and 1".to_string()),
+            Field::Wavelength | Field::Waist => Err("surfaces have no wavelength or waist".to_string())
+        }
+    }
+}
+
+/// State of the inspector window.
+#[derive(Resource, Default)]
+pub struct Inspector {
+    pub open: bool,
+    pub error: Option<String>,
+    /// Whether a field has the keyboard, so typed keys don't also act on the scene
+    pub typing: bool,
+    /// Whether the cursor is over the window, so clicks and the wheel are its own
+    pub hovered: bool
+}
+
+impl Inspector {
+    /// Whether typing goes into a field.
+    pub fn typing(&self) -> bool {
+        self.open && self.typing
+    }
+}
+
+/// F1 opens and closes the inspector.
+pub fn inspector_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut inspector: ResMut<Inspector>
+) {
+    if keys.just_pressed(KeyCode::F1) {
+        inspector.open = !inspector.open;
+        inspector.error = None;
+    }
+}
+
+/// Lists every source and surface in an egui window, each with a field per parameter,
+/// selected surfaces in the selection color. Dragging a field or clicking it and typing sets
+/// the value; a value the element can't take is refused with the reason below the list. Edits
+/// go through the components, so the rays are retraced as for any other change. Read-only
+/// scenes show the values without letting them be edited, and presentations hide the window.
+/// While the analysis views are detached, the window is drawn in theirs.
+pub fn inspector_system(
+    mut egui_context: ResMut<EguiContext>,
+    analysis_query: Analysis,
+    mut inspector: ResMut<Inspector>,
+    (locale, presentation, read_only): (Res<Locale>, Res<Presentation>, Res<ReadOnly>),
+    mut source_query: Query<(Entity, &mut BeamSource)>,
+    mut surface_query: Query<(Entity, &mut Surface, Option<&Selected>)>
+) {
+    if !inspector.open || presentation.active {
+        inspector.typing = false;
+        inspector.hovered = false;
+        return
+    }
+    // a window only has a context once it has been created, a frame after it is asked for
+    let ctx = match viewports::analysis_window(&analysis_query) {
+        Some(window) => egui_context.try_ctx_for_window_mut(window),
+        None => Some(egui_context.ctx_mut())
+    };
+    let Some(ctx) = ctx else {
+        return
+    };
+    let editable = !read_only.0;
+    let mut sources: Vec<_> = source_query.iter_mut().collect();
+    sources.sort_by_key(|(entity, _)| entity.index());
+    let mut surfaces: Vec<_> = surface_query.iter_mut().collect();
+    surfaces.sort_by_key(|(entity, ..)| entity.index());
+    // a field labelled as the locale has it, returning the new value once it is changed
+    let field = |ui: &mut egui::Ui, field: Field, value: f32| {
+        let label = locale.format(&format!("inspector.{}", field.key()), &[("value", &VALUE_MARK)]);
+        let (prefix, suffix) = label.split_once(VALUE_MARK).unwrap_or((&label, ""));
+        let mut edited = value;
+        let response = ui.add_enabled(editable, egui::DragValue::new(&mut edited)
+            .speed(field.speed())
+            .max_decimals(3)
+            .prefix(prefix)
+            .suffix(suffix));
+        (response.changed() && edited != value).then_some(edited)
+    };
+    let mut open = true;
+    // set to the outcome of the last edit, if anything was edited
+    let mut error = None;
+    egui::Window::new(locale.text("inspector.title")).open(&mut open).show(ctx, |ui| {
+        egui::ScrollArea::vertical().max_height(LIST_HEIGHT).show(ui, |ui| {
+            egui::Grid::new("inspector").striped(true).show(ui, |ui| {
+                for (k, (_, source)) in sources.iter_mut().enumerate() {
+                    ui.label(locale.format("inspector.source", &[("n", &(k + 1))]));
+                    for kind in SOURCE_FIELDS {
+                        let edited = kind.source(source).and_then(|value| field(ui, kind, value));
+                        if let Some(value) = edited {
+                            error = Some(kind.set_source(source, value).err());
+                        }
+                    }
+                    let switch = locale.text(if source.enabled { "inspector.on" } else { "inspector.off" });
+                    if ui.add_enabled(editable, egui::Button::new(switch)).clicked() {
+                        source.enabled = !source.enabled;
+                    }
+                    ui.end_row();
+                }
+                for (_, surface, selected) in surfaces.iter_mut() {
+                    let name = surface.label.clone().unwrap_or_else(|| locale.text(&format!("kind.{}", surface.kind())).to_string());
+                    let color = if selected.is_some() { SELECTED_COLOR } else { TEXT_COLOR };
+                    ui.label(egui::RichText::new(name).color(color32(color)));
+                    for kind in SURFACE_FIELDS {
+                        let edited = kind.surface(surface).and_then(|value| field(ui, kind, value));
+                        if let Some(value) = edited {
+                            error = Some(kind.set_surface(surface, value).err());
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+        if let Some(err) = &inspector.error {
+            ui.colored_label(egui::Color32::from_rgb(255, 77, 77), err);
+        }
+    });
+    if let Some(error) = error {
+        inspector.error = error;
+    }
+    inspector.open = open;
+    inspector.typing = ctx.wants_keyboard_input();
+    inspector.hovered = ctx.is_pointer_over_area();
+}
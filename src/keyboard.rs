@@ -0,0 +1,532 @@
+use std::{collections::HashMap, str::FromStr};
+
+use bevy::prelude::*;
+
+use crate::{
+    aperture::Aperture, bodies::{Body, Lens}, bookmarks::{self, Bookmarks}, config::Config, inspector::Inspector, links::{Expr, LinkEditor, Param, Parser}, locale::Locale, material::Material, polarization::Formalism,
+    prisms::PrismFace, scene_tree::{SceneFilter, Selected}, ui::{self, UiFont}, units::{Degrees, Millimeters, Nanometers}, viewports::MainCamera, BeamSource, Grating, RaycastEvent, Surface
+};
+
+// Distance (mm) an arrow key moves the selection
+const MOVE_STEP: f32 = 1.;
+// Angle (degrees) Ctrl+Left and Ctrl+Right turn the selection by
+const TURN_STEP: f32 = 1.;
+// Shift makes a step this many times larger and Alt this many times smaller
+const STEP_FACTOR: f32 = 10.;
+// Index of singlets placed from the palette, about N-BK7
+pub const SINGLET_INDEX: f32 = 1.52;
+// Band of white-light sources placed from the palette, and the lines it is sampled by
+const WHITE_LIGHT: (Nanometers, Nanometers) = (Nanometers(400.), Nanometers(700.));
+const WHITE_LINES: usize = 7;
+// Rays of point sources placed from the palette without a count
+const POINT_RAYS: usize = 15;
+// Keys that keep working while a text box takes the typing. The text boxes have no cursor, so
+// Tab and the arrows are held back too, rather than cycling or moving the selection.
+const EDITING_KEYS: [KeyCode; 3] = [KeyCode::Back, KeyCode::Escape, KeyCode::Return];
+
+// Surfaces that move on their own, unlike the faces of a prism pair
+type Movable = (With<Surface>, Without<PrismFace>);
+
+fn ctrl(keys: &Input<KeyCode>) -> bool {
+    keys.any_pressed([KeyCode::LControl, KeyCode::RControl])
+}
+
+fn step_factor(keys: &Input<KeyCode>) -> f32 {
+    if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        STEP_FACTOR
+    } else if keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt]) {
+        1. / STEP_FACTOR
+    } else {
+        1.
+    }
+}
+
+/// While the command palette, the links entry line, the scene search or an inspector field is
+/// open, typed keys go there only, so they don't also toggle panels and views or move the
+/// selection. Function keys and the keys that edit the text keep working.
+pub fn typing_capture_system(
+    mut keys: ResMut<Input<KeyCode>>,
+    palette: Res<Palette>,
+    (editor, inspector): (Res<LinkEditor>, Res<Inspector>),
+    filter: Res<SceneFilter>
+) {
+    if !palette.open && !editor.open && !filter.open && !inspector.typing() {
+        return
+    }
+    let typed: Vec<KeyCode> = keys.get_just_pressed()
+        .filter(|key| !EDITING_KEYS.contains(key) && !(KeyCode::F1..=KeyCode::F12).contains(*key))
+        .copied()
+        .collect();
+    for key in typed {
+        keys.clear_just_pressed(key);
+    }
+}
+
+/// Tab selects the next surface in the order they were added, Shift+Tab the previous one.
+pub fn selection_cycle_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    surface_query: Query<(Entity, Option<&Selected>), Movable>
+) {
+    if !keys.just_pressed(KeyCode::Tab) || ctrl(&keys) {
+        return
+    }
+    let mut surfaces: Vec<(Entity, bool)> = surface_query.iter().map(|(entity, selected)| (entity, selected.is_some())).collect();
+    if surfaces.is_empty() {
+        return
+    }
+    surfaces.sort_by_key(|(entity, _)| entity.index());
+    let count = surfaces.len();
+    let current = surfaces.iter().rposition(|(_, selected)| *selected);
+    let next = match (current, keys.any_pressed([KeyCode::LShift, KeyCode::RShift])) {
+        (None, false) => 0,
+        (None, true) => count - 1,
+        (Some(k), false) => (k + 1) % count,
+        (Some(k), true) => (k + count - 1) % count
+    };
+    for (entity, selected) in surfaces.iter() {
+        if *selected {
+            commands.entity(*entity).remove::<Selected>();
+        }
+    }
+    commands.entity(surfaces[next].0).insert(Selected);
+}
+
+/// Arrow keys move the selected surfaces and Ctrl+Left/Right turn them about their centers,
+/// by finer steps with Alt and coarser ones with Shift.
+pub fn nudge_system(
+    keys: Res<Input<KeyCode>>,
+    mut surface_query: Query<&mut Surface, (With<Selected>, Without<PrismFace>)>
+) {
+    let factor = step_factor(&keys);
+    let (shift, turn) = if ctrl(&keys) {
+        let turn = [(KeyCode::Left, 1.), (KeyCode::Right, -1.)].into_iter()
+            .filter(|(key, _)| keys.just_pressed(*key))
+            .map(|(_, sign)| sign)
+            .sum::<f32>();
+        (Vec2::ZERO, turn * TURN_STEP * factor)
+    } else {
+        let shift = [(KeyCode::Left, -Vec2::X), (KeyCode::Right, Vec2::X), (KeyCode::Down, -Vec2::Y), (KeyCode::Up, Vec2::Y)].into_iter()
+            .filter(|(key, _)| keys.just_pressed(*key))
+            .map(|(_, direction)| direction)
+            .sum::<Vec2>();
+        (shift * MOVE_STEP * factor, 0.)
+    };
+    if shift == Vec2::ZERO && turn == 0. {
+        return
+    }
+    for mut surface in surface_query.iter_mut() {
+        surface.p1 += shift;
+        surface.p2 += shift;
+        if let Some(angle) = Param::Angle.get(&surface).filter(|_| turn != 0.) {
+            let _ = Param::Angle.set(&mut surface, angle + turn);
+        }
+    }
+}
+
+/// Element kinds the palette can place, with the parameter each takes after its endpoints.
+const PLACEABLE: [(&str, Option<f32>); 13] = [
+    ("glass", Some(1.5)),
+    ("blocker", None),
+    ("mirror", Some(1.)),
+    ("splitter", Some(0.5)),
+    ("lens", Some(50.)),
+    ("attenuator", Some(1.)),
+    ("pickoff", None),
+    ("fiber", Some(0.22)),
+    ("grating", Some(600.)),
+    ("polarizer", Some(0.)),
+    ("iris", Some(2.)),
+    ("prism", Some(1.5)),
+    ("singlet", Some(50.))
+];
+
+/// A line typed into the command palette.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaletteCommand {
+    /// `param = expr` sets a parameter of every selected surface, `label.param = expr` that of
+    /// one labelled surface. Unlike a link, it is applied once.
+    Set(Option<String>, Param, Expr),
+    /// `kind x1, y1, x2, y2[, value]` places a surface between the two points, with the index,
+    /// focal length, optical density or NA it takes
+    Place(String, [Expr; 4], Option<Expr>),
+    /// `source x, y, angle[, waist]` places a beam, and `white` with the same values a beam of
+    /// white light
+    Source([Expr; 3], Option<Expr>, bool),
+    /// `point x, y, angle, spread[, rays]` places a point source fanning out over the full
+    /// angle `spread`
+    Point([Expr; 4], Option<Expr>),
+    /// `label name` names the selected surface
+    Label(String),
+    /// `material name` makes the selected surfaces of a catalog glass, `material none` gives
+    /// them back their fixed index
+    Material(Option<Material>),
+    /// `bookmark 3 name` bookmarks the current view on a number key, named `view 3` without
+    /// a name
+    Bookmark(usize, Option<String>),
+    /// `rename 3 name` renames the view bookmarked on a number key
+    Rename(usize, String)
+}
+
+impl FromStr for PaletteCommand {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut parser = Parser::new(text);
+        let word = parser.word(|c| c.is_alphanumeric() || c == '_');
+        let list = |parser: &mut Parser, count: usize| -> Result<Vec<Expr>, String> {
+            let mut values = vec![parser.sum()?];
+            while parser.eat(',') {
+                values.push(parser.sum()?);
+            }
+            if values.len() < count || values.len() > count + 1 {
+                return Err(format!("`{}` takes {} or {} values", word, count, count + 1))
+            }
+            Ok(values)
+        };
+        let command = if word == "label" && parser.peek().is_some_and(|c| c != '.') {
+            let name = parser.word(|c| c.is_alphanumeric() || c == '_');
+            if name.is_empty() {
+                return Err("expected a name after `label`".to_string())
+            }
+            PaletteCommand::Label(name.to_string())
+        } else if word == "material" && parser.peek().is_some_and(|c| c.is_alphabetic()) {
+            let name = text[parser.at..].trim();
+            parser.at = text.len();
+            match name {
+                "none" => PaletteCommand::Material(None),
+                _ => PaletteCommand::Material(Some(Material::named(name).ok_or(format!("no glass named `{}`", name))?))
+            }
+        } else if (word == "bookmark" || word == "rename") && parser.peek().is_some_and(|c| c.is_ascii_digit()) {
+            let slot = parser.word(|c| c.is_ascii_digit());
+            let slot = slot.parse::<usize>().ok()
+                .filter(|slot| (1..=9).contains(slot))
+                .ok_or(format!("bookmarks are on the keys 1 to 9, not {}", slot))?;
+            let name = text[parser.at..].trim();
+            parser.at = text.len();
+            match (word, name.is_empty()) {
+                ("bookmark", true) => PaletteCommand::Bookmark(slot - 1, None),
+                ("bookmark", false) => PaletteCommand::Bookmark(slot - 1, Some(name.to_string())),
+                (_, true) => return Err("expected a name after the key".to_string()),
+                _ => PaletteCommand::Rename(slot - 1, name.to_string())
+            }
+        } else if word == "source" || word == "white" {
+            let mut values = list(&mut parser, 3)?;
+            let extra = (values.len() > 3).then(|| values.remove(3));
+            let [x, y, angle]: [Expr; 3] = values.try_into().map_err(|_| format!("`{}` takes x, y and an angle", word))?;
+            PaletteCommand::Source([x, y, angle], extra, word == "white")
+        } else if word == "point" {
+            let mut values = list(&mut parser, 4)?;
+            let extra = (values.len() > 4).then(|| values.remove(4));
+            let values: [Expr; 4] = values.try_into().map_err(|_| "`point` takes x, y, an angle and a spread")?;
+            PaletteCommand::Point(values, extra)
+        } else if let Some((kind, default)) = PLACEABLE.iter().find(|(kind, _)| *kind == word) {
+            let mut values = list(&mut parser, 4)?;
+            let extra = (values.len() > 4).then(|| values.remove(4));
+            if extra.is_some() && default.is_none() {
+                return Err(format!("`{}` takes only its endpoints", kind))
+            }
+            let points: [Expr; 4] = values.try_into().map_err(|_| "expected x1, y1, x2, y2")?;
+            PaletteCommand::Place(kind.to_string(), points, extra)
+        } else if parser.eat('.') {
+            let param = parser.word(|c| c.is_alphanumeric()).parse()?;
+            if !parser.eat('=') {
+                return Err(format!("expected `=` at {}", parser.at))
+            }
+            PaletteCommand::Set(Some(word.to_string()), param, parser.sum()?)
+        } else {
+            let param = word.parse()?;
+            if !parser.eat('=') {
+                return Err(format!("expected `=` at {}", parser.at))
+            }
+            PaletteCommand::Set(None, param, parser.sum()?)
+        };
+        if parser.peek().is_some() {
+            return Err(format!("unexpected `{}` at {}", &text[parser.at..].trim(), parser.at))
+        }
+        Ok(command)
+    }
+}
+
+/// Quick-entry line for editing the scene from the keyboard. Enter opens it and runs the
+/// typed command, Escape closes it.
+#[derive(Resource, Default)]
+pub struct Palette {
+    pub open: bool,
+    pub entry: String,
+    pub error: Option<String>,
+    /// Parsed command waiting to be run
+    pending: Option<PaletteCommand>
+}
+
+#[derive(Component)]
+pub struct PalettePanel;
+
+pub fn palette_input_system(
+    keys: Res<Input<KeyCode>>,
+    mut chars: EventReader<ReceivedCharacter>,
+    editor: Res<LinkEditor>,
+    mut palette: ResMut<Palette>
+) {
+    if !palette.open {
+        chars.clear();
+        // the links entry line takes Enter while it is open
+        if keys.just_pressed(KeyCode::Return) && !editor.open {
+            palette.open = true;
+            palette.error = None;
+        }
+        return
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        palette.open = false;
+        palette.entry.clear();
+        chars.clear();
+        return
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        palette.entry.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        if palette.entry.trim().is_empty() {
+            palette.open = false;
+            return
+        }
+        match palette.entry.parse::<PaletteCommand>() {
+            Ok(command) => palette.pending = Some(command),
+            Err(err) => palette.error = Some(err)
+        }
+    }
+    for event in chars.iter() {
+        if !event.char.is_control() {
+            palette.entry.push(event.char);
+        }
+    }
+}
+
+/// Spawns `beam` and traces its rays.
+fn emit(beam: BeamSource, commands: &mut Commands, (config, formalism): (&Config, &Formalism), writer: &mut EventWriter<RaycastEvent>) {
+    let rays = formalism.launch(&beam, config.ray_density);
+    let source = commands.spawn(beam).id();
+    for ray in rays {
+        writer.send(RaycastEvent {
+            ray: Some(ray),
+            tree: None,
+            source: Some(source)
+        });
+    }
+}
+
+type PaletteSurfaces<'w, 's, 'a> = Query<'w, 's, (Entity, &'a mut Surface, Option<&'a Selected>)>;
+
+fn run(
+    command: PaletteCommand,
+    commands: &mut Commands,
+    (config, formalism): (&Config, &Formalism),
+    writer: &mut EventWriter<RaycastEvent>,
+    surface_query: &mut PaletteSurfaces,
+    (bookmarks, view): (&mut Bookmarks, Option<(Vec2, f32)>)
+) -> Result<(), String> {
+    let labelled: HashMap<String, Surface> = surface_query.iter()
+        .filter_map(|(_, surface, _)| Some((surface.label.clone()?, surface.clone())))
+        .collect();
+    let lookup = |label: &str, param: Param| {
+        let surface = labelled.get(label).ok_or(format!("no surface labelled `{}`", label))?;
+        param.get(surface).ok_or(format!("`{}` has no {}", label, param))
+    };
+    match command {
+        PaletteCommand::Set(label, param, expr) => {
+            let value = expr.eval(&lookup)?;
+            let mut targets = 0;
+            for (_, mut surface, selected) in surface_query.iter_mut() {
+                let hit = match &label {
+                    Some(label) => surface.label.as_deref() == Some(label.as_str()),
+                    None => selected.is_some()
+                };
+                if hit {
+                    param.set(&mut surface, value)?;
+                    targets += 1;
+                }
+            }
+            match (targets, label) {
+                (0, Some(label)) => Err(format!("no surface labelled `{}`", label)),
+                (0, None) => Err("nothing is selected".to_string()),
+                _ => Ok(())
+            }
+        }
+        PaletteCommand::Place(kind, points, extra) => {
+            let [x1, y1, x2, y2] = points.map(|expr| expr.eval(&lookup));
+            let (p1, p2) = (Vec2::new(x1?, y1?), Vec2::new(x2?, y2?));
+            let default = PLACEABLE.iter().find(|(name, _)| *name == kind).and_then(|(_, default)| *default);
+            let value = extra.map(|expr| expr.eval(&lookup)).transpose()?.or(default).unwrap_or_default();
+            for (entity, _, selected) in surface_query.iter() {
+                if selected.is_some() {
+                    commands.entity(entity).remove::<Selected>();
+                }
+            }
+            match kind.as_str() {
+                "prism" => {
+                    commands.spawn(Body::prism(p1, p2, value));
+                    return Ok(())
+                }
+                "iris" => {
+                    commands.spawn(Aperture::new(p1, p2, Millimeters(value)));
+                    return Ok(())
+                }
+                "singlet" => {
+                    let lens = Lens::symmetric((p1 + p2) / 2., Millimeters(p1.distance(p2)), Millimeters(value), SINGLET_INDEX)
+                        .axis((p2 - p1).perp());
+                    commands.spawn(lens.body().ok_or("the lens is too strong for its diameter")?);
+                    return Ok(())
+                }
+                _ => {}
+            }
+            let surface = match kind.as_str() {
+                "glass" => Surface::glass(p1, p2).index(value),
+                "blocker" => Surface::blocker(p1, p2),
+                "mirror" => Surface::mirror(p1, p2, value),
+                "splitter" => Surface::beamsplitter(p1, p2, value),
+                "lens" => Surface::thin_lens(p1, p2, Millimeters(value)),
+                "attenuator" => Surface::attenuator(p1, p2, value),
+                "pickoff" => Surface::pickoff(p1, p2),
+                "grating" => Surface::grating(p1, p2, Grating::new(value)),
+                "polarizer" => Surface::polarizer(p1, p2, Degrees(value)),
+                _ => Surface::fiber(p1, p2, value)
+            };
+            commands.spawn((surface, Selected));
+            Ok(())
+        }
+        PaletteCommand::Source([x, y, angle], waist, white) => {
+            let direction = Vec2::from_angle(angle.eval(&lookup)?.to_radians());
+            let waist = waist.map(|expr| expr.eval(&lookup)).transpose()?.unwrap_or(1.);
+            let mut beam = BeamSource::new(Vec2::new(x.eval(&lookup)?, y.eval(&lookup)?), direction, Millimeters(waist));
+            if white {
+                beam = beam.band(WHITE_LIGHT.0, WHITE_LIGHT.1, WHITE_LINES);
+            }
+            emit(beam, commands, (config, formalism), writer);
+            Ok(())
+        }
+        PaletteCommand::Point([x, y, angle, spread], rays) => {
+            let direction = Vec2::from_angle(angle.eval(&lookup)?.to_radians());
+            let spread = spread.eval(&lookup)?;
+            let rays = rays.map(|expr| expr.eval(&lookup)).transpose()?.map_or(POINT_RAYS, |rays| rays.round() as usize);
+            if !(0. ..=360.).contains(&spread) || rays == 0 {
+                return Err("a point source needs a spread of 0 to 360° and at least one ray".to_string())
+            }
+            let beam = BeamSource::new(Vec2::new(x.eval(&lookup)?, y.eval(&lookup)?), direction, Millimeters(0.))
+                .point(rays, Degrees(spread));
+            emit(beam, commands, (config, formalism), writer);
+            Ok(())
+        }
+        PaletteCommand::Label(name) => {
+            let mut selected: Vec<Mut<Surface>> = surface_query.iter_mut()
+                .filter(|(_, _, selected)| selected.is_some())
+                .map(|(_, surface, _)| surface)
+                .collect();
+            match selected.as_mut_slice() {
+                [surface] => {
+                    surface.label = Some(name);
+                    Ok(())
+                }
+                [] => Err("nothing is selected".to_string()),
+                _ => Err("select one surface to label".to_string())
+            }
+        }
+        PaletteCommand::Material(material) => {
+            let mut targets = 0;
+            let mut selected_surfaces = 0;
+            for (_, mut surface, selected) in surface_query.iter_mut() {
+                if selected.is_none() {
+                    continue
+                }
+                selected_surfaces += 1;
+                // only the faces of bodies know which side their glass is on
+                if surface.interior.is_some() {
+                    surface.dispersion = material;
+                    targets += 1;
+                }
+            }
+            if selected_surfaces == 0 {
+                return Err("nothing is selected".to_string())
+            }
+            if targets == 0 {
+                return Err("select the faces of a body".to_string())
+            }
+            Ok(())
+        }
+        PaletteCommand::Bookmark(slot, name) => {
+            let (center, zoom) = view.ok_or("there is no view to bookmark")?;
+            let name = name.unwrap_or_else(|| format!("view {}", slot + 1));
+            bookmarks.set(slot, &name, center, zoom);
+            Ok(())
+        }
+        PaletteCommand::Rename(slot, name) => {
+            let bookmark = bookmarks.0[slot].as_mut().ok_or(format!("nothing is bookmarked on {}", slot + 1))?;
+            bookmark.name = name;
+            Ok(())
+        }
+    }
+}
+
+/// Runs the command entered into the palette. A placed surface becomes the selection, so the
+/// arrow keys and further commands act on it.
+pub fn palette_run_system(
+    mut commands: Commands,
+    (config, formalism): (Res<Config>, Res<Formalism>),
+    mut palette: ResMut<Palette>,
+    mut writer: EventWriter<RaycastEvent>,
+    mut surface_query: PaletteSurfaces,
+    (windows, mut bookmarks): (Res<Windows>, ResMut<Bookmarks>),
+    camera_query: Query<(&Transform, &OrthographicProjection), MainCamera>
+) {
+    let Some(command) = palette.pending.take() else {
+        return
+    };
+    let view = bookmarks::current_view(&windows, &camera_query);
+    match run(command, &mut commands, (&config, &formalism), &mut writer, &mut surface_query, (&mut bookmarks, view)) {
+        Ok(()) => {
+            palette.entry.clear();
+            palette.error = None;
+        }
+        Err(err) => palette.error = Some(err)
+    }
+}
+
+pub fn palette_panel_system(
+    mut commands: Commands,
+    palette: Res<Palette>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<PalettePanel>>
+) {
+    if !palette.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !palette.open {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(30.),
+                top: Val::Percent(8.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, PalettePanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.format("commands.entry", &[("entry", &palette.entry)]), style.clone()));
+        panel.spawn(TextBundle::from_section(locale.text("commands.hint"), ui::text_style(&font, Color::rgb(0.6, 0.6, 0.6))));
+        if let Some(err) = &palette.error {
+            panel.spawn(TextBundle::from_section(err.clone(), ui::text_style(&font, Color::rgb(1.0, 0.3, 0.3))));
+        }
+    });
+}
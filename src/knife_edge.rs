@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+
+use crate::{
+    animation::Clock, config::Config, fans::RayFan, locale::Locale, scene_tree::Selected, trace,
+    ui::{self, UiFont}, units::Quantity, BeamSource, Ray, Surface
+};
+
+// Seconds for the knife to cross the beam
+const SWEEP_SECONDS: f32 = 4.;
+// Length (mm) of the blade behind its edge
+const BLADE_LENGTH: f32 = 20.;
+// Recorded positions per sweep
+const SAMPLES: f32 = 100.;
+// Sweep range beyond the beam's half width
+const MARGIN: f32 = 1.5;
+// Distance between the 90 % and 10 % points of a Gaussian beam's knife-edge curve, in units
+// of its 1/e² radius
+const CLIP_10_90: f32 = 1.2816;
+const CURVE_COLOR: Color = Color::rgb(0.9, 0.9, 0.4);
+
+/// Knife-edge curve fitted for a Gaussian beam.
+#[derive(Clone, Copy, Debug)]
+pub struct EdgeFit {
+    /// Knife position (mm) passing half the power
+    pub center: f32,
+    /// 1/e² radius (mm), from the 10 % and 90 % points
+    pub radius: f32
+}
+
+/// A sweep in progress: the knife crosses the beam across `across`, centered on `center`.
+#[derive(Clone, Copy, Debug)]
+struct Sweep {
+    start: f32,
+    center: Vec2,
+    across: Vec2,
+    range: f32,
+    meter: Entity
+}
+
+/// Knife-edge beam profiling of the fan's source. K drops a blade across the beam between the
+/// source and a power meter (the selected surface, or else the first surface the beam hits)
+/// and sweeps it through the beam with the clock, recording the power reaching the meter.
+#[derive(Resource, Default)]
+pub struct KnifeEdge {
+    pub enabled: bool,
+    /// (knife position across the beam (mm), power on the meter)
+    pub samples: Vec<(f32, f32)>,
+    pub meter: Option<String>,
+    pub fit: Option<EdgeFit>,
+    knife: Option<Entity>,
+    sweep: Option<Sweep>
+}
+
+#[derive(Component)]
+pub struct KnifePanel;
+
+/// Position where the decreasing curve `samples` falls through `level`, interpolated linearly.
+fn crossing(samples: &[(f32, f32)], level: f32) -> Option<f32> {
+    samples.windows(2).find_map(|pair| {
+        let [(s0, p0), (s1, p1)] = [pair[0], pair[1]];
+        (p0 >= level && p1 < level).then(|| s0 + (s1 - s0) * (p0 - level) / (p0 - p1))
+    })
+}
+
+/// Fits a Gaussian beam to a knife-edge curve with the 10-90 % clip method.
+pub fn fit(samples: &[(f32, f32)]) -> Option<EdgeFit> {
+    let total = samples.first()?.1;
+    if total <= 0. {
+        return None
+    }
+    let (high, half, low) = (crossing(samples, 0.9 * total)?, crossing(samples, 0.5 * total)?, crossing(samples, 0.1 * total)?);
+    Some(EdgeFit {
+        center: half,
+        radius: (low - high) / CLIP_10_90
+    })
+}
+
+/// Starts a sweep when K enables the tool, and removes the knife when it disables it.
+pub fn knife_toggle_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    fan: Res<RayFan>,
+    mut knife_edge: ResMut<KnifeEdge>,
+    mut clock: ResMut<Clock>,
+    source_query: Query<&BeamSource>,
+    surface_query: Query<(Entity, &Surface, Option<&Selected>)>
+) {
+    if !keys.just_pressed(KeyCode::K) {
+        return
+    }
+    knife_edge.enabled = !knife_edge.enabled;
+    if let Some(knife) = knife_edge.knife.take() {
+        commands.entity(knife).despawn_recursive();
+    }
+    knife_edge.sweep = None;
+    if !knife_edge.enabled {
+        return
+    }
+    knife_edge.samples.clear();
+    knife_edge.fit = None;
+    knife_edge.meter = None;
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    if sources.is_empty() {
+        return
+    }
+    let source = sources[fan.source % sources.len()];
+    let axis = source.direction.normalize_or_zero();
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().map(|(entity, surface, _)| (entity, surface)).collect();
+    let chief = trace(&Ray::new(source.pos, axis, source.index), &surfaces, |_| None);
+    let Some(first) = chief.segments.first() else {
+        return
+    };
+    let meter = surface_query.iter()
+        .find_map(|(entity, _, selected)| selected.map(|_| entity))
+        .unwrap_or(first.surface);
+    // halfway to the first surface, where the blade can't cut into anything
+    let distance = first.hit.distance / 2.;
+    let spread = distance * (source.divergence.to_radians() / 2.).tan();
+    let range = MARGIN * (source.waist.0 / 2. + spread);
+    let center = source.pos + axis * distance;
+    knife_edge.knife = Some(commands.spawn(Surface::blocker(center, center - axis.perp() * BLADE_LENGTH).label("knife")).id());
+    knife_edge.meter = surface_query.get(meter).ok()
+        .map(|(_, surface, _)| surface.label.clone().unwrap_or_else(|| surface.kind().to_string()));
+    knife_edge.sweep = Some(Sweep {
+        start: clock.time,
+        center,
+        across: axis.perp(),
+        range,
+        meter
+    });
+    clock.playing = true;
+}
+
+/// Moves the knife with the clock and records the power the source's rays carry onto the meter.
+pub fn knife_sweep_system(
+    clock: Res<Clock>,
+    config: Res<Config>,
+    fan: Res<RayFan>,
+    mut knife_edge: ResMut<KnifeEdge>,
+    source_query: Query<&BeamSource>,
+    mut surface_query: Query<(Entity, &mut Surface)>
+) {
+    let (Some(sweep), Some(knife)) = (knife_edge.sweep, knife_edge.knife) else {
+        return
+    };
+    let progress = (clock.time - sweep.start) / SWEEP_SECONDS;
+    let Ok((_, mut knife)) = surface_query.get_mut(knife) else {
+        return
+    };
+    let mut place = |position: f32| {
+        let edge = sweep.center + sweep.across * position;
+        knife.p1 = edge;
+        knife.p2 = edge - sweep.across * BLADE_LENGTH;
+    };
+    if progress >= 1. {
+        // back out of the beam
+        place(-sweep.range);
+        knife_edge.fit = fit(&knife_edge.samples);
+        knife_edge.sweep = None;
+        return
+    }
+    let position = sweep.range * (2. * progress - 1.);
+    if knife_edge.samples.last().is_some_and(|(last, _)| position - last < 2. * sweep.range / SAMPLES) {
+        return
+    }
+    place(position);
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    if sources.is_empty() {
+        return
+    }
+    let source = sources[fan.source % sources.len()];
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    let power: f32 = source.rays(config.ray_density).iter()
+        .flat_map(|ray| trace(ray, &surfaces, |_| None).segments)
+        .filter(|segment| segment.surface == sweep.meter)
+        .map(|segment| segment.ray.power * segment.ray.i)
+        .sum();
+    knife_edge.samples.push((position, power));
+}
+
+pub fn knife_panel_system(
+    mut commands: Commands,
+    knife_edge: Res<KnifeEdge>,
+    config: Res<Config>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<KnifePanel>>
+) {
+    if !knife_edge.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !knife_edge.enabled {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    let total = knife_edge.samples.first().map_or(0., |(_, power)| *power);
+    let range = knife_edge.samples.iter().fold(0., |range: f32, (position, _)| range.max(position.abs()));
+    let curve: Vec<Vec2> = knife_edge.samples.iter()
+        .map(|(position, power)| Vec2::new(*position, if total > 0. { power / total } else { 0. }))
+        .collect();
+    let status = match (&knife_edge.meter, knife_edge.sweep, knife_edge.fit) {
+        (None, _, _) => locale.text("knife.no-meter").to_string(),
+        (Some(_), Some(_), _) => locale.text("knife.sweeping").to_string(),
+        (Some(_), None, Some(fit)) => locale.format("knife.fit", &[
+            ("radius", &config.units.show(Quantity::Length, fit.radius)),
+            ("center", &format!("{:+}", config.units.show(Quantity::Length, fit.center)))
+        ]),
+        (Some(_), None, None) => locale.text("knife.no-fit").to_string()
+    };
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Percent(30.),
+                top: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, KnifePanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("knife.title"), style.clone()));
+        if let Some(meter) = &knife_edge.meter {
+            panel.spawn(TextBundle::from_section(
+                locale.format("knife.meter", &[("name", meter), ("range", &config.units.show(Quantity::Length, range))]),
+                style.clone()
+            ));
+            ui::plot(panel, &curve, (-range.max(f32::EPSILON), range.max(f32::EPSILON)), (0., 1.), CURVE_COLOR);
+        }
+        panel.spawn(TextBundle::from_section(status, style.clone()));
+    });
+}
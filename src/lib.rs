@@ -0,0 +1,8 @@
+// The simulation core as a library, so scripts, benchmarks and tests can trace scenes without
+// the app. The app itself uses these modules from here rather than compiling its own copies.
+
+pub mod beams_core;
+pub mod coating;
+pub mod complex;
+pub mod material;
+pub mod units;
@@ -0,0 +1,224 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use bevy::prelude::*;
+
+use crate::{
+    aperture::Aperture, bodies::{Body, Lens}, config::Config, detectors::Detector, keyboard::SINGLET_INDEX, locale::Locale,
+    mouse::{self, Pointer}, scene_tree::Selected, snapping, ui::{self, UiFont}, units::{Degrees, Millimeters}, Surface
+};
+
+// Size (mm) of the parts: lens diameters, prism legs, mirror and splitter lengths
+const PART_SIZE: f32 = 10.;
+const SCREEN_SIZE: f32 = 20.;
+// Focal length (mm) of the lenses and opening (mm) of the iris
+const LENS_FOCAL_LENGTH: f32 = 50.;
+const IRIS_OPENING: f32 = 2.;
+// Thickness (mm) and absorption coefficient (1/mm) of the filter, which passes e^-1 of the light
+const FILTER_THICKNESS: f32 = 2.;
+const FILTER_ABSORPTION: f32 = 0.5;
+
+const PART_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+const ARMED_COLOR: Color = Color::rgb(0.2, 0.35, 0.5);
+
+/// A catalog part that the library places, set up for a beam going along +x.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Part {
+    /// Curved side first, so it focuses a collimated beam with little spherical aberration
+    PlanoConvex,
+    Biconvex,
+    /// Folds a beam along +x down to -y by total internal reflection on its hypotenuse
+    RightAnglePrism,
+    /// 50:50, at 45° so it reflects up to +y
+    Beamsplitter,
+    Mirror,
+    /// Slab of tinted glass, see [`Body::absorbing`]
+    Filter,
+    /// Linear polarizer passing s light
+    Polarizer,
+    Iris,
+    /// Blocker recording what hits it, see [`Detector`]
+    Screen
+}
+
+pub const PARTS: [Part; 9] = [
+    Part::PlanoConvex, Part::Biconvex, Part::RightAnglePrism, Part::Beamsplitter, Part::Mirror, Part::Filter, Part::Polarizer, Part::Iris,
+    Part::Screen
+];
+
+impl Part {
+    fn key(self) -> &'static str {
+        match self {
+            Part::PlanoConvex => "library.plano-convex",
+            Part::Biconvex => "library.biconvex",
+            Part::RightAnglePrism => "library.right-angle-prism",
+            Part::Beamsplitter => "library.beamsplitter",
+            Part::Mirror => "library.mirror",
+            Part::Filter => "library.filter",
+            Part::Polarizer => "library.polarizer",
+            Part::Iris => "library.iris",
+            Part::Screen => "library.screen"
+        }
+    }
+
+    /// Spawns the part centered on `at`. Single surfaces become the selection.
+    pub fn spawn(self, at: Vec2, commands: &mut Commands) {
+        let across = |length: f32| (at - Vec2::Y * length / 2., at + Vec2::Y * length / 2.);
+        let (p1, p2) = across(PART_SIZE);
+        match self {
+            Part::PlanoConvex | Part::Biconvex => {
+                let (diameter, f) = (Millimeters(PART_SIZE), Millimeters(LENS_FOCAL_LENGTH));
+                let lens = match self {
+                    Part::PlanoConvex => Lens::plano_convex(at, diameter, f, SINGLET_INDEX),
+                    _ => Lens::symmetric(at, diameter, f, SINGLET_INDEX)
+                };
+                if let Some(body) = lens.body() {
+                    commands.spawn(body);
+                }
+            }
+            Part::RightAnglePrism => {
+                let h = PART_SIZE / 2.;
+                let corners = vec![at + Vec2::new(-h, -h), at + Vec2::new(h, -h), at + Vec2::new(-h, h)];
+                commands.spawn(Body::polygon(corners, SINGLET_INDEX));
+            }
+            Part::Beamsplitter => {
+                let half = Vec2::ONE * FRAC_1_SQRT_2 * PART_SIZE / 2.;
+                commands.spawn((Surface::beamsplitter(at - half, at + half, 0.5), Selected));
+            }
+            Part::Mirror => {
+                commands.spawn((Surface::mirror(p1, p2, 1.), Selected));
+            }
+            Part::Filter => {
+                let (w, h) = (FILTER_THICKNESS / 2., PART_SIZE / 2.);
+                let corners = vec![at + Vec2::new(-w, -h), at + Vec2::new(w, -h), at + Vec2::new(w, h), at + Vec2::new(-w, h)];
+                commands.spawn(Body::polygon(corners, SINGLET_INDEX).absorbing(FILTER_ABSORPTION));
+            }
+            Part::Polarizer => {
+                commands.spawn((Surface::polarizer(p1, p2, Degrees(0.)), Selected));
+            }
+            Part::Iris => {
+                commands.spawn(Aperture::new(p1, p2, Millimeters(IRIS_OPENING)));
+            }
+            Part::Screen => {
+                let (p1, p2) = across(SCREEN_SIZE);
+                commands.spawn((Surface::blocker(p1, p2), Detector, Selected));
+            }
+        }
+    }
+}
+
+/// Library of catalog parts. A opens and closes it; clicking a part arms it, and the next
+/// click on the table places it there, on the grid with Alt held. Escape disarms.
+#[derive(Resource, Default)]
+pub struct Library {
+    pub open: bool,
+    pub armed: Option<Part>
+}
+
+#[derive(Component)]
+pub struct LibraryPanel;
+
+#[derive(Component, Clone, Copy)]
+pub struct PartButton(pub Part);
+
+pub fn library_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut library: ResMut<Library>,
+    button_query: Query<(&Interaction, &PartButton), Changed<Interaction>>
+) {
+    if keys.just_pressed(KeyCode::A) {
+        library.open = !library.open;
+        library.armed = None;
+    }
+    if keys.just_pressed(KeyCode::Escape) && library.armed.is_some() {
+        library.armed = None;
+    }
+    for (interaction, PartButton(part)) in button_query.iter() {
+        if *interaction == Interaction::Clicked {
+            library.armed = (library.armed != Some(*part)).then_some(*part);
+        }
+    }
+}
+
+/// Places the armed part where the table is clicked.
+pub fn library_place_system(
+    mut commands: Commands,
+    (buttons, keys, config): (Res<Input<MouseButton>>, Res<Input<KeyCode>>, Res<Config>),
+    (windows, camera_query): Pointer,
+    panels: mouse::Panels,
+    mut library: ResMut<Library>,
+    selected_query: Query<Entity, With<Selected>>
+) {
+    let Some(part) = library.armed.filter(|_| library.open) else {
+        return
+    };
+    if !buttons.just_pressed(MouseButton::Left) || mouse::over_panels(&panels) {
+        return
+    }
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    let Some(cursor) = window.cursor_position() else {
+        return
+    };
+    let mut at = mouse::to_scene(cursor, window, camera, projection.scale);
+    if snapping::held(&keys) {
+        at = snapping::point(at, config.snap_grid);
+    }
+    for entity in selected_query.iter() {
+        commands.entity(entity).remove::<Selected>();
+    }
+    part.spawn(at, &mut commands);
+    library.armed = None;
+}
+
+pub fn library_panel_system(
+    mut commands: Commands,
+    library: Res<Library>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<LibraryPanel>>
+) {
+    if !library.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !library.open {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(10.),
+                top: Val::Percent(20.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, LibraryPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("library.title"), style.clone()));
+        for part in PARTS {
+            panel.spawn((ButtonBundle {
+                style: Style {
+                    margin: UiRect::top(Val::Px(4.)),
+                    padding: UiRect::horizontal(Val::Px(4.)),
+                    ..default()
+                },
+                background_color: if library.armed == Some(part) { ARMED_COLOR } else { PART_COLOR }.into(),
+                ..default()
+            }, PartButton(part))).with_children(|button| {
+                button.spawn(TextBundle::from_section(locale.text(part.key()), style.clone()));
+            });
+        }
+        if library.armed.is_some() {
+            panel.spawn(TextBundle::from_section(locale.text("library.place"), style.clone()));
+        }
+    });
+}
@@ -0,0 +1,492 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config, locale::Locale, scan::{LiveScan, ScanSpec}, ui::{self, UiFont}, units::{Degrees, Millimeters, Quantity, UnitSystem},
+    Mueller, Surface
+};
+
+// Linked values closer than this to the current one are left alone, so links settle
+const LINK_TOLERANCE: f32 = 1e-5;
+
+/// A parameter of a labelled surface that links can read and set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Param {
+    /// Center (mm)
+    X,
+    Y,
+    /// Direction from `p1` to `p2` (degrees)
+    Angle,
+    /// Length (mm)
+    Length,
+    /// Refractive index behind the surface
+    N,
+    /// Focal length (mm) of a thin lens
+    F,
+    /// Optical density of an attenuator
+    Od,
+    /// Absorption coefficient (1/mm) inside the body a face bounds
+    Alpha,
+    /// Transmission axis of a polarizer (degrees from s)
+    Axis
+}
+
+impl FromStr for Param {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "x" => Ok(Param::X),
+            "y" => Ok(Param::Y),
+            "angle" => Ok(Param::Angle),
+            "length" => Ok(Param::Length),
+            "n" => Ok(Param::N),
+            "f" => Ok(Param::F),
+            "od" => Ok(Param::Od),
+            "alpha" => Ok(Param::Alpha),
+            "axis" => Ok(Param::Axis),
+            _ => Err(format!("unknown parameter `{}`", name))
+        }
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Param::X => "x",
+            Param::Y => "y",
+            Param::Angle => "angle",
+            Param::Length => "length",
+            Param::N => "n",
+            Param::F => "f",
+            Param::Od => "od",
+            Param::Alpha => "alpha",
+            Param::Axis => "axis"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Param {
+    /// Kind of quantity the parameter is, with `value` in that quantity's unit. Indices,
+    /// optical densities and absorption coefficients are plain numbers.
+    pub fn quantity(self, value: f32) -> Option<(Quantity, f32)> {
+        match self {
+            Param::X | Param::Y | Param::Length | Param::F => Some((Quantity::Length, value)),
+            Param::Angle | Param::Axis => Some((Quantity::Angle, value.to_radians())),
+            Param::N | Param::Od | Param::Alpha => None
+        }
+    }
+
+    /// `value` of the parameter in `units`.
+    pub fn show(self, units: UnitSystem, value: f32) -> String {
+        match self.quantity(value) {
+            Some((quantity, value)) => units.show(quantity, value).to_string(),
+            None => format!("{:.3}", value)
+        }
+    }
+
+    pub fn get(self, surface: &Surface) -> Option<f32> {
+        let center = (surface.p1 + surface.p2) / 2.;
+        match self {
+            Param::X => Some(center.x),
+            Param::Y => Some(center.y),
+            Param::Angle => Some(surface.dp().y.atan2(surface.dp().x).to_degrees()),
+            Param::Length => Some(surface.length().0),
+            Param::N => Some(surface.index),
+            Param::F => surface.focal_length.map(|f| f.0),
+            Param::Od => surface.optical_density,
+            Param::Alpha => surface.interior.map(|_| surface.interior_absorption),
+            Param::Axis => surface.polarizer_axis().map(|axis| axis.0)
+        }
+    }
+
+    /// Sets the parameter, moving, turning or stretching the surface about its center.
+    pub fn set(self, surface: &mut Surface, value: f32) -> Result<(), String> {
+        let center = (surface.p1 + surface.p2) / 2.;
+        let half = surface.dp() / 2.;
+        let (p1, p2) = match self {
+            Param::X => (surface.p1 + Vec2::X * (value - center.x), surface.p2 + Vec2::X * (value - center.x)),
+            Param::Y => (surface.p1 + Vec2::Y * (value - center.y), surface.p2 + Vec2::Y * (value - center.y)),
+            Param::Angle => {
+                let half = Vec2::from_angle(value.to_radians()) * half.length();
+                (center - half, center + half)
+            }
+            Param::Length => {
+                let half = half.normalize_or_zero() * value / 2.;
+                (center - half, center + half)
+            }
+            Param::N => {
+                surface.index = value;
+                return Ok(())
+            }
+            Param::F => {
+                if surface.focal_length.is_none() {
+                    return Err("not a thin lens".to_string())
+                }
+                surface.focal_length = Some(Millimeters(value));
+                return Ok(())
+            }
+            Param::Od => {
+                if surface.optical_density.is_none() {
+                    return Err("not an attenuator".to_string())
+                }
+                surface.optical_density = Some(value.max(0.));
+                return Ok(())
+            }
+            Param::Alpha => {
+                if surface.interior.is_none() {
+                    return Err("not the face of a body".to_string())
+                }
+                surface.interior_absorption = value.max(0.);
+                return Ok(())
+            }
+            Param::Axis => {
+                if surface.polarizer_axis().is_none() {
+                    return Err("not a polarizer".to_string())
+                }
+                surface.mueller = Some(Mueller::polarizer(Degrees(value)));
+                return Ok(())
+            }
+        };
+        surface.p1 = p1;
+        surface.p2 = p2;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    /// Parameter of the surface with this label
+    Param(String, Param),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>)
+}
+
+impl Expr {
+    pub fn eval(&self, lookup: &impl Fn(&str, Param) -> Result<f32, String>) -> Result<f32, String> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Param(label, param) => lookup(label, *param),
+            Expr::Neg(expr) => Ok(-expr.eval(lookup)?),
+            Expr::Binary(a, op, b) => {
+                let (a, b) = (a.eval(lookup)?, b.eval(lookup)?);
+                Ok(match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    _ => a / b
+                })
+            }
+        }
+    }
+}
+
+/// Recursive descent over `sum := product (('+' | '-') product)*`,
+/// `product := unary (('*' | '/') unary)*`, `unary := '-' unary | atom`, and
+/// `atom := number | label '.' param | '(' sum ')'`.
+pub(crate) struct Parser<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) at: usize
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            at: 0
+        }
+    }
+
+    pub(crate) fn peek(&self) -> Option<char> {
+        self.text[self.at..].trim_start().chars().next()
+    }
+
+    pub(crate) fn eat(&mut self, c: char) -> bool {
+        let rest = self.text[self.at..].trim_start();
+        if rest.starts_with(c) {
+            self.at = self.text.len() - rest.len() + c.len_utf8();
+            return true
+        }
+        false
+    }
+
+    /// Longest run of characters matching `accept`, after any whitespace.
+    pub(crate) fn word(&mut self, accept: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.text[self.at..].trim_start();
+        let start = self.text.len() - rest.len();
+        let end = rest.find(|c| !accept(c)).map_or(self.text.len(), |k| start + k);
+        self.at = end;
+        &self.text[start..end]
+    }
+
+    pub(crate) fn reference(&mut self) -> Result<(String, Param), String> {
+        let label = self.word(|c| c.is_alphanumeric() || c == '_');
+        if label.is_empty() || !self.eat('.') {
+            return Err(format!("expected `label.parameter` at {}", self.at))
+        }
+        let param = self.word(|c| c.is_alphanumeric()).parse()?;
+        Ok((label.to_string(), param))
+    }
+
+    pub(crate) fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.eat(op);
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.peek().filter(|c| *c == '*' || *c == '/') {
+            self.eat(op);
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.unary()?)))
+        }
+        match self.peek() {
+            Some('(') => {
+                self.eat('(');
+                let expr = self.sum()?;
+                if !self.eat(')') {
+                    return Err(format!("expected `)` at {}", self.at))
+                }
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.word(|c| c.is_ascii_digit() || c == '.');
+                number.parse().map(Expr::Number).map_err(|_| format!("bad number `{}`", number))
+            }
+            Some(_) => self.reference().map(|(label, param)| Expr::Param(label, param)),
+            None => Err("unexpected end".to_string())
+        }
+    }
+}
+
+/// `target = expression`, e.g. `L2.x = L1.x + L1.f + L2.f`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    pub target: (String, Param),
+    pub expr: Expr,
+    pub text: String
+}
+
+impl FromStr for Link {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut parser = Parser::new(text);
+        let target = parser.reference()?;
+        if !parser.eat('=') {
+            return Err(format!("expected `=` at {}", parser.at))
+        }
+        let expr = parser.sum()?;
+        if parser.peek().is_some() {
+            return Err(format!("unexpected `{}` at {}", &text[parser.at..].trim(), parser.at))
+        }
+        Ok(Self {
+            target,
+            expr,
+            text: text.to_string()
+        })
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Parameters of labelled surfaces defined by expressions of other parameters. They are
+/// evaluated in order whenever a surface changes, so a link may use what an earlier one set.
+#[derive(Resource, Default)]
+pub struct Links(pub Vec<Link>);
+
+impl Links {
+    pub fn add(&mut self, text: &str) -> Result<(), String> {
+        self.0.push(text.parse()?);
+        Ok(())
+    }
+}
+
+/// Re-evaluates every link after a surface changes and moves the surfaces they define. The
+/// changes they make trigger another pass, which settles once nothing moves.
+pub fn link_system(
+    links: Res<Links>,
+    changed: Query<(), Changed<Surface>>,
+    mut surface_query: Query<(Entity, &mut Surface)>
+) {
+    if links.0.is_empty() || (changed.is_empty() && !links.is_changed()) {
+        return
+    }
+    let mut labelled: HashMap<String, (Entity, Surface)> = surface_query.iter()
+        .filter_map(|(entity, surface)| Some((surface.label.clone()?, (entity, surface.clone()))))
+        .collect();
+    for link in links.0.iter() {
+        let lookup = |label: &str, param: Param| {
+            let (_, surface) = labelled.get(label).ok_or(format!("no surface labelled `{}`", label))?;
+            param.get(surface).ok_or(format!("`{}` has no {:?}", label, param))
+        };
+        let value = link.expr.eval(&lookup);
+        let (label, param) = &link.target;
+        let result = value.and_then(|value| {
+            let (entity, surface) = labelled.get_mut(label).ok_or(format!("no surface labelled `{}`", label))?;
+            if param.get(surface).is_some_and(|old| (old - value).abs() < LINK_TOLERANCE) {
+                return Ok(())
+            }
+            param.set(surface, value)?;
+            if let Ok((_, mut target)) = surface_query.get_mut(*entity) {
+                *target = surface.clone();
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            warn!("link `{}`: {}", link, err);
+        }
+    }
+}
+
+/// State of the links panel and its entry line.
+#[derive(Resource, Default)]
+pub struct LinkEditor {
+    pub open: bool,
+    pub entry: String,
+    pub error: Option<String>
+}
+
+#[derive(Component)]
+pub struct LinksPanel;
+
+/// Removes the link at this index.
+#[derive(Component)]
+pub struct RemoveLink(pub usize);
+
+/// F12 opens and closes the links panel. While it is open, typing edits the entry line and
+/// Enter adds it as a link, or runs it when it is a `scan(..)`.
+pub fn link_input_system(
+    keys: Res<Input<KeyCode>>,
+    mut chars: EventReader<ReceivedCharacter>,
+    mut editor: ResMut<LinkEditor>,
+    mut links: ResMut<Links>,
+    mut live_scan: ResMut<LiveScan>,
+    config: Res<Config>
+) {
+    if keys.just_pressed(KeyCode::F12) {
+        editor.open = !editor.open;
+    }
+    if !editor.open {
+        chars.clear();
+        return
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        editor.entry.pop();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        editor.entry.clear();
+    }
+    if keys.just_pressed(KeyCode::Return) && !editor.entry.is_empty() {
+        let entry = std::mem::take(&mut editor.entry);
+        let result = if entry.trim_start().starts_with("scan(") {
+            entry.parse::<ScanSpec>().map(|spec| live_scan.start(spec, config.units))
+        } else {
+            links.add(&entry)
+        };
+        match result {
+            Ok(()) => editor.error = None,
+            Err(err) => {
+                editor.error = Some(err);
+                editor.entry = entry;
+            }
+        }
+    }
+    for event in chars.iter() {
+        if !event.char.is_control() {
+            editor.entry.push(event.char);
+        }
+    }
+}
+
+pub fn links_panel_system(
+    mut commands: Commands,
+    editor: Res<LinkEditor>,
+    links: Res<Links>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<LinksPanel>>
+) {
+    if !editor.is_changed() && !links.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !editor.open {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(30.),
+                top: Val::Percent(20.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, LinksPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.format("links.title", &[("count", &links.0.len())]), style.clone()));
+        for (k, link) in links.0.iter().enumerate() {
+            panel.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                row.spawn(TextBundle::from_section(link.to_string(), style.clone()));
+                row.spawn((ButtonBundle {
+                    style: Style {
+                        margin: UiRect::left(Val::Px(8.)),
+                        padding: UiRect::horizontal(Val::Px(4.)),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.25, 0.25, 0.25).into(),
+                    ..default()
+                }, RemoveLink(k))).with_children(|button| {
+                    button.spawn(TextBundle::from_section(locale.text("links.remove"), style.clone()));
+                });
+            });
+        }
+        panel.spawn(TextBundle::from_section(locale.format("links.entry", &[("entry", &editor.entry)]), style.clone()));
+        if let Some(err) = &editor.error {
+            panel.spawn(TextBundle::from_section(err.clone(), ui::text_style(&font, Color::rgb(1.0, 0.3, 0.3))));
+        }
+    });
+}
+
+pub fn remove_link_system(
+    mut links: ResMut<Links>,
+    button_query: Query<(&Interaction, &RemoveLink), Changed<Interaction>>
+) {
+    for (interaction, remove) in button_query.iter() {
+        if *interaction == Interaction::Clicked && remove.0 < links.0.len() {
+            links.0.remove(remove.0);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::HashMap, fmt::Display};
+
+use bevy::prelude::*;
+
+use crate::config::Config;
+
+// Language used for keys a bundle doesn't translate
+const FALLBACK: &str = "en";
+
+// Bundles are compiled in so the UI never waits on the asset server
+const BUNDLES: [(&str, &str); 2] = [
+    ("en", include_str!("../assets/locales/en.toml")),
+    ("de", include_str!("../assets/locales/de.toml"))
+];
+
+/// Languages with a bundle, in the order the settings dialog cycles through them.
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    BUNDLES.iter().map(|(language, _)| *language)
+}
+
+/// Flattens nested tables into dotted keys, `[settings] title` becoming `settings.title`.
+fn flatten(prefix: &str, value: toml::Value, strings: &mut HashMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+                flatten(&key, value, strings);
+            }
+        }
+        toml::Value::String(text) => {
+            strings.insert(prefix.to_string(), text);
+        }
+        other => {
+            strings.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+fn bundle(language: &str) -> HashMap<String, String> {
+    let mut strings = HashMap::new();
+    let Some((_, source)) = BUNDLES.iter().find(|(name, _)| *name == language) else {
+        return strings
+    };
+    match source.parse::<toml::Value>() {
+        Ok(value) => flatten("", value, &mut strings),
+        Err(err) => error!("invalid {} locale bundle: {}", language, err)
+    }
+    strings
+}
+
+/// UI strings for the configured language.
+#[derive(Resource)]
+pub struct Locale {
+    pub language: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::load(FALLBACK)
+    }
+}
+
+impl Locale {
+    pub fn load(language: &str) -> Self {
+        Self {
+            language: language.to_string(),
+            strings: bundle(language),
+            fallback: bundle(FALLBACK)
+        }
+    }
+
+    /// The string for `key`, or the key itself if no bundle has it.
+    pub fn text<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings.get(key)
+            .or_else(|| self.fallback.get(key))
+            .map(String::as_str)
+            .unwrap_or(key)
+    }
+
+    /// The string for `key` with each `{name}` replaced by its argument.
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let mut text = self.text(key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{}}}", name), &value.to_string());
+        }
+        text
+    }
+}
+
+pub fn locale_system(
+    config: Res<Config>,
+    mut locale: ResMut<Locale>
+) {
+    if config.is_changed() && locale.language != config.language {
+        *locale = Locale::load(&config.language);
+    }
+}
@@ -1,81 +1,161 @@
-use std::{f32::{EPSILON, consts::PI}, collections::btree_map::Iter};
-use itertools_num::linspace;
+use std::collections::{HashMap, HashSet};
 
-use bevy::{prelude::*, window::PresentMode};
+use bevy::{prelude::*, input::{mouse::{MouseScrollUnit, MouseWheel}, InputSystem}, window::{RequestRedraw, WindowResized}};
 use bevy_prototype_lyon::prelude::*;
 
-const WINDOW_W: usize = 1080;
-const WINDOW_H: usize = 920;
+mod alignment;
+mod animation;
+mod annotations;
+mod aperture;
+mod attenuator;
+mod bodies;
+mod bookmarks;
+mod bundle;
+mod chromatic;
+mod config;
+mod curves;
+mod detectors;
+mod diagnostics;
+mod etendue;
+mod fans;
+mod field;
+mod gaussian;
+mod knife_edge;
+mod library;
+mod golden;
+mod headless;
+mod history;
+mod inspector;
+mod keyboard;
+mod links;
+mod gpu;
+mod locale;
+mod measure;
+mod mouse;
+mod palette;
+mod paraxial;
+mod polarization;
+mod presentation;
+mod prisms;
+mod profile;
+mod raycaster;
+mod readout;
+mod scan;
+mod scene;
+mod scene_tree;
+mod session;
+mod snapping;
+mod tabs;
+mod touch;
+mod turbid;
+mod ui;
+mod viewer;
+mod viewports;
 
-const PX_PER_MM: usize = 20;
+use beams::{beams_core, coating, complex, material, units};
+use coating::Coating;
+use config::Config;
+use curves::CubicBezier;
+use polarization::Formalism;
+use units::{Millimeters, Nanometers};
+use viewports::MainCamera;
 
-const RAY_DENSITY: f32 = 0.2;
+pub use beams_core::*;
+// shadows bevy's own ray type from the prelude
+pub use beams_core::Ray;
 
-#[inline]
-pub fn cross2(a: Vec2, b: Vec2) -> f32 {
-    return a[0]*b[1] - b[0]*a[1]
+
+// Default pixels per mm at the default zoom, see `Config::px_per_mm`
+const PX_PER_MM: f32 = 20.;
+// Extent of the demo table (mm)
+const SCENE_W: f32 = 54.;
+const SCENE_H: f32 = 46.;
+
+// Default rays per mm of beam waist, see `Config::ray_density`
+const RAY_DENSITY: f32 = 4.;
+
+/// Scene geometry is in millimeters; the camera projection maps it to pixels. This is how many
+/// pixels a millimeter covers at the default zoom, from `Config::px_per_mm`. It is fixed for the
+/// session since the GPU views size their textures from it.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Scale {
+    pub px_per_mm: f32
 }
 
-pub fn intersect(ray: &Ray, surface: &Surface) -> f32 {
-    let v1 = ray.p - surface.p1;
-    let v2 = surface.p2 - surface.p1;
-    let v3 = Vec2::new(-ray.l[1], ray.l[0]);
-    let dot = v2.dot(v3);
-    if dot.abs() < 0.000001 {
-        return f32::INFINITY
-    } else {
-        let cross = v2.perp_dot(v1);
-        let t1 = cross / dot;
-        let t2 = v1.dot(v3) / dot;
-        if t1 >= 0.0 && (t2 >= 0.0 && t2 <= 1.0) {
-            return t1
-        } else {
-            return f32::INFINITY
+impl Scale {
+    pub fn new(px_per_mm: f32) -> Self {
+        // past 150 the GPU views' textures outgrow wgpu's default 8192 texel limit
+        Self {
+            px_per_mm: px_per_mm.clamp(1., 150.)
         }
     }
-}
 
-#[derive(Component, Clone)]
-pub struct BeamSource {
-    pub pos: Vec2,
-    pub direction: Vec2,
-    pub waist: f32,
-    pub w: f32,
-    pub index: f32
+    /// Converts a screen-space width in pixels to mm at the default zoom.
+    #[inline]
+    pub fn px(&self, width: f32) -> f32 {
+        width / self.px_per_mm
+    }
+
+    /// Zoom limits, as camera scale (mm per logical pixel)
+    pub fn zoom_limits(&self) -> (f32, f32) {
+        (0.01 / self.px_per_mm, 100. / self.px_per_mm)
+    }
 }
 
-impl BeamSource {
-    pub fn new(
-        pos: Vec2,
-        direction: Vec2,
-        waist: f32
-    ) -> Self {
-        Self {
-            pos: pos,
-            direction: direction,
-            waist: waist,
-            w: 532.,
-            index: 1.0
-        }
+/// Camera scale (mm per logical pixel) that fits the scene into a `width` x `height` window.
+/// Logical pixels keep the apparent size the same on HiDPI displays.
+pub fn fit_scale(width: f32, height: f32) -> f32 {
+    // a minimized window has no size, and any scale does until it is restored
+    if width <= 0. || height <= 0. {
+        return 1.
     }
+    (SCENE_W / width).max(SCENE_H / height)
 }
 
+/// Asks for `ray` to be traced into `tree`, or into a new tree when None. The tree is of the
+/// rays of `source`; without a ray, the tree is being cleared.
 struct RaycastEvent {
     ray: Option<Ray>,
-    tree: Option<Entity>
+    tree: Option<Entity>,
+    source: Option<Entity>
+}
+
+/// Sources with trees that a surface edit this frame retraced, left for `source_change_system`
+/// to resample when sampling is adaptive.
+#[derive(Resource, Default)]
+struct EditedSources(Vec<Entity>);
+
+/// Sent by the tracer for every ray-surface intersection, so other systems (targets, puzzles,
+/// measurements) can react to light without touching the tracer.
+#[derive(Clone)]
+pub struct RayHitEvent {
+    pub surface: Entity,
+    pub ray: Ray,
+    pub hit: Hit
 }
 
+/// Everything traced from one emitted ray. Each source's rays have trees of their own, so a
+/// change to one source only retraces its trees.
 #[derive(Component, Clone)]
 pub struct RayTree {
-    root: Ray,
-    branches: Vec::<Ray>
+    pub root: Ray,
+    pub branches: Vec::<Ray>,
+    /// How far the root and then each branch go, see [`Trace::reaches`]
+    pub reaches: Vec<f32>,
+    /// The source that emitted the root ray
+    pub source: Option<Entity>,
+    /// Power left in thermal lenses as (lens, fraction along it, power)
+    pub deposits: Vec<(Entity, f32, f32)>
 }
 
 impl RayTree {
-    pub fn new(ray: Ray) -> Self {
+    pub fn new(ray: Ray, source: Option<Entity>) -> Self {
         Self {
             root: ray.clone(),
-            branches: Vec::<Ray>::new()
+            branches: Vec::<Ray>::new(),
+            reaches: Vec::new(),
+            source,
+            deposits: Vec::new()
         }
     }
 }
@@ -83,238 +163,928 @@ impl RayTree {
 #[derive(Component, Clone)]
 pub struct RaySource;
 
+/// Drawn piece of a ray tree, from where a ray starts to where it hits.
 #[derive(Component, Clone)]
-pub struct RaySegment;
-
-#[derive(Clone)]
-pub struct Ray {
-    pub p: Vec2,
-    pub l: Vec2,
-    pub i: f32,
-    index: f32, 
-    w: f32
+pub struct RaySegment {
+    pub from: Vec2,
+    pub to: Vec2,
+    pub intensity: f32,
+    pub wavelength: Nanometers,
+    /// Optical path length (mm) from the source to `from`
+    pub opl: f32,
+    /// Refractive index along the segment
+    pub index: f32,
+    /// Polarization along the segment, when the scene tracks it
+    pub stokes: Option<Stokes>,
+    /// Power carried along the segment
+    pub power: f32,
+    /// Surface the segment ends on
+    pub surface: Entity,
+    /// Angles where the segment meets its surface, if it is refracted there
+    pub incidence: Option<Incidence>,
+    /// Interactions along the tree before the segment, 0 for the emitted ray
+    pub depth: usize,
+    /// Whether the segment is on a Fresnel reflection off the beam's own path
+    pub ghost: bool
 }
 
-impl Ray {
-    pub fn new(p: Vec2, l: Vec2, index: f32) -> Self {
+impl RaySegment {
+    pub fn of(segment: &TraceSegment) -> Self {
         Self {
-            p: p, 
-            l: l,
-            i: 1.0, 
-            index: index,
-            w: 532.
+            from: segment.ray.p,
+            to: segment.hit.point,
+            intensity: segment.ray.i,
+            wavelength: segment.ray.wavelength(),
+            opl: segment.ray.opl,
+            index: segment.ray.medium_index(),
+            stokes: segment.ray.stokes,
+            power: segment.ray.power * segment.ray.i,
+            surface: segment.surface,
+            incidence: segment.incidence,
+            depth: segment.depth,
+            ghost: segment.ray.ghost
         }
     }
 }
 
 #[derive(Component, Clone)]
-pub struct Surface {
-    pub p1: Vec2,
-    pub p2: Vec2,
-    pub dp: Vec2,
-    pub normal: Vec2,
-    pub length: f32,
-    pub index: f32,
-    pub reflection: f32,
-    pub absorption: f32  
-}
+pub struct GridLine;
 
-impl Surface {
-    pub fn glass(
-        p1: Vec2,
-        p2: Vec2,
-        index: f32,
-    ) -> Self {
-        Self {
-            p1: p1,
-            p2: p2,
-            dp: p2 - p1,
-            length: (p2 - p1).length(),
-            normal: (p2 - p1).normalize().perp(),
-            index: index,
-            reflection: 0.0,
-            absorption: 0.0
-        }
-    }
-    pub fn blocker(
-        p1: Vec2,
-        p2: Vec2,
-    ) -> Self {
-        Self {
-            p1: p1,
-            p2: p2,
-            dp: p2 - p1,
-            length: (p2 - p1).length(),
-            normal: (p2 - p1).normalize().perp(),
-            index: 1.0,
-            reflection: 0.0,
-            absorption: 1.0
-        }
-    }
+/// Drawn outline of a surface, kept and updated for as long as the surface exists.
+#[derive(Component, Clone, Copy)]
+pub struct SurfaceOutline {
+    pub surface: Entity
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = golden::run(&args) {
+        std::process::exit(code)
+    }
+    if let Some(code) = headless::run(&args) {
+        std::process::exit(code)
+    }
+    if let Some(code) = scan::run(&args) {
+        std::process::exit(code)
+    }
+    if let Some(code) = viewer::run(&args) {
+        std::process::exit(code)
+    }
+    let config = Config::load();
     App::new()
         .insert_resource(Msaa { samples: 4 })
-        .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
+        .insert_resource(ClearColor(config.theme.background()))
+        .insert_resource(config.winit_settings())
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             window: WindowDescriptor {
                 title: "Beams ".to_string() + env!("CARGO_PKG_VERSION"),
-                width: WINDOW_W as f32,
-                height: WINDOW_H as f32,
-                present_mode: PresentMode::AutoNoVsync,
+                width: config.window_width,
+                height: config.window_height,
+                present_mode: config.present_mode(),
+                canvas: cfg!(target_arch = "wasm32").then(|| "#beams".to_string()),
+                fit_canvas_to_parent: true,
                 // mode: WindowMode::BorderlessFullscreen,
                 ..default()
             },
             ..default()
+        }).set(AssetPlugin {
+            // reload edited WGSL kernels without restarting
+            watch_for_changes: cfg!(debug_assertions),
+            ..default()
         }))
+        .insert_resource(scene::SceneFile::from_args(&args, &config))
+        .insert_resource(Scale::new(config.px_per_mm))
+        .insert_resource(config)
+        .insert_resource(viewer::ReadOnly::from_args(&args))
         .add_plugin(ShapePlugin)
+        .add_plugin(bevy_egui::EguiPlugin)
+        .add_plugin(gpu::GpuPlugin)
         .add_event::<RaycastEvent>()
-        .add_startup_system(draw_grid_system)
+        .add_event::<RayHitEvent>()
+        .init_resource::<diagnostics::Diagnostics>()
+        .init_resource::<bookmarks::Bookmarks>()
+        .init_resource::<scene_tree::SceneFilter>()
+        .init_resource::<inspector::Inspector>()
+        .init_resource::<measure::Measure>()
+        .init_resource::<library::Library>()
+        .init_resource::<presentation::Presentation>()
+        .init_resource::<locale::Locale>()
+        .init_resource::<presentation::PresentationToggles>()
+        .init_resource::<Formalism>()
+        .init_resource::<EditedSources>()
+        .init_resource::<chromatic::ChromaticReport>()
+        .init_resource::<fans::RayFan>()
+        .init_resource::<field::FieldSweep>()
+        .init_resource::<etendue::EtendueReport>()
+        .init_resource::<paraxial::LayoutSolver>()
+        .init_resource::<paraxial::SystemReport>()
+        .init_resource::<raycaster::Raycaster>()
+        .init_resource::<gaussian::GaussianBeams>()
+        .init_resource::<links::Links>()
+        .init_resource::<links::LinkEditor>()
+        .init_resource::<alignment::BeamWalk>()
+        .init_resource::<animation::Clock>()
+        .init_resource::<knife_edge::KnifeEdge>()
+        .init_resource::<detectors::Recording>()
+        .init_resource::<detectors::DetectorProfile>()
+        .init_resource::<scan::LiveScan>()
+        .init_resource::<polarization::EllipseGlyphs>()
+        .init_resource::<annotations::AngleAnnotations>()
+        .init_resource::<tabs::Tabs>()
+        .init_resource::<history::History>()
+        .init_resource::<keyboard::Palette>()
+        .add_startup_system(ui::load_font_system)
         .add_startup_system(setup_system)
+        .add_startup_system_to_stage(StartupStage::PostStartup, session::session_restore_system.with_run_criteria(viewer::editable))
+        .add_system_to_stage(CoreStage::First, session::session_save_system.with_run_criteria(viewer::editable))
+        .add_system_to_stage(CoreStage::PreUpdate, keyboard::typing_capture_system.after(InputSystem))
         .add_system(draw_surface_system)
+        .add_system(turbid::draw_medium_system)
+        .add_system(thermal_lens_system.before(raycast_system))
+        .add_system(surface_change_system.after(touch::touch_drag_system).before(raycast_system))
+        .add_system(links::link_system
+            .after(touch::touch_drag_system)
+            .after(presentation::presentation_keys_system)
+            .before(surface_change_system))
+        .add_system(links::link_input_system.with_run_criteria(viewer::editable).before(links::link_system))
+        .add_system(links::remove_link_system.with_run_criteria(viewer::editable).before(links::link_system))
+        .add_system(links::links_panel_system.after(links::link_input_system).after(links::remove_link_system))
+        .add_system(raycaster::raycaster_rebuild_system.after(surface_change_system).before(raycast_system))
         .add_system(raycast_system)
+        .add_system(camera_zoom_system)
+        .add_system(window_resize_system)
+        .add_system(draw_grid_system)
+        .add_system(diagnostics::validation_system)
+        .add_system(diagnostics::diagnostics_panel_system.after(diagnostics::validation_system))
+        .add_system(diagnostics::diagnostic_click_system)
+        .add_system(config_retrace_system
+            .after(surface_change_system)
+            .after(raycaster::raycaster_rebuild_system)
+            .after(thermal_lens_system)
+            .after(presentation::presentation_toggle_system)
+            .before(raycast_system))
+        .add_system(profile::profile_drop_system.with_run_criteria(viewer::editable).after(fans::fan_toggle_system))
+        .add_system(profile::shape_cycle_system.with_run_criteria(viewer::editable).after(fans::fan_toggle_system))
+        .add_system(source_change_system
+            .after(surface_change_system)
+            .after(raycaster::raycaster_rebuild_system)
+            .after(thermal_lens_system)
+            .after(presentation::presentation_toggle_system)
+            .after(profile::profile_drop_system)
+            .after(profile::shape_cycle_system)
+            .before(raycast_system))
+        .add_system(polarization::formalism_toggle_system.before(config_retrace_system))
+        .add_system(polarization::ellipse_glyph_system)
+        .add_system(annotations::annotation_toggle_system)
+        .add_system(annotations::annotation_system.after(annotations::annotation_toggle_system))
+        .add_system(chromatic::chromatic_toggle_system)
+        .add_system(chromatic::chromatic_report_system.after(chromatic::chromatic_toggle_system))
+        .add_system(chromatic::chromatic_panel_system.after(chromatic::chromatic_report_system))
+        .add_system(fans::fan_toggle_system)
+        .add_system(fans::fan_system.after(fans::fan_toggle_system))
+        .add_system(fans::fan_panel_system.after(fans::fan_system))
+        .add_system(field::field_toggle_system)
+        .add_system(field::field_sweep_system.after(field::field_toggle_system).after(fans::fan_toggle_system))
+        .add_system(field::field_panel_system.after(field::field_sweep_system))
+        .add_system(etendue::etendue_toggle_system)
+        .add_system(etendue::etendue_system.after(etendue::etendue_toggle_system).after(fans::fan_toggle_system))
+        .add_system(etendue::etendue_panel_system.after(etendue::etendue_system))
+        .add_system(alignment::walk_input_system.with_run_criteria(viewer::editable))
+        .add_system(alignment::walk_solve_system.after(alignment::walk_input_system))
+        .add_system(alignment::walk_animation_system.after(alignment::walk_solve_system).before(surface_change_system))
+        .add_system(alignment::walk_panel_system.after(alignment::walk_animation_system))
+        .add_system(animation::clock_toggle_system)
+        .add_system(animation::clock_system.after(animation::clock_toggle_system))
+        .add_system(animation::track_system.after(animation::clock_system).before(surface_change_system))
+        .add_system(animation::chopper_system.after(animation::clock_system).before(surface_change_system))
+        .add_system(knife_edge::knife_toggle_system.after(fans::fan_toggle_system))
+        .add_system(knife_edge::knife_sweep_system.after(knife_edge::knife_toggle_system).after(animation::clock_system).before(surface_change_system))
+        .add_system(knife_edge::knife_panel_system.after(knife_edge::knife_sweep_system))
+        .add_system(detectors::detector_input_system.with_run_criteria(viewer::editable))
+        .add_system(detectors::recording_system.after(detectors::detector_input_system).after(animation::track_system))
+        .add_system(detectors::recording_panel_system.after(detectors::recording_system))
+        .add_system(detectors::detector_profile_system.before(detectors::profile_panel_system))
+        .add_system(detectors::profile_panel_system)
+        .add_system(scan::live_scan_system.after(links::link_input_system).before(surface_change_system))
+        .add_system(prisms::prism_spawn_system)
+        .add_system(bodies::body_spawn_system)
+        .add_system(aperture::aperture_spawn_system)
+        .add_system(aperture::aperture_coupling_system.after(touch::touch_drag_system).after(links::link_system).before(surface_change_system))
+        .add_system(aperture::aperture_keys_system.with_run_criteria(viewer::editable).before(aperture::aperture_coupling_system))
+        .add_system(bodies::body_coupling_system.after(touch::touch_drag_system).after(links::link_system).before(surface_change_system))
+        .add_system(prisms::prism_coupling_system.after(touch::touch_drag_system).after(links::link_system).before(surface_change_system))
+        .add_system(attenuator::attenuator_panel_system)
+        .add_system(attenuator::od_slider_system.with_run_criteria(viewer::editable).before(surface_change_system))
+        .add_system(attenuator::sweep_button_system.with_run_criteria(viewer::editable))
+        .add_system(attenuator::attenuator_readout_system.after(attenuator::attenuator_panel_system).after(animation::track_system))
+        .add_system(gaussian::gaussian_toggle_system)
+        .add_system(gaussian::gaussian_beam_system.after(gaussian::gaussian_toggle_system))
+        .add_system(gaussian::gaussian_draw_system.after(gaussian::gaussian_beam_system))
+        .add_system(paraxial::layout_toggle_system)
+        .add_system(paraxial::layout_button_system.with_run_criteria(viewer::editable))
+        .add_system(paraxial::system_report_system.after(paraxial::layout_toggle_system).after(fans::fan_toggle_system))
+        .add_system(paraxial::layout_panel_system
+            .after(paraxial::layout_toggle_system)
+            .after(paraxial::layout_button_system)
+            .after(paraxial::system_report_system))
+        .add_system(config::apply_config_system)
+        .add_system(locale::locale_system)
+        .add_system(config::settings_toggle_system)
+        .add_system(config::settings_panel_system.after(config::settings_toggle_system))
+        .add_system(config::settings_button_system)
+        .add_system(viewports::open_view_system)
+        .add_system(viewports::view_resize_system)
+        .add_system(viewports::close_view_system)
+        .add_system(detectors::detector_view_system.after(detectors::detector_profile_system))
+        .add_system(bookmarks::bookmark_system.after(keyboard::palette_input_system))
+        .add_system(tabs::tab_input_system.with_run_criteria(viewer::editable))
+        .add_system(tabs::tab_switch_system.after(tabs::tab_input_system).before(surface_change_system).before(raycast_system))
+        .add_system(scene::scene_save_system.with_run_criteria(viewer::editable))
+        .add_system(tabs::tab_bar_system.after(tabs::tab_switch_system))
+        .add_system(history::history_record_system.with_run_criteria(viewer::editable).after(tabs::tab_switch_system))
+        .add_system(history::history_keys_system.with_run_criteria(viewer::editable).after(history::history_record_system).before(surface_change_system).before(raycast_system))
+        .add_system(scene_tree::scene_filter_input_system)
+        .add_system(scene_tree::scene_tree_panel_system.after(scene_tree::scene_filter_input_system))
+        .add_system(inspector::inspector_toggle_system)
+        .add_system(inspector::inspector_system
+            .after(inspector::inspector_toggle_system)
+            .before(surface_change_system)
+            .before(source_change_system))
+        .add_system(scene_tree::scene_tree_click_system.after(scene_tree::scene_tree_panel_system))
+        .add_system(touch::touch_camera_system)
+        .add_system(readout::readout_system.after(camera_zoom_system))
+        .add_system(measure::measure_toggle_system)
+        .add_system(measure::measure_click_system.after(measure::measure_toggle_system))
+        .add_system(measure::measure_draw_system.after(measure::measure_click_system))
+        .add_system(library::library_toggle_system.with_run_criteria(viewer::editable))
+        .add_system(library::library_place_system.with_run_criteria(viewer::editable).after(library::library_toggle_system).after(mouse::mouse_drag_system))
+        .add_system(library::library_panel_system.after(library::library_place_system))
+        .add_system(mouse::source_switch_system.with_run_criteria(viewer::editable).before(source_change_system))
+        .add_system(touch::touch_drag_system.with_run_criteria(viewer::editable))
+        .add_system(mouse::mouse_drag_system
+            .with_run_criteria(viewer::editable)
+            .before(links::link_system)
+            .before(bodies::body_coupling_system)
+            .before(prisms::prism_coupling_system)
+            .before(aperture::aperture_coupling_system)
+            .before(surface_change_system)
+            .before(source_change_system))
+        .add_system(presentation::presentation_toggle_system.before(raycast_system))
+        .add_system(presentation::presentation_visibility_system)
+        .add_system(presentation::presentation_keys_system.before(surface_change_system))
+        .add_system(keyboard::selection_cycle_system)
+        .add_system(keyboard::nudge_system.with_run_criteria(viewer::editable).before(surface_change_system))
+        .add_system(keyboard::palette_input_system.with_run_criteria(viewer::editable))
+        .add_system(keyboard::palette_run_system.after(keyboard::palette_input_system).before(surface_change_system))
+        .add_system(keyboard::palette_panel_system.after(keyboard::palette_run_system))
+        .add_system(viewer::play_button_system)
+        .add_system(viewer::play_click_system)
         .run();
 }
 
 fn raycast_system(
     mut commands: Commands,
-    reader: EventReader<RaycastEvent>,
-    surface_query: Query<&Surface>
+    (config, presentation, scale): (Res<Config>, Res<presentation::Presentation>, Res<Scale>),
+    mut reader: EventReader<RaycastEvent>,
+    mut hit_writer: EventWriter<RayHitEvent>,
+    raycaster: Res<raycaster::Raycaster>,
+    tree_query: Query<(Entity, &RayTree, Option<&Children>)>,
+    mut lens_query: Query<&mut ThermalLens>
 ) {
-    for raycast_event in reader.iter() {
-        if let Some(ray) = raycast_event.ray {
-            if let Some(old_tree) = raycast_event.tree {
-                commands.entity(old_tree).despawn();
-            }
-            let tree = RayTree::new(ray);
-            'surfaces: for surface in surface_query.iter() {
-                let d = intersect(&ray, surface);
-                if d.is_finite() && d > 0.1 {
-                    println!("Intersection at {}", d);
-                    let mut path_builder = PathBuilder::new();
-                    path_builder.move_to(ray.p);
-                    path_builder.line_to(ray.p + ray.l * d);
-                    commands.spawn(GeometryBuilder::build_as(
-                        &path_builder.build(),
-                        DrawMode::Stroke(StrokeMode::new(Color::YELLOW, 1.0)),
-                        Transform::default(),
-                    )).insert(RaySegment);                
-                    if surface.absorption < 1.0 {
-                        let normal = if surface.normal.angle_between(ray.l) > surface.normal.angle_between(ray.l) {
-                            surface.normal
-                        } else {
-                            -1. * surface.normal
-                        };
-                        let refracted = ((ray.index * normal.perp_dot(ray.l)) / surface.index).asin();
-                        println!("incident is {} refracted is {}", ray.l.angle_between(normal), refracted);
-                        tree.branches.push(Ray::new(
-                            ray.p + ray.l * d,
-                            Vec2::from_angle(refracted).normalize(),
-                            surface.index
-                        ));
+    if reader.is_empty() {
+        return
+    }
+    for mut lens in lens_query.iter_mut() {
+        lens.deposited.iter_mut().for_each(|p| *p = 0.);
+    }
+    let mut deposits = Vec::new();
+    let events: Vec<&RaycastEvent> = reader.iter().collect();
+    // several systems may ask for the same tree in one frame; the last request wins, so a
+    // tree re-emitted from a moved source isn't retraced from its old root
+    let last_request: HashMap<Entity, usize> = events.iter()
+        .enumerate()
+        .filter_map(|(n, event)| event.tree.map(|tree| (tree, n)))
+        .collect();
+    for (n, raycast_event) in events.into_iter().enumerate() {
+        if let Some(ray) = &raycast_event.ray {
+            // a retraced tree keeps its entity and reuses its segments, updating their paths,
+            // so dense fans don't despawn and respawn every segment on each change
+            let (tree_entity, mut pooled) = match raycast_event.tree {
+                Some(old_tree) => {
+                    if last_request.get(&old_tree) != Some(&n) {
+                        continue
+                    }
+                    let pooled: Vec<Entity> = tree_query.get(old_tree).ok()
+                        .and_then(|(.., children)| children)
+                        .map_or(Vec::new(), |children| children.to_vec());
+                    (old_tree, pooled.into_iter())
+                }
+                None => (commands.spawn(SpatialBundle::default()).id(), Vec::new().into_iter())
+            };
+            let mut tree = RayTree::new(ray.clone(), raycast_event.source);
+            let traced = trace_with(
+                ray,
+                |ray, exclude| raycaster.cast(ray, exclude),
+                |entity| lens_query.get(entity).ok(),
+                config.limits()
+            );
+            for segment in traced.segments {
+                let w = segment.ray.wavelength();
+                // rays fading in tinted glass are drawn at their mean intensity along the way
+                let shown = segment.ray.mean_intensity(segment.hit.distance);
+                let drawn = (
+                    config.palette.path(w, segment.ray.p, segment.hit.point, &scale),
+                    DrawMode::Stroke(StrokeMode::new(config.palette.stroke(w, shown), scale.px(presentation.stroke()))),
+                    RaySegment::of(&segment)
+                );
+                match pooled.next() {
+                    Some(shape) => {
+                        // anything drawn along the old segment, like polarization glyphs
+                        let mut shape = commands.entity(shape);
+                        shape.despawn_descendants();
+                        shape.insert(drawn);
+                    }
+                    None => {
+                        let (path, draw_mode, ray_segment) = drawn;
+                        let shape = commands.spawn(GeometryBuilder::build_as(&path, draw_mode, Transform::default()))
+                            .insert(ray_segment)
+                            .id();
+                        commands.entity(tree_entity).add_child(shape);
                     }
-                    break 'surfaces;
                 }
+                hit_writer.send(RayHitEvent {
+                    surface: segment.surface,
+                    ray: segment.ray,
+                    hit: segment.hit
+                });
             }
+            for unused in pooled {
+                commands.entity(unused).despawn_recursive();
+            }
+            tree.branches = traced.branches;
+            tree.reaches = traced.reaches;
+            deposits.extend(traced.deposits.iter().copied());
+            tree.deposits = traced.deposits;
+            commands.entity(tree_entity).insert(tree);
+        }
+    }
+    // lenses also keep what the trees that weren't retraced left in them
+    for (entity, tree, _) in tree_query.iter() {
+        if !last_request.contains_key(&entity) {
+            deposits.extend(tree.deposits.iter().copied());
+        }
+    }
+    for (entity, t, power) in deposits {
+        if let Ok(mut lens) = lens_query.get_mut(entity) {
+            lens.deposit(t, power);
         }
     }
 }
 
-fn setup_system(
-    mut commands: Commands,
+/// Relaxes thermal lenses and retraces while they are still settling. Keeps requesting frames
+/// until then, so lenses settle in power-saving mode too.
+fn thermal_lens_system(
+    time: Res<Time>,
+    mut lens_query: Query<&mut ThermalLens>,
+    tree_query: Query<(Entity, &RayTree)>,
+    mut writer: EventWriter<RaycastEvent>,
+    mut redraw: EventWriter<RequestRedraw>
+) {
+    let mut change: f32 = 0.;
+    for mut lens in lens_query.iter_mut() {
+        change = change.max(lens.relax(time.delta_seconds()));
+    }
+    if change > THERMAL_TOLERANCE {
+        redraw.send(RequestRedraw);
+        for (entity, tree) in tree_query.iter() {
+            writer.send(RaycastEvent {
+                ray: Some(tree.root.clone()),
+                tree: Some(entity),
+                source: tree.source
+            });
+        }
+    }
+}
+
+/// Retraces the ray trees a surface edit can affect: those with a segment ending on a surface
+/// that was edited or removed, and those with a ray that would now hit an edited or added
+/// surface before where it currently ends. Other trees are left as they were.
+fn surface_change_system(
+    changed: Query<(Entity, &Surface), Changed<Surface>>,
+    removed: RemovedComponents<Surface>,
+    tree_query: Query<(Entity, &RayTree, Option<&Children>)>,
+    segment_query: Query<&RaySegment>,
+    mut sources: ResMut<EditedSources>,
     mut writer: EventWriter<RaycastEvent>
 ) {
-    commands.spawn(Camera2dBundle {
-        transform: Transform::from_translation(Vec3::new((WINDOW_W / 2) as f32, (WINDOW_H / 2) as f32, 0.)),
-        ..Default::default()
-    });
+    let edited: Vec<Entity> = changed.iter().map(|(entity, _)| entity).chain(removed.iter()).collect();
+    if edited.is_empty() {
+        return
+    }
+    for (entity, tree, children) in tree_query.iter() {
+        let segments: Vec<&RaySegment> = children
+            .map_or(Vec::new(), |children| segment_query.iter_many(children).collect());
+        let ends_on_edited = segments.iter().any(|segment| edited.contains(&segment.surface));
+        // rays that were never traced run on to infinity
+        let reaches = tree.reaches.iter().copied().chain(std::iter::repeat(f32::INFINITY));
+        let blocked = || std::iter::once(&tree.root).chain(tree.branches.iter()).zip(reaches).any(|(ray, reach)| {
+            changed.iter().any(|(_, surface)| intersect(ray, surface) < reach)
+        });
+        if ends_on_edited || blocked() {
+            if let Some(source) = tree.source.filter(|source| !sources.0.contains(source)) {
+                sources.0.push(source);
+            }
+            writer.send(RaycastEvent {
+                ray: Some(tree.root.clone()),
+                tree: Some(entity),
+                source: tree.source
+            });
+        }
+    }
+}
 
+/// The built-in scene shown when no other scene is given.
+pub fn demo_scene() -> (Vec<BeamSource>, Vec<Surface>) {
     let beam = BeamSource::new(
-        Vec2::new(200., 650.),
+        Vec2::new(10., 32.5),
         Vec2::new(1., -0.02).normalize(),
-        10.
-    )
-
-    commands.spawn(beam);
-    for x in linspace(-beam.waist / 2., beam.waist / 2., (beam.waist * RAY_DENSITY) as usize) {
-        let beam_ray = Ray::new(
-            beam.pos + x * Vec2::new(-beam.direction[1], beam.direction[0]),
-            beam.direction,
-            1.0
-        )
+        Millimeters(0.5)
+    );
+
+    let mut surfaces = vec![
+        Surface::glass(
+            Vec2::new(25., 30.), 
+            Vec2::new(25., 35.)
+        ).index(1.5).label("slab"),
+        Surface::glass(
+            Vec2::new(45., 30.), 
+            Vec2::new(47.5, 35.)
+        ).index(1.0)
+    ];
+    let reflector = CubicBezier::new(
+        Vec2::new(49., 27.),
+        Vec2::new(51., 30.),
+        Vec2::new(51., 35.),
+        Vec2::new(49., 38.)
+    );
+    surfaces.extend(curves::bezier(&reflector, 0.01, |p1, p2| Surface::metal(p1, p2, Coating::Aluminium)));
+    surfaces.extend([
+        Surface::backdrop(Vec2::new(0., 0.), Vec2::X),
+        Surface::backdrop(Vec2::new(0., SCENE_H), Vec2::X),
+        Surface::backdrop(Vec2::new(0., 0.), Vec2::Y),
+        Surface::backdrop(Vec2::new(SCENE_W, 0.), Vec2::Y)
+    ]);
+    (vec![beam], surfaces)
+}
+
+fn setup_system(
+    mut commands: Commands,
+    (config, formalism, scale): (Res<Config>, Res<Formalism>, Res<Scale>),
+    windows: Res<Windows>,
+    scene_file: Res<scene::SceneFile>,
+    mut bookmarks: ResMut<bookmarks::Bookmarks>,
+    mut toggles: ResMut<presentation::PresentationToggles>,
+    mut writer: EventWriter<RaycastEvent>
+) {
+    // keep the default depth so shapes at z > 0 stay in front of the near plane
+    let mut camera = Camera2dBundle::default();
+    camera.transform.translation.x = SCENE_W / 2.;
+    camera.transform.translation.y = SCENE_H / 2.;
+    camera.projection.scale = match windows.get_primary() {
+        Some(window) => fit_scale(window.width(), window.height()),
+        None => scale.px(1.)
+    };
+    commands.spawn(camera);
+
+    let scene = scene_file.scene();
+    if let Some(beam) = scene.sources.first() {
+        bookmarks.set(1, "source", beam.pos, 4.);
+    }
+    if let Some(slab) = scene.elements.iter().find(|element| element.surface.label.as_deref() == Some("slab")) {
+        // Q: the same slab in diamond
+        toggles.bind(KeyCode::Q, slab.surface.clone().index(2.42));
+    }
+    scene.spawn(&mut commands, &config, &formalism, &mut writer);
+}
+
+fn camera_zoom_system(
+    mut scroll: EventReader<MouseWheel>,
+    (scale, inspector): (Res<Scale>, Res<inspector::Inspector>),
+    mut query: Query<&mut OrthographicProjection, MainCamera>
+) {
+    let delta: f32 = scroll.iter().map(|event| match event.unit {
+        MouseScrollUnit::Line => event.y,
+        MouseScrollUnit::Pixel => event.y / 100.
+    }).sum();
+    // the wheel scrolls the inspector's list while the cursor is over it
+    if delta != 0. && !inspector.hovered {
+        let (min, max) = scale.zoom_limits();
+        for mut projection in query.iter_mut() {
+            projection.scale = (projection.scale * 0.9_f32.powf(delta)).clamp(min, max);
+        }
+    }
+}
+
+/// Keeps the scene centered and fitted when the window is resized, preserving any zoom
+/// relative to the fitted scale.
+fn window_resize_system(
+    mut resized: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    mut fitted: Local<Option<f32>>,
+    mut query: Query<(&mut Transform, &mut OrthographicProjection), MainCamera>
+) {
+    let Some(window) = windows.get_primary() else {
+        return
+    };
+    if !resized.iter().any(|event| event.id == window.id()) {
+        return
+    }
+    let fit = fit_scale(window.width(), window.height());
+    for (mut transform, mut projection) in query.iter_mut() {
+        let zoom = fitted.map_or(1., |previous| projection.scale / previous);
+        projection.scale = fit * zoom;
+        transform.translation.x = SCENE_W / 2.;
+        transform.translation.y = SCENE_H / 2.;
+    }
+    *fitted = Some(fit);
+}
+
+/// Rays `beam` emits under the current settings. With adaptive sampling on, rays are added
+/// between neighbours whose paths through the scene part ways, see [`BeamSource::adaptive_rays`].
+fn launch(beam: &BeamSource, config: &Config, formalism: Formalism, raycaster: &raycaster::Raycaster) -> Vec<Ray> {
+    if !config.adaptive_rays {
+        return formalism.launch(beam, config.ray_density)
+    }
+    let limits = TraceLimits { ghosts: false, ..config.limits() };
+    formalism.launch_adaptive(beam, config.ray_density, |ray| {
+        let traced = trace_with(ray, |ray, exclude| raycaster.cast(ray, exclude), |_| None::<&ThermalLens>, limits);
+        // without ghosts, the branch traced first is the beam's own path
+        traced.segments.iter().enumerate()
+            .take_while(|(k, segment)| segment.depth == *k)
+            .map(|(_, segment)| segment.hit.point)
+            .collect()
+    })
+}
+
+/// Settings the rays were last emitted and traced with: density, adaptive sampling, limits,
+/// palette and formalism.
+type TraceSettings = (f32, bool, TraceLimits, palette::Palette, Formalism);
+
+/// Re-emits every source when the ray density or sampling, trace limits, palette or
+/// polarization formalism changes.
+fn config_retrace_system(
+    mut commands: Commands,
+    (config, formalism, raycaster): (Res<Config>, Res<Formalism>, Res<raycaster::Raycaster>),
+    mut traced_with: Local<Option<TraceSettings>>,
+    source_query: Query<(Entity, &BeamSource)>,
+    tree_query: Query<(Entity, &RayTree)>,
+    mut writer: EventWriter<RaycastEvent>
+) {
+    let settings = (config.ray_density, config.adaptive_rays, config.limits(), config.palette, *formalism);
+    if !(config.is_changed() || formalism.is_changed()) || *traced_with == Some(settings) {
+        return
+    }
+    // the startup emission already used the initial settings
+    if traced_with.replace(settings).is_none() {
+        return
+    }
+    for (source, beam) in source_query.iter() {
+        let rays = launch(beam, &config, *formalism, &raycaster);
+        reemit(&mut commands, source, rays.into_iter(), trees_of(source, &tree_query).into_iter(), &mut writer);
+    }
+}
+
+/// Re-emits the sources that changed, e.g. when one is moved, switched off or has a profile
+/// loaded onto it. Only their own trees are retraced; trees left by removed sources are cleared.
+/// With adaptive sampling on, sources are also re-emitted when a surface edit retraced any of
+/// their trees, since where rays are needed moves with the optics.
+#[allow(clippy::too_many_arguments)]
+fn source_change_system(
+    mut commands: Commands,
+    (config, formalism, raycaster): (Res<Config>, Res<Formalism>, Res<raycaster::Raycaster>),
+    source_query: Query<(Entity, &BeamSource, ChangeTrackers<BeamSource>)>,
+    mut edited: ResMut<EditedSources>,
+    tree_query: Query<(Entity, &RayTree)>,
+    mut unrefined: Local<Vec<Entity>>,
+    mut writer: EventWriter<RaycastEvent>
+) {
+    let edited = std::mem::take(&mut edited.0);
+    for (source, beam, tracker) in source_query.iter() {
+        // new sources are emitted evenly by whatever spawns them, and resampled once their
+        // trees are in
+        if tracker.is_added() {
+            if config.adaptive_rays {
+                unrefined.push(source);
+            }
+            continue
+        }
+        let trees = trees_of(source, &tree_query);
+        let pending = unrefined.contains(&source) && !trees.is_empty();
+        let resample = config.adaptive_rays && edited.contains(&source);
+        if tracker.is_changed() || pending || resample {
+            let rays = launch(beam, &config, *formalism, &raycaster);
+            reemit(&mut commands, source, rays.into_iter(), trees.into_iter(), &mut writer);
+            unrefined.retain(|entity| *entity != source);
+        }
+    }
+    unrefined.retain(|source| source_query.contains(*source));
+    for (tree, _) in tree_query.iter().filter(|(_, tree)| tree.source.is_some_and(|source| !source_query.contains(source))) {
+        clear(&mut commands, tree, &mut writer);
+    }
+}
+
+/// The trees of the rays emitted by `source`.
+fn trees_of(source: Entity, tree_query: &Query<(Entity, &RayTree)>) -> Vec<Entity> {
+    tree_query.iter().filter(|(_, tree)| tree.source == Some(source)).map(|(entity, _)| entity).collect()
+}
+
+/// Traces `rays` of `source` into its existing ray trees, which keep their entities and
+/// segments, and clears the trees left over when there are fewer rays than before.
+fn reemit(
+    commands: &mut Commands,
+    source: Entity,
+    rays: impl Iterator<Item = Ray>,
+    mut trees: impl Iterator<Item = Entity>,
+    writer: &mut EventWriter<RaycastEvent>
+) {
+    for ray in rays {
         writer.send(RaycastEvent {
-            ray: Some(beam_ray),
-            tree: None
-        })
-    }
-
-
-    commands.spawn(Surface::glass(
-        Vec2::new(500., 600.), 
-        Vec2::new(500., 700.),
-        1.5
-    ));
-    commands.spawn(Surface::glass(
-        Vec2::new(900., 600.), 
-        Vec2::new(950., 700.),
-        1.0
-    ));
-    commands.spawn(Surface::blocker(
-        Vec2::new(0., 0.), 
-        Vec2::new(WINDOW_W as f32, 0.),
-    ));
-    commands.spawn(Surface::blocker(
-        Vec2::new(0., WINDOW_H as f32), 
-        Vec2::new(WINDOW_W as f32, WINDOW_H as f32),
-    ));
-    commands.spawn(Surface::blocker(
-        Vec2::new(0., 0.), 
-        Vec2::new(0., WINDOW_H as f32),
-    ));
-    commands.spawn(Surface::blocker(
-        Vec2::new(WINDOW_W as f32, 0.), 
-        Vec2::new(WINDOW_W as f32, WINDOW_H as f32),
-    ));
+            ray: Some(ray),
+            tree: trees.next(),
+            source: Some(source)
+        });
+    }
+    for unused in trees {
+        clear(commands, unused, writer);
+    }
 }
 
+/// Despawns a ray tree, telling the tracer so what it deposited in thermal lenses goes too.
+fn clear(commands: &mut Commands, tree: Entity, writer: &mut EventWriter<RaycastEvent>) {
+    writer.send(RaycastEvent {
+        ray: None,
+        tree: Some(tree),
+        source: None
+    });
+    commands.entity(tree).despawn_recursive();
+}
+
+type OutlineChanged = Or<(Changed<Surface>, Changed<scene_tree::Selected>)>;
+
+fn surface_path(surface: &Surface) -> Path {
+    let outline = surface.outline();
+    let mut path_builder = PathBuilder::new();
+    path_builder.move_to(outline[0]);
+    for point in &outline[1..] {
+        path_builder.line_to(*point);
+    }
+    path_builder.build()
+}
+
+/// Gives each surface an outline when it appears and redraws it only when the surface, its
+/// selection or the stroke style changes. Outlines of surfaces that are gone are despawned.
 fn draw_surface_system(
     mut commands: Commands,
-    query: Query<&Surface>
+    (config, presentation, scale): (Res<Config>, Res<presentation::Presentation>, Res<Scale>),
+    surface_query: Query<(Entity, &Surface, Option<&scene_tree::Selected>)>,
+    changed_query: Query<Entity, OutlineChanged>,
+    deselected: RemovedComponents<scene_tree::Selected>,
+    mut outline_query: Query<(Entity, &SurfaceOutline, &mut Path, &mut DrawMode)>
 ) {
-    for surface in query.iter() {
-        let mut path_builder = PathBuilder::new();
-        path_builder.move_to(surface.p1);
-        path_builder.line_to(surface.p2);
+    let restyle = config.is_changed() || presentation.is_changed();
+    let stroke = |selected: Option<&scene_tree::Selected>| {
+        let color = if selected.is_some() { scene_tree::SELECTED_COLOR } else { config.theme.surface() };
+        DrawMode::Stroke(StrokeMode::new(color, scale.px(presentation.stroke())))
+    };
+    let stale: HashSet<Entity> = changed_query.iter().chain(deselected.iter()).collect();
+    let mut drawn: HashSet<Entity> = HashSet::new();
+    for (outline, target, mut path, mut draw_mode) in outline_query.iter_mut() {
+        let Ok((entity, surface, selected)) = surface_query.get(target.surface) else {
+            commands.entity(outline).despawn();
+            continue
+        };
+        drawn.insert(entity);
+        if !restyle && !stale.contains(&entity) {
+            continue
+        }
+        *path = surface_path(surface);
+        *draw_mode = stroke(selected);
+    }
+    for (entity, surface, selected) in surface_query.iter() {
+        if drawn.contains(&entity) {
+            continue
+        }
         commands.spawn(GeometryBuilder::build_as(
-            &path_builder.build(),
-            DrawMode::Stroke(StrokeMode::new(Color::WHITE, 1.0)),
+            &surface_path(surface),
+            stroke(selected),
             Transform::default(),
-        ));
+        )).insert(SurfaceOutline {
+            surface: entity
+        });
     }
 }
 
+/// Redraws the 1 mm grid over everything the window shows at the fitted scale, whenever
+/// that area changes.
 fn draw_grid_system(
-    mut commands: Commands
+    mut commands: Commands,
+    (config, scale): (Res<Config>, Res<Scale>),
+    windows: Res<Windows>,
+    mut drawn: Local<Option<(IVec2, IVec2)>>,
+    grid_query: Query<Entity, With<GridLine>>
 ) {
-    for i in 0..(WINDOW_W / PX_PER_MM) {
+    let Some(window) = windows.get_primary() else {
+        return
+    };
+    let half = Vec2::new(window.width(), window.height()) * fit_scale(window.width(), window.height()) / 2.;
+    let center = Vec2::new(SCENE_W, SCENE_H) / 2.;
+    let min = (center - half).floor().as_ivec2();
+    let max = (center + half).ceil().as_ivec2();
+    if *drawn == Some((min, max)) {
+        return
+    }
+    *drawn = Some((min, max));
+    for line in grid_query.iter() {
+        commands.entity(line).despawn();
+    }
+    for i in min.x..=max.x {
         let mut path_builder = PathBuilder::new();
-        path_builder.move_to(Vec2::new((i * PX_PER_MM) as f32, 0.,));
-        path_builder.line_to(Vec2::new((i * PX_PER_MM) as f32, WINDOW_H as f32,));
+        path_builder.move_to(Vec2::new(i as f32, min.y as f32));
+        path_builder.line_to(Vec2::new(i as f32, max.y as f32));
         commands.spawn(GeometryBuilder::build_as(
             &path_builder.build(),
-            DrawMode::Stroke(StrokeMode::new(Color::rgb(0.5, 0.5, 0.5), 0.3)),
+            DrawMode::Stroke(StrokeMode::new(config.theme.grid(), scale.px(0.3))),
             Transform::default(),
-        ));
+        )).insert(GridLine);
     }
-    for j in 0..(WINDOW_H / PX_PER_MM) {
+    for j in min.y..=max.y {
         let mut path_builder = PathBuilder::new();
-        path_builder.move_to(Vec2::new(0., (j * PX_PER_MM) as f32,));
-        path_builder.line_to(Vec2::new(WINDOW_W as f32, (j * PX_PER_MM) as f32,));
+        path_builder.move_to(Vec2::new(min.x as f32, j as f32));
+        path_builder.line_to(Vec2::new(max.x as f32, j as f32));
         commands.spawn(GeometryBuilder::build_as(
             &path_builder.build(),
-            DrawMode::Stroke(StrokeMode::new(Color::rgb(0.5, 0.5, 0.5), 0.3)),
+            DrawMode::Stroke(StrokeMode::new(config.theme.grid(), scale.px(0.3))),
             Transform::default(),
-        ));
+        )).insert(GridLine);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::units::Degrees;
+
+    fn entities(surfaces: &[Surface]) -> Vec<(Entity, &Surface)> {
+        surfaces.iter().enumerate().map(|(k, surface)| (Entity::from_raw(k as u32), surface)).collect()
+    }
+
+    #[test]
+    fn nearer_surface_wins_whatever_the_order() {
+        let near = Surface::blocker(Vec2::new(10., -5.), Vec2::new(10., 5.));
+        let far = Surface::blocker(Vec2::new(20., -5.), Vec2::new(20., 5.));
+        let ray = Ray::new(Vec2::ZERO, Vec2::X, 1.);
+        for surfaces in [vec![near.clone(), far.clone()], vec![far.clone(), near.clone()]] {
+            let surfaces = entities(&surfaces);
+            let (d, _, surface) = nearest_hit(&ray, surfaces.iter().copied(), None).unwrap();
+            assert_eq!(d, 10.);
+            assert_eq!(surface.p1, near.p1);
+        }
+    }
+
+    #[test]
+    fn overlapping_surfaces_are_hit_in_order_along_the_ray() {
+        // a long blocker behind a short mirror that overlaps it across the beam, listed first
+        let surfaces = [
+            Surface::blocker(Vec2::new(15., -20.), Vec2::new(15., 20.)),
+            Surface::mirror(Vec2::new(14., -2.), Vec2::new(12., 2.), 1.)
+        ];
+        let surfaces = entities(&surfaces);
+        let traced = trace(&Ray::new(Vec2::ZERO, Vec2::X, 1.), &surfaces, |_| None);
+        assert_eq!(traced.segments[0].surface, Entity::from_raw(1));
+        assert!((traced.segments[0].hit.distance - 13.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn excluded_surface_is_skipped() {
+        let surfaces = [
+            Surface::glass(Vec2::new(5., -5.), Vec2::new(5., 5.)),
+            Surface::glass(Vec2::new(8., -5.), Vec2::new(8., 5.))
+        ];
+        let surfaces = entities(&surfaces);
+        let ray = Ray::new(Vec2::new(5., 0.), Vec2::X, 1.);
+        let (d, entity, _) = nearest_hit(&ray, surfaces.iter().copied(), Some(Entity::from_raw(0))).unwrap();
+        assert_eq!((d, entity), (3., Entity::from_raw(1)));
+    }
+
+    #[test]
+    fn every_traced_segment_ends_on_the_nearest_surface() {
+        // a fan of crossing slabs and arcs, so most rays pass several overlapping surfaces
+        let surfaces: Vec<Surface> = (0..12).map(|k| {
+            let angle = k as f32 * PI / 12.;
+            let center = Vec2::new(30. + 2. * k as f32, 0.);
+            let half = Vec2::from_angle(angle) * 15.;
+            let surface = Surface::glass(center - half, center + half).index(1.5);
+            if k % 3 == 0 { surface.curvature(0.05) } else { surface }
+        }).collect();
+        let surfaces = entities(&surfaces);
+        let source = BeamSource::new(Vec2::ZERO, Vec2::X, Millimeters(10.)).divergence(Degrees(20.));
+        for ray in source.rays(2.) {
+            let traced = trace(&ray, &surfaces, |_| None);
+            for segment in traced.segments.iter() {
+                // the surface the ray left can only be hit again where it is an arc
+                let left = traced.segments.iter()
+                    .find(|parent| parent.hit.point.distance(segment.ray.p) < 10. * SURFACE_OFFSET)
+                    .map(|parent| parent.surface);
+                let nearest = surfaces.iter()
+                    .filter_map(|(entity, surface)| hit_distance(&segment.ray, *entity, surface, left))
+                    .fold(f32::INFINITY, f32::min);
+                assert!(segment.hit.distance <= nearest, "{} beyond a hit at {}", segment.hit.distance, nearest);
+            }
+        }
+    }
+
+    #[test]
+    fn raycaster_agrees_with_linear_search() {
+        let mut surfaces: Vec<Surface> = (0..40).map(|k| {
+            let p = Vec2::new((k * 7 % 40) as f32, (k * 13 % 40) as f32);
+            let surface = Surface::glass(p, p + Vec2::from_angle(k as f32) * 8.);
+            if k % 4 == 0 { surface.curvature(-0.1) } else { surface }
+        }).collect();
+        let mut line = Surface::blocker(Vec2::new(45., 0.), Vec2::new(45., 1.));
+        line.extent = Extent::Line;
+        surfaces.push(line);
+        // an exact duplicate ties, and goes to the surface listed first
+        surfaces.push(surfaces[3].clone());
+        let surfaces = entities(&surfaces);
+        let raycaster = raycaster::Raycaster::new(&surfaces);
+        for k in 0..200 {
+            let ray = Ray::new(Vec2::new(-5., k as f32 / 5.), Vec2::from_angle(k as f32 * 0.37), 1.);
+            let linear = nearest_hit(&ray, surfaces.iter().copied(), None).map(|(d, entity, _)| (d, entity));
+            let cast = raycaster.cast(&ray, None).map(|(d, entity, _)| (d, entity));
+            assert_eq!(linear, cast);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn headless_dump_ends_rays_that_hit_nothing_open() {
+        let out = std::env::temp_dir().join(format!("beams-headless-{}.csv", std::process::id()));
+        // a beam through one glass surface, both halves of it leaving the table
+        let scene = scene::Scene {
+            sources: vec![BeamSource::new(Vec2::ZERO, Vec2::X, Millimeters(2.))],
+            elements: vec![scene::Element::of(&Surface::glass(Vec2::new(10., -5.), Vec2::new(10., 5.)), (None, None, None))],
+            ..default()
+        };
+        headless::trace_scene(&scene, &out, &Config::default()).unwrap();
+        let text = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        let rows: Vec<Vec<&str>> = text.lines().skip(1).map(|line| line.split(',').collect()).collect();
+        assert!(rows.iter().all(|row| row.len() == 13));
+        // length and surface are blank together, on rays that hit nothing
+        assert!(rows.iter().all(|row| row[7].is_empty() == row[11].is_empty()));
+        assert!(rows.iter().any(|row| row[7].is_empty()));
+        assert!(rows.iter().any(|row| !row[7].is_empty()));
+    }
+
+    #[test]
+    fn final_leg_follows_the_beam_past_ghosts_and_pickoffs() {
+        // a window with its ghosts, then a plate at 45° picking off a tenth of the beam
+        let surfaces = [
+            Surface::glass(Vec2::new(10., -20.), Vec2::new(14., 20.)).index(1.5),
+            Surface::glass(Vec2::new(20., -20.), Vec2::new(24., 20.)).index(1.),
+            Surface::beamsplitter(Vec2::new(35., -5.), Vec2::new(45., 5.), 0.1),
+            Surface::blocker(Vec2::new(-50., -50.), Vec2::new(-50., 50.)),
+            Surface::blocker(Vec2::new(60., -50.), Vec2::new(60., 50.)),
+            Surface::blocker(Vec2::new(0., 50.), Vec2::new(60., 50.)),
+            Surface::blocker(Vec2::new(0., -50.), Vec2::new(60., -50.))
+        ];
+        let surfaces = entities(&surfaces);
+        let traced = trace(&Ray::new(Vec2::ZERO, Vec2::X, 1.), &surfaces, |_| None);
+        let segments: Vec<RaySegment> = traced.segments.iter().map(RaySegment::of).collect();
+        // the picked off light is traced last
+        assert_ne!(segments.last().unwrap().surface, surfaces[4].0);
+        let last = chromatic::final_leg(segments.iter()).unwrap();
+        assert_eq!(last.surface, surfaces[4].0);
+        assert!((last.to - last.from).normalize().distance(Vec2::X) < 1e-5);
+    }
+
+    #[test]
+    fn tissue_scene_sends_its_beam_into_the_medium() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/tissue.ron");
+        let scene = scene::Scene::load(&path).unwrap();
+        let [medium] = scene.media.as_slice() else {
+            panic!("{} media", scene.media.len())
+        };
+        let surfaces = scene.surfaces();
+        let surfaces = entities(&surfaces);
+        let ray = &scene.sources[0].rays(Config::default().ray_density)[0];
+        // the beam stops where it enters the block, for the Monte Carlo to carry on
+        let traced = trace(ray, &surfaces, |_| None);
+        let [segment] = traced.segments.as_slice() else {
+            panic!("{} segments", traced.segments.len())
+        };
+        assert!(medium.contains(segment.hit.point + ray.l * 1e-3));
+        assert!(!medium.contains(ray.p));
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::units::Nanometers;
+
+/// Dispersion model of a glass, giving its refractive index at each wavelength. Coefficients
+/// take the wavelength in µm, as glass catalogs list them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Material {
+    /// n² = 1 + Σ Bᵢ λ² / (λ² - Cᵢ)
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3]
+    },
+    /// n = A + B / λ² + C / λ⁴
+    Cauchy {
+        a: f32,
+        b: f32,
+        c: f32
+    }
+}
+
+/// Schott N-BK7, the common borosilicate crown.
+pub const BK7: Material = Material::Sellmeier {
+    b: [1.039612, 0.2317923, 1.010469],
+    c: [0.006000699, 0.02001791, 103.5607]
+};
+
+/// Fused silica, after Malitson.
+pub const FUSED_SILICA: Material = Material::Sellmeier {
+    b: [0.6961663, 0.4079426, 0.8974794],
+    c: [0.004679148, 0.01351206, 97.934]
+};
+
+/// Schott SF11, a dense flint with strong dispersion.
+pub const SF11: Material = Material::Sellmeier {
+    b: [1.737597, 0.3137473, 1.898781],
+    c: [0.01318871, 0.06230681, 155.2363]
+};
+
+/// Built-in glasses by name.
+pub const CATALOG: [(&str, Material); 3] = [
+    ("BK7", BK7),
+    ("fused silica", FUSED_SILICA),
+    ("SF11", SF11)
+];
+
+impl Material {
+    /// Refractive index at wavelength `w`.
+    pub fn index(&self, w: Nanometers) -> f32 {
+        let l2 = (w.0 / 1000.).powi(2);
+        match self {
+            Material::Sellmeier { b, c } => {
+                let sum: f32 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1. + sum).max(1.).sqrt()
+            }
+            Material::Cauchy { a, b, c } => a + b / l2 + c / (l2 * l2)
+        }
+    }
+
+    /// A catalog glass by name, ignoring case.
+    pub fn named(name: &str) -> Option<Self> {
+        CATALOG.iter()
+            .find(|(catalog_name, _)| catalog_name.eq_ignore_ascii_case(name))
+            .map(|(_, material)| *material)
+    }
+
+    /// Catalog name of the glass, if it is one of the built-in ones.
+    pub fn name(&self) -> Option<&'static str> {
+        CATALOG.iter()
+            .find(|(_, material)| material == self)
+            .map(|(name, _)| *name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // catalog indices at the helium d line
+    const D_LINE: Nanometers = Nanometers(587.56);
+
+    #[test]
+    fn sellmeier_matches_catalog_indices() {
+        for (material, n_d) in [(BK7, 1.5168), (FUSED_SILICA, 1.4585), (SF11, 1.78472)] {
+            assert!((material.index(D_LINE) - n_d).abs() < 2e-4, "{:?}: {}", material.name(), material.index(D_LINE));
+        }
+        // normal dispersion: blue is slowed more than red
+        assert!(BK7.index(Nanometers(486.13)) > BK7.index(Nanometers(656.27)));
+    }
+
+    #[test]
+    fn cauchy_adds_its_terms() {
+        let material = Material::Cauchy { a: 1.5, b: 0.004, c: 0. };
+        assert!((material.index(Nanometers(1000.)) - 1.504).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    config::Config, locale::Locale, mouse::{self, Pointer}, presentation::Presentation, scene_tree::SELECTED_COLOR, snapping, ui::{self, UiFont},
+    units::Quantity, Scale
+};
+
+// Radius (px at the default zoom) of the marks on measured points
+const MARK_RADIUS: f32 = 3.;
+
+/// Ruler and protractor. While on, clicks on the table place points instead of grabbing
+/// elements: two give a distance, a third the angle at the second point between the other
+/// two. A fourth click starts over. M switches it on and off, Escape clears the points.
+#[derive(Resource, Default)]
+pub struct Measure {
+    pub enabled: bool,
+    pub points: Vec<Vec2>
+}
+
+impl Measure {
+    /// Angle (radians, 0 to π) at the second point, once there are three.
+    pub fn angle(&self) -> Option<f32> {
+        let [a, vertex, b] = self.points[..] else {
+            return None
+        };
+        let (u, v) = (a - vertex, b - vertex);
+        (u != Vec2::ZERO && v != Vec2::ZERO).then(|| u.angle_between(v).abs())
+    }
+}
+
+#[derive(Component)]
+pub struct MeasureOverlay;
+
+pub fn measure_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut measure: ResMut<Measure>
+) {
+    if keys.just_pressed(KeyCode::M) {
+        measure.enabled = !measure.enabled;
+        measure.points.clear();
+    }
+    if keys.just_pressed(KeyCode::Escape) && !measure.points.is_empty() {
+        measure.points.clear();
+    }
+}
+
+/// Places a point where the table is clicked, on the grid with Alt held.
+pub fn measure_click_system(
+    (buttons, keys, config): (Res<Input<MouseButton>>, Res<Input<KeyCode>>, Res<Config>),
+    (windows, camera_query): Pointer,
+    panels: mouse::Panels,
+    mut measure: ResMut<Measure>
+) {
+    if !measure.enabled || !buttons.just_pressed(MouseButton::Left) {
+        return
+    }
+    if mouse::over_panels(&panels) {
+        return
+    }
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    let Some(cursor) = window.cursor_position() else {
+        return
+    };
+    let mut p = mouse::to_scene(cursor, window, camera, projection.scale);
+    if snapping::held(&keys) {
+        p = snapping::point(p, config.snap_grid);
+    }
+    if measure.points.len() == 3 {
+        measure.points.clear();
+    }
+    measure.points.push(p);
+}
+
+/// Draws the measured points, the lines between them and a panel with the readings.
+pub fn measure_draw_system(
+    mut commands: Commands,
+    (config, locale, font, scale): (Res<Config>, Res<Locale>, Res<UiFont>, Res<Scale>),
+    (measure, presentation): (Res<Measure>, Res<Presentation>),
+    overlay_query: Query<Entity, With<MeasureOverlay>>
+) {
+    if !measure.is_changed() && !config.is_changed() && !locale.is_changed() && !presentation.is_changed() {
+        return
+    }
+    for entity in overlay_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !measure.enabled {
+        return
+    }
+    let transform = Transform::from_xyz(0., 0., 0.25);
+    for p in measure.points.iter() {
+        commands.spawn((GeometryBuilder::build_as(
+            &shapes::Circle {
+                radius: scale.px(MARK_RADIUS * presentation.size()),
+                center: *p
+            },
+            DrawMode::Fill(FillMode::color(SELECTED_COLOR)),
+            transform
+        ), MeasureOverlay));
+    }
+    if measure.points.len() > 1 {
+        let mut path_builder = PathBuilder::new();
+        path_builder.move_to(measure.points[0]);
+        for p in measure.points.iter().skip(1) {
+            path_builder.line_to(*p);
+        }
+        commands.spawn((GeometryBuilder::build_as(
+            &path_builder.build(),
+            DrawMode::Stroke(StrokeMode::new(SELECTED_COLOR, scale.px(presentation.stroke()))),
+            transform
+        ), MeasureOverlay));
+    }
+    let show = |quantity: Quantity, value: f32| config.units.show(quantity, value).to_string();
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(60.),
+                top: Val::Percent(20.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, MeasureOverlay)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("measure.title"), style.clone()));
+        if measure.points.len() < 2 {
+            panel.spawn(TextBundle::from_section(locale.text("measure.hint"), style.clone()));
+        }
+        for (k, pair) in measure.points.windows(2).enumerate() {
+            let delta = pair[1] - pair[0];
+            panel.spawn(TextBundle::from_section(locale.format("measure.distance", &[
+                ("from", &(k + 1)),
+                ("to", &(k + 2)),
+                ("length", &show(Quantity::Length, delta.length())),
+                ("dx", &show(Quantity::Length, delta.x)),
+                ("dy", &show(Quantity::Length, delta.y))
+            ]), style.clone()));
+        }
+        if let Some(angle) = measure.angle() {
+            panel.spawn(TextBundle::from_section(locale.format("measure.angle", &[("angle", &show(Quantity::Angle, angle))]), style.clone()));
+        }
+    });
+}
@@ -0,0 +1,194 @@
+use bevy::prelude::*;
+
+use crate::{config::Config, inspector::Inspector, library::Library, links::Param, measure::Measure, presentation::Presentation, scene_tree::Selected, snapping, touch::distance_to_surface, viewports::MainCamera, BeamSource, Surface};
+
+// The cursor grabs the nearest endpoint, source or surface within this many logical pixels
+const PICK_RADIUS: f32 = 8.;
+
+type Inputs<'w> = (Res<'w, Input<MouseButton>>, Res<'w, Input<KeyCode>>);
+type Modes<'w> = (Res<'w, Measure>, Res<'w, Library>, Res<'w, Presentation>);
+pub type Pointer<'w, 's> = (Res<'w, Windows>, Query<'w, 's, (&'static Transform, &'static OrthographicProjection), MainCamera>);
+pub type Panels<'w, 's> = (Query<'w, 's, &'static Interaction>, Res<'w, Inspector>);
+
+/// Whether the cursor is on a panel, a button or the inspector, so presses there are theirs.
+pub fn over_panels((ui_query, inspector): &Panels) -> bool {
+    inspector.hovered || ui_query.iter().any(|interaction| *interaction != Interaction::None)
+}
+
+/// Part of a surface held by the mouse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handle {
+    P1,
+    P2,
+    Whole
+}
+
+/// What the mouse is dragging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grab {
+    Surface(Entity, Handle),
+    Source(Entity)
+}
+
+/// Converts a cursor position (logical pixels, origin bottom-left) to scene mm.
+pub fn to_scene(cursor: Vec2, window: &Window, camera: &Transform, scale: f32) -> Vec2 {
+    let centered = cursor - Vec2::new(window.width(), window.height()) / 2.;
+    camera.translation.truncate() + centered * scale
+}
+
+/// The nearest endpoint or source within `radius` of `p`, else the nearest surface.
+fn pick(p: Vec2, radius: f32, surfaces: &[(Entity, &Surface)], sources: &[(Entity, &BeamSource)]) -> Option<Grab> {
+    let nearest = |candidates: Vec<(f32, Grab)>| candidates.into_iter()
+        .filter(|(distance, _)| *distance < radius)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, grab)| grab);
+    let handles = surfaces.iter()
+        .flat_map(|(entity, surface)| [
+            (p.distance(surface.p1), Grab::Surface(*entity, Handle::P1)),
+            (p.distance(surface.p2), Grab::Surface(*entity, Handle::P2))
+        ])
+        .chain(sources.iter().map(|(entity, source)| (p.distance(source.pos), Grab::Source(*entity))))
+        .collect();
+    nearest(handles).or_else(|| nearest(surfaces.iter()
+        .map(|(entity, surface)| (distance_to_surface(p, surface), Grab::Surface(*entity, Handle::Whole)))
+        .collect()))
+}
+
+/// Left-dragging edits the scene. An endpoint of a surface follows the cursor, stretching and
+/// turning the surface; the rest of a surface, or a source, moves with it. With Ctrl held,
+/// surfaces turn about their centers and sources turn to face the cursor. Pressing on a
+/// surface selects it, or adds it to the selection with Shift. With Alt held, dragged
+/// endpoints, surfaces and sources snap to the grid and turns to whole steps of the snapping
+/// angle, both set in the settings. The edits retrace the rays as any other change does.
+pub fn mouse_drag_system(
+    mut commands: Commands,
+    ((buttons, keys), config, (measure, library, presentation)): (Inputs, Res<Config>, Modes),
+    (windows, camera_query): Pointer,
+    panels: Panels,
+    mut surface_query: Query<(Entity, &mut Surface, Option<&Selected>)>,
+    mut source_query: Query<(Entity, &mut BeamSource)>,
+    mut grabbed: Local<Option<(Grab, Vec2)>>
+) {
+    // clicks place measuring points or a part from the library instead
+    if !buttons.pressed(MouseButton::Left) || measure.enabled || library.armed.is_some() {
+        *grabbed = None;
+        return
+    }
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    let Some(cursor) = window.cursor_position() else {
+        return
+    };
+    let p = to_scene(cursor, window, camera, projection.scale);
+    if buttons.just_pressed(MouseButton::Left) {
+        if over_panels(&panels) {
+            return
+        }
+        let surfaces: Vec<_> = surface_query.iter().map(|(entity, surface, _)| (entity, surface)).collect();
+        let sources: Vec<_> = source_query.iter().collect();
+        *grabbed = pick(p, PICK_RADIUS * presentation.size() * projection.scale, &surfaces, &sources).map(|grab| (grab, p));
+        if let Some((Grab::Surface(entity, _), _)) = *grabbed {
+            if !keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+                for (other, _, selected) in surface_query.iter() {
+                    if selected.is_some() && other != entity {
+                        commands.entity(other).remove::<Selected>();
+                    }
+                }
+            }
+            commands.entity(entity).insert(Selected);
+        }
+        return
+    }
+    let Some((grab, last)) = *grabbed else {
+        return
+    };
+    if p == last {
+        return
+    }
+    let turning = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let snap = snapping::held(&keys);
+    let to_grid = |q: Vec2| if snap { snapping::point(q, config.snap_grid) } else { q };
+    let to_step = |degrees: f32| if snap { snapping::angle(degrees, config.snap_angle) } else { degrees };
+    // a snapped move too small to reach the next step keeps the cursor's motion for the next frame
+    let moved = match grab {
+        Grab::Surface(entity, handle) => {
+            let Ok((_, mut surface, _)) = surface_query.get_mut(entity) else {
+                return
+            };
+            match handle {
+                Handle::P1 => {
+                    surface.p1 = to_grid(p);
+                    true
+                }
+                Handle::P2 => {
+                    surface.p2 = to_grid(p);
+                    true
+                }
+                Handle::Whole if turning => {
+                    let center = (surface.p1 + surface.p2) / 2.;
+                    let turn = (last - center).angle_between(p - center).to_degrees();
+                    match Param::Angle.get(&surface).filter(|_| turn.is_finite()) {
+                        Some(angle) if to_step(angle + turn) != angle => {
+                            let _ = Param::Angle.set(&mut surface, to_step(angle + turn));
+                            true
+                        }
+                        _ => false
+                    }
+                }
+                Handle::Whole => {
+                    let delta = to_grid(surface.p1 + p - last) - surface.p1;
+                    surface.p1 += delta;
+                    surface.p2 += delta;
+                    delta != Vec2::ZERO
+                }
+            }
+        }
+        Grab::Source(entity) => {
+            let Ok((_, mut source)) = source_query.get_mut(entity) else {
+                return
+            };
+            if turning {
+                let toward = p - source.pos;
+                let direction = Vec2::from_angle(to_step(toward.y.atan2(toward.x).to_degrees()).to_radians());
+                if toward != Vec2::ZERO && direction != source.direction {
+                    source.direction = direction;
+                }
+                true
+            } else {
+                let delta = to_grid(source.pos + p - last) - source.pos;
+                source.pos += delta;
+                delta != Vec2::ZERO
+            }
+        }
+    };
+    if moved || !snap {
+        *grabbed = Some((grab, p));
+    }
+}
+
+/// X switches the source nearest the cursor on or off. A switched off source stays where it
+/// is and can still be moved, but its rays are neither traced nor drawn.
+pub fn source_switch_system(
+    (keys, presentation): (Res<Input<KeyCode>>, Res<Presentation>),
+    (windows, camera_query): Pointer,
+    mut source_query: Query<&mut BeamSource>
+) {
+    if !keys.just_pressed(KeyCode::X) {
+        return
+    }
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    let Some(cursor) = window.cursor_position() else {
+        return
+    };
+    let p = to_scene(cursor, window, camera, projection.scale);
+    let nearest = source_query.iter_mut()
+        .map(|source| (p.distance(source.pos), source))
+        .filter(|(distance, _)| *distance < PICK_RADIUS * presentation.size() * projection.scale)
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+    if let Some((_, mut source)) = nearest {
+        source.enabled = !source.enabled;
+    }
+}
@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{units::Nanometers, Scale};
+
+// Upper edges of the wavelength bands rays are grouped into; the last band is open-ended
+const BAND_EDGES: [f32; 5] = [450., 500., 570., 620., 750.];
+
+// Rays fade from opaque at full intensity to MIN_ALPHA this many decades below it
+const FADE_DECADES: f32 = 3.;
+const MIN_ALPHA: f32 = 0.15;
+
+// Visible range (nm) the spectral palette colors
+const VISIBLE: (f32, f32) = (380., 780.);
+
+// Okabe & Ito, "Color Universal Design" (2008), one hue per band
+const CVD_COLORS: [Color; 6] = [
+    Color::rgb(0.80, 0.47, 0.65),
+    Color::rgb(0.00, 0.45, 0.70),
+    Color::rgb(0.00, 0.62, 0.45),
+    Color::rgb(0.94, 0.89, 0.26),
+    Color::rgb(0.84, 0.37, 0.00),
+    Color::rgb(0.60, 0.60, 0.60)
+];
+
+// Dash and gap lengths (px) per band, so bands stay apart without color
+const CVD_DASHES: [Option<(f32, f32)>; 6] = [
+    Some((2., 3.)),
+    Some((8., 3.)),
+    None,
+    Some((4., 4.)),
+    Some((12., 4.)),
+    Some((1., 5.))
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Palette {
+    /// Every ray yellow
+    Standard,
+    /// Colorblind-safe hue and a distinct dash pattern per wavelength band
+    Colorblind,
+    /// Every ray in the color of its wavelength, so white light spreads into a rainbow
+    #[default]
+    Spectral
+}
+
+/// Approximate color of light of wavelength `w`, after Bruton's piecewise fit. Wavelengths
+/// outside 380-780 nm take the color at the nearer end, and the ends dim as vision fades.
+pub fn spectral(w: Nanometers) -> Color {
+    let w = w.0.clamp(VISIBLE.0, VISIBLE.1);
+    let (r, g, b) = match w {
+        w if w < 440. => ((440. - w) / 60., 0., 1.),
+        w if w < 490. => (0., (w - 440.) / 50., 1.),
+        w if w < 510. => (0., 1., (510. - w) / 20.),
+        w if w < 580. => ((w - 510.) / 70., 1., 0.),
+        w if w < 645. => (1., (645. - w) / 65., 0.),
+        _ => (1., 0., 0.)
+    };
+    let fade = if w < 420. {
+        0.3 + 0.7 * (w - VISIBLE.0) / 40.
+    } else if w > 700. {
+        0.3 + 0.7 * (VISIBLE.1 - w) / 80.
+    } else {
+        1.
+    };
+    Color::rgb(r * fade, g * fade, b * fade)
+}
+
+/// Index of the band `w` falls into.
+pub fn band(w: Nanometers) -> usize {
+    BAND_EDGES.iter().position(|edge| w.0 < *edge).unwrap_or(BAND_EDGES.len())
+}
+
+impl Palette {
+    pub fn color(&self, w: Nanometers) -> Color {
+        match self {
+            Palette::Standard => Color::YELLOW,
+            Palette::Colorblind => CVD_COLORS[band(w)],
+            Palette::Spectral => spectral(w)
+        }
+    }
+
+    /// Stroke color of a ray of wavelength `w` with `intensity` left. Weaker rays are drawn
+    /// more transparent, on a log scale, so attenuated rays stand back from the main beam.
+    pub fn stroke(&self, w: Nanometers, intensity: f32) -> Color {
+        let alpha = 1. + intensity.max(f32::MIN_POSITIVE).log10() / FADE_DECADES;
+        *self.color(w).set_a(alpha.clamp(MIN_ALPHA, 1.))
+    }
+
+    /// Path from `from` to `to`, broken into dashes if the band of `w` has a pattern.
+    pub fn path(&self, w: Nanometers, from: Vec2, to: Vec2, scale: &Scale) -> Path {
+        let mut path_builder = PathBuilder::new();
+        let dashes = match self {
+            Palette::Standard | Palette::Spectral => None,
+            Palette::Colorblind => CVD_DASHES[band(w)]
+        };
+        let Some((dash, gap)) = dashes else {
+            path_builder.move_to(from);
+            path_builder.line_to(to);
+            return path_builder.build()
+        };
+        let (dash, period) = (scale.px(dash), scale.px(dash + gap));
+        let length = from.distance(to);
+        let direction = (to - from).normalize_or_zero();
+        let mut start = 0.;
+        while start < length {
+            path_builder.move_to(from + start * direction);
+            path_builder.line_to(from + (start + dash).min(length) * direction);
+            start += period;
+        }
+        path_builder.build()
+    }
+}
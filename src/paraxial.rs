@@ -0,0 +1,617 @@
+use bevy::prelude::*;
+
+use crate::{
+    complex::Complex, config::Config, fans::RayFan, locale::Locale, ui::{self, UiFont}, units::{Millimeters, Quantity, UnitSystem},
+    nearest_hit, offset_from_surface, BeamSource, Ray, RaySegment, Surface
+};
+
+// Length (mm) of the placed thin lenses
+const LENS_HEIGHT: f32 = 10.;
+const BUTTON_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+// Cosines below this are treated as grazing, to keep the matrices finite
+const MIN_COSINE: f32 = 1e-3;
+
+/// Ray transfer matrix acting on the height and angle of a paraxial ray, and through
+/// q' = (Aq + B) / (Cq + D) on the complex beam parameter of a Gaussian beam. The determinant
+/// is n / n' across an index step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Abcd {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32
+}
+
+impl Abcd {
+    pub const IDENTITY: Self = Self {
+        a: 1.,
+        b: 0.,
+        c: 0.,
+        d: 1.
+    };
+
+    /// Free propagation over `d` mm.
+    pub fn propagation(d: f32) -> Self {
+        Self {
+            b: d,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// This matrix followed by `next`.
+    pub fn then(self, next: Abcd) -> Self {
+        Self {
+            a: next.a * self.a + next.b * self.c,
+            b: next.a * self.b + next.b * self.d,
+            c: next.c * self.a + next.d * self.c,
+            d: next.c * self.b + next.d * self.d
+        }
+    }
+
+    /// Transforms the complex beam parameter `q` (mm).
+    pub fn apply(&self, q: Complex) -> Complex {
+        (q.scale(self.a) + Complex::from(self.b)) / (q.scale(self.c) + Complex::from(self.d))
+    }
+}
+
+/// Tangential matrix of `surface` for a ray crossing it at `p` from `incident` to `outgoing`,
+/// whether refracted, reflected or diffracted. Oblique incidence stretches the beam across
+/// by cos θ' / cos θ, and a curved surface adds its power in the plane of incidence; an ideal
+/// thin lens keeps its focal length at any angle.
+pub fn surface_matrix(surface: &Surface, p: Vec2, incident: &Ray, outgoing: &Ray) -> Abcd {
+    let normal = surface.normal_at(p);
+    let (along_in, along_out) = (incident.l.dot(normal), outgoing.l.dot(normal));
+    let reflected = along_in * along_out < 0.;
+    let (cos_i, cos_o) = (along_in.abs().max(MIN_COSINE), along_out.abs().max(MIN_COSINE));
+    let n1 = incident.medium_index();
+    let n2 = if reflected { n1 } else { outgoing.medium_index() };
+    let c = match (surface.focal_length, surface.circle()) {
+        (Some(f), _) => -1. / f.0,
+        (None, Some((center, radius))) => {
+            // positive when the center lies ahead of the outgoing ray, as for a concave mirror
+            // or a convex face seen from outside
+            let r = if (center - p).dot(outgoing.l) > 0. { radius } else { -radius };
+            if reflected {
+                -(cos_i + cos_o) / (r * cos_i * cos_o)
+            } else {
+                (n1 * cos_i - n2 * cos_o) / (r * n2 * cos_i * cos_o)
+            }
+        }
+        _ => 0.
+    };
+    Abcd {
+        a: cos_o / cos_i,
+        b: 0.,
+        c,
+        d: n1 * cos_i / (n2 * cos_o)
+    }
+}
+
+/// Surface met along a chief ray.
+#[derive(Clone, Debug)]
+pub struct Element {
+    pub surface: Entity,
+    /// Where the chief ray meets it
+    pub at: Vec2,
+    /// From the previous element, or from the start of the ray for the first (mm)
+    pub distance: f32,
+    /// Index of the medium before the element
+    pub index: f32,
+    pub matrix: Abcd
+}
+
+/// Follows the strongest ray out of each surface from `ray`, over at most `max` surfaces.
+/// Returns the surfaces met and the ray leaving the last of them, or None when that surface
+/// stops the light.
+pub fn chief_path(ray: &Ray, surfaces: &[(Entity, &Surface)], max: usize) -> (Vec<Element>, Option<Ray>) {
+    let mut ray = ray.clone();
+    let mut elements: Vec<Element> = Vec::new();
+    while elements.len() < max {
+        let Some((d, entity, surface)) = nearest_hit(&ray, surfaces.iter().copied(), elements.last().map(|e| e.surface)) else {
+            break
+        };
+        let p = ray.p + ray.l * d;
+        let out = surface.interact(&ray, d).into_iter().max_by(|a, b| a.i.total_cmp(&b.i));
+        elements.push(Element {
+            surface: entity,
+            at: p,
+            distance: d,
+            index: ray.medium_index(),
+            matrix: out.as_ref().map_or(Abcd::IDENTITY, |out| surface_matrix(surface, p, &ray, out))
+        });
+        let Some(mut out) = out else {
+            return (elements, None)
+        };
+        offset_from_surface(&mut out, surface.normal_at(p));
+        ray = out;
+    }
+    (elements, Some(ray))
+}
+
+// Below this power (1/mm) a system is afocal
+const MIN_POWER: f32 = 1e-9;
+// Surfaces followed along the chief ray for the system matrix
+const MAX_ELEMENTS: usize = 64;
+
+/// First-order model of the elements along a source's chief ray. Distances are along the
+/// unfolded axis: from the first surface for the front, from the last for the rear, and
+/// positive in the direction the light goes.
+#[derive(Clone, Debug)]
+pub struct ParaxialSystem {
+    /// From the first surface to the last
+    pub matrix: Abcd,
+    pub elements: Vec<Element>,
+    /// Index before the first surface and after the last
+    pub indices: (f32, f32),
+    /// From the source to the first surface (mm)
+    pub object: f32
+}
+
+impl ParaxialSystem {
+    /// Traces the chief ray of `source`, or None when it meets no surface.
+    pub fn of(source: &BeamSource, surfaces: &[(Entity, &Surface)]) -> Option<Self> {
+        let ray = source.chief_ray();
+        let (elements, last) = chief_path(&ray, surfaces, MAX_ELEMENTS);
+        let first = elements.first()?;
+        let matrix = elements.iter().skip(1)
+            .fold(first.matrix, |m, e| m.then(Abcd::propagation(e.distance)).then(e.matrix));
+        Some(Self {
+            matrix,
+            indices: (first.index, last.map_or(elements[elements.len() - 1].index, |ray| ray.medium_index())),
+            object: first.distance,
+            elements
+        })
+    }
+
+    fn power(&self) -> Option<f32> {
+        Some(-self.matrix.c).filter(|p| p.abs() > MIN_POWER)
+    }
+
+    /// Effective focal length, the inverse of the optical power. The rear focus lies n' times
+    /// as far from the rear principal plane, with n' the index after the last surface. None for
+    /// an afocal system.
+    pub fn focal_length(&self) -> Option<f32> {
+        self.power().map(|p| 1. / (self.indices.1 * p))
+    }
+
+    /// Rear focus, from the last surface.
+    pub fn back_focal_distance(&self) -> Option<f32> {
+        self.power().map(|p| self.matrix.a / p)
+    }
+
+    /// Front focus, from the first surface; negative when it lies before it.
+    pub fn front_focal_distance(&self) -> Option<f32> {
+        self.power().map(|p| -self.matrix.d / p)
+    }
+
+    /// Front principal plane from the first surface and rear principal plane from the last.
+    pub fn principal_planes(&self) -> Option<(f32, f32)> {
+        let Abcd { a, b, c, d } = self.matrix;
+        let det = a * d - b * c;
+        self.power().map(|_| ((d - det) / c, (1. - a) / c))
+    }
+
+    /// Image of the source plane: (distance from the last surface, magnification). None when
+    /// the image is at infinity.
+    pub fn image(&self) -> Option<(f32, f32)> {
+        let total = Abcd::propagation(self.object).then(self.matrix);
+        Some(-total.b / total.d)
+            .filter(|s| s.is_finite() && total.d.abs() > MIN_POWER)
+            .map(|s| (s, total.a + s * total.c))
+    }
+}
+
+/// Paraxial model of the fan's source's system, shown by the layout panel.
+#[derive(Resource, Default)]
+pub struct SystemReport {
+    pub source: usize,
+    pub system: Option<ParaxialSystem>
+}
+
+/// First-order quantities a layout can be pinned to. Distances are measured along the axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    /// Object to the first lens
+    Object,
+    /// Last lens to the image
+    Image,
+    Magnification,
+    /// Object to image
+    Track,
+    /// Between the two lenses of a relay
+    Spacing
+}
+
+const CONSTRAINTS: [Constraint; 5] = [
+    Constraint::Object,
+    Constraint::Image,
+    Constraint::Magnification,
+    Constraint::Track,
+    Constraint::Spacing
+];
+
+impl Constraint {
+    fn key(self) -> &'static str {
+        match self {
+            Constraint::Object => "layout.object",
+            Constraint::Image => "layout.image",
+            Constraint::Magnification => "layout.magnification",
+            Constraint::Track => "layout.track",
+            Constraint::Spacing => "layout.spacing"
+        }
+    }
+
+    /// `value` of the constraint in `units`; the magnification is a plain ratio.
+    fn show(self, units: UnitSystem, value: f32) -> String {
+        match self {
+            Constraint::Magnification => format!("{:.3}", value),
+            _ => units.show(Quantity::Length, value).to_string()
+        }
+    }
+
+    fn step(self) -> f32 {
+        match self {
+            Constraint::Magnification => 0.25,
+            _ => 1.
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolveError {
+    Underconstrained,
+    Overconstrained,
+    /// The constraints ask for a virtual object or image, or a lens of infinite power
+    Unphysical
+}
+
+impl SolveError {
+    fn key(self) -> &'static str {
+        match self {
+            SolveError::Underconstrained => "layout.underconstrained",
+            SolveError::Overconstrained => "layout.overconstrained",
+            SolveError::Unphysical => "layout.unphysical"
+        }
+    }
+}
+
+/// Ideal thin lenses placed along the axis, at distances from the object.
+#[derive(Clone, Debug)]
+pub struct Layout {
+    /// (distance from the object, focal length) of each lens
+    pub lenses: Vec<(Millimeters, Millimeters)>,
+    pub image: Millimeters
+}
+
+/// Solves one thin lens from any two of object distance, image distance, magnification and track.
+fn single(
+    object: Option<f32>,
+    image: Option<f32>,
+    magnification: Option<f32>,
+    track: Option<f32>
+) -> Result<Layout, SolveError> {
+    let (s, i) = match (object, image, magnification, track) {
+        (Some(s), Some(i), None, None) => (s, i),
+        (Some(s), None, Some(m), None) => (s, -m * s),
+        (None, Some(i), Some(m), None) => (-i / m, i),
+        (Some(s), None, None, Some(l)) => (s, l - s),
+        (None, Some(i), None, Some(l)) => (l - i, i),
+        (None, None, Some(m), Some(l)) => (l / (1. - m), -m * l / (1. - m)),
+        pinned => {
+            let count = [pinned.0, pinned.1, pinned.2, pinned.3].iter().filter(|p| p.is_some()).count();
+            return Err(if count < 2 { SolveError::Underconstrained } else { SolveError::Overconstrained })
+        }
+    };
+    let f = s * i / (s + i);
+    if !(s > 0. && i > 0. && f.is_finite()) {
+        return Err(SolveError::Unphysical)
+    }
+    Ok(Layout {
+        lenses: vec![(Millimeters(s), Millimeters(f))],
+        image: Millimeters(s + i)
+    })
+}
+
+/// Solves a two-lens relay from object and image distances, magnification and the spacing
+/// (given, or left over from the track). Follows the marginal ray from the axial object point
+/// with unit slope; the magnification is the ratio of its slopes in object and image space.
+fn relay(
+    object: Option<f32>,
+    image: Option<f32>,
+    magnification: Option<f32>,
+    track: Option<f32>,
+    spacing: Option<f32>
+) -> Result<Layout, SolveError> {
+    let (Some(s), Some(i), Some(m)) = (object, image, magnification) else {
+        return Err(SolveError::Underconstrained)
+    };
+    let d = match (spacing, track) {
+        (Some(d), None) => d,
+        (None, Some(l)) => l - s - i,
+        (None, None) => return Err(SolveError::Underconstrained),
+        (Some(_), Some(_)) => return Err(SolveError::Overconstrained)
+    };
+    if !(s > 0. && i > 0. && d > 0. && m != 0.) {
+        return Err(SolveError::Unphysical)
+    }
+    let slope = 1. / m;
+    let (y1, y2) = (s, -i * slope);
+    let u1 = (y2 - y1) / d;
+    let (f1, f2) = (y1 / (1. - u1), y2 / (u1 - slope));
+    if !(f1.is_finite() && f2.is_finite()) {
+        return Err(SolveError::Unphysical)
+    }
+    Ok(Layout {
+        lenses: vec![(Millimeters(s), Millimeters(f1)), (Millimeters(s + d), Millimeters(f2))],
+        image: Millimeters(s + d + i)
+    })
+}
+
+/// Paraxial layout solver. L opens it; pinned constraints are solved for the focal lengths and
+/// positions of one or two ideal thin lenses, which "place" puts on the fan's source axis for
+/// swapping with real elements.
+#[derive(Resource)]
+pub struct LayoutSolver {
+    pub enabled: bool,
+    /// 1 for a single lens, 2 for a relay
+    pub lenses: usize,
+    pub values: [f32; 5],
+    pub pinned: [bool; 5]
+}
+
+impl Default for LayoutSolver {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lenses: 1,
+            values: [10., 20., -2., 30., 10.],
+            pinned: [true, true, false, false, false]
+        }
+    }
+}
+
+impl LayoutSolver {
+    fn pinned(&self, constraint: Constraint) -> Option<f32> {
+        let k = constraint as usize;
+        self.pinned[k].then_some(self.values[k])
+    }
+
+    pub fn solve(&self) -> Result<Layout, SolveError> {
+        let (s, i, m, l) = (
+            self.pinned(Constraint::Object),
+            self.pinned(Constraint::Image),
+            self.pinned(Constraint::Magnification),
+            self.pinned(Constraint::Track)
+        );
+        match self.lenses {
+            1 => single(s, i, m, l),
+            _ => relay(s, i, m, l, self.pinned(Constraint::Spacing))
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct LayoutPanel;
+
+/// Thin lens placed by the solver, replaced when it places again.
+#[derive(Component)]
+pub struct SolvedLens;
+
+#[derive(Component, Clone, Copy)]
+pub enum LayoutButton {
+    Pin(Constraint),
+    Down(Constraint),
+    Up(Constraint),
+    Lenses,
+    Place
+}
+
+pub fn layout_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut solver: ResMut<LayoutSolver>
+) {
+    if keys.just_pressed(KeyCode::L) {
+        solver.enabled = !solver.enabled;
+    }
+}
+
+/// Rebuilds the system report along the fan's source whenever rays are retraced.
+pub fn system_report_system(
+    mut report: ResMut<SystemReport>,
+    (solver, fan): (Res<LayoutSolver>, Res<RayFan>),
+    changed: Query<(), Changed<RaySegment>>,
+    source_query: Query<&BeamSource>,
+    surface_query: Query<(Entity, &Surface)>
+) {
+    if !solver.enabled || (!solver.is_changed() && !fan.is_changed() && changed.is_empty()) {
+        return
+    }
+    let sources: Vec<&BeamSource> = source_query.iter().collect();
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    report.source = fan.source % sources.len().max(1);
+    report.system = sources.get(report.source).and_then(|source| ParaxialSystem::of(source, &surfaces));
+}
+
+/// The report's lines: focal length, focal distances, principal planes and the image of the
+/// source plane.
+fn system_lines(report: &SystemReport, units: UnitSystem, locale: &Locale) -> Vec<String> {
+    let length = |value: f32| units.show(Quantity::Length, value).to_string();
+    let mut lines = vec![locale.format("layout.system", &[("source", &(report.source + 1))])];
+    let Some(system) = &report.system else {
+        lines.push(locale.text("layout.no-system").to_string());
+        return lines
+    };
+    lines.push(locale.format("layout.surfaces", &[("count", &system.elements.len())]));
+    match (system.focal_length(), system.front_focal_distance(), system.back_focal_distance(), system.principal_planes()) {
+        (Some(f), Some(ffd), Some(bfd), Some((front, rear))) => {
+            lines.push(locale.format("layout.efl", &[("f", &length(f))]));
+            lines.push(locale.format("layout.focal-distances", &[("front", &length(ffd)), ("back", &length(bfd))]));
+            lines.push(locale.format("layout.principal", &[("front", &length(front)), ("rear", &length(rear))]));
+        }
+        _ => lines.push(locale.text("layout.afocal").to_string())
+    }
+    lines.push(match system.image() {
+        Some((at, m)) => locale.format("layout.system-image", &[("at", &length(at)), ("m", &format!("{:.3}", m))]),
+        None => locale.text("layout.image-infinity").to_string()
+    });
+    lines
+}
+
+pub fn layout_panel_system(
+    mut commands: Commands,
+    (solver, report): (Res<LayoutSolver>, Res<SystemReport>),
+    config: Res<Config>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    panel_query: Query<Entity, With<LayoutPanel>>
+) {
+    if !solver.is_changed() && !report.is_changed() && !config.is_changed() && !locale.is_changed() {
+        return
+    }
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !solver.enabled {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    let pin = |constraint: Constraint| locale.text(if solver.pinned[constraint as usize] { "layout.unpin" } else { "layout.pin" });
+    let mut rows: Vec<(String, Vec<(&str, LayoutButton)>)> = vec![
+        (locale.format("layout.lenses", &[("count", &solver.lenses)]), vec![(locale.text("common.toggle"), LayoutButton::Lenses)])
+    ];
+    for constraint in CONSTRAINTS {
+        if constraint == Constraint::Spacing && solver.lenses == 1 {
+            continue
+        }
+        let k = constraint as usize;
+        let state = locale.text(if solver.pinned[k] { "layout.pinned" } else { "layout.free" });
+        rows.push((
+            locale.format(constraint.key(), &[("value", &constraint.show(config.units, solver.values[k])), ("state", &state)]),
+            vec![(pin(constraint), LayoutButton::Pin(constraint)), ("-", LayoutButton::Down(constraint)), ("+", LayoutButton::Up(constraint))]
+        ));
+    }
+    let results: Vec<String> = match solver.solve() {
+        Ok(layout) => layout.lenses.iter().enumerate()
+            .map(|(k, (at, f))| locale.format("layout.lens", &[
+                ("k", &(k + 1)),
+                ("f", &config.units.show(Quantity::Length, f.0)),
+                ("at", &config.units.show(Quantity::Length, at.0))
+            ]))
+            .chain([locale.format("layout.result-image", &[("at", &config.units.show(Quantity::Length, layout.image.0))])])
+            .collect(),
+        Err(err) => vec![locale.text(err.key()).to_string()]
+    };
+    let system = system_lines(&report, config.units, &locale);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.),
+                top: Val::Percent(60.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, LayoutPanel)).with_children(|panel| {
+        panel.spawn(TextBundle::from_section(locale.text("layout.title"), style.clone()));
+        let place = (String::new(), vec![(locale.text("layout.place"), LayoutButton::Place)]);
+        for (label, buttons) in rows.into_iter().chain([place]) {
+            panel.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                row.spawn(TextBundle::from_section(label, style.clone()));
+                for (text, button) in buttons {
+                    row.spawn((ButtonBundle {
+                        style: Style {
+                            margin: UiRect::left(Val::Px(8.)),
+                            padding: UiRect::horizontal(Val::Px(4.)),
+                            ..default()
+                        },
+                        background_color: BUTTON_COLOR.into(),
+                        ..default()
+                    }, button)).with_children(|button| {
+                        button.spawn(TextBundle::from_section(text, style.clone()));
+                    });
+                }
+            });
+        }
+        for line in results.into_iter().chain(system) {
+            panel.spawn(TextBundle::from_section(line, style.clone()));
+        }
+    });
+}
+
+/// Edits the constraints, and on "place" swaps the previously placed lenses for the solution,
+/// along the fan's source from its position.
+pub fn layout_button_system(
+    mut commands: Commands,
+    mut solver: ResMut<LayoutSolver>,
+    fan: Res<RayFan>,
+    button_query: Query<(&Interaction, &LayoutButton), Changed<Interaction>>,
+    source_query: Query<&BeamSource>,
+    lens_query: Query<Entity, With<SolvedLens>>
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue
+        }
+        match *button {
+            LayoutButton::Pin(constraint) => solver.pinned[constraint as usize] ^= true,
+            LayoutButton::Down(constraint) => solver.values[constraint as usize] -= constraint.step(),
+            LayoutButton::Up(constraint) => solver.values[constraint as usize] += constraint.step(),
+            LayoutButton::Lenses => solver.lenses = if solver.lenses == 1 { 2 } else { 1 },
+            LayoutButton::Place => {
+                let sources: Vec<&BeamSource> = source_query.iter().collect();
+                let (false, Ok(layout)) = (sources.is_empty(), solver.solve()) else {
+                    continue
+                };
+                let source = sources[fan.source % sources.len()];
+                let axis = source.direction.normalize_or_zero();
+                for lens in lens_query.iter() {
+                    commands.entity(lens).despawn_recursive();
+                }
+                for (k, (at, f)) in layout.lenses.iter().enumerate() {
+                    let center = source.pos + axis * at.0;
+                    let half = axis.perp() * LENS_HEIGHT / 2.;
+                    commands.spawn((
+                        Surface::thin_lens(center + half, center - half, *f).label(&format!("L{}", k + 1)),
+                        SolvedLens
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bodies::Lens, trace_limited, TraceLimits};
+
+    #[test]
+    fn singlet_focus_matches_where_traced_rays_cross_the_axis() {
+        // biconvex, 4 mm thick, with its back vertex at x = 22
+        let lens = Lens::new(Vec2::new(20., 0.), Millimeters(20.), Millimeters(40.), Millimeters(-60.), Millimeters(4.), 1.5);
+        let faces = lens.body().unwrap().faces();
+        let surfaces: Vec<(Entity, &Surface)> = faces.iter().enumerate().map(|(k, face)| (Entity::from_raw(k as u32), face)).collect();
+        let system = ParaxialSystem::of(&BeamSource::new(Vec2::ZERO, Vec2::X, Millimeters(1.)), &surfaces).unwrap();
+        let (efl, bfd) = (system.focal_length().unwrap(), system.back_focal_distance().unwrap());
+        assert!((efl - lens.focal_length().0).abs() < 1e-3 * efl, "{efl}");
+        // a ray parallel to the axis and close to it, followed out of the back face
+        let height = 0.2;
+        let traced = trace_limited(&Ray::new(Vec2::new(0., height), Vec2::X, 1.), &surfaces, |_| None, TraceLimits::sequential());
+        let (out, _) = traced.escaped.iter().max_by_key(|(_, depth)| *depth).unwrap();
+        let crossing = out.p.x - out.p.y * out.l.x / out.l.y;
+        assert!((crossing - 22. - bfd).abs() < 1e-2 * bfd, "crosses at {crossing}, BFD {bfd}");
+        let traced_efl = -height * out.l.x / out.l.y;
+        assert!((traced_efl - efl).abs() < 1e-2 * efl, "{traced_efl} against {efl}");
+    }
+}
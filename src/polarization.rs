@@ -0,0 +1,143 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{config::Config, BeamSource, Ray, RaySegment, Scale, Stokes};
+
+// Distance (mm) between polarization glyphs along a segment
+const GLYPH_SPACING: f32 = 2.;
+// Semi-major axis (mm) of a fully polarized glyph
+const GLYPH_SIZE: f32 = 0.4;
+const GLYPH_POINTS: usize = 24;
+
+/// How a scene models polarization. Scalar rays carry intensity only; in Stokes mode every ray
+/// carries a [`Stokes`] vector and surfaces act on it through Mueller matrices, which also
+/// covers partially polarized and depolarized light.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Formalism {
+    #[default]
+    Scalar,
+    Stokes
+}
+
+impl Formalism {
+    /// The source's rays, polarized as the source is when this formalism tracks polarization.
+    pub fn launch(self, source: &BeamSource, density: f32) -> Vec<Ray> {
+        self.polarize(source, source.rays(density))
+    }
+
+    /// As [`Formalism::launch`], with the rays resampled along `path`; see
+    /// [`BeamSource::adaptive_rays`].
+    pub fn launch_adaptive(self, source: &BeamSource, density: f32, path: impl Fn(&Ray) -> Vec<Vec2>) -> Vec<Ray> {
+        self.polarize(source, source.adaptive_rays(density, path))
+    }
+
+    fn polarize(self, source: &BeamSource, mut rays: Vec<Ray>) -> Vec<Ray> {
+        if self == Formalism::Stokes {
+            for ray in rays.iter_mut() {
+                ray.stokes = Some(source.polarization);
+            }
+        }
+        rays
+    }
+}
+
+/// Whether polarization ellipses are drawn along the rays.
+#[derive(Resource, Default)]
+pub struct EllipseGlyphs(pub bool);
+
+#[derive(Component)]
+pub struct EllipseGlyph;
+
+/// P switches the scene between scalar and Stokes rays; the sources re-emit when it changes.
+/// Shift+P shows and hides the polarization ellipses.
+pub fn formalism_toggle_system(
+    keys: Res<Input<KeyCode>>,
+    mut formalism: ResMut<Formalism>,
+    mut glyphs: ResMut<EllipseGlyphs>
+) {
+    if !keys.just_pressed(KeyCode::P) {
+        return
+    }
+    if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        glyphs.0 = !glyphs.0;
+    } else {
+        *formalism = match *formalism {
+            Formalism::Scalar => Formalism::Stokes,
+            Formalism::Stokes => Formalism::Scalar
+        };
+        info!("polarization: {:?}", *formalism);
+    }
+}
+
+/// Polarization ellipse traced by the field, centered on `center`. The glyph is drawn in the
+/// plane across the ray folded onto the table: p lies across the ray as it does in the table,
+/// and s, which points out of the table, is drawn along the ray. It shrinks with the degree
+/// of polarization, so unpolarized light shows as a dot.
+fn ellipse(stokes: &Stokes, center: Vec2, direction: Vec2) -> Path {
+    let s = stokes.0;
+    let orientation = s.z.atan2(s.y) / 2.;
+    let polarized = s.truncate().length();
+    let ellipticity = if polarized > 0. { (s.w / polarized).clamp(-1., 1.).asin() / 2. } else { 0. };
+    let size = GLYPH_SIZE * stokes.degree().max(0.1);
+    let (major, minor) = (size * ellipticity.cos(), size * ellipticity.sin());
+    // s along the ray, p across it
+    let s_axis = direction;
+    let p_axis = -direction.perp();
+    let mut path_builder = PathBuilder::new();
+    for k in 0..=GLYPH_POINTS {
+        let t = TAU * k as f32 / GLYPH_POINTS as f32;
+        let local = Vec2::from_angle(orientation).rotate(Vec2::new(major * t.cos(), minor * t.sin()));
+        let point = center + s_axis * local.x + p_axis * local.y;
+        if k == 0 {
+            path_builder.move_to(point);
+        } else {
+            path_builder.line_to(point);
+        }
+    }
+    path_builder.build()
+}
+
+/// Draws ellipses along traced segments while glyphs are on, as children of each segment so
+/// they go away with their tree.
+pub fn ellipse_glyph_system(
+    mut commands: Commands,
+    (config, scale): (Res<Config>, Res<Scale>),
+    glyphs: Res<EllipseGlyphs>,
+    glyph_query: Query<Entity, With<EllipseGlyph>>,
+    all_segments: Query<(Entity, &RaySegment)>,
+    changed_segments: Query<(Entity, &RaySegment), Changed<RaySegment>>
+) {
+    if glyphs.is_changed() {
+        for glyph in glyph_query.iter() {
+            commands.entity(glyph).despawn_recursive();
+        }
+    }
+    if !glyphs.0 {
+        return
+    }
+    let segments: Vec<(Entity, &RaySegment)> = if glyphs.is_changed() {
+        all_segments.iter().collect()
+    } else {
+        changed_segments.iter().collect()
+    };
+    for (entity, segment) in segments {
+        let Some(stokes) = &segment.stokes else {
+            continue
+        };
+        let length = segment.from.distance(segment.to);
+        let direction = (segment.to - segment.from).normalize_or_zero();
+        let color = config.palette.color(segment.wavelength);
+        let count = (length / GLYPH_SPACING) as usize;
+        for k in 0..count {
+            let center = segment.from + direction * GLYPH_SPACING * (k as f32 + 0.5);
+            let glyph = commands.spawn((GeometryBuilder::build_as(
+                &ellipse(stokes, center, direction),
+                DrawMode::Stroke(StrokeMode::new(color, scale.px(1.))),
+                Transform::from_xyz(0., 0., 0.5)
+            ), EllipseGlyph)).id();
+            commands.entity(entity).add_child(glyph);
+        }
+    }
+}
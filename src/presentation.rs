@@ -0,0 +1,102 @@
+use bevy::{prelude::*, window::WindowMode};
+
+use crate::{viewports::MainCamera, GridLine, RayTree, RaycastEvent, Surface};
+
+// Stroke widths are multiplied by this while presenting, for projectors
+const STROKE_SCALE: f32 = 2.5;
+// Labels, marks and the reach of drag handles are multiplied by this while presenting
+const SIZE_SCALE: f32 = 2.;
+
+#[derive(Resource, Default)]
+pub struct Presentation {
+    pub active: bool
+}
+
+impl Presentation {
+    /// Multiplier for stroke widths.
+    pub fn stroke(&self) -> f32 {
+        if self.active { STROKE_SCALE } else { 1. }
+    }
+
+    /// Multiplier for labels, marks and how far from an endpoint or source it can be grabbed.
+    pub fn size(&self) -> f32 {
+        if self.active { SIZE_SCALE } else { 1. }
+    }
+}
+
+/// Keys that swap a labelled surface for a prepared alternative while presenting, e.g.
+/// to show the same setup with and without a lens. Pressing the key again swaps back.
+#[derive(Resource, Default)]
+pub struct PresentationToggles(pub Vec<(KeyCode, Surface)>);
+
+impl PresentationToggles {
+    /// Binds `key` to swapping the surface labelled like `alternate` with `alternate`.
+    pub fn bind(&mut self, key: KeyCode, alternate: Surface) {
+        self.0.push((key, alternate));
+    }
+}
+
+/// F5 switches presentation mode: fullscreen, no UI, thicker strokes, larger labels and handles.
+pub fn presentation_toggle_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut presentation: ResMut<Presentation>,
+    mut windows: ResMut<Windows>,
+    camera_query: Query<Entity, MainCamera>,
+    tree_query: Query<(Entity, &RayTree)>,
+    mut writer: EventWriter<RaycastEvent>
+) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return
+    }
+    presentation.active = !presentation.active;
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_mode(if presentation.active { WindowMode::BorderlessFullscreen } else { WindowMode::Windowed });
+    }
+    for camera in camera_query.iter() {
+        commands.entity(camera).insert(UiCameraConfig { show_ui: !presentation.active });
+    }
+    // redraw rays at the new stroke width
+    for (entity, tree) in tree_query.iter() {
+        writer.send(RaycastEvent {
+            ray: Some(tree.root.clone()),
+            tree: Some(entity),
+            source: tree.source
+        });
+    }
+}
+
+/// Hides the grid while presenting, including lines redrawn after a resize.
+pub fn presentation_visibility_system(
+    presentation: Res<Presentation>,
+    added: Query<(), Added<GridLine>>,
+    mut grid_query: Query<&mut Visibility, With<GridLine>>
+) {
+    if !presentation.is_changed() && added.is_empty() {
+        return
+    }
+    for mut visibility in grid_query.iter_mut() {
+        visibility.is_visible = !presentation.active;
+    }
+}
+
+pub fn presentation_keys_system(
+    keys: Res<Input<KeyCode>>,
+    presentation: Res<Presentation>,
+    mut toggles: ResMut<PresentationToggles>,
+    mut surface_query: Query<&mut Surface>
+) {
+    if !presentation.active {
+        return
+    }
+    for (key, alternate) in toggles.0.iter_mut() {
+        if !keys.just_pressed(*key) {
+            continue
+        }
+        let target = surface_query.iter_mut()
+            .find(|surface| surface.label.is_some() && surface.label == alternate.label);
+        if let Some(mut surface) = target {
+            std::mem::swap(surface.as_mut(), alternate);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{cross2, refract, trace_limited, units::{Millimeters, Nanometers}, BeamSource, Ray, Surface, TraceLimits};
+
+// Face edits smaller than this (mm) are the pair's own updates, not a prism being moved
+const FACE_TOLERANCE: f32 = 1e-4;
+// Steps of the secant solve for the first prism's tilt
+const SOLVE_STEPS: usize = 20;
+// Smallest apex angle (degrees), for magnifications close to one
+const MIN_APEX: f32 = 1.;
+
+/// Pair of identical prisms that stretches a beam across its direction by `magnification` (or
+/// squeezes it, below one) and sends it on parallel to where it came from, as used to make an
+/// elliptical diode-laser beam round. Each prism takes half of the magnification: the beam
+/// enters one face at an angle and leaves through the other at normal incidence (the other
+/// way round when squeezing). The second prism is the first mirrored about the bisector of
+/// the beam's directions before and after it, so it undoes the deviation while doubling the
+/// magnification, however the first prism is tilted.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct AnamorphicPair {
+    /// Where the beam axis enters the first prism
+    pub pos: Vec2,
+    /// Direction of the incoming beam
+    pub direction: Vec2,
+    /// Magnification the prisms are cut for, at zero tilt
+    pub magnification: f32,
+    pub index: f32,
+    /// Length (mm) of the entry face of the first prism
+    pub size: f32,
+    /// Distance (mm) along the beam from the first prism to the second
+    pub spacing: f32,
+    /// Tilt (radians) of the first prism about `pos`, counterclockwise
+    pub tilt: f32
+}
+
+/// One of the six faces of a pair: entry, exit and base of each prism, in order.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PrismFace {
+    pub pair: Entity,
+    pub face: usize
+}
+
+/// Where the beam axis crosses a prism: entry and exit points, and the direction it leaves in.
+struct Passage {
+    entry: Vec2,
+    exit: Vec2,
+    direction: Vec2
+}
+
+impl AnamorphicPair {
+    pub fn new(pos: Vec2, direction: Vec2, magnification: f32) -> Self {
+        Self {
+            pos,
+            direction: direction.normalize_or_zero(),
+            magnification,
+            index: 1.5,
+            size: 10.,
+            spacing: 10.,
+            tilt: 0.
+        }
+    }
+
+    pub fn index(mut self, index: f32) -> Self {
+        self.index = index;
+        self
+    }
+
+    pub fn spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Corners of the untilted first prism relative to `pos`: the apex, then the far ends of
+    /// the entry and exit faces. The apex angle α gives one prism a magnification
+    /// m = cos α / cos θ with sin θ = n sin α, so sin² α = (m² - 1) / (m² n² - 1).
+    fn corners(&self) -> [Vec2; 3] {
+        let n = self.index;
+        let stretch = self.magnification.abs().sqrt();
+        let m = if stretch >= 1. { stretch } else { 1. / stretch };
+        let apex = ((m * m - 1.) / (m * m * n * n - 1.)).max(0.).sqrt().asin().max(MIN_APEX.to_radians());
+        let incidence = (n * apex.sin()).min(1.).asin();
+        let d = self.direction;
+        // stretching: tilted entry face, normal exit; squeezing: normal entry, tilted exit
+        let entry_normal = if stretch >= 1. { -Vec2::from_angle(incidence).rotate(d) } else { -d };
+        let inside = refract(d, entry_normal, 1., n).unwrap_or(d);
+        let exit_normal = if stretch >= 1. { inside } else { Vec2::from_angle(apex).rotate(d) };
+        let across = exit_normal.perp();
+        let denominator = cross2(inside, across);
+        if denominator.abs() < f32::EPSILON {
+            return [Vec2::ZERO; 3]
+        }
+        // the apex is on the side of the entry face where the exit face meets the beam ahead
+        for side in [1., -1.] {
+            let apex_point = entry_normal.perp() * side * self.size / 2.;
+            let t = cross2(apex_point, across) / denominator;
+            if t > 0. {
+                let exit = inside * t;
+                return [apex_point, -apex_point, 2. * exit - apex_point]
+            }
+        }
+        [Vec2::ZERO; 3]
+    }
+
+    /// Faces of one prism with corners `corners`: entry into glass, exit into air and a
+    /// blackened base.
+    fn prism(&self, corners: [Vec2; 3]) -> [Surface; 3] {
+        let [apex, entry_end, exit_end] = corners;
+        [
+            Surface::glass(apex, entry_end).index(self.index),
+            Surface::glass(apex, exit_end).index(1.0),
+            Surface::blocker(entry_end, exit_end)
+        ]
+    }
+
+    fn first(&self) -> [Surface; 3] {
+        let rotation = Vec2::from_angle(self.tilt);
+        self.prism(self.corners().map(|corner| self.pos + rotation.rotate(corner)))
+    }
+
+    /// Follows the beam axis through one prism.
+    fn passage(&self, faces: &[Surface; 3], from: Vec2, direction: Vec2) -> Option<Passage> {
+        let surfaces: Vec<(Entity, &Surface)> = faces.iter().enumerate()
+            .map(|(k, surface)| (Entity::from_raw(k as u32), surface))
+            .collect();
+        let traced = trace_limited(&Ray::new(from - direction * self.size, direction, 1.), &surfaces, |_| None, TraceLimits::sequential());
+        let [entry, exit] = &traced.segments[..] else {
+            return None
+        };
+        if exit.surface != Entity::from_raw(1) {
+            return None
+        }
+        Some(Passage {
+            entry: entry.hit.point,
+            exit: exit.hit.point,
+            direction: traced.branches.last()?.l
+        })
+    }
+
+    /// All six faces, or `None` when the beam axis misses the first prism or is totally
+    /// reflected inside it.
+    pub fn faces(&self) -> Option<[Surface; 6]> {
+        let first = self.first();
+        let passage = self.passage(&first, self.pos, self.direction)?;
+        // mirror about the bisector of the directions, then carry the entry point onto the beam
+        let bisector = (self.direction + passage.direction).normalize_or_zero();
+        let target = passage.exit + passage.direction * self.spacing;
+        let mirror = |p: Vec2| {
+            let v = p - passage.entry;
+            target + 2. * v.dot(bisector) * bisector - v
+        };
+        let [a, b, c] = first.clone();
+        let second = first.map(|face| Surface {
+            p1: mirror(face.p1),
+            p2: mirror(face.p2),
+            ..face
+        });
+        let [d, e, f] = second;
+        Some([a, b, c, d, e, f])
+    }
+
+    /// Magnification of the beam through both prisms, from two neighbouring axis rays.
+    pub fn beam_magnification(&self) -> Option<f32> {
+        let faces = self.faces()?;
+        let surfaces: Vec<(Entity, &Surface)> = faces.iter().enumerate()
+            .map(|(k, surface)| (Entity::from_raw(k as u32), surface))
+            .collect();
+        let offset = self.size * 1e-3;
+        let leave = |shift: f32| {
+            let start = self.pos + self.direction.perp() * shift - self.direction * self.size;
+            trace_limited(&Ray::new(start, self.direction, 1.), &surfaces, |_| None, TraceLimits::sequential()).branches.last().map(|ray| ray.p)
+        };
+        let (lo, hi) = (leave(-offset)?, leave(offset)?);
+        Some((hi - lo).dot(self.direction.perp()).abs() / (2. * offset))
+    }
+
+    /// Angle (radians) of the second prism's entry face.
+    fn second_angle(&self) -> Option<f32> {
+        let faces = self.faces()?;
+        let dp = faces[3].dp();
+        Some(dp.y.atan2(dp.x))
+    }
+}
+
+/// Diode laser whose beam is stretched threefold by a prism pair.
+pub fn diode_demo() -> (BeamSource, AnamorphicPair) {
+    let source = BeamSource::new(Vec2::new(4., 19.), Vec2::X, Millimeters(0.6))
+        .wavelength(Nanometers(660.));
+    let pair = AnamorphicPair::new(Vec2::new(12., 19.), Vec2::X, 3.)
+        .index(1.5)
+        .spacing(8.);
+    (source, pair)
+}
+
+/// Difference of two angles, wrapped to [-π, π).
+fn wrap(angle: f32) -> f32 {
+    (angle + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI
+}
+
+/// Spawns the faces of new pairs.
+pub fn prism_spawn_system(
+    mut commands: Commands,
+    pair_query: Query<(Entity, &AnamorphicPair), Added<AnamorphicPair>>
+) {
+    for (entity, pair) in pair_query.iter() {
+        let Some(faces) = pair.faces() else {
+            warn!("the beam misses the anamorphic prism pair at {}", pair.pos);
+            continue
+        };
+        for (face, surface) in faces.into_iter().enumerate() {
+            commands.spawn((surface, PrismFace {
+                pair: entity,
+                face
+            }));
+        }
+    }
+}
+
+/// Keeps the prisms of a pair coupled. Moving or turning a face of the first prism moves the
+/// whole prism with it; turning a face of the second tilts the first until the second turns
+/// the same. The second prism then follows, so the beam leaves parallel to where it came
+/// from. Other edits of the second prism, and ones that lose the beam, are undone.
+pub fn prism_coupling_system(
+    mut pair_query: Query<&mut AnamorphicPair>,
+    mut face_query: Query<(&PrismFace, &mut Surface, ChangeTrackers<Surface>)>
+) {
+    let edits: Vec<(PrismFace, Surface)> = face_query.iter()
+        .filter(|(_, _, changes)| changes.is_changed() && !changes.is_added())
+        .map(|(face, surface, _)| (*face, surface.clone()))
+        .collect();
+    let mut touched: Vec<Entity> = Vec::new();
+    for (face, surface) in edits {
+        let Ok(mut pair) = pair_query.get_mut(face.pair) else {
+            continue
+        };
+        let Some(expected) = pair.faces() else {
+            continue
+        };
+        let expected = &expected[face.face];
+        if expected.p1.distance(surface.p1) < FACE_TOLERANCE && expected.p2.distance(surface.p2) < FACE_TOLERANCE {
+            continue
+        }
+        let turn = wrap(surface.dp().y.atan2(surface.dp().x) - expected.dp().y.atan2(expected.dp().x));
+        let mut moved = pair.clone();
+        if face.face < 3 {
+            // rigid motion carrying the expected face onto the edited one
+            let (from, to) = ((expected.p1 + expected.p2) / 2., (surface.p1 + surface.p2) / 2.);
+            moved.pos = to + Vec2::from_angle(turn).rotate(pair.pos - from);
+            moved.tilt += turn;
+        } else if let Some(current) = pair.second_angle() {
+            let target = current + turn;
+            let error = |tilt: f32| {
+                let mut trial = pair.clone();
+                trial.tilt = tilt;
+                trial.second_angle().map(|angle| wrap(angle - target))
+            };
+            // the second prism turns about as much as the first, the other way
+            let (mut t0, mut t1) = (pair.tilt, pair.tilt - turn);
+            let mut e0 = error(t0).unwrap_or(f32::NAN);
+            for _ in 0..SOLVE_STEPS {
+                let Some(e1) = error(t1) else {
+                    break
+                };
+                if e1.abs() < FACE_TOLERANCE || e1 == e0 {
+                    break
+                }
+                (t0, t1, e0) = (t1, t1 - e1 * (t1 - t0) / (e1 - e0), e1);
+            }
+            moved.tilt = t1;
+        }
+        if moved.faces().is_some() {
+            *pair = moved;
+            info!("anamorphic pair magnification: {:.3}", pair.beam_magnification().unwrap_or(f32::NAN));
+        }
+        touched.push(face.pair);
+    }
+    for (face, mut surface, _) in face_query.iter_mut() {
+        if !touched.contains(&face.pair) {
+            continue
+        }
+        let Some(faces) = pair_query.get(face.pair).ok().and_then(|pair| pair.faces()) else {
+            continue
+        };
+        let expected = &faces[face.face];
+        if expected.p1.distance(surface.p1) >= FACE_TOLERANCE || expected.p2.distance(surface.p2) >= FACE_TOLERANCE {
+            surface.p1 = expected.p1;
+            surface.p2 = expected.p2;
+        }
+    }
+}
+
@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+
+use crate::{fans::RayFan, BeamSource, Profile};
+
+/// B cycles the fan's source through top-hat, Gaussian and donut profiles; it re-emits.
+pub fn shape_cycle_system(
+    keys: Res<Input<KeyCode>>,
+    fan: Res<RayFan>,
+    mut source_query: Query<&mut BeamSource>
+) {
+    if !keys.just_pressed(KeyCode::B) {
+        return
+    }
+    let count = source_query.iter().count();
+    if let Some(mut source) = source_query.iter_mut().nth(fan.source % count.max(1)) {
+        source.shape = source.shape.next(source.waist);
+        info!("beam shape: {:?}", source.shape);
+    }
+}
+
+/// Dropping a profile (`.csv`) on the window loads it onto the fan's source, which re-emits.
+pub fn profile_drop_system(
+    fan: Res<RayFan>,
+    mut events: EventReader<FileDragAndDrop>,
+    mut source_query: Query<&mut BeamSource>
+) {
+    for event in events.iter() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = event else {
+            continue
+        };
+        if path_buf.extension().and_then(|extension| extension.to_str()) != Some("csv") {
+            continue
+        }
+        let count = source_query.iter().count();
+        let Some(mut source) = source_query.iter_mut().nth(fan.source % count.max(1)) else {
+            continue
+        };
+        match Profile::load(path_buf) {
+            Ok(profile) => {
+                info!("loaded {:?} profile from {}", profile.axis, path_buf.display());
+                *source = source.clone().profile(profile);
+            }
+            Err(err) => error!("could not load profile {}: {}", path_buf.display(), err)
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+
+use crate::{hit_distance, Extent, Ray, Surface};
+
+// Surfaces per leaf of the hierarchy
+const LEAF_SIZE: usize = 4;
+// Margin (mm) around each box, so that axis-aligned surfaces don't give flat boxes
+const BOX_MARGIN: f32 = 1e-3;
+
+/// Axis-aligned box.
+#[derive(Clone, Copy, Debug)]
+struct Bounds {
+    min: Vec2,
+    max: Vec2
+}
+
+impl Bounds {
+    const EMPTY: Self = Self {
+        min: Vec2::splat(f32::INFINITY),
+        max: Vec2::splat(f32::NEG_INFINITY)
+    };
+
+    /// Box around a bounded surface, its arc included.
+    fn of(surface: &Surface) -> Self {
+        let (min, max) = surface.bounds();
+        Self {
+            min: min - BOX_MARGIN,
+            max: max + BOX_MARGIN
+        }
+    }
+
+    fn with(self, p: Vec2) -> Self {
+        Self {
+            min: self.min.min(p),
+            max: self.max.max(p)
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max)
+        }
+    }
+
+    fn center(&self) -> Vec2 {
+        (self.min + self.max) / 2.
+    }
+
+    /// Distance along `ray` to where it enters the box, 0 from inside, or None if it misses.
+    fn entry(&self, ray: &Ray) -> Option<f32> {
+        let (mut near, mut far) = (0f32, f32::INFINITY);
+        for axis in 0..2 {
+            let (p, l) = (ray.p[axis], ray.l[axis]);
+            if l == 0. {
+                if p < self.min[axis] || p > self.max[axis] {
+                    return None
+                }
+                continue
+            }
+            let (t1, t2) = ((self.min[axis] - p) / l, (self.max[axis] - p) / l);
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    /// Surfaces `start..end` of the ordering
+    Leaf { bounds: Bounds, start: usize, end: usize },
+    Split { bounds: Bounds, left: usize, right: usize }
+}
+
+impl Node {
+    fn bounds(&self) -> &Bounds {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Split { bounds, .. } => bounds
+        }
+    }
+}
+
+/// Bounding volume hierarchy over the scene's surfaces, for finding the nearest hit of a ray
+/// without testing every surface. Rebuilt whenever a surface changes. Surfaces extending to
+/// infinity have no box and are tested on every cast.
+#[derive(Resource, Default)]
+pub struct Raycaster {
+    surfaces: Vec<(Entity, Surface)>,
+    /// Indices into `surfaces`, grouped by leaf
+    order: Vec<usize>,
+    nodes: Vec<Node>,
+    unbounded: Vec<usize>
+}
+
+impl Raycaster {
+    /// Hierarchy over `surfaces`. Ties between equally near hits go to the surface listed
+    /// first, as in [`crate::nearest_hit`], so traces don't depend on the tree's layout.
+    pub fn new(surfaces: &[(Entity, &Surface)]) -> Self {
+        let (bounded, unbounded): (Vec<usize>, Vec<usize>) = (0..surfaces.len())
+            .partition(|k| surfaces[*k].1.extent == Extent::Segment);
+        let mut raycaster = Self {
+            surfaces: surfaces.iter().map(|(entity, surface)| (*entity, (*surface).clone())).collect(),
+            order: bounded,
+            nodes: Vec::new(),
+            unbounded
+        };
+        let boxes: Vec<Bounds> = raycaster.surfaces.iter().map(|(_, surface)| Bounds::of(surface)).collect();
+        if !raycaster.order.is_empty() {
+            raycaster.build(&boxes, 0, raycaster.order.len());
+        }
+        raycaster
+    }
+
+    /// Adds the node over `order[start..end]`, splitting it at the median along its longer side,
+    /// and returns its index.
+    fn build(&mut self, boxes: &[Bounds], start: usize, end: usize) -> usize {
+        let bounds = self.order[start..end].iter().fold(Bounds::EMPTY, |b, k| b.union(boxes[*k]));
+        let index = self.nodes.len();
+        if end - start <= LEAF_SIZE {
+            self.nodes.push(Node::Leaf { bounds, start, end });
+            return index
+        }
+        let centers = self.order[start..end].iter().fold(Bounds::EMPTY, |b, k| b.with(boxes[*k].center()));
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y { 0 } else { 1 };
+        self.order[start..end].sort_by(|a, b| boxes[*a].center()[axis].total_cmp(&boxes[*b].center()[axis]));
+        let middle = (start + end) / 2;
+        // placeholder until both halves are built
+        self.nodes.push(Node::Leaf { bounds, start, end });
+        let left = self.build(boxes, start, middle);
+        let right = self.build(boxes, middle, end);
+        self.nodes[index] = Node::Split { bounds, left, right };
+        index
+    }
+
+    /// Closest surface hit by `ray`, as (distance, entity, surface), skipping `exclude`. Gives
+    /// the same hit as [`crate::nearest_hit`] over the surfaces in their original order.
+    pub fn cast(&self, ray: &Ray, exclude: Option<Entity>) -> Option<(f32, Entity, &Surface)> {
+        // (distance, index into surfaces)
+        let mut nearest: Option<(f32, usize)> = None;
+        let test = |k: usize, nearest: &mut Option<(f32, usize)>| {
+            let (entity, surface) = &self.surfaces[k];
+            let Some(d) = hit_distance(ray, *entity, surface, exclude) else {
+                return
+            };
+            if nearest.is_none_or(|(best, j)| d < best || (d == best && k < j)) {
+                *nearest = Some((d, k));
+            }
+        };
+        for k in self.unbounded.iter() {
+            test(*k, &mut nearest);
+        }
+        let mut stack: Vec<usize> = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let Some(entry) = node.bounds().entry(ray) else {
+                continue
+            };
+            if nearest.is_some_and(|(best, _)| entry > best) {
+                continue
+            }
+            match node {
+                Node::Leaf { start, end, .. } => {
+                    for k in self.order[*start..*end].iter() {
+                        test(*k, &mut nearest);
+                    }
+                }
+                Node::Split { left, right, .. } => {
+                    // nearer child last, so it's searched first
+                    let far_first = |a: usize, b: usize| {
+                        let distance = |n: usize| self.nodes[n].bounds().entry(ray).unwrap_or(f32::INFINITY);
+                        if distance(a) <= distance(b) { [b, a] } else { [a, b] }
+                    };
+                    stack.extend(far_first(*left, *right));
+                }
+            }
+        }
+        nearest.map(|(d, k)| (d, self.surfaces[k].0, &self.surfaces[k].1))
+    }
+}
+
+/// Rebuilds the raycaster when surfaces are added, edited or removed.
+pub fn raycaster_rebuild_system(
+    mut raycaster: ResMut<Raycaster>,
+    changed: Query<(), Changed<Surface>>,
+    removed: RemovedComponents<Surface>,
+    surface_query: Query<(Entity, &Surface)>
+) {
+    if changed.is_empty() && removed.iter().next().is_none() {
+        return
+    }
+    let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+    *raycaster = Raycaster::new(&surfaces);
+}
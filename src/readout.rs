@@ -0,0 +1,51 @@
+use bevy::prelude::*;
+
+use crate::{
+    config::Config, locale::Locale, mouse::{self, Pointer}, scene_tree::Selected, ui::{self, UiFont}, units::Quantity, Surface
+};
+
+/// Line at the bottom right giving where the cursor is on the table, how much of it a pixel
+/// covers at the current zoom, and the length of the selected surface, in the configured units.
+#[derive(Component)]
+pub struct Readout;
+
+pub fn readout_system(
+    mut commands: Commands,
+    (config, locale, font): (Res<Config>, Res<Locale>, Res<UiFont>),
+    (windows, camera_query): Pointer,
+    selected_query: Query<&Surface, With<Selected>>,
+    mut readout_query: Query<&mut Text, With<Readout>>
+) {
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    let show = |quantity: Quantity, value: f32| config.units.show(quantity, value).to_string();
+    let mut parts = Vec::new();
+    if let Some(cursor) = window.cursor_position() {
+        let p = mouse::to_scene(cursor, window, camera, projection.scale);
+        parts.push(locale.format("readout.cursor", &[("x", &show(Quantity::Length, p.x)), ("y", &show(Quantity::Length, p.y))]));
+    }
+    parts.push(locale.format("readout.pixel", &[("size", &show(Quantity::SmallLength, projection.scale))]));
+    if let Ok(surface) = selected_query.get_single() {
+        parts.push(locale.format("readout.length", &[("length", &show(Quantity::Length, surface.length().0))]));
+    }
+    let line = parts.join("   ");
+    match readout_query.get_single_mut() {
+        Ok(mut text) => {
+            if text.sections[0].value != line {
+                text.sections[0].value = line;
+            }
+        }
+        Err(_) => {
+            commands.spawn((TextBundle::from_section(line, ui::text_style(&font, ui::TEXT_COLOR)).with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(10.),
+                    bottom: Val::Px(10.),
+                    ..default()
+                },
+                ..default()
+            }), Readout));
+        }
+    }
+}
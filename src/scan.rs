@@ -0,0 +1,292 @@
+use std::{collections::HashMap, fs, str::FromStr};
+
+use bevy::prelude::*;
+
+use crate::{
+    bundle::{BundleStats, Crossing}, config::Config, demo_scene, links::{Param, Parser}, trace, units::{Quantity, UnitSystem},
+    BeamSource, Surface, TraceSegment, RAY_DENSITY
+};
+
+// Seconds each step of a live scan stays on screen
+const STEP_SECONDS: f32 = 0.25;
+// Where a live scan writes its table
+const SCAN_FILE: &str = "scan.csv";
+
+/// A quantity a scan records at every step.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Output {
+    /// Power reaching the surface with this label
+    Power(String),
+    /// Power-weighted RMS spread (mm) of where rays hit the surface with this label
+    SpotRms(String)
+}
+
+impl Output {
+    fn name(&self) -> String {
+        match self {
+            Output::Power(label) => label.clone(),
+            Output::SpotRms(label) => format!("spot_rms({})", label)
+        }
+    }
+
+    fn quantity(&self) -> Quantity {
+        match self {
+            Output::Power(_) => Quantity::Power,
+            Output::SpotRms(_) => Quantity::Length
+        }
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            Output::Power(label) | Output::SpotRms(label) => label
+        }
+    }
+
+    fn measure(&self, segments: &[&TraceSegment]) -> f32 {
+        let crossings: Vec<Crossing> = segments.iter().map(|segment| Crossing::of_segment(segment)).collect();
+        let stats = BundleStats::of(&crossings);
+        match self {
+            Output::Power(_) => stats.map_or(0., |stats| stats.power),
+            Output::SpotRms(_) => stats.map_or(0., |stats| stats.rms_radius)
+        }
+    }
+}
+
+/// `scan(label.param, from, to, steps, record=[output, ..])`: steps a parameter of a labelled
+/// surface evenly from `from` to `to` and records the outputs at every step. An output is a
+/// surface label (the power reaching it) or `spot_rms(label)`; a bare `spot_rms` measures on
+/// the first surface named in the list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanSpec {
+    pub target: (String, Param),
+    pub from: f32,
+    pub to: f32,
+    pub steps: usize,
+    pub record: Vec<Output>
+}
+
+impl FromStr for ScanSpec {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut parser = Parser::new(text);
+        let expect = |parser: &mut Parser, c: char| if parser.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}` at {}", c, parser.at))
+        };
+        let number = |parser: &mut Parser| parser.sum()?.eval(&|label, _| Err(format!("`{}` in a scan bound", label)));
+        if parser.word(|c| c.is_alphanumeric()) != "scan" {
+            return Err("expected `scan(`".to_string())
+        }
+        expect(&mut parser, '(')?;
+        let target = parser.reference()?;
+        expect(&mut parser, ',')?;
+        let from = number(&mut parser)?;
+        expect(&mut parser, ',')?;
+        let to = number(&mut parser)?;
+        expect(&mut parser, ',')?;
+        let steps = number(&mut parser)?;
+        if steps < 1. || steps.fract() != 0. {
+            return Err(format!("`{}` steps is not a positive whole number", steps))
+        }
+        let mut names = Vec::new();
+        if parser.eat(',') {
+            if parser.word(|c| c.is_alphanumeric()) != "record" {
+                return Err(format!("expected `record=[..]` at {}", parser.at))
+            }
+            expect(&mut parser, '=')?;
+            expect(&mut parser, '[')?;
+            while !parser.eat(']') {
+                let name = parser.word(|c| c.is_alphanumeric() || c == '_' || c == '(' || c == ')');
+                if name.is_empty() {
+                    return Err(format!("expected an output at {}", parser.at))
+                }
+                names.push(name.to_string());
+                if !parser.eat(',') {
+                    expect(&mut parser, ']')?;
+                    break
+                }
+            }
+        }
+        expect(&mut parser, ')')?;
+        if parser.peek().is_some() {
+            return Err(format!("unexpected `{}` at {}", &text[parser.at..].trim(), parser.at))
+        }
+        let first = names.iter().find(|name| !name.starts_with("spot_rms")).cloned();
+        let record = names.into_iter().map(|name| {
+            match name.strip_prefix("spot_rms") {
+                Some("") => first.clone().map(Output::SpotRms).ok_or("`spot_rms` needs a surface to measure on".to_string()),
+                Some(rest) => rest.strip_prefix('(')
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .map(|label| Output::SpotRms(label.to_string()))
+                    .ok_or(format!("bad output `{}`", name)),
+                None => Ok(Output::Power(name))
+            }
+        }).collect::<Result<Vec<Output>, String>>()?;
+        Ok(Self {
+            target,
+            from,
+            to,
+            steps: steps as usize,
+            record
+        })
+    }
+}
+
+impl ScanSpec {
+    pub fn value(&self, step: usize) -> f32 {
+        if self.steps < 2 {
+            return self.from
+        }
+        self.from + (self.to - self.from) * step as f32 / (self.steps - 1) as f32
+    }
+
+    /// Header of the results table: one column per variable, with its unit in `units`.
+    pub fn header(&self, units: UnitSystem) -> String {
+        let (label, param) = &self.target;
+        let column = |name: String, quantity: Option<Quantity>| match quantity {
+            Some(quantity) => format!("{} [{}]", name, units.export_unit(quantity).1),
+            None => name
+        };
+        let mut columns = vec!["step".to_string(), column(format!("{}.{}", label, param), param.quantity(0.).map(|(quantity, _)| quantity))];
+        columns.extend(self.record.iter().map(|output| column(output.name(), Some(output.quantity()))));
+        columns.join(",")
+    }
+
+    /// Row of the results table for `step` in `units`, tracing `sources` through `surfaces`.
+    pub fn row(
+        &self,
+        step: usize,
+        sources: &[&BeamSource],
+        surfaces: &[(Entity, &Surface)],
+        density: f32,
+        units: UnitSystem
+    ) -> Result<String, String> {
+        let labelled: HashMap<&str, Entity> = surfaces.iter()
+            .filter_map(|(entity, surface)| Some((surface.label.as_deref()?, *entity)))
+            .collect();
+        let traced: Vec<TraceSegment> = sources.iter()
+            .flat_map(|source| source.rays(density))
+            .flat_map(|ray| trace(&ray, surfaces, |_| None).segments)
+            .collect();
+        let export = |quantity: Quantity, value: f32| (value * units.export_unit(quantity).0).to_string();
+        let value = self.value(step);
+        let mut columns = vec![step.to_string(), match self.target.1.quantity(value) {
+            Some((quantity, value)) => export(quantity, value),
+            None => value.to_string()
+        }];
+        for output in self.record.iter() {
+            let entity = labelled.get(output.label()).ok_or(format!("no surface labelled `{}`", output.label()))?;
+            let on: Vec<&TraceSegment> = traced.iter().filter(|segment| segment.surface == *entity).collect();
+            columns.push(export(output.quantity(), output.measure(&on)));
+        }
+        Ok(columns.join(","))
+    }
+
+    /// Sets the scanned parameter for `step` on the labelled surface among `surfaces`.
+    pub fn apply(&self, step: usize, surfaces: &mut [&mut Surface]) -> Result<(), String> {
+        let (label, param) = &self.target;
+        let surface = surfaces.iter_mut()
+            .find(|surface| surface.label.as_deref() == Some(label.as_str()))
+            .ok_or(format!("no surface labelled `{}`", label))?;
+        param.set(surface, self.value(step))
+    }
+
+    /// Runs the whole scan without drawing and returns the results table.
+    pub fn run(&self, sources: &[BeamSource], surfaces: &mut [Surface], density: f32, units: UnitSystem) -> Result<String, String> {
+        let sources: Vec<&BeamSource> = sources.iter().collect();
+        let mut table = vec![self.header(units)];
+        for step in 0..self.steps {
+            self.apply(step, &mut surfaces.iter_mut().collect::<Vec<_>>())?;
+            let listed: Vec<(Entity, &Surface)> = surfaces.iter().enumerate()
+                .map(|(k, surface)| (Entity::from_raw(k as u32), surface))
+                .collect();
+            table.push(self.row(step, &sources, &listed, density, units)?);
+        }
+        Ok(table.join("\n") + "\n")
+    }
+}
+
+/// Handles `--scan "<scan>" <file>`: runs the scan on the demo scene without a window and
+/// writes the results table in the configured units. Returns the process exit code if it was given.
+pub fn run(args: &[String]) -> Option<i32> {
+    let [mode, text, path, ..] = args else {
+        return None
+    };
+    if mode != "--scan" {
+        return None
+    }
+    let (sources, mut surfaces) = demo_scene();
+    let table = text.parse::<ScanSpec>().and_then(|spec| spec.run(&sources, &mut surfaces, RAY_DENSITY, Config::load().units));
+    Some(match table.and_then(|table| fs::write(path, table).map_err(|err| err.to_string())) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("scan failed: {}", err);
+            1
+        }
+    })
+}
+
+/// Scan running in the window, typed into the links panel. It takes one step every
+/// [`STEP_SECONDS`], so the scene redraws at each, and writes [`SCAN_FILE`] at the end.
+#[derive(Resource, Default)]
+pub struct LiveScan {
+    spec: Option<ScanSpec>,
+    step: usize,
+    rows: Vec<String>,
+    elapsed: f32,
+    /// Units of the table, fixed when the scan starts
+    units: UnitSystem
+}
+
+impl LiveScan {
+    pub fn start(&mut self, spec: ScanSpec, units: UnitSystem) {
+        self.rows = vec![spec.header(units)];
+        self.units = units;
+        self.spec = Some(spec);
+        self.step = 0;
+        self.elapsed = STEP_SECONDS;
+    }
+}
+
+pub fn live_scan_system(
+    time: Res<Time>,
+    config: Res<Config>,
+    mut live_scan: ResMut<LiveScan>,
+    source_query: Query<&BeamSource>,
+    mut surface_query: Query<(Entity, &mut Surface)>
+) {
+    let Some(spec) = live_scan.spec.clone() else {
+        return
+    };
+    live_scan.elapsed += time.delta_seconds();
+    if live_scan.elapsed < STEP_SECONDS {
+        return
+    }
+    live_scan.elapsed = 0.;
+    let (step, units) = (live_scan.step, live_scan.units);
+    let result = spec.apply(step, &mut surface_query.iter_mut().map(|(_, surface)| surface.into_inner()).collect::<Vec<_>>())
+        .and_then(|()| {
+            let sources: Vec<&BeamSource> = source_query.iter().collect();
+            let surfaces: Vec<(Entity, &Surface)> = surface_query.iter().collect();
+            spec.row(step, &sources, &surfaces, config.ray_density, units)
+        });
+    match result {
+        Ok(row) => live_scan.rows.push(row),
+        Err(err) => {
+            error!("scan: {}", err);
+            live_scan.spec = None;
+            return
+        }
+    }
+    live_scan.step += 1;
+    if live_scan.step < spec.steps {
+        return
+    }
+    live_scan.spec = None;
+    match fs::write(SCAN_FILE, live_scan.rows.join("\n") + "\n") {
+        Ok(()) => info!("scan written to {}", SCAN_FILE),
+        Err(err) => error!("could not write {}: {}", SCAN_FILE, err)
+    }
+}
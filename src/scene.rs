@@ -0,0 +1,226 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::Track, aperture::{Aperture, ApertureBlade}, bodies::{Body, BodyFace}, config::Config, demo_scene, detectors::Detector, polarization::Formalism,
+    prisms::{self, AnamorphicPair, PrismFace}, turbid::{MediumShade, TurbidMedium}, BeamSource, RayTree, RaycastEvent, Surface, ThermalLens
+};
+
+// Where Ctrl+S saves a scene that wasn't loaded from a file
+const DEFAULT_SCENE: &str = "scene.ron";
+
+pub type SceneEntity = Or<(
+    With<Surface>, With<BeamSource>, With<AnamorphicPair>, With<Body>, With<Aperture>, With<TurbidMedium>, With<MediumShade>, With<RayTree>
+)>;
+pub type SceneItem<'a> = (
+    Entity,
+    Option<&'a Surface>,
+    Option<&'a BeamSource>,
+    Option<&'a AnamorphicPair>,
+    (Option<&'a Body>, Option<&'a Aperture>),
+    Option<&'a TurbidMedium>,
+    (Option<&'a PrismFace>, Option<&'a BodyFace>, Option<&'a ApertureBlade>)
+);
+pub type Extras<'a> = (Option<&'a Track>, Option<&'a Detector>, Option<&'a ThermalLens>);
+
+/// A surface with the components that go along when it is stored or moves to another tab.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Element {
+    pub surface: Surface,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<Track>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub detector: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thermal_lens: Option<ThermalLens>
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl Element {
+    pub fn of(surface: &Surface, (track, detector, thermal_lens): Extras) -> Self {
+        Self {
+            surface: surface.clone(),
+            track: track.copied(),
+            detector: detector.is_some(),
+            thermal_lens: thermal_lens.cloned()
+        }
+    }
+
+    pub fn spawn(&self, commands: &mut Commands) {
+        let mut entity = commands.spawn(self.surface.clone());
+        if let Some(track) = self.track {
+            entity.insert(track);
+        }
+        if self.detector {
+            entity.insert(Detector);
+        }
+        if let Some(thermal_lens) = &self.thermal_lens {
+            entity.insert(thermal_lens.clone());
+        }
+    }
+}
+
+/// Everything on the table: sources, surfaces with their extras, and the elements that spawn
+/// their own faces, which are kept whole. It is what a tab keeps while another is shown, and
+/// what scene files hold, as RON or JSON by their extension.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub sources: Vec<BeamSource>,
+    pub elements: Vec<Element>,
+    pub pairs: Vec<AnamorphicPair>,
+    pub bodies: Vec<Body>,
+    pub apertures: Vec<Aperture>,
+    pub media: Vec<TurbidMedium>
+}
+
+impl Scene {
+    /// The built-in scene: the demo table and a diode laser with its anamorphic prism pair.
+    pub fn demo() -> Self {
+        let (sources, surfaces) = demo_scene();
+        let (diode, pair) = prisms::diode_demo();
+        Self {
+            sources: [sources, vec![diode]].concat(),
+            elements: surfaces.iter().map(|surface| Element::of(surface, (None, None, None))).collect(),
+            pairs: vec![pair],
+            bodies: Vec::new(),
+            apertures: Vec::new(),
+            media: Vec::new()
+        }
+    }
+
+    /// Reads the scene in the world, leaving out faces and blades that their element spawns.
+    pub fn capture(scene_query: &Query<SceneItem, SceneEntity>, extras_query: &Query<Extras>) -> Self {
+        let mut scene = Self::default();
+        for (entity, surface, source, pair, (body, aperture), medium, faces) in scene_query.iter() {
+            match (surface, source, pair, body, aperture, medium) {
+                (Some(surface), ..) if faces.0.is_none() && faces.1.is_none() && faces.2.is_none() => {
+                    scene.elements.push(Element::of(surface, extras_query.get(entity).unwrap_or_default()));
+                }
+                (_, Some(source), ..) => scene.sources.push(source.clone()),
+                (_, _, Some(pair), ..) => scene.pairs.push(pair.clone()),
+                (_, _, _, Some(body), ..) => scene.bodies.push(body.clone()),
+                (_, _, _, _, Some(aperture), _) => scene.apertures.push(aperture.clone()),
+                (.., Some(medium)) => scene.media.push(medium.clone()),
+                _ => {}
+            }
+        }
+        scene
+    }
+
+    /// Spawns the scene and emits its sources' rays.
+    pub fn spawn(self, commands: &mut Commands, config: &Config, formalism: &Formalism, writer: &mut EventWriter<RaycastEvent>) {
+        for source in self.sources {
+            let rays = formalism.launch(&source, config.ray_density);
+            let entity = commands.spawn(source).id();
+            for ray in rays {
+                writer.send(RaycastEvent {
+                    ray: Some(ray),
+                    tree: None,
+                    source: Some(entity)
+                });
+            }
+        }
+        for element in self.elements.iter() {
+            element.spawn(commands);
+        }
+        for pair in self.pairs {
+            commands.spawn(pair);
+        }
+        for body in self.bodies {
+            commands.spawn(body);
+        }
+        for aperture in self.apertures {
+            commands.spawn(aperture);
+        }
+        for medium in self.media {
+            commands.spawn(medium);
+        }
+    }
+
+    /// Every surface of the scene: the elements' in order, then the faces and blades that
+    /// pairs, bodies and apertures spawn.
+    pub fn surfaces(&self) -> Vec<Surface> {
+        self.elements.iter()
+            .map(|element| element.surface.clone())
+            .chain(self.pairs.iter().filter_map(|pair| pair.faces()).flatten())
+            .chain(self.bodies.iter().flat_map(|body| body.faces()))
+            .chain(self.apertures.iter().flat_map(|aperture| aperture.blades()))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => ron::from_str(&text).map_err(|err| err.to_string()),
+            Some("json") => serde_json::from_str(&text).map_err(|err| err.to_string()),
+            _ => Err("scene files end in .ron or .json".to_string())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())?,
+            Some("json") => serde_json::to_string_pretty(self).map_err(|err| err.to_string())?,
+            _ => return Err("scene files end in .ron or .json".to_string())
+        };
+        fs::write(path, text).map_err(|err| err.to_string())
+    }
+}
+
+/// The file the scene was loaded from and is saved to: the first argument that isn't a flag,
+/// else the last scene in the config.
+#[derive(Resource, Default)]
+pub struct SceneFile(pub Option<PathBuf>);
+
+impl SceneFile {
+    pub fn from_args(args: &[String], config: &Config) -> Self {
+        let named = args.iter().find(|arg| !arg.starts_with("--")).map(PathBuf::from);
+        Self(named.or_else(|| config.last_scene.clone().filter(|path| path.exists())))
+    }
+
+    /// The scene in the file, or the demo when there is none or it can't be read.
+    pub fn scene(&self) -> Scene {
+        let Some(path) = &self.0 else {
+            return Scene::demo()
+        };
+        match Scene::load(path) {
+            Ok(scene) => {
+                info!("loaded the scene from {}", path.display());
+                scene
+            }
+            Err(err) => {
+                error!("could not load the scene from {}: {}", path.display(), err);
+                Scene::demo()
+            }
+        }
+    }
+}
+
+/// Ctrl+S saves the shown scene to its file, or to `scene.ron` when it has none, and
+/// remembers it as the last scene.
+pub fn scene_save_system(
+    keys: Res<Input<KeyCode>>,
+    mut config: ResMut<Config>,
+    mut file: ResMut<SceneFile>,
+    scene_query: Query<SceneItem, SceneEntity>,
+    extras_query: Query<Extras>
+) {
+    if !(keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) && keys.just_pressed(KeyCode::S)) {
+        return
+    }
+    let path = file.0.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_SCENE));
+    match Scene::capture(&scene_query, &extras_query).save(&path) {
+        Ok(()) => {
+            info!("saved the scene to {}", path.display());
+            config.last_scene = Some(path.clone());
+            file.0 = Some(path);
+        }
+        Err(err) => error!("could not save the scene to {}: {}", path.display(), err)
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+
+use crate::{locale::Locale, ui::{self, UiFont}, Surface};
+
+// Selected entries and their surfaces are drawn in this color
+pub const SELECTED_COLOR: Color = Color::rgb(0.3, 0.7, 1.0);
+
+#[derive(Component)]
+pub struct Selected;
+
+/// State of the scene tree panel and its search box.
+#[derive(Resource, Default)]
+pub struct SceneFilter {
+    pub open: bool,
+    pub query: String
+}
+
+/// Name, kind and material of `surface` as shown in the tree.
+fn columns(surface: &Surface, locale: &Locale) -> [String; 3] {
+    let kind = locale.text(&format!("kind.{}", surface.kind())).to_string();
+    let name = surface.label.clone().unwrap_or_else(|| kind.clone());
+    let material = match &surface.coating {
+        Some(coating) => locale.text(&format!("coating.{:?}", coating).to_lowercase()).to_string(),
+        None => surface.material()
+    };
+    [name, kind, material]
+}
+
+impl SceneFilter {
+    /// Case-insensitive match on the displayed name, kind or material.
+    pub fn matches(&self, columns: &[String; 3]) -> bool {
+        let query = self.query.to_lowercase();
+        columns.iter().any(|field| field.to_lowercase().contains(&query))
+    }
+}
+
+#[derive(Component, Clone, Copy)]
+pub enum TreeButton {
+    /// Toggles selection of one element
+    Entry(Entity),
+    /// Selects every element of a kind, and nothing else
+    AllOfKind(&'static str)
+}
+
+/// F4 opens and closes the scene tree. While it is open, typing edits the search box.
+pub fn scene_filter_input_system(
+    keys: Res<Input<KeyCode>>,
+    mut chars: EventReader<ReceivedCharacter>,
+    mut filter: ResMut<SceneFilter>
+) {
+    if keys.just_pressed(KeyCode::F4) {
+        filter.open = !filter.open;
+        filter.query.clear();
+    }
+    if !filter.open {
+        chars.clear();
+        return
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        filter.query.pop();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        filter.query.clear();
+    }
+    for event in chars.iter() {
+        if !event.char.is_control() {
+            filter.query.push(event.char);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn scene_tree_panel_system(
+    mut commands: Commands,
+    filter: Res<SceneFilter>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    changed: Query<(), Changed<Surface>>,
+    removed: RemovedComponents<Surface>,
+    surface_query: Query<(Entity, &Surface, Option<&Selected>)>,
+    mut panel: Local<Option<Entity>>
+) {
+    if !filter.is_changed() && !locale.is_changed() && changed.is_empty() && removed.iter().next().is_none() {
+        return
+    }
+    if let Some(panel) = panel.take() {
+        commands.entity(panel).despawn_recursive();
+    }
+    if !filter.open {
+        return
+    }
+    let mut surfaces: Vec<_> = surface_query.iter()
+        .map(|(entity, surface, selected)| (entity, surface, columns(surface, &locale), selected))
+        .collect();
+    surfaces.sort_by_key(|(entity, _, _, _)| entity.index());
+    let total = surfaces.len();
+    surfaces.retain(|(_, _, columns, _)| filter.matches(columns));
+
+    *panel = Some(commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(10.),
+                top: Val::Px(10.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }).with_children(|panel| {
+        let style = ui::text_style(&font, ui::TEXT_COLOR);
+        panel.spawn(TextBundle::from_section(locale.format("scene.title", &[("shown", &surfaces.len()), ("total", &total)]), style.clone()));
+        panel.spawn(TextBundle::from_section(locale.format("scene.search", &[("query", &filter.query)]), style.clone()));
+        for (entity, surface, [name, kind, material], selected) in surfaces {
+            let color = if selected.is_some() { SELECTED_COLOR } else { ui::TEXT_COLOR };
+            panel.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                row.spawn(ButtonBundle {
+                    background_color: Color::NONE.into(),
+                    ..default()
+                }).insert(TreeButton::Entry(entity)).with_children(|button| {
+                    button.spawn(TextBundle::from_section(
+                        format!("{:<12} {:<12} {}", name, kind, material),
+                        ui::text_style(&font, color)
+                    ));
+                });
+                row.spawn(ButtonBundle {
+                    style: Style {
+                        margin: UiRect::left(Val::Px(8.)),
+                        padding: UiRect::horizontal(Val::Px(4.)),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.25, 0.25, 0.25).into(),
+                    ..default()
+                }).insert(TreeButton::AllOfKind(surface.kind())).with_children(|button| {
+                    button.spawn(TextBundle::from_section(locale.text("common.all"), style.clone()));
+                });
+            });
+        }
+    }).id());
+}
+
+/// Runs after the panel so the rebuild it triggers sees the updated selection.
+pub fn scene_tree_click_system(
+    mut commands: Commands,
+    mut filter: ResMut<SceneFilter>,
+    button_query: Query<(&Interaction, &TreeButton), Changed<Interaction>>,
+    surface_query: Query<(Entity, &Surface, Option<&Selected>)>
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue
+        }
+        filter.set_changed();
+        match *button {
+            TreeButton::Entry(entity) => match surface_query.get(entity) {
+                Ok((_, _, Some(_))) => {
+                    commands.entity(entity).remove::<Selected>();
+                }
+                Ok((_, _, None)) => {
+                    commands.entity(entity).insert(Selected);
+                }
+                Err(_) => ()
+            },
+            TreeButton::AllOfKind(kind) => {
+                for (entity, surface, selected) in surface_query.iter() {
+                    if surface.kind() == kind {
+                        commands.entity(entity).insert(Selected);
+                    } else if selected.is_some() {
+                        commands.entity(entity).remove::<Selected>();
+                    }
+                }
+            }
+        }
+    }
+}
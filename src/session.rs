@@ -0,0 +1,228 @@
+use std::{fs, path::PathBuf};
+
+use bevy::{prelude::*, window::WindowCloseRequested};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alignment::BeamWalk, animation::Clock, bookmarks::Bookmarks, chromatic::ChromaticReport, config::{Config, SettingsPanel},
+    etendue::EtendueReport, fans::RayFan, field::FieldSweep, fit_scale, links::{LinkEditor, Links}, paraxial::LayoutSolver,
+    polarization::EllipseGlyphs, scene_tree::{SceneFilter, Selected}, viewports::MainCamera, Surface
+};
+
+/// A camera view, with `zoom` relative to the scale that fits the table into the window as
+/// for bookmarks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct View {
+    pub name: String,
+    pub center: [f32; 2],
+    pub zoom: f32
+}
+
+/// A bookmarked view and the number key it is on, from 0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Slot {
+    pub slot: usize,
+    pub view: View
+}
+
+/// Which panels and overlays are open.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Panels {
+    pub settings: bool,
+    pub scene: bool,
+    pub links: bool,
+    pub chromatic: bool,
+    pub fans: bool,
+    pub field: bool,
+    pub etendue: bool,
+    pub layout: bool,
+    pub walk: bool,
+    pub ellipses: bool
+}
+
+/// Everything about a session besides the scene itself: the view, open panels, selection,
+/// animation clock and what the analysis tools are pinned to. It is written to `session.toml`
+/// beside the config file when a window is closed and restored at startup. The scene is rebuilt
+/// as usual, so selected surfaces are remembered by their place in it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub scene_query: String,
+    /// Surfaces selected, by spawn order
+    pub selected: Vec<usize>,
+    pub fan_source: usize,
+    pub layout_lenses: usize,
+    pub layout_values: Vec<f32>,
+    pub layout_pinned: Vec<bool>,
+    pub walk_targets: Vec<[f32; 2]>,
+    pub links: Vec<String>,
+    pub time: f32,
+    pub playing: bool,
+    // tables after plain values, as TOML needs
+    pub camera: Option<View>,
+    pub panels: Panels,
+    pub bookmarks: Vec<Slot>
+}
+
+impl Session {
+    /// `session.toml` in the config file's directory.
+    pub fn path() -> Option<PathBuf> {
+        Some(Config::path()?.parent()?.join("session.toml"))
+    }
+
+    fn load() -> Option<Self> {
+        let path = Self::path()?;
+        let text = fs::read_to_string(&path).ok()?;
+        toml::from_str(&text)
+            .map_err(|err| warn!("ignoring invalid session {}: {}", path.display(), err))
+            .ok()
+    }
+
+    fn save(&self) -> Result<PathBuf, String> {
+        let path = Self::path().ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let text = toml::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&path, text).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+
+    /// Reads the session from the world.
+    fn capture(world: &mut World) -> Self {
+        let fit = world.resource::<Windows>().get_primary().map_or(1., |window| fit_scale(window.width(), window.height()));
+        let camera = world.query_filtered::<(&Transform, &OrthographicProjection), MainCamera>()
+            .iter(world)
+            .next()
+            .map(|(transform, projection)| View {
+                name: String::new(),
+                center: transform.translation.truncate().into(),
+                zoom: fit / projection.scale
+            });
+        let mut surfaces: Vec<(Entity, bool)> = world.query::<(Entity, &Surface, Option<&Selected>)>()
+            .iter(world)
+            .map(|(entity, _, selected)| (entity, selected.is_some()))
+            .collect();
+        surfaces.sort_by_key(|(entity, _)| entity.index());
+        let settings = world.query_filtered::<(), With<SettingsPanel>>().iter(world).next().is_some();
+        let (layout, walk, filter, clock) = (
+            world.resource::<LayoutSolver>(),
+            world.resource::<BeamWalk>(),
+            world.resource::<SceneFilter>(),
+            world.resource::<Clock>()
+        );
+        Self {
+            camera,
+            bookmarks: world.resource::<Bookmarks>().0.iter().enumerate().filter_map(|(slot, bookmark)| {
+                let bookmark = bookmark.as_ref()?;
+                Some(Slot {
+                    slot,
+                    view: View {
+                        name: bookmark.name.clone(),
+                        center: bookmark.center.into(),
+                        zoom: bookmark.zoom
+                    }
+                })
+            }).collect(),
+            panels: Panels {
+                settings,
+                scene: filter.open,
+                links: world.resource::<LinkEditor>().open,
+                chromatic: world.resource::<ChromaticReport>().enabled,
+                fans: world.resource::<RayFan>().enabled,
+                field: world.resource::<FieldSweep>().enabled,
+                etendue: world.resource::<EtendueReport>().enabled,
+                layout: layout.enabled,
+                walk: walk.enabled,
+                ellipses: world.resource::<EllipseGlyphs>().0
+            },
+            scene_query: filter.query.clone(),
+            selected: surfaces.iter().enumerate().filter(|(_, (_, selected))| *selected).map(|(k, _)| k).collect(),
+            fan_source: world.resource::<RayFan>().source,
+            layout_lenses: layout.lenses,
+            layout_values: layout.values.to_vec(),
+            layout_pinned: layout.pinned.to_vec(),
+            walk_targets: walk.targets.iter().map(|target| (*target).into()).collect(),
+            links: world.resource::<Links>().0.iter().map(|link| link.to_string()).collect(),
+            time: clock.time,
+            playing: clock.playing
+        }
+    }
+
+    /// Puts the session back into the world.
+    fn restore(&self, world: &mut World) {
+        let fit = world.resource::<Windows>().get_primary().map_or(1., |window| fit_scale(window.width(), window.height()));
+        if let Some(view) = &self.camera {
+            for (mut transform, mut projection) in world.query_filtered::<(&mut Transform, &mut OrthographicProjection), MainCamera>().iter_mut(world) {
+                transform.translation.x = view.center[0];
+                transform.translation.y = view.center[1];
+                projection.scale = fit / view.zoom.max(f32::EPSILON);
+            }
+        }
+        let mut bookmarks = world.resource_mut::<Bookmarks>();
+        let slots = bookmarks.0.len();
+        for Slot { slot, view } in self.bookmarks.iter().filter(|bookmark| bookmark.slot < slots) {
+            bookmarks.set(*slot, &view.name, view.center.into(), view.zoom);
+        }
+        let mut surfaces: Vec<Entity> = world.query_filtered::<Entity, With<Surface>>().iter(world).collect();
+        surfaces.sort_by_key(|entity| entity.index());
+        for k in self.selected.iter() {
+            if let Some(entity) = surfaces.get(*k) {
+                world.entity_mut(*entity).insert(Selected);
+            }
+        }
+        if self.panels.settings {
+            world.spawn((NodeBundle::default(), SettingsPanel));
+        }
+        let mut filter = world.resource_mut::<SceneFilter>();
+        filter.open = self.panels.scene;
+        filter.query = self.scene_query.clone();
+        world.resource_mut::<LinkEditor>().open = self.panels.links;
+        world.resource_mut::<ChromaticReport>().enabled = self.panels.chromatic;
+        let mut fan = world.resource_mut::<RayFan>();
+        fan.enabled = self.panels.fans;
+        fan.source = self.fan_source;
+        world.resource_mut::<FieldSweep>().enabled = self.panels.field;
+        world.resource_mut::<EtendueReport>().enabled = self.panels.etendue;
+        world.resource_mut::<EllipseGlyphs>().0 = self.panels.ellipses;
+        let mut layout = world.resource_mut::<LayoutSolver>();
+        layout.enabled = self.panels.layout;
+        layout.lenses = self.layout_lenses.clamp(1, 2);
+        if let (Ok(values), Ok(pinned)) = (self.layout_values.clone().try_into(), self.layout_pinned.clone().try_into()) {
+            layout.values = values;
+            layout.pinned = pinned;
+        }
+        let mut walk = world.resource_mut::<BeamWalk>();
+        walk.enabled = self.panels.walk;
+        walk.targets = self.walk_targets.iter().map(|target| (*target).into()).collect();
+        let mut links = world.resource_mut::<Links>();
+        for link in self.links.iter() {
+            if let Err(err) = links.add(link) {
+                warn!("session link `{}`: {}", link, err);
+            }
+        }
+        let mut clock = world.resource_mut::<Clock>();
+        clock.time = self.time;
+        clock.playing = self.playing;
+    }
+}
+
+/// Restores the last session once the scene is set up.
+pub fn session_restore_system(world: &mut World) {
+    if let Some(session) = Session::load() {
+        session.restore(world);
+        info!("restored the last session");
+    }
+}
+
+/// Saves the session when a window is asked to close, before it goes away.
+pub fn session_save_system(world: &mut World) {
+    if world.resource::<Events<WindowCloseRequested>>().is_empty() {
+        return
+    }
+    match Session::capture(world).save() {
+        Ok(path) => info!("saved the session to {}", path.display()),
+        Err(err) => error!("could not save the session: {}", err)
+    }
+}
@@ -0,0 +1,32 @@
+use bevy::prelude::*;
+
+// Steps the settings dialog cycles through: grid steps (mm) and angle steps (degrees)
+pub const GRID_STEPS: [f32; 4] = [0.1, 0.5, 1., 5.];
+pub const ANGLE_STEPS: [f32; 3] = [1., 5., 15.];
+
+/// Whether dragging snaps: while Alt is held.
+pub fn held(keys: &Input<KeyCode>) -> bool {
+    keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt])
+}
+
+/// Nearest grid intersection to `p`, for a grid of `step` mm lined up with the drawn one.
+pub fn point(p: Vec2, step: f32) -> Vec2 {
+    if step <= 0. {
+        return p
+    }
+    (p / step).round() * step
+}
+
+/// `degrees` rounded to a whole number of `step` degrees.
+pub fn angle(degrees: f32, step: f32) -> f32 {
+    if step <= 0. {
+        return degrees
+    }
+    (degrees / step).round() * step
+}
+
+/// The step after `current` in `steps`, wrapping around.
+pub fn next_step(steps: &[f32], current: f32) -> f32 {
+    let next = steps.iter().position(|step| *step == current).map_or(0, |k| k + 1);
+    steps[next % steps.len()]
+}
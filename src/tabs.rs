@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+
+use crate::{
+    aperture::ApertureBlade, bodies::BodyFace, config::Config, locale::Locale, polarization::Formalism, prisms::PrismFace,
+    scene::{Element, Extras, Scene, SceneEntity, SceneItem}, scene_tree::Selected, ui::{self, UiFont}, RaycastEvent, Surface
+};
+
+const TAB_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+const CURRENT_TAB_COLOR: Color = Color::rgb(0.4, 0.4, 0.55);
+
+type Copyable = (With<Selected>, Without<PrismFace>, Without<BodyFace>, Without<ApertureBlade>);
+
+pub struct Tab {
+    pub name: String,
+    /// Scene of the tab while another one is shown
+    pub stored: Option<Scene>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TabRequest {
+    Show(usize),
+    /// Opens a copy of the shown scene
+    Duplicate,
+    Close
+}
+
+/// Scenes open side by side. Only the shown tab's scene is in the world; the others wait as
+/// snapshots. Ctrl+N opens a copy of the shown scene to try a variant on, Ctrl+Tab and
+/// Ctrl+Shift+Tab switch, Ctrl+W closes the shown tab. Ctrl+C copies the selected surfaces
+/// and Ctrl+V pastes them into the shown tab.
+#[derive(Resource)]
+pub struct Tabs {
+    pub tabs: Vec<Tab>,
+    pub current: usize,
+    pub clipboard: Vec<Element>,
+    pub request: Option<TabRequest>,
+    /// Tabs opened so far, for naming new ones
+    opened: usize
+}
+
+impl Default for Tabs {
+    fn default() -> Self {
+        Self {
+            tabs: vec![Tab {
+                name: "1".to_string(),
+                stored: None
+            }],
+            current: 0,
+            clipboard: Vec::new(),
+            request: None,
+            opened: 1
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct TabBar;
+
+#[derive(Component)]
+pub struct TabButton(pub usize);
+
+/// Turns keys and clicks on the tab bar into requests, and copies and pastes surfaces.
+pub fn tab_input_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut tabs: ResMut<Tabs>,
+    button_query: Query<(&Interaction, &TabButton), Changed<Interaction>>,
+    selected_query: Query<(&Surface, Extras), Copyable>
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction == Interaction::Clicked {
+            tabs.request = Some(TabRequest::Show(button.0));
+        }
+    }
+    if !keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        return
+    }
+    let count = tabs.tabs.len();
+    if keys.just_pressed(KeyCode::Tab) {
+        let step = if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) { count - 1 } else { 1 };
+        tabs.request = Some(TabRequest::Show((tabs.current + step) % count));
+    } else if keys.just_pressed(KeyCode::N) {
+        tabs.request = Some(TabRequest::Duplicate);
+    } else if keys.just_pressed(KeyCode::W) {
+        tabs.request = Some(TabRequest::Close);
+    } else if keys.just_pressed(KeyCode::C) {
+        tabs.clipboard = selected_query.iter().map(|(surface, extras)| Element::of(surface, extras)).collect();
+        info!("copied {} surfaces", tabs.clipboard.len());
+    } else if keys.just_pressed(KeyCode::V) {
+        for element in tabs.clipboard.iter() {
+            element.spawn(&mut commands);
+        }
+    }
+}
+
+/// Carries out a tab request: stores the shown scene, clears it from the world and brings in
+/// the requested one.
+pub fn tab_switch_system(
+    mut commands: Commands,
+    (config, formalism): (Res<Config>, Res<Formalism>),
+    mut tabs: ResMut<Tabs>,
+    mut writer: EventWriter<RaycastEvent>,
+    scene_query: Query<SceneItem, SceneEntity>,
+    extras_query: Query<Extras>
+) {
+    let Some(request) = tabs.request.take() else {
+        return
+    };
+    let shown = Scene::capture(&scene_query, &extras_query);
+    let current = tabs.current;
+    let next = match request {
+        TabRequest::Show(k) if k < tabs.tabs.len() && k != current => {
+            tabs.tabs[current].stored = Some(shown);
+            k
+        }
+        TabRequest::Duplicate => {
+            tabs.opened += 1;
+            let name = tabs.opened.to_string();
+            tabs.tabs[current].stored = Some(shown.clone());
+            tabs.tabs.insert(current + 1, Tab {
+                name,
+                stored: Some(shown)
+            });
+            current + 1
+        }
+        TabRequest::Close if tabs.tabs.len() > 1 => {
+            tabs.tabs.remove(current);
+            current.min(tabs.tabs.len() - 1)
+        }
+        _ => return
+    };
+    let Some(scene) = tabs.tabs[next].stored.take() else {
+        return
+    };
+    tabs.current = next;
+    for (entity, ..) in scene_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    scene.spawn(&mut commands, &config, &formalism, &mut writer);
+}
+
+/// Shows the tab bar while more than one tab is open.
+pub fn tab_bar_system(
+    mut commands: Commands,
+    tabs: Res<Tabs>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    bar_query: Query<Entity, With<TabBar>>
+) {
+    if !tabs.is_changed() && !locale.is_changed() {
+        return
+    }
+    for bar in bar_query.iter() {
+        commands.entity(bar).despawn_recursive();
+    }
+    if tabs.tabs.len() < 2 {
+        return
+    }
+    let style = ui::text_style(&font, ui::TEXT_COLOR);
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Percent(40.),
+                top: Val::Px(4.),
+                ..default()
+            },
+            flex_direction: FlexDirection::Row,
+            ..default()
+        },
+        ..default()
+    }, TabBar)).with_children(|bar| {
+        for (k, tab) in tabs.tabs.iter().enumerate() {
+            bar.spawn((ButtonBundle {
+                style: Style {
+                    margin: UiRect::horizontal(Val::Px(2.)),
+                    padding: UiRect::horizontal(Val::Px(8.)),
+                    ..default()
+                },
+                background_color: if k == tabs.current { CURRENT_TAB_COLOR } else { TAB_COLOR }.into(),
+                ..default()
+            }, TabButton(k))).with_children(|button| {
+                button.spawn(TextBundle::from_section(locale.format("tabs.tab", &[("name", &tab.name)]), style.clone()));
+            });
+        }
+    });
+}
@@ -0,0 +1,88 @@
+use bevy::{input::touch::Touch, prelude::*};
+
+use crate::{presentation::Presentation, viewports::MainCamera, Scale, Surface};
+
+// A touch grabs the nearest surface within this many logical pixels
+const PICK_RADIUS: f32 = 12.;
+
+// Pens arrive from winit as touches; their pressure is ignored throughout.
+
+/// Converts a touch position (logical pixels, origin top-left) to scene mm.
+fn to_scene(position: Vec2, window: &Window, camera: &Transform, scale: f32) -> Vec2 {
+    let centered = position - Vec2::new(window.width(), window.height()) / 2.;
+    camera.translation.truncate() + Vec2::new(centered.x, -centered.y) * scale
+}
+
+/// Distance from `p` to the drawn outline of `surface`, its arc included.
+pub fn distance_to_surface(p: Vec2, surface: &Surface) -> f32 {
+    surface.outline().windows(2).map(|pair| {
+        let (a, b) = (pair[0], pair[1]);
+        let ab = b - a;
+        let t = ((p - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+        p.distance(a + t * ab)
+    }).fold(f32::INFINITY, f32::min)
+}
+
+/// Two fingers pan and pinch-zoom the main camera.
+pub fn touch_camera_system(
+    touches: Res<Touches>,
+    scale: Res<Scale>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), MainCamera>
+) {
+    let fingers: Vec<&Touch> = touches.iter().collect();
+    let [a, b] = fingers[..] else {
+        return
+    };
+    let (mid, previous_mid) = ((a.position() + b.position()) / 2., (a.previous_position() + b.previous_position()) / 2.);
+    let (spread, previous_spread) = (a.position().distance(b.position()), a.previous_position().distance(b.previous_position()));
+    let (min, max) = scale.zoom_limits();
+    for (mut transform, mut projection) in camera_query.iter_mut() {
+        let pan = mid - previous_mid;
+        transform.translation.x -= pan.x * projection.scale;
+        transform.translation.y += pan.y * projection.scale;
+        if spread > 0. && previous_spread > 0. {
+            projection.scale = (projection.scale * previous_spread / spread).clamp(min, max);
+        }
+    }
+}
+
+/// One finger dragged from a surface moves that surface.
+pub fn touch_drag_system(
+    (touches, presentation): (Res<Touches>, Res<Presentation>),
+    windows: Res<Windows>,
+    camera_query: Query<(&Transform, &OrthographicProjection), MainCamera>,
+    mut surface_query: Query<(Entity, &mut Surface)>,
+    mut grabbed: Local<Option<(u64, Entity)>>
+) {
+    let (Some(window), Ok((camera, projection))) = (windows.get_primary(), camera_query.get_single()) else {
+        return
+    };
+    if touches.iter().count() != 1 {
+        *grabbed = None;
+        return
+    }
+    for touch in touches.iter_just_pressed() {
+        let p = to_scene(touch.position(), window, camera, projection.scale);
+        *grabbed = surface_query.iter()
+            .map(|(entity, surface)| (distance_to_surface(p, surface), entity))
+            .filter(|(distance, _)| *distance < PICK_RADIUS * presentation.size() * projection.scale)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, entity)| (touch.id(), entity));
+    }
+    let Some((id, entity)) = *grabbed else {
+        return
+    };
+    let Some(touch) = touches.get_pressed(id) else {
+        *grabbed = None;
+        return
+    };
+    let delta = to_scene(touch.position(), window, camera, projection.scale)
+        - to_scene(touch.previous_position(), window, camera, projection.scale);
+    if delta == Vec2::ZERO {
+        return
+    }
+    if let Ok((_, mut surface)) = surface_query.get_mut(entity) {
+        surface.p1 += delta;
+        surface.p2 += delta;
+    }
+}
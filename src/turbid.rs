@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, units::Nanometers, RaySegment};
+
+// Segment ends this close (mm) to the boundary count as entering the medium
+const ENTRY_TOLERANCE: f32 = 1e-3;
+
+/// Rectangular block of scattering, absorbing material such as tissue. Rays stop at blockers
+/// along its edges and the GPU Monte Carlo carries the light on as photon packets; its index is
+/// matched to the surroundings, so packets leave through the boundary without reflection.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct TurbidMedium {
+    pub min: Vec2,
+    pub max: Vec2,
+    /// Absorption coefficient (1/mm)
+    pub mu_a: f32,
+    /// Scattering coefficient (1/mm)
+    pub mu_s: f32,
+    /// Scattering anisotropy, the mean cosine of the deflection
+    pub g: f32
+}
+
+/// Shading drawn inside a medium.
+#[derive(Component)]
+pub struct MediumShade;
+
+/// Where a traced ray enters a medium, and with what share of its source's power.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub point: Vec2,
+    pub direction: Vec2,
+    pub weight: f32,
+    pub wavelength: Nanometers
+}
+
+impl TurbidMedium {
+    pub fn size(&self) -> Vec2 {
+        self.max - self.min
+    }
+
+    pub fn contains(&self, p: Vec2) -> bool {
+        p.cmpge(self.min).all() && p.cmple(self.max).all()
+    }
+
+    /// Traced segments that end on the boundary heading inwards.
+    pub fn entries<'a>(&self, segments: impl Iterator<Item = &'a RaySegment>) -> Vec<Entry> {
+        segments.filter_map(|segment| {
+            let direction = (segment.to - segment.from).normalize_or_zero();
+            let inside = self.contains(segment.to + direction * ENTRY_TOLERANCE);
+            if !inside || self.contains(segment.from) {
+                return None
+            }
+            Some(Entry {
+                point: segment.to,
+                direction,
+                weight: segment.intensity,
+                wavelength: segment.wavelength
+            })
+        }).collect()
+    }
+}
+
+/// Shades the inside of newly added media; their edges are drawn as surfaces.
+pub fn draw_medium_system(
+    mut commands: Commands,
+    config: Res<Config>,
+    medium_query: Query<&TurbidMedium, Added<TurbidMedium>>
+) {
+    for medium in medium_query.iter() {
+        let shape = shapes::Rectangle {
+            extents: medium.size(),
+            origin: RectangleOrigin::BottomLeft
+        };
+        commands.spawn((GeometryBuilder::build_as(
+            &shape,
+            DrawMode::Fill(FillMode::color(*config.theme.surface().set_a(0.15))),
+            Transform::from_xyz(medium.min.x, medium.min.y, 0.)
+        ), MediumShade));
+    }
+}
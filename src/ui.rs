@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use bevy_egui::egui;
+
+pub const FONT_SIZE: f32 = 14.;
+pub const PANEL_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.85);
+pub const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const PLOT_WIDTH: f32 = 240.;
+const PLOT_HEIGHT: f32 = 120.;
+const PLOT_BACKGROUND: Color = Color::rgba(0., 0., 0., 0.4);
+const PLOT_AXIS_COLOR: Color = Color::rgba(0.9, 0.9, 0.9, 0.3);
+// Side (px) of a plotted point
+const PLOT_DOT: f32 = 3.;
+
+#[derive(Resource)]
+pub struct UiFont(pub Handle<Font>);
+
+pub fn load_font_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>
+) {
+    commands.insert_resource(UiFont(asset_server.load("fonts/DejaVuSansMono.ttf")));
+}
+
+pub fn text_style(font: &UiFont, color: Color) -> TextStyle {
+    TextStyle {
+        font: font.0.clone(),
+        font_size: FONT_SIZE,
+        color
+    }
+}
+
+/// The same color for egui.
+pub fn color32(color: Color) -> egui::Color32 {
+    let [r, g, b, _] = color.as_rgba_f32();
+    egui::Color32::from_rgb((r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8)
+}
+
+/// [`plot`] in an egui window, which keeps it apart from other plots by `id`.
+pub fn egui_plot(ui: &mut egui::Ui, id: impl std::hash::Hash, points: &[Vec2], x: (f32, f32), y: (f32, f32), color: Color) {
+    let points: egui::plot::PlotPoints = points.iter().map(|p| [p.x as f64, p.y as f64]).collect();
+    egui::plot::Plot::new(id)
+        .width(PLOT_WIDTH)
+        .height(PLOT_HEIGHT)
+        .include_x(x.0)
+        .include_x(x.1)
+        .include_y(y.0)
+        .include_y(y.1)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot| plot.points(egui::plot::Points::new(points).radius(PLOT_DOT / 2.).color(color32(color))));
+}
+
+/// Scatter plot of `points` over the `x` and `y` ranges, with axes through zero where it is
+/// in range. Points outside the ranges are clipped to the edge.
+pub fn plot(parent: &mut ChildBuilder, points: &[Vec2], x: (f32, f32), y: (f32, f32), color: Color) {
+    let to_px = |p: Vec2| Vec2::new(
+        ((p.x - x.0) / (x.1 - x.0)).clamp(0., 1.) * PLOT_WIDTH,
+        (1. - ((p.y - y.0) / (y.1 - y.0)).clamp(0., 1.)) * PLOT_HEIGHT
+    );
+    let rect = |left: f32, top: f32, width: f32, height: f32, color: Color| NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(left),
+                top: Val::Px(top),
+                ..default()
+            },
+            size: Size::new(Val::Px(width), Val::Px(height)),
+            ..default()
+        },
+        background_color: color.into(),
+        ..default()
+    };
+    parent.spawn(NodeBundle {
+        style: Style {
+            size: Size::new(Val::Px(PLOT_WIDTH), Val::Px(PLOT_HEIGHT)),
+            ..default()
+        },
+        background_color: PLOT_BACKGROUND.into(),
+        ..default()
+    }).with_children(|plot| {
+        let origin = to_px(Vec2::ZERO);
+        if x.0 < 0. && x.1 > 0. {
+            plot.spawn(rect(origin.x, 0., 1., PLOT_HEIGHT, PLOT_AXIS_COLOR));
+        }
+        if y.0 < 0. && y.1 > 0. {
+            plot.spawn(rect(0., origin.y, PLOT_WIDTH, 1., PLOT_AXIS_COLOR));
+        }
+        for point in points {
+            let p = to_px(*point);
+            plot.spawn(rect(p.x - PLOT_DOT / 2., p.y - PLOT_DOT / 2., PLOT_DOT, PLOT_DOT, color));
+        }
+    });
+}
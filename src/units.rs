@@ -0,0 +1,212 @@
+use std::{fmt, ops::{Add, Div, Mul, Neg, Sub}};
+
+use serde::{Deserialize, Serialize};
+
+/// Wavelengths.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Nanometers(pub f32);
+
+/// Scene lengths and positions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Millimeters(pub f32);
+
+/// User-facing angles. Internal math works in radians; convert with [`Degrees::to_radians`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Degrees(pub f32);
+
+impl Nanometers {
+    pub fn to_mm(self) -> Millimeters {
+        Millimeters(self.0 * 1e-6)
+    }
+}
+
+impl Millimeters {
+    pub fn to_nm(self) -> Nanometers {
+        Nanometers(self.0 * 1e6)
+    }
+}
+
+impl Degrees {
+    pub fn from_radians(radians: f32) -> Self {
+        Self(radians.to_degrees())
+    }
+
+    pub fn to_radians(self) -> f32 {
+        self.0.to_radians()
+    }
+}
+
+macro_rules! quantity {
+    ($t:ident, $unit:literal) => {
+        impl Add for $t {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl Sub for $t {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        impl Neg for $t {
+            type Output = Self;
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+
+        impl Mul<f32> for $t {
+            type Output = Self;
+            fn mul(self, rhs: f32) -> Self {
+                Self(self.0 * rhs)
+            }
+        }
+
+        impl Div<f32> for $t {
+            type Output = Self;
+            fn div(self, rhs: f32) -> Self {
+                Self(self.0 / rhs)
+            }
+        }
+
+        /// Dimensionless ratio of two quantities.
+        impl Div for $t {
+            type Output = f32;
+            fn div(self, rhs: Self) -> f32 {
+                self.0 / rhs.0
+            }
+        }
+
+        impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match f.precision() {
+                    Some(precision) => write!(f, "{:.*} {}", precision, self.0, $unit),
+                    None => write!(f, "{} {}", self.0, $unit)
+                }
+            }
+        }
+    };
+}
+
+quantity!(Nanometers, "nm");
+quantity!(Millimeters, "mm");
+quantity!(Degrees, "°");
+
+/// Kinds of quantity shown in panels and written to exports. Values are passed in the scene's
+/// own units, given for each kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantity {
+    /// Positions and sizes, in mm
+    Length,
+    /// Aberrations and other lengths well below a millimeter, in mm
+    SmallLength,
+    /// In nm
+    Wavelength,
+    /// Orientations of elements, in radians
+    Angle,
+    /// Divergence and other small angles within a beam, in radians
+    BeamAngle,
+    /// In mW, the unit of source power
+    Power
+}
+
+/// Units quantities are shown and exported in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitSystem {
+    /// Units customary on an optical table: mm, µm for aberrations, nm, degrees, mrad for
+    /// beam angles and mW
+    #[default]
+    Lab,
+    /// Meters, radians and watts, with an SI prefix picked for each value
+    Si
+}
+
+// Significant digits of values without a fixed number of decimals
+const SIGNIFICANT: i32 = 4;
+const PREFIXES: [(f32, &str); 5] = [(1e3, "k"), (1., ""), (1e-3, "m"), (1e-6, "µ"), (1e-9, "n")];
+
+/// Decimals that show `value` to [`SIGNIFICANT`] digits.
+fn significant(value: f32) -> usize {
+    if value == 0. || !value.is_finite() {
+        return 0
+    }
+    (SIGNIFICANT - 1 - value.abs().log10().floor() as i32).max(0) as usize
+}
+
+/// A value ready to display with its unit. The `+` flag shows the sign of positive values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Formatted {
+    pub value: f32,
+    pub decimals: usize,
+    pub unit: String
+}
+
+impl fmt::Display for Formatted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let space = if self.unit == "°" { "" } else { " " };
+        if f.sign_plus() {
+            write!(f, "{:+.*}{}{}", self.decimals, self.value, space, self.unit)
+        } else {
+            write!(f, "{:.*}{}{}", self.decimals, self.value, space, self.unit)
+        }
+    }
+}
+
+impl UnitSystem {
+    /// Unit `quantity` is exported in, and the factor taking a value to it.
+    pub fn export_unit(self, quantity: Quantity) -> (f32, &'static str) {
+        match (self, quantity) {
+            (UnitSystem::Lab, Quantity::Length) => (1., "mm"),
+            (UnitSystem::Lab, Quantity::SmallLength) => (1e3, "µm"),
+            (UnitSystem::Lab, Quantity::Wavelength) => (1., "nm"),
+            (UnitSystem::Lab, Quantity::Angle) => (180. / std::f32::consts::PI, "deg"),
+            (UnitSystem::Lab, Quantity::BeamAngle) => (1e3, "mrad"),
+            (UnitSystem::Lab, Quantity::Power) => (1., "mW"),
+            (UnitSystem::Si, Quantity::Length | Quantity::SmallLength) => (1e-3, "m"),
+            (UnitSystem::Si, Quantity::Wavelength) => (1e-9, "m"),
+            (UnitSystem::Si, Quantity::Angle | Quantity::BeamAngle) => (1., "rad"),
+            (UnitSystem::Si, Quantity::Power) => (1e-3, "W")
+        }
+    }
+
+    /// `value` of `quantity` in this system's display unit and precision.
+    pub fn show(self, quantity: Quantity, value: f32) -> Formatted {
+        let (factor, unit) = self.export_unit(quantity);
+        let value = value * factor;
+        match self {
+            UnitSystem::Lab => {
+                let (unit, decimals) = match quantity {
+                    Quantity::Length => (unit, 3),
+                    Quantity::SmallLength | Quantity::Wavelength => (unit, 1),
+                    Quantity::Angle => ("°", 2),
+                    Quantity::BeamAngle => (unit, 2),
+                    Quantity::Power => (unit, significant(value))
+                };
+                Formatted {
+                    value,
+                    decimals,
+                    unit: unit.to_string()
+                }
+            }
+            UnitSystem::Si => {
+                let (scale, prefix) = PREFIXES.iter()
+                    .find(|(scale, _)| value.abs() >= *scale)
+                    .copied()
+                    .unwrap_or(if value == 0. { (1., "") } else { PREFIXES[PREFIXES.len() - 1] });
+                Formatted {
+                    value: value / scale,
+                    decimals: significant(value / scale),
+                    unit: format!("{}{}", prefix, unit)
+                }
+            }
+        }
+    }
+}
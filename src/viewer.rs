@@ -0,0 +1,145 @@
+use std::{fs, io, path::Path};
+
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+
+use crate::{animation::Clock, locale::Locale, ui::{self, UiFont}};
+
+// Where `wasm-bindgen --target web` is pointed for the web build
+const WEB_BUILD: &str = "target/web";
+// Files of the web build the page loads
+const WEB_FILES: [&str; 2] = ["beams.js", "beams_bg.wasm"];
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Beams</title>
+<style>
+html, body { margin: 0; height: 100%; background: #111; }
+canvas { display: block; width: 100%; height: 100%; }
+</style>
+</head>
+<body>
+<canvas id="beams"></canvas>
+<script type="module">
+import init from "./beams.js";
+init();
+</script>
+</body>
+</html>
+"#;
+
+/// Whether the scene can be edited. The web build and `--viewer` only show the scene: they
+/// can be panned, zoomed and played, and the analysis panels open, but nothing in the scene
+/// moves except by its animation, and the session is neither restored nor saved.
+#[derive(Resource)]
+pub struct ReadOnly(pub bool);
+
+impl ReadOnly {
+    pub fn from_args(args: &[String]) -> Self {
+        Self(cfg!(target_arch = "wasm32") || args.iter().any(|arg| arg == "--viewer"))
+    }
+}
+
+/// Run criteria for systems that edit the scene or the session.
+pub fn editable(read_only: Res<ReadOnly>) -> ShouldRun {
+    if read_only.0 { ShouldRun::No } else { ShouldRun::Yes }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+fn export(dir: &Path) -> Result<(), String> {
+    let build = Path::new(WEB_BUILD);
+    if let Some(missing) = WEB_FILES.iter().find(|file| !build.join(file).exists()) {
+        return Err(format!("{} is missing from {}; build the web viewer first (see README)", missing, WEB_BUILD))
+    }
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    for file in WEB_FILES {
+        fs::copy(build.join(file), dir.join(file)).map_err(|err| format!("{}: {}", file, err))?;
+    }
+    copy_dir(Path::new("assets"), &dir.join("assets")).map_err(|err| format!("assets: {}", err))?;
+    fs::write(dir.join("index.html"), PAGE).map_err(|err| err.to_string())
+}
+
+/// Handles `--export-viewer <dir>`: puts the web build, the assets and a page that runs them
+/// into `dir`, ready to be served as a static site. Returns the process exit code if it was given.
+pub fn run(args: &[String]) -> Option<i32> {
+    let [mode, dir, ..] = args else {
+        return None
+    };
+    if mode != "--export-viewer" {
+        return None
+    }
+    Some(match export(Path::new(dir)) {
+        Ok(()) => {
+            println!("viewer exported to {}", dir);
+            0
+        }
+        Err(err) => {
+            eprintln!("export failed: {}", err);
+            1
+        }
+    })
+}
+
+#[derive(Component)]
+pub struct PlayButton;
+
+/// Shows a play/pause button in read-only mode, where the keyboard may not be at hand.
+pub fn play_button_system(
+    mut commands: Commands,
+    read_only: Res<ReadOnly>,
+    clock: Res<Clock>,
+    locale: Res<Locale>,
+    font: Res<UiFont>,
+    mut shown: Local<Option<bool>>,
+    button_query: Query<Entity, With<PlayButton>>
+) {
+    if !read_only.0 || (*shown == Some(clock.playing) && !locale.is_changed()) {
+        return
+    }
+    *shown = Some(clock.playing);
+    for button in button_query.iter() {
+        commands.entity(button).despawn_recursive();
+    }
+    commands.spawn((ButtonBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                // where the tab bar would be, as tabs can't be opened
+                left: Val::Percent(40.),
+                top: Val::Px(4.),
+                ..default()
+            },
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        background_color: ui::PANEL_COLOR.into(),
+        ..default()
+    }, PlayButton)).with_children(|button| {
+        let key = if clock.playing { "viewer.pause" } else { "viewer.play" };
+        button.spawn(TextBundle::from_section(locale.text(key), ui::text_style(&font, ui::TEXT_COLOR)));
+    });
+}
+
+pub fn play_click_system(
+    mut clock: ResMut<Clock>,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<PlayButton>)>
+) {
+    if button_query.iter().any(|interaction| *interaction == Interaction::Clicked) {
+        clock.playing = !clock.playing;
+    }
+}
@@ -0,0 +1,104 @@
+use bevy::{
+    prelude::*,
+    render::{camera::RenderTarget, view::RenderLayers},
+    window::{CreateWindow, WindowClosed, WindowId, WindowResized}
+};
+
+use crate::{fit_scale, locale::Locale, SCENE_H, SCENE_W};
+
+const VIEW_WIDTH: f32 = 640.;
+const VIEW_HEIGHT: f32 = 480.;
+// Render layer of analysis windows, which nothing on the table is on, so they show only egui
+const ANALYSIS_LAYER: u8 = 1;
+
+/// Camera rendering into a detached OS window rather than the main one.
+#[derive(Component)]
+pub struct DetachedView(pub WindowId);
+
+/// Camera of the detached window showing the analysis views instead of the table: the
+/// detector plots, the spectrometer and the inspector.
+#[derive(Component)]
+pub struct AnalysisView;
+
+/// Query filter for the camera of the main window.
+pub type MainCamera = (With<Camera2d>, Without<DetachedView>);
+pub type Analysis<'w, 's> = Query<'w, 's, &'static DetachedView, With<AnalysisView>>;
+
+/// The window the analysis views are detached into, if one is open.
+pub fn analysis_window(analysis_query: &Analysis) -> Option<WindowId> {
+    analysis_query.iter().next().map(|view| view.0)
+}
+
+/// F3 opens another window showing the whole table, e.g. for a second monitor. Shift+F3 opens
+/// a window for the analysis views instead, which then leave the main window; there is one at
+/// most.
+pub fn open_view_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    locale: Res<Locale>,
+    mut create: EventWriter<CreateWindow>,
+    (view_query, analysis_query): (Query<(), With<DetachedView>>, Analysis)
+) {
+    if !keys.just_pressed(KeyCode::F3) {
+        return
+    }
+    let analysis = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if analysis && analysis_window(&analysis_query).is_some() {
+        return
+    }
+    let id = WindowId::new();
+    let title = if analysis {
+        locale.text("views.analysis").to_string()
+    } else {
+        locale.format("views.title", &[("number", &(view_query.iter().count() + 1))])
+    };
+    create.send(CreateWindow {
+        id,
+        descriptor: WindowDescriptor {
+            title,
+            width: VIEW_WIDTH,
+            height: VIEW_HEIGHT,
+            ..default()
+        }
+    });
+    let mut camera = Camera2dBundle::default();
+    camera.camera.target = RenderTarget::Window(id);
+    if analysis {
+        // egui draws the views over what the camera clears the window to
+        commands.spawn((camera, UiCameraConfig { show_ui: false }, RenderLayers::layer(ANALYSIS_LAYER), DetachedView(id), AnalysisView));
+        return
+    }
+    camera.transform.translation.x = SCENE_W / 2.;
+    camera.transform.translation.y = SCENE_H / 2.;
+    camera.projection.scale = fit_scale(VIEW_WIDTH, VIEW_HEIGHT);
+    // bevy_ui lays out against the primary window only
+    commands.spawn((camera, UiCameraConfig { show_ui: false }, DetachedView(id)));
+}
+
+/// Keeps each detached view fitted to its own window.
+pub fn view_resize_system(
+    mut resized: EventReader<WindowResized>,
+    mut view_query: Query<(&DetachedView, &mut OrthographicProjection)>
+) {
+    for event in resized.iter() {
+        for (view, mut projection) in view_query.iter_mut() {
+            if view.0 == event.id {
+                projection.scale = fit_scale(event.width, event.height);
+            }
+        }
+    }
+}
+
+pub fn close_view_system(
+    mut commands: Commands,
+    mut closed: EventReader<WindowClosed>,
+    view_query: Query<(Entity, &DetachedView)>
+) {
+    for event in closed.iter() {
+        for (camera, view) in view_query.iter() {
+            if view.0 == event.id {
+                commands.entity(camera).despawn();
+            }
+        }
+    }
+}